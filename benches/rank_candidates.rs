@@ -0,0 +1,55 @@
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use rust_embedding::embeddings::storage::{build_record, StoragePrecision, StoredEmbedding};
+use rust_embedding::{rank_candidates, RankOptions};
+
+const DIMENSIONS: usize = 64;
+
+/// Deterministic vectors with components in [-1, 1), from a linear congruential generator
+fn synthetic_vectors(count: usize, seed: u64) -> Vec<Vec<f64>> {
+    let mut state = seed;
+    (0..count)
+        .map(|_| {
+            (0..DIMENSIONS)
+                .map(|_| {
+                    state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+                    (state >> 11) as f64 / (1u64 << 52) as f64 - 1.0
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// Records as they are read from a data file, over a few types
+fn synthetic_records(count: usize) -> Vec<serde_json::Value> {
+    synthetic_vectors(count, 7)
+        .iter()
+        .enumerate()
+        .map(|(i, vector)| {
+            let embedding = StoredEmbedding::encode(vector, StoragePrecision::F64);
+            build_record(&format!("text {}", i), &embedding, "bench", ["a", "b", "c"][i % 3], false, None)
+        })
+        .collect()
+}
+
+fn bench_rank_candidates(c: &mut Criterion) {
+    let query = &synthetic_vectors(1, 99)[0];
+    let mut group = c.benchmark_group("rank_candidates");
+    group.sample_size(10);
+    for count in [1_000, 10_000, 100_000] {
+        let records = synthetic_records(count);
+        for (name, parallel) in [("top10", false), ("top10_parallel", true)] {
+            let options = RankOptions { top_k: Some(10), parallel, ..RankOptions::default() };
+            group.bench_with_input(BenchmarkId::new(name, count), &records, |b, records| {
+                b.iter(|| rank_candidates(black_box(records), "", black_box(query), &options))
+            });
+        }
+        let options = RankOptions::default();
+        group.bench_with_input(BenchmarkId::new("all", count), &records, |b, records| {
+            b.iter(|| rank_candidates(black_box(records), "", black_box(query), &options))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_rank_candidates);
+criterion_main!(benches);
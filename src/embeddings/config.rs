@@ -0,0 +1,205 @@
+use crate::embeddings::idempotency::DEFAULT_IDEMPOTENCY_TTL_SECS;
+use crate::embeddings::provider::{provider_from_env, EmbeddingProvider};
+use crate::embeddings::service::{
+    TieBreak, DEFAULT_EMBEDDING_CACHE_SIZE, DEFAULT_EMBEDDING_TYPE, DEFAULT_FLUSH_MAX_RECORDS,
+    DEFAULT_MAX_CONCURRENT_EMBEDDINGS, DEFAULT_MAX_TEXT_CHARS,
+};
+use crate::embeddings::storage::{StorageFormat, StoragePrecision};
+use crate::http::client::DEFAULT_HTTP_TIMEOUT_SECS;
+use dotenv::dotenv;
+use std::env;
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+
+/// A setting that can't work, found when the service is created or a store is attempted
+#[derive(Debug, PartialEq, Eq)]
+pub enum ConfigError {
+    /// `DATA_PATH` names an existing directory rather than a file
+    DataPathIsDirectory(String),
+    /// A directory `DATA_PATH` would be created in is an existing file
+    DataPathUnderFile { path: String, file: String },
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::DataPathIsDirectory(path) => {
+                write!(f, "DATA_PATH points to a directory: {}; set it to a file such as {}/embeddings.jsonl", path, path.trim_end_matches('/'))
+            }
+            ConfigError::DataPathUnderFile { path, file } => {
+                write!(f, "DATA_PATH {} can't be created because {} is a file, not a directory", path, file)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+/// Check that `data_path` is a regular file, or can be created as one: it isn't a directory,
+/// and its closest existing ancestor is a directory
+pub fn check_data_path(data_path: &str) -> Result<(), ConfigError> {
+    let path = Path::new(data_path);
+    if path.is_dir() {
+        return Err(ConfigError::DataPathIsDirectory(data_path.to_string()));
+    }
+    if path.exists() {
+        return Ok(());
+    }
+    let existing = path.ancestors().skip(1).find(|ancestor| !ancestor.as_os_str().is_empty() && ancestor.exists());
+    match existing {
+        Some(ancestor) if !ancestor.is_dir() => Err(ConfigError::DataPathUnderFile {
+            path: data_path.to_string(),
+            file: ancestor.display().to_string(),
+        }),
+        _ => Ok(()),
+    }
+}
+
+/// Everything an `EmbeddingService` is configured with, read once at startup by `from_env`
+/// or built in code with `new`
+pub struct Config {
+    /// Computes the embeddings
+    pub provider: Box<dyn EmbeddingProvider>,
+    /// Models requests may ask for; `None` falls back to the provider's own list, if it has one
+    pub allowed_models: Option<Vec<String>>,
+    /// File the embeddings are stored in
+    pub data_path: String,
+    /// Layout of the data file; JSONL becomes gzipped JSONL for a `*.gz` path
+    pub storage_format: StorageFormat,
+    /// Precision new vectors are written with
+    pub storage_precision: StoragePrecision,
+    /// Total timeout of each provider call, in seconds
+    pub http_timeout_secs: u64,
+    /// Longest text, in characters, that requests may ask to embed
+    pub max_text_chars: usize,
+    /// Type used by requests that don't name one
+    pub default_embedding_type: String,
+    /// Embeddings kept in memory; 0 disables the cache
+    pub embedding_cache_size: usize,
+    /// Provider calls allowed in flight at once
+    pub max_concurrent_embeddings: usize,
+    /// Seconds a store's response is replayed to requests repeating its `Idempotency-Key`; 0
+    /// ignores the header
+    pub idempotency_ttl_secs: u64,
+    /// Most records kept before the oldest are evicted; 0 keeps everything
+    pub max_stored_entries: usize,
+    /// Milliseconds stores are buffered in memory before being appended to the data file
+    /// together; 0 writes each store as it happens
+    pub flush_interval_ms: u64,
+    /// Buffered stores that trigger a flush before the interval is up
+    pub flush_max_records: usize,
+    /// Whether vectors are L2-normalized before being stored
+    pub normalize_embeddings: bool,
+    /// Whether stores whose dimension differs from the type's stored vectors are rejected
+    pub strict_dimensions: bool,
+    /// Whether readiness checks make a real embedding call to the provider
+    pub deep_readiness_check: bool,
+    /// Whether `/clear` is refused, so the data can't be wiped through the API
+    pub disable_clear: bool,
+    /// Whether full-scan comparisons of large stores are scored in parallel
+    pub parallel_compare: bool,
+    /// How results with equal similarity are ordered
+    pub tie_break: TieBreak,
+    /// Whether the store is read into memory at construction instead of on first use
+    pub preload_on_start: bool,
+    /// Whether `top_k` comparisons are answered from an HNSW index
+    pub use_ann_index: bool,
+    /// Whether `top_k` comparisons are scored in the projected space once one is fitted
+    pub use_projection: bool,
+}
+
+impl Config {
+    /// The defaults for every setting, without looking at the environment
+    pub fn new(provider: Box<dyn EmbeddingProvider>, data_path: impl Into<String>) -> Self {
+        Self {
+            provider,
+            allowed_models: None,
+            data_path: data_path.into(),
+            storage_format: StorageFormat::default(),
+            storage_precision: StoragePrecision::default(),
+            http_timeout_secs: DEFAULT_HTTP_TIMEOUT_SECS,
+            max_text_chars: DEFAULT_MAX_TEXT_CHARS,
+            default_embedding_type: DEFAULT_EMBEDDING_TYPE.to_string(),
+            embedding_cache_size: DEFAULT_EMBEDDING_CACHE_SIZE,
+            max_concurrent_embeddings: DEFAULT_MAX_CONCURRENT_EMBEDDINGS,
+            idempotency_ttl_secs: DEFAULT_IDEMPOTENCY_TTL_SECS,
+            max_stored_entries: 0,
+            flush_interval_ms: 0,
+            flush_max_records: DEFAULT_FLUSH_MAX_RECORDS,
+            normalize_embeddings: false,
+            strict_dimensions: false,
+            deep_readiness_check: false,
+            disable_clear: false,
+            parallel_compare: false,
+            tie_break: TieBreak::default(),
+            preload_on_start: false,
+            use_ann_index: false,
+            use_projection: false,
+        }
+    }
+
+    /// Read the configuration from the environment, loading `.env` first. Settings that are
+    /// unset or don't parse keep their defaults.
+    pub fn from_env() -> Self {
+        dotenv().ok();
+        let storage_format = StorageFormat::from_env();
+        let data_path = env::var("DATA_PATH").unwrap_or_else(|_| storage_format.default_data_path().to_string());
+        let defaults = Self::new(provider_from_env(), data_path);
+        Self {
+            allowed_models: allowed_models_from_env(),
+            storage_format,
+            storage_precision: StoragePrecision::from_env(),
+            http_timeout_secs: parse_var("HTTP_TIMEOUT_SECS").unwrap_or(defaults.http_timeout_secs),
+            max_text_chars: parse_var("MAX_TEXT_CHARS").unwrap_or(defaults.max_text_chars),
+            default_embedding_type: env::var("DEFAULT_EMBEDDING_TYPE")
+                .ok()
+                .filter(|v| !v.trim().is_empty())
+                .unwrap_or(defaults.default_embedding_type),
+            embedding_cache_size: parse_var("EMBEDDING_CACHE_SIZE").unwrap_or(defaults.embedding_cache_size),
+            max_concurrent_embeddings: parse_var("MAX_CONCURRENT_EMBEDDINGS").unwrap_or(defaults.max_concurrent_embeddings),
+            idempotency_ttl_secs: parse_var("IDEMPOTENCY_TTL_SECS").unwrap_or(defaults.idempotency_ttl_secs),
+            max_stored_entries: parse_var("MAX_STORED_ENTRIES").unwrap_or(defaults.max_stored_entries),
+            flush_interval_ms: parse_var("FLUSH_INTERVAL_MS").unwrap_or(defaults.flush_interval_ms),
+            flush_max_records: parse_var("FLUSH_MAX_RECORDS").unwrap_or(defaults.flush_max_records),
+            normalize_embeddings: flag("NORMALIZE_EMBEDDINGS"),
+            strict_dimensions: flag("STRICT_DIMENSIONS"),
+            deep_readiness_check: flag("READINESS_DEEP_CHECK"),
+            disable_clear: flag("DISABLE_CLEAR"),
+            parallel_compare: flag("PARALLEL_COMPARE"),
+            tie_break: TieBreak::from_env(),
+            preload_on_start: flag("PRELOAD_ON_START"),
+            use_ann_index: flag("USE_ANN_INDEX"),
+            use_projection: flag("USE_PROJECTION"),
+            ..defaults
+        }
+    }
+
+    /// Check the settings that can be wrong in ways the type doesn't rule out
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        check_data_path(&self.data_path)
+    }
+}
+
+/// Parse the comma-separated `ALLOWED_MODELS` env var, if set
+fn allowed_models_from_env() -> Option<Vec<String>> {
+    let models: Vec<String> = env::var("ALLOWED_MODELS").ok()?
+        .split(',')
+        .map(|m| m.trim().to_string())
+        .filter(|m| !m.is_empty())
+        .collect();
+    if models.is_empty() {
+        None
+    } else {
+        Some(models)
+    }
+}
+
+fn parse_var<T: FromStr>(name: &str) -> Option<T> {
+    env::var(name).ok().and_then(|v| v.parse().ok())
+}
+
+/// Whether the env var is set to `true`
+fn flag(name: &str) -> bool {
+    env::var(name).is_ok_and(|v| v == "true")
+}
@@ -0,0 +1,120 @@
+use lru::LruCache;
+use std::fmt;
+use std::num::NonZeroUsize;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// How long a key's response is replayed when `IDEMPOTENCY_TTL_SECS` is unset: a day, which
+/// outlasts any client's retries
+pub const DEFAULT_IDEMPOTENCY_TTL_SECS: u64 = 86_400;
+
+/// Most keys remembered at once; past it the least recently used are forgotten first
+pub const MAX_IDEMPOTENCY_KEYS: usize = 10_000;
+
+/// Longest `Idempotency-Key` accepted
+pub const MAX_IDEMPOTENCY_KEY_LEN: usize = 255;
+
+/// Why a request can't go ahead under its key
+#[derive(Debug, PartialEq, Eq)]
+pub enum IdempotencyError {
+    /// The first request sent with the key hasn't finished yet
+    InProgress,
+    /// The key was first sent with a different request
+    Mismatch,
+}
+
+impl fmt::Display for IdempotencyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IdempotencyError::InProgress => write!(f, "a request with this Idempotency-Key is still in progress"),
+            IdempotencyError::Mismatch => write!(f, "this Idempotency-Key was already used with a different request"),
+        }
+    }
+}
+
+impl std::error::Error for IdempotencyError {}
+
+/// The outcome of claiming a key
+pub enum Claim<T> {
+    /// The key was already handled; this is the response it got
+    Replay(T),
+    /// The key is new, or its response expired. The request should be handled and its
+    /// response recorded with `IdempotencyGuard::complete`
+    New(IdempotencyGuard<T>),
+}
+
+struct Entry<T> {
+    /// Identifies the request the key was first sent with
+    fingerprint: String,
+    /// When the key was claimed, then when its response was recorded
+    updated: Instant,
+    /// `None` while the first request is being handled
+    response: Option<T>,
+}
+
+/// Responses recorded by `Idempotency-Key`, replayed to requests repeating a key for `ttl`
+/// after the first one finished. Keys whose request failed are forgotten, so a retry runs it
+/// again.
+pub struct IdempotencyCache<T> {
+    ttl: Duration,
+    entries: Mutex<LruCache<String, Entry<T>>>,
+}
+
+impl<T: Clone> IdempotencyCache<T> {
+    pub fn new(ttl: Duration, capacity: NonZeroUsize) -> Self {
+        Self { ttl, entries: Mutex::new(LruCache::new(capacity)) }
+    }
+
+    /// Claim `key` for the request identified by `fingerprint`, or get the response it
+    /// already has
+    pub fn claim(self: &Arc<Self>, key: &str, fingerprint: &str) -> Result<Claim<T>, IdempotencyError> {
+        let mut entries = self.entries.lock().unwrap();
+        if let Some(entry) = entries.get(key).filter(|entry| entry.updated.elapsed() < self.ttl) {
+            if entry.fingerprint != fingerprint {
+                return Err(IdempotencyError::Mismatch);
+            }
+            return match &entry.response {
+                Some(response) => Ok(Claim::Replay(response.clone())),
+                None => Err(IdempotencyError::InProgress),
+            };
+        }
+        entries.put(
+            key.to_string(),
+            Entry { fingerprint: fingerprint.to_string(), updated: Instant::now(), response: None },
+        );
+        Ok(Claim::New(IdempotencyGuard { cache: self.clone(), key: key.to_string(), completed: false }))
+    }
+}
+
+/// A claimed key. Dropping it without `complete`, because the request failed, releases the
+/// key for a retry.
+pub struct IdempotencyGuard<T> {
+    cache: Arc<IdempotencyCache<T>>,
+    key: String,
+    completed: bool,
+}
+
+impl<T> IdempotencyGuard<T> {
+    /// Record the response to replay for the key
+    pub fn complete(mut self, response: T) {
+        if let Some(entry) = self.cache.entries.lock().unwrap().get_mut(&self.key) {
+            entry.response = Some(response);
+            entry.updated = Instant::now();
+        }
+        self.completed = true;
+    }
+}
+
+impl<T> Drop for IdempotencyGuard<T> {
+    fn drop(&mut self) {
+        if self.completed {
+            return;
+        }
+        // Also reached while a handler panics, so a poisoned lock is left alone
+        if let Ok(mut entries) = self.cache.entries.lock() {
+            if entries.peek(&self.key).is_some_and(|entry| entry.response.is_none()) {
+                entries.pop(&self.key);
+            }
+        }
+    }
+}
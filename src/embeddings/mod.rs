@@ -1,2 +1,5 @@
+pub mod config;
+pub mod idempotency;
+pub mod provider;
 pub mod service;
 pub mod storage; 
\ No newline at end of file
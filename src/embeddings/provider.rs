@@ -0,0 +1,703 @@
+use crate::http::client::{make_http_request, HttpError, HttpResponse};
+use async_trait::async_trait;
+use reqwest::{Client, Method};
+use std::collections::HashMap;
+use std::env;
+use std::fmt;
+
+pub const DEFAULT_OLLAMA_URL: &str = "http://localhost:11434";
+pub const DEFAULT_OPENAI_URL: &str = "https://api.openai.com/v1";
+pub const DEFAULT_COHERE_URL: &str = "https://api.cohere.ai/v1";
+
+const OPENAI_MODELS: [&str; 3] = [
+    "text-embedding-3-large",
+    "text-embedding-3-small",
+    "text-embedding-3-base",
+];
+
+/// Full output length of the OpenAI models that accept a shorter `dimensions`
+const OPENAI_MAX_DIMENSIONS: [(&str, usize); 2] = [
+    ("text-embedding-3-large", 3072),
+    ("text-embedding-3-small", 1536),
+];
+
+/// Output length of the Cohere models accepted when `ALLOWED_MODELS` is unset
+const COHERE_MODELS: [(&str, usize); 4] = [
+    ("embed-english-v3.0", 1024),
+    ("embed-multilingual-v3.0", 1024),
+    ("embed-english-light-v3.0", 384),
+    ("embed-multilingual-light-v3.0", 384),
+];
+
+/// Most texts Cohere's `/embed` accepts in one call
+pub const COHERE_MAX_BATCH_SIZE: usize = 96;
+
+/// What a text is embedded for. Cohere embeds documents and search queries differently;
+/// other providers ignore it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum InputType {
+    /// A text being stored
+    Document,
+    /// A text being compared against the store
+    Query,
+}
+
+/// Why an embedding call failed
+#[derive(Debug)]
+pub enum ProviderError {
+    /// The provider answered with an error
+    Api {
+        provider: &'static str,
+        /// HTTP status of the response
+        status: u16,
+        /// The provider's description of the error
+        message: String,
+        /// The provider's error category, e.g. OpenAI's `invalid_request_error`
+        error_type: Option<String>,
+    },
+    /// The provider couldn't be reached or didn't answer in time
+    Http(HttpError),
+    /// The provider answered successfully but not in the expected shape
+    InvalidResponse(String),
+    /// Something the provider needs, like an API key, isn't configured
+    NotConfigured(String),
+}
+
+impl ProviderError {
+    /// HTTP status of the provider's error response, if it sent one
+    pub fn status(&self) -> Option<u16> {
+        match self {
+            ProviderError::Api { status, .. } => Some(*status),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for ProviderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProviderError::Api { provider, message, .. } => write!(f, "{}: {}", provider, message),
+            ProviderError::Http(e) => write!(f, "{}", e),
+            ProviderError::InvalidResponse(message) | ProviderError::NotConfigured(message) => {
+                write!(f, "{}", message)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ProviderError {}
+
+impl From<HttpError> for ProviderError {
+    fn from(e: HttpError) -> Self {
+        ProviderError::Http(e)
+    }
+}
+
+/// An embedding backend used by `EmbeddingService`
+#[async_trait]
+pub trait EmbeddingProvider: Send + Sync {
+    /// Short lowercase name, used in logs, metrics and `/models`
+    fn name(&self) -> &'static str;
+
+    /// The model used when a request doesn't name one
+    fn default_model(&self) -> &'static str;
+
+    /// Models accepted when `ALLOWED_MODELS` is unset, or `None` to accept any name
+    fn default_allowed_models(&self) -> Option<Vec<String>> {
+        None
+    }
+
+    /// Full output length of a model, when known
+    fn model_dimensions(&self, _model: &str) -> Option<usize> {
+        None
+    }
+
+    /// Check a requested output length against what the provider and model support
+    fn validate_dimensions(&self, _model: &str, _dimensions: usize) -> Result<(), String> {
+        Err(format!("the {} provider does not support dimensions", self.name()))
+    }
+
+    /// Check that the provider has what it needs to make calls, without calling it
+    fn check_configured(&self) -> Result<(), String> {
+        Ok(())
+    }
+
+    /// Embed `text`, asking for a shortened vector when `dimensions` is given. Callers check
+    /// it with `validate_dimensions` first.
+    async fn embed(
+        &self,
+        client: &Client,
+        text: &str,
+        model: &str,
+        dimensions: Option<usize>,
+        input_type: InputType,
+    ) -> Result<Vec<f64>, ProviderError>;
+
+    /// Embed several texts, returning their vectors in the same order. Providers whose API
+    /// takes a list of inputs do it in one call; the default makes one `embed` call per text.
+    async fn embed_batch(
+        &self,
+        client: &Client,
+        texts: &[String],
+        model: &str,
+        dimensions: Option<usize>,
+        input_type: InputType,
+    ) -> Result<Vec<Vec<f64>>, ProviderError> {
+        let mut embeddings = Vec::with_capacity(texts.len());
+        for text in texts {
+            embeddings.push(self.embed(client, text, model, dimensions, input_type).await?);
+        }
+        Ok(embeddings)
+    }
+}
+
+/// Select the provider from `EMBEDDING_PROVIDER` (`openai`, `ollama`, `cohere` or `mock`),
+/// defaulting to OpenAI
+pub fn provider_from_env() -> Box<dyn EmbeddingProvider> {
+    match env::var("EMBEDDING_PROVIDER").unwrap_or_default().to_lowercase().as_str() {
+        "ollama" => Box::new(OllamaProvider::new(
+            env::var("OLLAMA_URL").unwrap_or_else(|_| DEFAULT_OLLAMA_URL.to_string()),
+        )),
+        "cohere" => Box::new(CohereProvider::new()),
+        "mock" => Box::new(MockProvider::default()),
+        _ => Box::new(OpenAiProvider::new()),
+    }
+}
+
+/// The explicitly configured key, else the contents of the file named by `{var}_FILE` (for
+/// secret mounts), else the named env var. The file is read on every call, so a rotated
+/// secret is picked up without a restart.
+fn api_key(configured: &Option<String>, var: &str) -> Result<String, ProviderError> {
+    if let Some(api_key) = configured {
+        return Ok(api_key.clone());
+    }
+    let file_var = format!("{}_FILE", var);
+    if let Some(path) = env::var(&file_var).ok().filter(|path| !path.trim().is_empty()) {
+        let api_key = std::fs::read_to_string(&path)
+            .map_err(|e| ProviderError::NotConfigured(format!("cannot read {} {}: {}", file_var, path, e)))?;
+        let api_key = api_key.trim();
+        if api_key.is_empty() {
+            return Err(ProviderError::NotConfigured(format!("{} {} is empty", file_var, path)));
+        }
+        return Ok(api_key.to_string());
+    }
+    env::var(var).map_err(|_| ProviderError::NotConfigured(format!("{} is not set", var)))
+}
+
+/// Send a JSON body, with any `extra_headers`, and parse the JSON answer. Error responses
+/// are returned as parsed too, so the caller can read the provider's error shape; a body that
+/// isn't JSON parses as null.
+async fn post_json(
+    client: &Client,
+    url: &str,
+    api_key: Option<&str>,
+    extra_headers: &[(&str, &str)],
+    body: serde_json::Value,
+) -> Result<(HttpResponse, serde_json::Value), ProviderError> {
+    let mut headers = HashMap::new();
+    headers.insert("Content-Type".to_string(), "application/json".to_string());
+    if let Some(api_key) = api_key {
+        headers.insert("Authorization".to_string(), format!("Bearer {}", api_key));
+    }
+    for (name, value) in extra_headers {
+        headers.insert(name.to_string(), value.to_string());
+    }
+
+    let response = make_http_request(client, Method::POST, url, Some(headers), None, Some(body.to_string())).await?;
+    let json_response = serde_json::from_str(&response.body).unwrap_or_default();
+    Ok((response, json_response))
+}
+
+fn unexpected_status(provider: &'static str, status: u16) -> ProviderError {
+    ProviderError::Api {
+        provider,
+        status,
+        message: format!("unexpected response with status {}", status),
+        error_type: None,
+    }
+}
+
+fn invalid_response() -> ProviderError {
+    ProviderError::InvalidResponse("Failed to parse embedding response".to_string())
+}
+
+/// OpenAI, or any server speaking its embeddings protocol (LM Studio, vLLM, LiteLLM, llama.cpp)
+pub struct OpenAiProvider {
+    /// Base URL of the API, with or without the `/v1` suffix, e.g. `https://api.openai.com/v1`
+    pub base_url: String,
+    /// Key sent with every call; read from `OPENAI_API_KEY_FILE` or `OPENAI_API_KEY` at call
+    /// time when `None`
+    pub api_key: Option<String>,
+    /// Sent as `OpenAI-Organization`, for keys that belong to several organizations
+    pub organization: Option<String>,
+    /// Sent as `OpenAI-Project`, to bill calls to a project
+    pub project: Option<String>,
+}
+
+impl Default for OpenAiProvider {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl OpenAiProvider {
+    /// Sends requests to `OPENAI_BASE_URL`, defaulting to the OpenAI API, with the
+    /// organization and project from `OPENAI_ORG_ID` and `OPENAI_PROJECT_ID` if set
+    pub fn new() -> Self {
+        let var = |name| env::var(name).ok().map(|value| value.trim().to_string()).filter(|value| !value.is_empty());
+        Self {
+            base_url: var("OPENAI_BASE_URL").unwrap_or_else(|| DEFAULT_OPENAI_URL.to_string()),
+            api_key: None,
+            organization: var("OPENAI_ORG_ID"),
+            project: var("OPENAI_PROJECT_ID"),
+        }
+    }
+
+    /// Send requests to another OpenAI-compatible base URL, e.g. a proxy
+    pub fn with_base_url(mut self, base_url: impl Into<String>) -> Self {
+        self.base_url = base_url.into();
+        self
+    }
+
+    pub fn with_api_key(mut self, api_key: impl Into<String>) -> Self {
+        self.api_key = Some(api_key.into());
+        self
+    }
+
+    pub fn with_organization(mut self, organization: impl Into<String>) -> Self {
+        self.organization = Some(organization.into());
+        self
+    }
+
+    pub fn with_project(mut self, project: impl Into<String>) -> Self {
+        self.project = Some(project.into());
+        self
+    }
+
+    /// The embeddings endpoint under `base_url`, adding `/v1` when the base URL lacks it
+    pub fn embeddings_url(&self) -> String {
+        let base = self.base_url.trim().trim_end_matches('/');
+        if base.ends_with("/embeddings") {
+            base.to_string()
+        } else if base.ends_with("/v1") {
+            format!("{}/embeddings", base)
+        } else {
+            format!("{}/v1/embeddings", base)
+        }
+    }
+
+    /// `embeddings_url`, or a configuration error if `base_url` doesn't make a valid URL
+    fn checked_embeddings_url(&self) -> Result<String, ProviderError> {
+        let url = self.embeddings_url();
+        reqwest::Url::parse(&url)
+            .map_err(|e| ProviderError::NotConfigured(format!("OPENAI_BASE_URL {} is not a valid URL: {}", self.base_url, e)))?;
+        Ok(url)
+    }
+
+    /// The `OpenAI-Organization` and `OpenAI-Project` headers to send, or a configuration error
+    /// if a value can't be sent as a header, e.g. one containing a newline
+    fn checked_headers(&self) -> Result<Vec<(&'static str, &str)>, ProviderError> {
+        let mut headers = Vec::new();
+        for (name, var, value) in [
+            ("OpenAI-Organization", "OPENAI_ORG_ID", &self.organization),
+            ("OpenAI-Project", "OPENAI_PROJECT_ID", &self.project),
+        ] {
+            if let Some(value) = value {
+                reqwest::header::HeaderValue::from_str(value)
+                    .map_err(|_| ProviderError::NotConfigured(format!("{} is not a valid header value", var)))?;
+                headers.push((name, value.as_str()));
+            }
+        }
+        Ok(headers)
+    }
+
+    /// Embed `input`, a string or a list of them, returning the vectors in input order
+    async fn request_embeddings(
+        &self,
+        client: &Client,
+        input: serde_json::Value,
+        model: &str,
+        dimensions: Option<usize>,
+    ) -> Result<Vec<Vec<f64>>, ProviderError> {
+        let url = self.checked_embeddings_url()?;
+        let api_key = api_key(&self.api_key, "OPENAI_API_KEY")?;
+
+        let mut body = serde_json::json!({
+            "model": model,
+            "input": input
+        });
+        if let Some(dimensions) = dimensions {
+            body["dimensions"] = dimensions.into();
+        }
+
+        let headers = self.checked_headers()?;
+        let (response, json_response) = post_json(client, &url, Some(&api_key), &headers, body).await?;
+
+        // Errors come back as `{"error": {"message": ..., "type": ...}}`
+        if let Some(error) = json_response.get("error") {
+            return Err(ProviderError::Api {
+                provider: "OpenAI",
+                status: response.status,
+                message: error["message"].as_str().unwrap_or("unknown error").to_string(),
+                error_type: error["type"].as_str().map(str::to_string),
+            });
+        }
+        if !response.is_success() {
+            return Err(unexpected_status("OpenAI", response.status));
+        }
+        let data = json_response
+            .get("data")
+            .and_then(|data| data.as_array())
+            .ok_or_else(invalid_response)?;
+
+        // Each item carries the `index` of its input, which servers needn't answer in order
+        let mut embeddings: Vec<(u64, Vec<f64>)> = data
+            .iter()
+            .enumerate()
+            .map(|(position, item)| {
+                let embedding = item.get("embedding").and_then(|embedding| embedding.as_array())?;
+                Some((item["index"].as_u64().unwrap_or(position as u64), parse_vector(embedding)))
+            })
+            .collect::<Option<_>>()
+            .ok_or_else(invalid_response)?;
+        embeddings.sort_by_key(|(index, _)| *index);
+        Ok(embeddings.into_iter().map(|(_, embedding)| embedding).collect())
+    }
+}
+
+#[async_trait]
+impl EmbeddingProvider for OpenAiProvider {
+    fn name(&self) -> &'static str {
+        "openai"
+    }
+
+    fn default_model(&self) -> &'static str {
+        "text-embedding-3-large"
+    }
+
+    fn default_allowed_models(&self) -> Option<Vec<String>> {
+        Some(OPENAI_MODELS.iter().map(|m| m.to_string()).collect())
+    }
+
+    fn model_dimensions(&self, model: &str) -> Option<usize> {
+        OPENAI_MAX_DIMENSIONS
+            .iter()
+            .find(|(name, _)| *name == model)
+            .map(|(_, dimensions)| *dimensions)
+    }
+
+    fn validate_dimensions(&self, model: &str, dimensions: usize) -> Result<(), String> {
+        if dimensions == 0 {
+            return Err("dimensions must be at least 1".to_string());
+        }
+        match self.model_dimensions(model) {
+            Some(max) if dimensions > max => Err(format!(
+                "model {} produces at most {} dimensions, got {}",
+                model, max, dimensions
+            )),
+            Some(_) => Ok(()),
+            None => Err(format!("model {} does not support dimensions", model)),
+        }
+    }
+
+    fn check_configured(&self) -> Result<(), String> {
+        self.checked_embeddings_url().map_err(|e| e.to_string())?;
+        self.checked_headers().map_err(|e| e.to_string())?;
+        api_key(&self.api_key, "OPENAI_API_KEY").map(|_| ()).map_err(|e| e.to_string())
+    }
+
+    async fn embed(
+        &self,
+        client: &Client,
+        text: &str,
+        model: &str,
+        dimensions: Option<usize>,
+        _input_type: InputType,
+    ) -> Result<Vec<f64>, ProviderError> {
+        let embeddings = self.request_embeddings(client, text.into(), model, dimensions).await?;
+        embeddings.into_iter().next().ok_or_else(invalid_response)
+    }
+
+    async fn embed_batch(
+        &self,
+        client: &Client,
+        texts: &[String],
+        model: &str,
+        dimensions: Option<usize>,
+        _input_type: InputType,
+    ) -> Result<Vec<Vec<f64>>, ProviderError> {
+        let embeddings = self.request_embeddings(client, texts.into(), model, dimensions).await?;
+        if embeddings.len() != texts.len() {
+            return Err(invalid_response());
+        }
+        Ok(embeddings)
+    }
+}
+
+pub struct OllamaProvider {
+    /// Base URL of the Ollama server, e.g. `http://localhost:11434`
+    pub url: String,
+}
+
+impl OllamaProvider {
+    pub fn new(url: impl Into<String>) -> Self {
+        Self { url: url.into() }
+    }
+}
+
+#[async_trait]
+impl EmbeddingProvider for OllamaProvider {
+    fn name(&self) -> &'static str {
+        "ollama"
+    }
+
+    fn default_model(&self) -> &'static str {
+        "nomic-embed-text"
+    }
+
+    async fn embed(
+        &self,
+        client: &Client,
+        text: &str,
+        model: &str,
+        _dimensions: Option<usize>,
+        _input_type: InputType,
+    ) -> Result<Vec<f64>, ProviderError> {
+        let url = format!("{}/api/embeddings", self.url.trim_end_matches('/'));
+
+        let body = serde_json::json!({
+            "model": model,
+            "prompt": text
+        });
+
+        let (response, json_response) = post_json(client, &url, None, &[], body).await?;
+
+        if !response.is_success() {
+            // Ollama reports errors as `{"error": "..."}`, e.g. for a model that isn't pulled
+            return Err(match json_response["error"].as_str() {
+                Some(message) => ProviderError::Api {
+                    provider: "Ollama",
+                    status: response.status,
+                    message: message.to_string(),
+                    error_type: None,
+                },
+                None => unexpected_status("Ollama", response.status),
+            });
+        }
+
+        // Ollama returns a flat `{ "embedding": [...] }` rather than OpenAI's `data` array
+        let embedding = json_response
+            .get("embedding")
+            .and_then(|embedding| embedding.as_array())
+            .ok_or_else(invalid_response)?;
+
+        Ok(parse_vector(embedding))
+    }
+}
+
+pub struct CohereProvider {
+    /// Base URL of the API, e.g. `https://api.cohere.ai/v1`
+    pub base_url: String,
+    /// Key sent with every call; read from `COHERE_API_KEY_FILE` or `COHERE_API_KEY` at call
+    /// time when `None`
+    pub api_key: Option<String>,
+}
+
+impl Default for CohereProvider {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CohereProvider {
+    pub fn new() -> Self {
+        Self { base_url: DEFAULT_COHERE_URL.to_string(), api_key: None }
+    }
+
+    pub fn with_base_url(mut self, base_url: impl Into<String>) -> Self {
+        self.base_url = base_url.into();
+        self
+    }
+
+    pub fn with_api_key(mut self, api_key: impl Into<String>) -> Self {
+        self.api_key = Some(api_key.into());
+        self
+    }
+
+    /// Embed `texts` in one call, returning one vector per text in their order
+    async fn request_embeddings(
+        &self,
+        client: &Client,
+        texts: &[&str],
+        model: &str,
+        input_type: InputType,
+    ) -> Result<Vec<Vec<f64>>, ProviderError> {
+        let api_key = api_key(&self.api_key, "COHERE_API_KEY")?;
+        let url = format!("{}/embed", self.base_url.trim_end_matches('/'));
+
+        let body = serde_json::json!({
+            "texts": texts,
+            "model": model,
+            "input_type": match input_type {
+                InputType::Document => "search_document",
+                InputType::Query => "search_query",
+            }
+        });
+
+        let (response, json_response) = post_json(client, &url, Some(&api_key), &[], body).await?;
+
+        if !response.is_success() {
+            // Cohere reports errors as `{"message": "..."}`
+            return Err(match json_response["message"].as_str() {
+                Some(message) => ProviderError::Api {
+                    provider: "Cohere",
+                    status: response.status,
+                    message: message.to_string(),
+                    error_type: None,
+                },
+                None => unexpected_status("Cohere", response.status),
+            });
+        }
+
+        // One vector per input text, under `embeddings`
+        let embeddings = json_response
+            .get("embeddings")
+            .and_then(|embeddings| embeddings.as_array())
+            .filter(|embeddings| embeddings.len() == texts.len())
+            .ok_or_else(invalid_response)?;
+        embeddings
+            .iter()
+            .map(|embedding| embedding.as_array().map(|values| parse_vector(values)))
+            .collect::<Option<_>>()
+            .ok_or_else(invalid_response)
+    }
+}
+
+#[async_trait]
+impl EmbeddingProvider for CohereProvider {
+    fn name(&self) -> &'static str {
+        "cohere"
+    }
+
+    fn default_model(&self) -> &'static str {
+        "embed-english-v3.0"
+    }
+
+    fn default_allowed_models(&self) -> Option<Vec<String>> {
+        Some(COHERE_MODELS.iter().map(|(m, _)| m.to_string()).collect())
+    }
+
+    fn model_dimensions(&self, model: &str) -> Option<usize> {
+        COHERE_MODELS
+            .iter()
+            .find(|(name, _)| *name == model)
+            .map(|(_, dimensions)| *dimensions)
+    }
+
+    fn check_configured(&self) -> Result<(), String> {
+        api_key(&self.api_key, "COHERE_API_KEY").map(|_| ()).map_err(|e| e.to_string())
+    }
+
+    async fn embed(
+        &self,
+        client: &Client,
+        text: &str,
+        model: &str,
+        _dimensions: Option<usize>,
+        input_type: InputType,
+    ) -> Result<Vec<f64>, ProviderError> {
+        let embeddings = self.request_embeddings(client, &[text], model, input_type).await?;
+        embeddings.into_iter().next().ok_or_else(invalid_response)
+    }
+
+    async fn embed_batch(
+        &self,
+        client: &Client,
+        texts: &[String],
+        model: &str,
+        _dimensions: Option<usize>,
+        input_type: InputType,
+    ) -> Result<Vec<Vec<f64>>, ProviderError> {
+        let mut embeddings = Vec::with_capacity(texts.len());
+        for chunk in texts.chunks(COHERE_MAX_BATCH_SIZE) {
+            let chunk: Vec<&str> = chunk.iter().map(String::as_str).collect();
+            embeddings.extend(self.request_embeddings(client, &chunk, model, input_type).await?);
+        }
+        Ok(embeddings)
+    }
+}
+
+/// Output length of `MockProvider` when a request doesn't ask for `dimensions`
+pub const DEFAULT_MOCK_DIMENSIONS: usize = 64;
+
+/// Offline stand-in for OpenAI, for tests and local development. Each lowercased word of the
+/// text adds ±1 to a component picked by its hash, so identical texts get identical vectors
+/// and texts sharing words score as similar. It accepts OpenAI's model names, which make no
+/// difference to the vectors.
+pub struct MockProvider {
+    pub dimensions: usize,
+}
+
+impl Default for MockProvider {
+    fn default() -> Self {
+        Self { dimensions: DEFAULT_MOCK_DIMENSIONS }
+    }
+}
+
+impl MockProvider {
+    /// The vector for `text`, of the given length
+    pub fn embed_text(text: &str, dimensions: usize) -> Vec<f64> {
+        let mut embedding = vec![0.0; dimensions];
+        let words: Vec<String> = text.split_whitespace().map(str::to_lowercase).collect();
+        // Texts without words still get a non-zero vector, from the whole text
+        let features = if words.is_empty() { vec![text.to_string()] } else { words };
+        for feature in features {
+            let hash = blake3::hash(feature.as_bytes());
+            let bytes = hash.as_bytes();
+            let index = u64::from_le_bytes(bytes[..8].try_into().unwrap()) as usize % dimensions;
+            embedding[index] += if bytes[8] & 1 == 0 { 1.0 } else { -1.0 };
+        }
+        embedding
+    }
+}
+
+#[async_trait]
+impl EmbeddingProvider for MockProvider {
+    fn name(&self) -> &'static str {
+        "mock"
+    }
+
+    fn default_model(&self) -> &'static str {
+        "text-embedding-3-large"
+    }
+
+    fn default_allowed_models(&self) -> Option<Vec<String>> {
+        Some(OPENAI_MODELS.iter().map(|m| m.to_string()).collect())
+    }
+
+    fn model_dimensions(&self, _model: &str) -> Option<usize> {
+        Some(self.dimensions)
+    }
+
+    fn validate_dimensions(&self, _model: &str, dimensions: usize) -> Result<(), String> {
+        if dimensions == 0 {
+            return Err("dimensions must be at least 1".to_string());
+        }
+        Ok(())
+    }
+
+    async fn embed(
+        &self,
+        _client: &Client,
+        text: &str,
+        _model: &str,
+        dimensions: Option<usize>,
+        _input_type: InputType,
+    ) -> Result<Vec<f64>, ProviderError> {
+        Ok(Self::embed_text(text, dimensions.unwrap_or(self.dimensions)))
+    }
+}
+
+fn parse_vector(values: &[serde_json::Value]) -> Vec<f64> {
+    values.iter().filter_map(|v| v.as_f64()).collect()
+}
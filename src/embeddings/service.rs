@@ -1,169 +1,2658 @@
-use crate::http::client::make_http_request;
-use crate::embeddings::storage::save_embedding_to_jsonl;
-use crate::utils::similarity::cosine_similarity;
-use crate::ComparisonResult;
-use dotenv::dotenv;
-use reqwest::Method;
-use std::collections::HashMap;
+use crate::embeddings::config::{check_data_path, Config, ConfigError};
+use crate::embeddings::idempotency::{IdempotencyCache, MAX_IDEMPOTENCY_KEYS};
+use crate::embeddings::provider::{EmbeddingProvider, InputType, ProviderError};
+use crate::embeddings::storage::{
+    append_records, build_record, content_hash, iter_entries, load_entries, record_created_at, record_dimensions,
+    record_embedding, record_hash, record_metadata, replace_records, timestamp_now, StorageFormat, StoragePrecision, StoredEmbedding,
+};
+use crate::http::client::build_client_with_timeout;
+use crate::http::slow_requests::{count_compared, Phase, PhaseTimer};
+use crate::utils::hnsw::HnswIndex;
+use crate::utils::lexical::Bm25Corpus;
+use crate::utils::metrics;
+use crate::utils::paths::sanitize_path_component;
+use crate::utils::projection::{Projection, DEFAULT_FIT_ITERATIONS};
+use crate::utils::similarity::{
+    builtin_similarity_functions, cosine_similarity, descending_similarity, dot_product, normalize_vector, top_k_sorted_by,
+    SimilarityFn,
+};
+use crate::utils::clustering::{centroid_similarity, kmeans};
+use crate::{
+    CentroidSummary, Cluster, ClusterResponse, ComparisonResult, FitProjectionResponse, ImportResponse, ModelInfo,
+    ReembedProgress, ReembedResponse, StatsResponse, StoreResponse, StoredItem,
+};
+use chrono::{DateTime, Utc};
+use lru::LruCache;
+use rayon::prelude::*;
+use reqwest::Client;
+use std::borrow::Borrow;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::env;
 use std::fs;
-use serde_json;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering as AtomicOrdering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, BufReader};
+use tokio::sync::Semaphore;
+use tokio_stream::wrappers::LinesStream;
+use tokio_stream::{Stream, StreamExt};
 
-pub struct EmbeddingService;
+pub const DEFAULT_DATA_PATH: &str = "data/embeddings.jsonl";
+
+/// Embeddings kept in memory when `EMBEDDING_CACHE_SIZE` is unset
+pub const DEFAULT_EMBEDDING_CACHE_SIZE: usize = 1000;
+
+/// Longest text accepted for embedding when `MAX_TEXT_CHARS` is unset, roughly the 8k token
+/// input limit of the OpenAI models
+pub const DEFAULT_MAX_TEXT_CHARS: usize = 32_000;
+
+/// Type given to entries stored without one when `DEFAULT_EMBEDDING_TYPE` is unset
+pub const DEFAULT_EMBEDDING_TYPE: &str = "default";
+
+/// Provider calls allowed in flight at once when `MAX_CONCURRENT_EMBEDDINGS` is unset
+pub const DEFAULT_MAX_CONCURRENT_EMBEDDINGS: usize = 8;
+
+/// Stores smaller than this are scored on one thread even with `PARALLEL_COMPARE=true`, since
+/// spreading a few hundred dot products over threads costs more than it saves
+pub const PARALLEL_COMPARE_MIN_ENTRIES: usize = 2_000;
+
+/// Records read from the data file and scored together by a parallel comparison, which bounds
+/// how many are held in memory at once
+const PARALLEL_COMPARE_CHUNK: usize = 8_192;
+
+/// Records a write buffer holds before a store flushes them when `FLUSH_MAX_RECORDS` is unset
+pub const DEFAULT_FLUSH_MAX_RECORDS: usize = 1000;
+
+/// Computed embeddings keyed by `(text, model, requested dimensions, input type)`
+type EmbeddingCache = LruCache<(String, String, Option<usize>, InputType), Vec<f64>>;
+
+/// How many index candidates are fetched per requested result, leaving room for entries the
+/// type filter or self-comparison check drop before reranking
+const ANN_CANDIDATES_PER_RESULT: usize = 4;
+const ANN_MIN_CANDIDATES: usize = 32;
+
+/// Approximate index over the stored records. Graph ids are positions in `records`.
+struct AnnIndex {
+    graph: HnswIndex,
+    records: Vec<serde_json::Value>,
+}
+
+impl AnnIndex {
+    fn build(records: Vec<serde_json::Value>) -> Self {
+        let mut index = AnnIndex { graph: HnswIndex::default(), records: Vec::new() };
+        for record in records {
+            index.insert(record);
+        }
+        index
+    }
+
+    fn insert(&mut self, record: serde_json::Value) {
+        let Some(embedding) = record_embedding(&record) else {
+            return;
+        };
+        self.graph.insert(&embedding);
+        self.records.push(record);
+    }
+}
+
+/// Stored records not yet appended to the data file. Stores add to it instead of writing
+/// each record, and it is flushed in one append every `flush_interval`, once it holds
+/// `max_records`, before the data file is rewritten and when the service shuts down.
+struct WriteBuffer {
+    flush_interval: Duration,
+    max_records: usize,
+    /// Held while a flush appends them, so readers see each record in the file or here
+    records: Mutex<Vec<serde_json::Value>>,
+}
+
+impl WriteBuffer {
+    fn new(flush_interval: Duration, max_records: usize) -> Self {
+        Self { flush_interval, max_records: max_records.max(1), records: Mutex::new(Vec::new()) }
+    }
+}
+
+/// A provider call in flight. Handlers run provider calls inline rather than in spawned
+/// tasks, so when a client disconnects and axum drops the handler's future, the call's
+/// future is dropped too, which aborts its HTTP request and frees its concurrency permit.
+/// Dropping this before `finish` logs and counts that.
+struct ProviderCall {
+    provider: &'static str,
+    start: Instant,
+    finished: bool,
+}
+
+impl ProviderCall {
+    fn start(provider: &'static str) -> Self {
+        Self { provider, start: Instant::now(), finished: false }
+    }
+
+    /// Time since the call started, recorded as the provider's latency
+    fn finish(mut self) -> Duration {
+        self.finished = true;
+        let elapsed = self.start.elapsed();
+        ::metrics::histogram!(metrics::PROVIDER_REQUEST_SECONDS, "provider" => self.provider).record(elapsed.as_secs_f64());
+        elapsed
+    }
+}
+
+impl Drop for ProviderCall {
+    fn drop(&mut self) {
+        if self.finished {
+            return;
+        }
+        ::metrics::counter!(metrics::PROVIDER_CANCELLED_TOTAL, "provider" => self.provider).increment(1);
+        tracing::warn!(elapsed_ms = self.start.elapsed().as_millis() as u64, "embedding request cancelled");
+    }
+}
+
+/// Content hashes and per-type vector dimensions of the stored records, so stores can be
+/// checked without reading the data file
+#[derive(Default)]
+struct ContentIndex {
+    hashes: HashSet<String>,
+    /// Hashes of the stored texts under any type, so a compare can rule out an exact match
+    /// without reading the data file
+    texts: HashSet<u64>,
+    /// Dimension of the first vector stored for each embedding type
+    dimensions: HashMap<String, usize>,
+}
+
+impl ContentIndex {
+    fn build(records: impl IntoIterator<Item = impl std::borrow::Borrow<serde_json::Value>>) -> Self {
+        let mut index = ContentIndex::default();
+        for record in records {
+            index.insert(record.borrow());
+        }
+        index
+    }
+
+    fn insert(&mut self, record: &serde_json::Value) {
+        self.hashes.insert(record_hash(record));
+        self.texts.insert(Self::text_key(record["text"].as_str().unwrap_or_default()));
+        if let Some(dimensions) = record_dimensions(record) {
+            let embedding_type = record["embedding_type"].as_str().unwrap_or_default();
+            self.dimensions.entry(embedding_type.to_string()).or_insert(dimensions);
+        }
+    }
+
+    /// Whether `text` may be stored under some type; a collision only costs a file read
+    fn may_hold_text(&self, text: &str) -> bool {
+        self.texts.contains(&Self::text_key(text))
+    }
+
+    fn text_key(text: &str) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        text.hash(&mut hasher);
+        hasher.finish()
+    }
+}
+
+/// A range of creation times, from `after` (inclusive) up to `before` (exclusive); either end
+/// may be open
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct CreatedRange {
+    pub after: Option<DateTime<Utc>>,
+    pub before: Option<DateTime<Utc>>,
+}
+
+impl CreatedRange {
+    /// Whether a record was created within the range. Records without a timestamp only fall
+    /// in a range open at both ends.
+    pub fn contains(&self, entry: &serde_json::Value) -> bool {
+        if self.after.is_none() && self.before.is_none() {
+            return true;
+        }
+        let Some(created_at) = record_created_at(entry) else {
+            return false;
+        };
+        self.after.is_none_or(|after| created_at >= after) && self.before.is_none_or(|before| created_at < before)
+    }
+}
+
+/// How `rank_candidates` scores and ranks stored records, besides the query itself
+#[derive(Clone, Debug, Default)]
+pub struct RankOptions {
+    /// Number of results to return; all of them when `None`
+    pub top_k: Option<usize>,
+    /// Number of results to return for each embedding type, ranked within it, instead of
+    /// `top_k` overall. The results are then grouped by type, in order of the type names
+    pub per_type_k: Option<usize>,
+    /// Whether results carry the stored vectors
+    pub include_embeddings: bool,
+    /// Which records are scored, and how results are boosted and deduplicated
+    pub filter: CompareFilter,
+    /// Lowest similarity a result may have
+    pub min_similarity: Option<f64>,
+    /// How results with equal similarity are ordered
+    pub tie_break: TieBreak,
+    /// Whether records are scored on rayon's thread pool
+    pub parallel: bool,
+}
+
+/// Restrictions on which stored records a comparison scores
+#[derive(Clone, Debug, Default)]
+pub struct CompareFilter {
+    /// Only records of one of these embedding types
+    pub embedding_types: Option<Vec<String>>,
+    /// Only records embedded with this model, whatever model embeds the query
+    pub model: Option<String>,
+    /// Skip records with the query's text under any type, not just the filtered types
+    pub exclude_self: bool,
+    /// Drop a result whose cosine similarity to a higher-ranked kept result is above this
+    pub dedup_threshold: Option<f64>,
+    /// Only records whose metadata has every one of these keys with an equal value
+    pub metadata: Option<serde_json::Map<String, serde_json::Value>>,
+    /// Multiply the similarity of records of these types by the factor before ranking; the
+    /// product is returned as `adjusted_similarity`
+    pub type_boosts: Option<HashMap<String, f64>>,
+    /// Rank by `alpha * similarity + (1 - alpha) * lexical score`, where the lexical score is
+    /// the record's BM25 keyword score against the query text, scaled so the best scoring
+    /// result gets 1. The blend is returned as `adjusted_similarity`, before any type boost
+    pub hybrid_alpha: Option<f64>,
+    /// Name of the similarity function to score with, from those registered on the service,
+    /// instead of cosine similarity
+    pub metric: Option<String>,
+    /// Only records whose text is one of these, e.g. a shortlist from a cheaper first stage.
+    /// Other records are skipped before their vectors are decoded
+    pub candidates: Option<HashSet<String>>,
+    /// Only records created within this range
+    pub created: CreatedRange,
+    /// The model the query vector was embedded from the query text with. Records of the
+    /// same text and model then score exactly 1; `None` when the vector came from elsewhere
+    /// and the text is only a label
+    pub query_model: Option<String>,
+}
+
+/// One of the comparisons answered together by `EmbeddingService::compare_embeddings_batch`
+#[derive(Clone, Debug, Default)]
+pub struct CompareQuery {
+    /// The query's text, which records are left out for as by `compare_embeddings`
+    pub text: String,
+    /// The query vector, or `None` to only match the records whose text is exactly `text`, as
+    /// `find_exact_matches` does
+    pub embedding: Option<Vec<f64>>,
+    /// Number of results to return; all of them when `None`
+    pub top_k: Option<usize>,
+    /// Number of results to return for each embedding type instead of `top_k` overall
+    pub per_type_k: Option<usize>,
+    /// Whether results carry the stored vectors
+    pub include_embeddings: bool,
+    /// Which records are scored, and how results are boosted and deduplicated
+    pub filter: CompareFilter,
+    /// Lowest similarity a result may have
+    pub min_similarity: Option<f64>,
+}
+
+/// Why a store or update was refused, as opposed to failing to read or write the store
+#[derive(Debug, PartialEq, Eq)]
+pub enum StoreError {
+    /// The text to update isn't stored under the type
+    NotFound { embedding_type: String },
+    /// The text is already stored under the type
+    Duplicate { embedding_type: String },
+    /// With `STRICT_DIMENSIONS`, the type already holds vectors of another length
+    DimensionMismatch { embedding_type: String, stored: usize, got: usize },
+}
+
+impl std::fmt::Display for StoreError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StoreError::NotFound { embedding_type } => write!(f, "text not found for type {}", embedding_type),
+            StoreError::Duplicate { embedding_type } => write!(f, "duplicate text entry for type {}", embedding_type),
+            StoreError::DimensionMismatch { embedding_type, stored, got } => write!(
+                f,
+                "dimension mismatch: embedding_type {} holds {}-dimensional vectors, got {}",
+                embedding_type, stored, got
+            ),
+        }
+    }
+}
+
+impl std::error::Error for StoreError {}
+
+/// Why `fit_projection` failed: the stored vectors can't be fitted as asked, or the store
+/// couldn't be read or rewritten
+#[derive(Debug)]
+pub enum FitProjectionError {
+    /// Too few vectors are stored, or `dimensions` isn't below their length
+    InvalidInput(String),
+    Store(Box<dyn std::error::Error>),
+}
+
+impl std::fmt::Display for FitProjectionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FitProjectionError::InvalidInput(message) => write!(f, "{}", message),
+            FitProjectionError::Store(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for FitProjectionError {}
+
+/// How comparison results with equal similarity are ordered
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TieBreak {
+    /// By text, then type, so the order doesn't depend on how the data file is laid out
+    #[default]
+    Text,
+    /// In the order the entries are stored
+    Insertion,
+}
+
+impl TieBreak {
+    /// Read `COMPARE_TIE_BREAK` (`text` or `insertion`), defaulting to `text`
+    pub fn from_env() -> Self {
+        env::var("COMPARE_TIE_BREAK").ok().and_then(|v| Self::parse(&v)).unwrap_or_default()
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_lowercase().as_str() {
+            "text" => Some(TieBreak::Text),
+            "insertion" => Some(TieBreak::Insertion),
+            _ => None,
+        }
+    }
+
+    /// The ranking order of two results: highest (adjusted) similarity first, ties broken by
+    /// this rule. With `Insertion`, ties are left equal for a stable sort to keep in place.
+    pub fn rank(&self, a: &ComparisonResult, b: &ComparisonResult) -> std::cmp::Ordering {
+        let order = descending_similarity(a.ranking_score(), b.ranking_score());
+        match self {
+            TieBreak::Text => order
+                .then_with(|| a.text.cmp(&b.text))
+                .then_with(|| a.embedding_type.cmp(&b.embedding_type)),
+            TieBreak::Insertion => order,
+        }
+    }
+}
+
+/// Longest namespace name accepted
+pub const MAX_NAMESPACE_LEN: usize = 64;
+
+/// Most namespaces kept open at once. Past it the least recently used one that no request is
+/// using is closed, after writing out its buffered stores, and reopened from its data file
+/// when next used.
+pub const MAX_OPEN_NAMESPACES: usize = 256;
+
+pub struct EmbeddingService {
+    /// Shared with the namespaces' services, like the client, permits and embedding cache
+    provider: Arc<dyn EmbeddingProvider>,
+    /// Models requests may ask for; `None` accepts any model name
+    allowed_models: Option<Vec<String>>,
+    /// Longest text, in characters, that requests may ask to embed
+    max_text_chars: usize,
+    /// Type used by requests that don't name one
+    default_embedding_type: String,
+    /// HTTP client reused for every provider call so connections are pooled
+    client: Client,
+    /// Bounds the provider calls in flight; further calls wait for a permit
+    provider_permits: Arc<Semaphore>,
+    /// File the embeddings are stored in
+    data_path: String,
+    /// Whether `data_path` holds JSONL or length-prefixed bincode records
+    storage_format: StorageFormat,
+    /// Directories this service created to hold the data file, deepest first. Only these are
+    /// removed again when a clear leaves them empty.
+    created_data_dirs: Mutex<Vec<PathBuf>>,
+    /// One lock per data file, serializing the read-check-append of stores and clears
+    write_locks: Mutex<HashMap<String, Arc<tokio::sync::Mutex<()>>>>,
+    /// Whether vectors are L2-normalized before being stored
+    normalize_embeddings: bool,
+    /// Precision new vectors are written with
+    storage_precision: StoragePrecision,
+    /// Whether stores whose dimension differs from the vectors already stored for the type
+    /// are rejected rather than just logged
+    strict_dimensions: bool,
+    /// Whether readiness checks make a real embedding call to the provider
+    deep_readiness_check: bool,
+    /// Whether `/clear` is refused
+    clear_disabled: bool,
+    /// Similarity functions compares can select by name
+    similarity_functions: HashMap<String, SimilarityFn>,
+    /// Cached number of stored vectors, kept up to date on writes instead of recounting the file
+    stored_vectors: AtomicUsize,
+    /// Recently computed embeddings; `None` when caching is disabled
+    embedding_cache: Option<Arc<Mutex<EmbeddingCache>>>,
+    /// Responses of stores sent with an `Idempotency-Key`; `None` when keys are ignored
+    idempotency_cache: Option<Arc<IdempotencyCache<StoreResponse>>>,
+    /// Most records kept; storing past it evicts the oldest. `None` keeps everything
+    max_stored_entries: Option<usize>,
+    /// Whether full-scan comparisons of large stores score entries on rayon's thread pool
+    parallel_compare: bool,
+    /// How results with equal similarity are ordered
+    tie_break: TieBreak,
+    /// Approximate nearest-neighbor index used for `top_k` comparisons when `USE_ANN_INDEX=true`
+    ann_index: Option<Mutex<AnnIndex>>,
+    /// Hashes of the stored records, read from the data file on first use
+    content_index: Mutex<Option<ContentIndex>>,
+    /// Projection fitted by `fit_projection`, read from its file next to the data file
+    projection: Mutex<Option<Arc<Projection>>>,
+    /// Whether `top_k` comparisons are scored in the projected space when a projection exists
+    use_projection: bool,
+    /// Set while a requested startup preload hasn't succeeded, which keeps readiness failing
+    preload_pending: AtomicBool,
+    /// Name of the namespace this service stores, `None` for the default store
+    namespace: Option<String>,
+    /// Services of the namespaces open, each with its own data file, least recently used last
+    namespaces: Mutex<LruCache<String, Arc<EmbeddingService>>>,
+    /// Stores not yet written to the data file; `None` writes each store as it happens
+    write_buffer: Option<WriteBuffer>,
+}
+
+impl Drop for EmbeddingService {
+    fn drop(&mut self) {
+        if let Err(e) = self.flush_buffer() {
+            tracing::error!(path = %self.data_path, error = %e, "failed to flush buffered stores");
+        }
+    }
+}
+
+impl Default for EmbeddingService {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
 impl EmbeddingService {
+    /// Create a service configured from the environment, see `Config::from_env`
     pub fn new() -> Self {
-        Self
+        Self::new_with_config(Config::from_env())
+    }
+
+    /// Create a service storing embeddings in the given JSONL file instead of `DATA_PATH`
+    pub fn with_data_path(data_path: impl Into<String>) -> Self {
+        Self::new_with_config(Config { data_path: data_path.into(), ..Config::from_env() })
+    }
+
+    pub fn with_provider(provider: Box<dyn EmbeddingProvider>) -> Self {
+        Self::new_with_config(Config { provider, ..Config::from_env() })
+    }
+
+    pub fn with_provider_and_data_path(provider: Box<dyn EmbeddingProvider>, data_path: impl Into<String>) -> Self {
+        Self::new_with_config(Config { provider, data_path: data_path.into(), ..Config::from_env() })
+    }
+
+    /// `new_with_config`, failing with a clear error when the configuration can't work, e.g.
+    /// when `data_path` is a directory. The other constructors only find out on first use.
+    pub fn try_new_with_config(config: Config) -> Result<Self, ConfigError> {
+        config.validate()?;
+        Ok(Self::new_with_config(config))
+    }
+
+    /// Create a service from an explicit configuration, without reading the environment
+    pub fn new_with_config(config: Config) -> Self {
+        let allowed_models = config.allowed_models.or_else(|| config.provider.default_allowed_models());
+        let data_path = config.data_path;
+        let storage_format = config.storage_format.for_path(&data_path);
+        let stored_vectors = count_entries(&data_path, storage_format);
+        ::metrics::gauge!(metrics::STORED_VECTORS).set(stored_vectors as f64);
+        let projection = load_projection(&data_path);
+        let service = Self {
+            provider: Arc::from(config.provider),
+            allowed_models,
+            max_text_chars: config.max_text_chars,
+            default_embedding_type: config.default_embedding_type,
+            client: build_client_with_timeout(Duration::from_secs(config.http_timeout_secs)),
+            provider_permits: Arc::new(Semaphore::new(DEFAULT_MAX_CONCURRENT_EMBEDDINGS)),
+            data_path,
+            storage_format,
+            created_data_dirs: Mutex::new(Vec::new()),
+            write_locks: Mutex::new(HashMap::new()),
+            normalize_embeddings: config.normalize_embeddings,
+            storage_precision: config.storage_precision,
+            strict_dimensions: config.strict_dimensions,
+            deep_readiness_check: config.deep_readiness_check,
+            clear_disabled: config.disable_clear,
+            similarity_functions: builtin_similarity_functions(),
+            stored_vectors: AtomicUsize::new(stored_vectors),
+            max_stored_entries: None,
+            parallel_compare: config.parallel_compare,
+            tie_break: config.tie_break,
+            ann_index: None,
+            embedding_cache: None,
+            idempotency_cache: None,
+            content_index: Mutex::new(None),
+            projection: Mutex::new(projection),
+            use_projection: config.use_projection,
+            preload_pending: AtomicBool::new(false),
+            namespace: None,
+            namespaces: Mutex::new(LruCache::unbounded()),
+            write_buffer: None,
+        };
+        service
+            .with_write_buffer(config.flush_interval_ms, config.flush_max_records)
+            .with_max_stored_entries(config.max_stored_entries)
+            .with_embedding_cache_size(config.embedding_cache_size)
+            .with_idempotency_ttl(config.idempotency_ttl_secs)
+            .with_max_concurrent_embeddings(config.max_concurrent_embeddings)
+            // Before the ANN index, which is built from the file anyway, so it isn't built twice
+            .with_preload_on_start(config.preload_on_start)
+            .with_ann_index(config.use_ann_index)
+    }
+
+    /// Replace the HTTP client used for provider calls, e.g. to change timeouts in tests
+    pub fn with_http_client(mut self, client: Client) -> Self {
+        self.client = client;
+        self
+    }
+
+    /// Store L2-normalized vectors so comparisons against them reduce to a dot product
+    pub fn with_normalize_embeddings(mut self, normalize_embeddings: bool) -> Self {
+        self.normalize_embeddings = normalize_embeddings;
+        self
+    }
+
+    /// Write new vectors as `f32` or int8 instead of `f64` to shrink the data file. Records
+    /// already stored keep their precision.
+    pub fn with_storage_precision(mut self, storage_precision: StoragePrecision) -> Self {
+        self.storage_precision = storage_precision;
+        self
+    }
+
+    /// Register a similarity function compares can select by `name` with
+    /// `CompareFilter::metric`, e.g. a cosine that weighs some dimensions less. Higher scores
+    /// must mean closer, and a NaN score leaves the record out. A function of the same name is
+    /// replaced, including the built-in `cosine`, `dot` and `euclidean`.
+    pub fn with_similarity_function(
+        mut self,
+        name: impl Into<String>,
+        function: impl Fn(&[f64], &[f64]) -> f64 + Send + Sync + 'static,
+    ) -> Self {
+        self.similarity_functions.insert(name.into(), Arc::new(function));
+        self
+    }
+
+    /// Names of the registered similarity functions, sorted
+    pub fn similarity_functions(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.similarity_functions.keys().cloned().collect();
+        names.sort();
+        names
+    }
+
+    /// The registered function `metric` names, or `None` to score by cosine similarity
+    fn similarity_function(&self, metric: Option<&str>) -> Result<Option<&SimilarityFn>, Box<dyn std::error::Error>> {
+        metric
+            .map(|name| self.similarity_functions.get(name).ok_or_else(|| format!("unknown metric {}", name).into()))
+            .transpose()
+    }
+
+    /// Refuse `/clear` requests, e.g. on a production instance, so its data can't be wiped
+    /// through the API. `clear_data` and `clear_type` still work when called directly.
+    pub fn with_clear_disabled(mut self, clear_disabled: bool) -> Self {
+        self.clear_disabled = clear_disabled;
+        self
+    }
+
+    /// Whether `/clear` requests are refused
+    pub fn clear_disabled(&self) -> bool {
+        self.clear_disabled
+    }
+
+    /// Reject stores whose dimension doesn't match the vectors already stored for the same
+    /// embedding type, e.g. after switching models without clearing the data
+    pub fn with_strict_dimensions(mut self, strict_dimensions: bool) -> Self {
+        self.strict_dimensions = strict_dimensions;
+        self
+    }
+
+    /// Keep at most `max` records, evicting the oldest when a store would exceed it. A maximum
+    /// of 0 keeps everything.
+    pub fn with_max_stored_entries(mut self, max: usize) -> Self {
+        self.max_stored_entries = Some(max).filter(|max| *max > 0);
+        self
+    }
+
+    /// Score entries in parallel when a comparison scans at least
+    /// `PARALLEL_COMPARE_MIN_ENTRIES` of them. Results are the same either way.
+    pub fn with_parallel_compare(mut self, parallel_compare: bool) -> Self {
+        self.parallel_compare = parallel_compare;
+        self
+    }
+
+    /// Read the store into memory right away instead of on the first request, see `preload`.
+    /// If that fails the error is logged and readiness keeps failing.
+    pub fn with_preload_on_start(self, preload: bool) -> Self {
+        if preload {
+            self.preload_pending.store(true, AtomicOrdering::SeqCst);
+            if let Err(e) = self.preload() {
+                tracing::error!(path = %self.data_path, error = %e, "failed to preload the store");
+            }
+        }
+        self
+    }
+
+    /// Score `top_k` comparisons against the projected vectors once `fit_projection` has been
+    /// run, reranking the best candidates with the full vectors
+    pub fn with_projected_compare(mut self, use_projection: bool) -> Self {
+        self.use_projection = use_projection;
+        self
+    }
+
+    /// Buffer stores in memory and append them to the data file together, every
+    /// `flush_interval_ms` (see `spawn_flush_task`) or once `max_records` are waiting. Reads
+    /// include the buffered records. 0 writes each store to the file before it returns.
+    pub fn with_write_buffer(mut self, flush_interval_ms: u64, max_records: usize) -> Self {
+        self.flush_buffer().ok();
+        self.write_buffer = (flush_interval_ms > 0)
+            .then(|| WriteBuffer::new(Duration::from_millis(flush_interval_ms), max_records));
+        self
+    }
+
+    /// Order results with equal similarity by `tie_break` instead of by text
+    pub fn with_tie_break(mut self, tie_break: TieBreak) -> Self {
+        self.tie_break = tie_break;
+        self
+    }
+
+    /// Keep up to `capacity` embeddings in memory so repeated texts don't cost another
+    /// provider call. A capacity of 0 disables the cache.
+    pub fn with_embedding_cache_size(mut self, capacity: usize) -> Self {
+        self.embedding_cache = NonZeroUsize::new(capacity).map(|capacity| Arc::new(Mutex::new(LruCache::new(capacity))));
+        self
+    }
+
+    /// Replay the response of a store sent with an `Idempotency-Key` to requests repeating the
+    /// key within `ttl_secs`, instead of embedding the text again. 0 ignores the keys.
+    pub fn with_idempotency_ttl(mut self, ttl_secs: u64) -> Self {
+        let capacity = NonZeroUsize::new(MAX_IDEMPOTENCY_KEYS).unwrap();
+        self.idempotency_cache = (ttl_secs > 0).then(|| Arc::new(IdempotencyCache::new(Duration::from_secs(ttl_secs), capacity)));
+        self
+    }
+
+    /// The responses of stores by `Idempotency-Key`, unless keys are ignored
+    pub fn idempotency_cache(&self) -> Option<&Arc<IdempotencyCache<StoreResponse>>> {
+        self.idempotency_cache.as_ref()
+    }
+
+    /// Reject texts longer than `max_text_chars` characters before they reach the provider
+    pub fn with_max_text_chars(mut self, max_text_chars: usize) -> Self {
+        self.max_text_chars = max_text_chars;
+        self
+    }
+
+    /// Use `embedding_type` for requests that don't name a type
+    pub fn with_default_embedding_type(mut self, embedding_type: impl Into<String>) -> Self {
+        self.default_embedding_type = embedding_type.into();
+        self
+    }
+
+    /// Longest text, in characters, that `validate_text` accepts
+    pub fn max_text_chars(&self) -> usize {
+        self.max_text_chars
+    }
+
+    /// Allow at most `limit` provider calls in flight at once, so bursts of requests queue
+    /// instead of tripping the provider's rate limits. A limit of 0 is treated as 1.
+    pub fn with_max_concurrent_embeddings(mut self, limit: usize) -> Self {
+        self.provider_permits = Arc::new(Semaphore::new(limit.max(1)));
+        self
     }
 
-    fn get_data_path() -> String {
-        dotenv().ok();
-        // Use a test-specific file if we're running tests
-        if std::thread::current().name().map_or(false, |n| n.starts_with("test_")) {
-            format!("data/test_{}.jsonl", std::thread::current().name().unwrap())
+    /// Read and write the data file in the given format instead of `STORAGE_FORMAT`
+    pub fn with_storage_format(mut self, storage_format: StorageFormat) -> Self {
+        self.storage_format = storage_format;
+        self.set_stored_vectors(count_entries(&self.data_path, storage_format));
+        self.content_index = Mutex::new(None);
+        let use_ann_index = self.ann_index.is_some();
+        self.with_ann_index(use_ann_index)
+    }
+
+    /// Answer `top_k` comparisons from an in-memory HNSW index built from the data file,
+    /// reranking its candidates exactly. Without it every comparison scans the whole file.
+    pub fn with_ann_index(mut self, use_ann_index: bool) -> Self {
+        self.ann_index = if use_ann_index {
+            let records = self.load_all().unwrap_or_default();
+            Some(Mutex::new(AnnIndex::build(records)))
         } else {
-            env::var("DATA_PATH").unwrap_or_else(|_| "data/embeddings.jsonl".to_string())
+            None
+        };
+        self
+    }
+
+    pub fn provider(&self) -> &dyn EmbeddingProvider {
+        self.provider.as_ref()
+    }
+
+    /// Resolve the model for a request, falling back to the provider's default when none is
+    /// given and rejecting models outside the allowlist
+    pub fn resolve_model(&self, model: Option<String>) -> Result<String, String> {
+        let model = model.unwrap_or_else(|| self.provider.default_model().to_string());
+        match &self.allowed_models {
+            Some(allowed) if !allowed.contains(&model) => Err(format!(
+                "unsupported model '{}', expected one of: {}",
+                model,
+                allowed.join(", ")
+            )),
+            _ => Ok(model),
+        }
+    }
+
+    /// Resolve the embedding type for a request, falling back to the configured default
+    pub fn resolve_embedding_type(&self, embedding_type: Option<String>) -> String {
+        embedding_type.unwrap_or_else(|| self.default_embedding_type.clone())
+    }
+
+    /// Check a request's `field` holds text worth embedding: not blank, and within the
+    /// configured length
+    pub fn validate_text(&self, field: &str, text: &str) -> Result<(), String> {
+        if text.trim().is_empty() {
+            return Err(format!("{} must not be empty", field));
+        }
+        let chars = text.chars().count();
+        if chars > self.max_text_chars {
+            return Err(format!(
+                "{} is {} characters long, over the limit of {}",
+                field, chars, self.max_text_chars
+            ));
+        }
+        Ok(())
+    }
+
+    /// Distinct vector lengths stored under `embedding_types`, or under any type when `None`,
+    /// shortest first. Only the first vector stored of each type is counted.
+    pub fn stored_dimensions(&self, embedding_types: Option<&[String]>) -> Result<Vec<usize>, Box<dyn std::error::Error>> {
+        self.with_content_index(|index| {
+            let mut dimensions: Vec<usize> = index
+                .dimensions
+                .iter()
+                .filter(|(embedding_type, _)| embedding_types.is_none_or(|types| types.contains(embedding_type)))
+                .map(|(_, dimensions)| *dimensions)
+                .collect();
+            dimensions.sort_unstable();
+            dimensions.dedup();
+            dimensions
+        })
+    }
+
+    /// Whether requests are limited to a list of models
+    pub fn has_model_allowlist(&self) -> bool {
+        self.allowed_models.is_some()
+    }
+
+    /// Models requests may name, with their output length where known. When any model is
+    /// accepted only the default is listed.
+    pub fn models(&self) -> Vec<ModelInfo> {
+        let default_model = self.provider.default_model();
+        let names = match &self.allowed_models {
+            Some(allowed) => allowed.clone(),
+            None => vec![default_model.to_string()],
+        };
+        names
+            .into_iter()
+            .map(|name| ModelInfo {
+                dimensions: self.provider.model_dimensions(&name),
+                provider: self.provider.name().to_string(),
+                default: name == default_model,
+                name,
+            })
+            .collect()
+    }
+
+    /// Check an optional requested output length against the provider and model
+    pub fn resolve_dimensions(&self, model: &str, dimensions: Option<usize>) -> Result<Option<usize>, String> {
+        if let Some(dimensions) = dimensions {
+            self.provider.validate_dimensions(model, dimensions)?;
+        }
+        Ok(dimensions)
+    }
+
+    pub fn data_path(&self) -> &str {
+        &self.data_path
+    }
+
+    /// The lock guarding writes to the given data file
+    fn write_lock(&self, path: &str) -> Arc<tokio::sync::Mutex<()>> {
+        self.write_locks.lock().unwrap()
+            .entry(path.to_string())
+            .or_default()
+            .clone()
+    }
+
+    /// Append the buffered stores to the data file, returning how many were written. Callers
+    /// hold the write lock, so no rewrite of the file can drop them.
+    fn flush_buffer(&self) -> Result<usize, Box<dyn std::error::Error>> {
+        let Some(buffer) = &self.write_buffer else {
+            return Ok(0);
+        };
+        let mut records = buffer.records.lock().unwrap();
+        if records.is_empty() {
+            return Ok(0);
+        }
+        self.ensure_data_dir()?;
+        append_records(&self.data_path, self.storage_format, &records)?;
+        let flushed = records.len();
+        records.clear();
+        tracing::debug!(path = %self.data_path, flushed, "buffered stores flushed");
+        Ok(flushed)
+    }
+
+    /// Write the buffered stores of this store and of the namespaces open to their
+    /// data files, returning how many were written. Done periodically by `spawn_flush_task`
+    /// and once more on shutdown.
+    pub async fn flush(&self) -> Result<usize, Box<dyn std::error::Error>> {
+        let namespaces: Vec<Arc<EmbeddingService>> = self.namespaces.lock().unwrap().iter().map(|(_, service)| service.clone()).collect();
+        let mut flushed = 0;
+        for service in std::iter::once(self).chain(namespaces.iter().map(Arc::as_ref)) {
+            let lock = service.write_lock(&service.data_path);
+            let _guard = lock.lock().await;
+            flushed += service.flush_buffer()?;
+        }
+        Ok(flushed)
+    }
+
+    /// Start flushing the write buffer every `FLUSH_INTERVAL_MS` in the background, if stores
+    /// are buffered. The task ends once the service is dropped.
+    pub fn spawn_flush_task(self: &Arc<Self>) -> Option<tokio::task::JoinHandle<()>> {
+        let flush_interval = self.write_buffer.as_ref()?.flush_interval;
+        let service = Arc::downgrade(self);
+        Some(tokio::spawn(async move {
+            let mut interval = tokio::time::interval(flush_interval);
+            interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            loop {
+                interval.tick().await;
+                let Some(service) = service.upgrade() else {
+                    return;
+                };
+                if let Err(e) = service.flush().await {
+                    tracing::error!(path = %service.data_path, error = %e, "failed to flush buffered stores");
+                }
+            }
+        }))
+    }
+
+    /// The stored records: those in the data file followed by the buffered ones, as of one
+    /// moment. JSONL files are read one line at a time as in `iter_entries`.
+    fn entries(&self) -> Result<Box<dyn Iterator<Item = serde_json::Value> + Send>, Box<dyn std::error::Error>> {
+        ::metrics::counter!(metrics::STORE_READS_TOTAL).increment(1);
+        let Some(buffer) = &self.write_buffer else {
+            return iter_entries(&self.data_path, self.storage_format);
+        };
+        // Flushes append under this lock, and the file is only read up to its length when
+        // opened, so a record being flushed is seen once
+        let records = buffer.records.lock().unwrap();
+        let on_disk = iter_entries(&self.data_path, self.storage_format)?;
+        Ok(Box::new(on_disk.chain(records.clone())))
+    }
+
+    /// Every stored record, buffered ones last, like `entries` but read in one go, so a data
+    /// file that can't be read is an error rather than an empty store
+    fn load_all(&self) -> Result<Vec<serde_json::Value>, Box<dyn std::error::Error>> {
+        ::metrics::counter!(metrics::STORE_READS_TOTAL).increment(1);
+        let records = self.write_buffer.as_ref().map(|buffer| buffer.records.lock().unwrap());
+        let mut entries = load_entries(&self.data_path, self.storage_format)?;
+        entries.extend(records.iter().flat_map(|records| records.iter().cloned()));
+        Ok(entries)
+    }
+
+    /// Number of vectors in the store, from the cached count
+    pub fn stored_vectors(&self) -> usize {
+        self.stored_vectors.load(AtomicOrdering::SeqCst)
+    }
+
+    fn set_stored_vectors(&self, count: usize) {
+        self.stored_vectors.store(count, AtomicOrdering::SeqCst);
+        // The gauge tracks the default store only
+        if self.namespace.is_none() {
+            ::metrics::gauge!(metrics::STORED_VECTORS).set(count as f64);
+        }
+    }
+
+    /// The service storing `namespace`, or this one for `None`. Each namespace keeps its
+    /// records in its own file next to the default one, `{namespace}.jsonl` for JSONL, and
+    /// shares this service's provider and settings. Names must be 1 to `MAX_NAMESPACE_LEN`
+    /// ASCII letters, digits, `-` or `_`, so they can't point outside the data directory.
+    pub fn namespace(self: &Arc<Self>, namespace: Option<&str>) -> Result<Arc<Self>, String> {
+        Ok(self.open_namespace(namespace, true)?.expect("missing namespaces are created"))
+    }
+
+    /// Like `namespace`, but `None` for a namespace that isn't open and has no data file,
+    /// rather than opening it, for requests that only read or delete: it has nothing stored
+    pub fn existing_namespace(self: &Arc<Self>, namespace: Option<&str>) -> Result<Option<Arc<Self>>, String> {
+        self.open_namespace(namespace, false)
+    }
+
+    fn open_namespace(self: &Arc<Self>, namespace: Option<&str>, create: bool) -> Result<Option<Arc<Self>>, String> {
+        let Some(namespace) = namespace else {
+            return Ok(Some(self.clone()));
+        };
+        // The name becomes part of a file name, so it gets the filesystem check as well as the
+        // stricter namespace rules
+        let valid = sanitize_path_component(namespace).is_ok()
+            && namespace.len() <= MAX_NAMESPACE_LEN
+            && !namespace.contains('.');
+        if !valid {
+            return Err(format!(
+                "namespace must be 1 to {} letters, digits, '-' or '_', got {:?}",
+                MAX_NAMESPACE_LEN, namespace
+            ));
+        }
+        if self.namespace.is_some() {
+            return Err("namespaces can't be nested".to_string());
+        }
+
+        let mut namespaces = self.namespaces.lock().unwrap();
+        if let Some(service) = namespaces.get(namespace) {
+            return Ok(Some(service.clone()));
+        }
+        let file_name = format!("{}.{}", namespace, self.storage_format.extension());
+        let data_path = match Path::new(&self.data_path).parent() {
+            Some(parent) => parent.join(file_name),
+            None => PathBuf::from(file_name),
+        };
+        if data_path == Path::new(&self.data_path) {
+            return Err(format!("namespace '{}' would share the default data file", namespace));
+        }
+        if !create && fs::metadata(&data_path).is_err() {
+            return Ok(None);
+        }
+        let service = Arc::new(self.namespaced(namespace, data_path.to_string_lossy().into_owned()));
+        close_idle_namespaces(&mut namespaces);
+        namespaces.put(namespace.to_string(), service.clone());
+        Ok(Some(service))
+    }
+
+    /// A service with this one's provider and settings, storing `namespace` in `data_path`
+    fn namespaced(&self, namespace: &str, data_path: String) -> Self {
+        let stored_vectors = count_entries(&data_path, self.storage_format);
+        let projection = load_projection(&data_path);
+        let service = Self {
+            provider: self.provider.clone(),
+            allowed_models: self.allowed_models.clone(),
+            max_text_chars: self.max_text_chars,
+            default_embedding_type: self.default_embedding_type.clone(),
+            client: self.client.clone(),
+            provider_permits: self.provider_permits.clone(),
+            data_path,
+            storage_format: self.storage_format,
+            created_data_dirs: Mutex::new(Vec::new()),
+            write_locks: Mutex::new(HashMap::new()),
+            normalize_embeddings: self.normalize_embeddings,
+            storage_precision: self.storage_precision,
+            strict_dimensions: self.strict_dimensions,
+            deep_readiness_check: self.deep_readiness_check,
+            clear_disabled: self.clear_disabled,
+            similarity_functions: self.similarity_functions.clone(),
+            stored_vectors: AtomicUsize::new(stored_vectors),
+            embedding_cache: self.embedding_cache.clone(),
+            // Keys are shared too, so one key can't name stores in two namespaces
+            idempotency_cache: self.idempotency_cache.clone(),
+            max_stored_entries: self.max_stored_entries,
+            parallel_compare: self.parallel_compare,
+            tie_break: self.tie_break,
+            ann_index: None,
+            content_index: Mutex::new(None),
+            projection: Mutex::new(projection),
+            use_projection: self.use_projection,
+            preload_pending: AtomicBool::new(false),
+            namespace: Some(namespace.to_string()),
+            namespaces: Mutex::new(LruCache::unbounded()),
+            write_buffer: self.write_buffer.as_ref().map(|buffer| WriteBuffer::new(buffer.flush_interval, buffer.max_records)),
+        };
+        service.with_ann_index(self.ann_index.is_some())
+    }
+
+    /// Run `f` on the content index, building it from the data file if this is its first use.
+    /// Records written before hashes were stored are hashed as they are loaded.
+    fn with_content_index<R>(
+        &self,
+        f: impl FnOnce(&mut ContentIndex) -> R,
+    ) -> Result<R, Box<dyn std::error::Error>> {
+        let mut content_index = self.content_index.lock().unwrap();
+        let index = match content_index.as_mut() {
+            Some(index) => index,
+            None => content_index.insert(ContentIndex::build(self.entries()?)),
+        };
+        Ok(f(index))
+    }
+
+    pub async fn clear_data(&self) -> Result<(), Box<dyn std::error::Error>> {
+        let path = &self.data_path;
+        let lock = self.write_lock(path);
+        let _guard = lock.lock().await;
+        if let Some(buffer) = &self.write_buffer {
+            buffer.records.lock().unwrap().clear();
+        }
+        if fs::metadata(path).is_ok() {
+            fs::remove_file(path)?;
+        }
+        let projection_path = projection_path(path);
+        if fs::metadata(&projection_path).is_ok() {
+            fs::remove_file(&projection_path)?;
+        }
+        *self.projection.lock().unwrap() = None;
+        self.set_stored_vectors(0);
+        *self.content_index.lock().unwrap() = Some(ContentIndex::default());
+        if let Some(index) = &self.ann_index {
+            *index.lock().unwrap() = AnnIndex::build(Vec::new());
+        }
+        // Also remove the directories created for the data file if that left them empty; a
+        // directory that already existed may be shared and is kept
+        let mut created_data_dirs = self.created_data_dirs.lock().unwrap();
+        while let Some(dir) = created_data_dirs.first() {
+            let is_empty = fs::read_dir(dir).is_ok_and(|mut entries| entries.next().is_none());
+            if !is_empty {
+                break;
+            }
+            fs::remove_dir(dir)?;
+            created_data_dirs.remove(0);
+        }
+        Ok(())
+    }
+
+    /// Create the directory the data file lives in, and any missing parents, before the first
+    /// write to it. The directories created are remembered so `clear_data` can remove them.
+    fn ensure_data_dir(&self) -> std::io::Result<()> {
+        let Some(parent) = Path::new(&self.data_path).parent().filter(|parent| !parent.as_os_str().is_empty()) else {
+            return Ok(());
+        };
+        if parent.is_dir() {
+            return Ok(());
+        }
+        let missing: Vec<PathBuf> = parent
+            .ancestors()
+            .take_while(|dir| !dir.as_os_str().is_empty() && !dir.exists())
+            .map(Path::to_path_buf)
+            .collect();
+        fs::create_dir_all(parent)?;
+        tracing::info!(path = %parent.display(), "created data directory");
+        let mut created_data_dirs = self.created_data_dirs.lock().unwrap();
+        for dir in missing.into_iter().rev() {
+            if !created_data_dirs.contains(&dir) {
+                created_data_dirs.insert(0, dir);
+            }
+        }
+        Ok(())
+    }
+
+    /// Rewrite the data file without its malformed records, e.g. lines cut short by a crash,
+    /// returning how many were dropped. Reads already skip them; this just reclaims the space
+    /// and silences the warnings.
+    pub async fn repair_data(&self) -> Result<usize, Box<dyn std::error::Error>> {
+        let lock = self.write_lock(&self.data_path);
+        let _guard = lock.lock().await;
+        self.flush_buffer()?;
+        if fs::metadata(&self.data_path).is_err() {
+            return Ok(0);
+        }
+
+        let total = count_records(&self.data_path, self.storage_format);
+        let entries = load_entries(&self.data_path, self.storage_format)?;
+        replace_records(&self.data_path, self.storage_format, &entries)?;
+        let dropped = total.saturating_sub(entries.len());
+        if dropped > 0 {
+            tracing::warn!(path = %self.data_path, dropped, "dropped malformed records from data file");
+        }
+
+        self.reset_indexes(entries);
+        Ok(dropped)
+    }
+
+    /// Delete the records of one embedding type, keeping the others, and return how many
+    /// were removed. The data file is rewritten and swapped in with a rename.
+    pub async fn clear_type(&self, embedding_type: &str) -> Result<usize, Box<dyn std::error::Error>> {
+        let lock = self.write_lock(&self.data_path);
+        let _guard = lock.lock().await;
+        self.flush_buffer()?;
+        if fs::metadata(&self.data_path).is_err() {
+            return Ok(0);
+        }
+
+        let mut entries = load_entries(&self.data_path, self.storage_format)?;
+        let total = entries.len();
+        entries.retain(|entry| entry["embedding_type"].as_str() != Some(embedding_type));
+        let removed = total - entries.len();
+        if removed > 0 {
+            replace_records(&self.data_path, self.storage_format, &entries)?;
+            self.reset_indexes(entries);
+        }
+        tracing::info!(embedding_type, removed, "embedding type cleared");
+        Ok(removed)
+    }
+
+    /// Bring the count and in-memory indexes in line with `entries` after the data file was
+    /// rewritten with them
+    fn reset_indexes(&self, entries: Vec<serde_json::Value>) {
+        self.set_stored_vectors(entries.len());
+        *self.content_index.lock().unwrap() = Some(ContentIndex::build(&entries));
+        if let Some(index) = &self.ann_index {
+            *index.lock().unwrap() = AnnIndex::build(entries);
         }
     }
 
-    pub fn clear_data(&self) -> Result<(), Box<dyn std::error::Error>> {
-        let path = Self::get_data_path();
-        if fs::metadata(&path).is_ok() {
-            fs::remove_file(&path)?;
+    /// Read every stored record and build the in-memory indexes from them (the content index
+    /// and, when enabled, the ANN index), so the first requests don't pay for it. Returns the
+    /// number of vectors loaded.
+    pub fn preload(&self) -> Result<usize, Box<dyn std::error::Error>> {
+        let start = Instant::now();
+        let vectors = self.reload()?;
+        tracing::info!(vectors, elapsed_ms = start.elapsed().as_millis() as u64, "store preloaded");
+        Ok(vectors)
+    }
+
+    /// Reload this store and the open namespaces from disk, rebuilding their indexes
+    /// and counts, e.g. after a data file was edited or restored while the server was running.
+    /// Returns the number of records reloaded across them.
+    pub fn reindex(&self) -> Result<usize, Box<dyn std::error::Error>> {
+        let start = Instant::now();
+        let namespaces: Vec<Arc<EmbeddingService>> = self.namespaces.lock().unwrap().iter().map(|(_, service)| service.clone()).collect();
+        let mut records = self.reload()?;
+        for service in namespaces {
+            records += service.reload()?;
         }
-        // Also remove the parent directory if it's empty
-        if let Some(parent) = std::path::Path::new(&path).parent() {
-            if fs::metadata(parent).is_ok() {
-                if let Ok(entries) = fs::read_dir(parent) {
-                    if entries.count() == 0 {
-                        fs::remove_dir(parent)?;
+        tracing::info!(records, elapsed_ms = start.elapsed().as_millis() as u64, "stores reindexed");
+        Ok(records)
+    }
+
+    /// The projection fitted by `fit_projection`, if any
+    pub fn projection(&self) -> Option<Arc<Projection>> {
+        self.projection.lock().unwrap().clone()
+    }
+
+    /// Fit a projection of the stored vectors down to `dimensions` by PCA, see
+    /// `Projection::fit`, over the vectors of the most common length. It is saved next to the
+    /// data file, and every record of that length is rewritten with its projected vector;
+    /// records stored from then on get one as they are written.
+    pub async fn fit_projection(&self, dimensions: usize) -> Result<FitProjectionResponse, FitProjectionError> {
+        let start = Instant::now();
+        let lock = self.write_lock(&self.data_path);
+        let _guard = lock.lock().await;
+        self.flush_buffer().map_err(FitProjectionError::Store)?;
+
+        let mut entries = load_entries(&self.data_path, self.storage_format).map_err(FitProjectionError::Store)?;
+        let mut lengths: HashMap<usize, usize> = HashMap::new();
+        for dimensions in entries.iter().filter_map(record_dimensions) {
+            *lengths.entry(dimensions).or_default() += 1;
+        }
+        let source_dimensions = lengths
+            .into_iter()
+            .max_by_key(|(length, count)| (*count, *length))
+            .map(|(length, _)| length)
+            .ok_or_else(|| FitProjectionError::InvalidInput("cannot fit projection: no vectors are stored".to_string()))?;
+        let vectors: Vec<Vec<f64>> = entries
+            .iter()
+            .filter_map(record_embedding)
+            .filter(|vector| vector.len() == source_dimensions)
+            .collect();
+        let projection = Projection::fit(&vectors, dimensions, DEFAULT_FIT_ITERATIONS).map_err(FitProjectionError::InvalidInput)?;
+        let response = FitProjectionResponse {
+            source_dimensions,
+            dimensions,
+            vectors: vectors.len(),
+            explained_variance: projection.explained_variance(&vectors),
+        };
+        drop(vectors);
+
+        for entry in entries.iter_mut() {
+            match record_embedding(entry).and_then(|vector| projection.project(&vector)) {
+                Some(projected) => entry["projected"] = projected.into(),
+                None => {
+                    if let Some(entry) = entry.as_object_mut() {
+                        entry.remove("projected");
                     }
                 }
             }
         }
+        self.save_projection(&entries, &projection).map_err(FitProjectionError::Store)?;
+        *self.projection.lock().unwrap() = Some(Arc::new(projection));
+        self.reset_indexes(entries);
+
+        tracing::info!(
+            source_dimensions,
+            dimensions,
+            vectors = response.vectors,
+            explained_variance = response.explained_variance,
+            elapsed_ms = start.elapsed().as_millis() as u64,
+            "projection fitted"
+        );
+        Ok(response)
+    }
+
+    /// Rewrite the data file with `entries`, then save `projection` next to it
+    fn save_projection(&self, entries: &[serde_json::Value], projection: &Projection) -> Result<(), Box<dyn std::error::Error>> {
+        replace_records(&self.data_path, self.storage_format, entries)?;
+        let path = projection_path(&self.data_path);
+        let temp_path = format!("{}.tmp", path);
+        fs::write(&temp_path, serde_json::to_vec(projection)?)?;
+        fs::rename(&temp_path, &path)?;
         Ok(())
     }
 
-    pub async fn get_embedding(&self, text: &str, model: &str) -> Result<Vec<f64>, Box<dyn std::error::Error>> {
-        dotenv().ok();
-        let api_key = env::var("OPENAI_API_KEY").expect("OPENAI_API_KEY not set");
+    /// Add the projected vector to a record about to be written, if a projection for its
+    /// length exists
+    fn add_projected(&self, record: &mut serde_json::Value) {
+        let Some(projection) = self.projection() else {
+            return;
+        };
+        if let Some(projected) = record_embedding(record).and_then(|vector| projection.project(&vector)) {
+            record["projected"] = projected.into();
+        }
+    }
 
-        let url = "https://api.openai.com/v1/embeddings".to_string();
+    /// Read every stored record and rebuild the indexes from them, returning the record count
+    fn reload(&self) -> Result<usize, Box<dyn std::error::Error>> {
+        let entries = self.load_all()?;
+        let records = entries.len();
+        self.reset_indexes(entries);
+        *self.projection.lock().unwrap() = load_projection(&self.data_path);
+        self.preload_pending.store(false, AtomicOrdering::SeqCst);
+        Ok(records)
+    }
 
-        let mut headers = HashMap::new();
-        headers.insert("Content-Type".to_string(), "application/json".to_string());
-        headers.insert("Authorization".to_string(), format!("Bearer {}", api_key));
+    /// Whether the in-memory index of the stored records has been built, by a preload or by
+    /// the first request needing it
+    pub fn is_index_loaded(&self) -> bool {
+        self.content_index.lock().unwrap().is_some()
+    }
 
-        let body = serde_json::json!({
-            "model": model,
-            "input": text
-        });
+    /// Check whether the service can handle traffic: the provider is configured and the data
+    /// directory is writable. Only pings the provider (a billable call) when
+    /// `READINESS_DEEP_CHECK=true`. Returns the list of problems found, if any.
+    pub async fn check_readiness(&self) -> Result<(), Vec<String>> {
+        let mut problems = Vec::new();
+
+        if self.preload_pending.load(AtomicOrdering::SeqCst) {
+            problems.push(format!("store {} has not been preloaded", self.data_path));
+        }
+
+        if let Err(e) = self.provider.check_configured() {
+            problems.push(e);
+        }
+
+        let data_dir = match Path::new(&self.data_path).parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+            _ => PathBuf::from("."),
+        };
+        let probe = data_dir.join(".readiness_probe");
+        match self.ensure_data_dir().and_then(|()| fs::write(&probe, b"")) {
+            Ok(()) => {
+                let _ = fs::remove_file(&probe);
+                // The directory can be written, but not as the data file if that's a directory
+                if let Err(e) = check_data_path(&self.data_path) {
+                    problems.push(e.to_string());
+                }
+            }
+            Err(e) => problems.push(format!("data directory {} is not writable: {}", data_dir.display(), e)),
+        }
+
+        if self.deep_readiness_check && problems.is_empty() {
+            // Bypass the cache, which would hide a provider that has since gone down
+            let probe = self.fetch_embedding("readiness check", self.provider.default_model(), None, InputType::Query);
+            if let Err(e) = probe.await {
+                problems.push(format!("provider {} is not reachable: {}", self.provider.name(), e));
+            }
+        }
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(problems)
+        }
+    }
+
+    /// Embed `text` with `model`, shortened to `dimensions` if given, answering from the cache
+    /// when the same request was made recently
+    pub async fn get_embedding(
+        &self,
+        text: &str,
+        model: &str,
+        dimensions: Option<usize>,
+        input_type: InputType,
+    ) -> Result<Vec<f64>, ProviderError> {
+        let key = (text.to_string(), model.to_string(), dimensions, input_type);
+        if let Some(cache) = &self.embedding_cache {
+            if let Some(embedding) = cache.lock().unwrap().get(&key) {
+                tracing::debug!(model, text_len = text.len(), "embedding cache hit");
+                return Ok(embedding.clone());
+            }
+        }
+
+        let embedding = self.fetch_embedding(text, model, dimensions, input_type).await?;
+        if let Some(cache) = &self.embedding_cache {
+            cache.lock().unwrap().put(key, embedding.clone());
+        }
+        Ok(embedding)
+    }
+
+    /// Embed several texts like `get_embedding`, with a single provider call for those that
+    /// aren't cached. The vectors are returned in the order of `texts`.
+    pub async fn get_embeddings(
+        &self,
+        texts: &[String],
+        model: &str,
+        dimensions: Option<usize>,
+        input_type: InputType,
+    ) -> Result<Vec<Vec<f64>>, ProviderError> {
+        let key = |text: &str| (text.to_string(), model.to_string(), dimensions, input_type);
+        let mut embeddings: Vec<Option<Vec<f64>>> = match &self.embedding_cache {
+            Some(cache) => {
+                let mut cache = cache.lock().unwrap();
+                texts.iter().map(|text| cache.get(&key(text)).cloned()).collect()
+            }
+            None => vec![None; texts.len()],
+        };
 
-        let response = make_http_request(
-            Method::POST,
-            &url,
-            Some(headers),
-            None,
-            Some(body.to_string()),
-        )
-        .await?;
-        
-        let json_response: serde_json::Value = serde_json::from_str(&response)?;
-        let embedding = json_response
-            .get("data")
-            .and_then(|data| data.get(0))
-            .and_then(|first_embedding| first_embedding.get("embedding"))
-            .and_then(|embedding| embedding.as_array())
-            .ok_or("Failed to parse embedding response")?;
-
-        let embedding_vec: Vec<f64> = embedding
+        // A text repeated in the batch is only sent once
+        let mut seen = HashSet::new();
+        let missing: Vec<String> = texts
             .iter()
-            .filter_map(|v| v.as_f64())
+            .zip(&embeddings)
+            .filter(|(text, embedding)| embedding.is_none() && seen.insert(text.as_str()))
+            .map(|(text, _)| text.clone())
             .collect();
+        if !missing.is_empty() {
+            let fetched = self.fetch_embeddings(&missing, model, dimensions, input_type).await?;
+            let fetched: HashMap<&str, Vec<f64>> = missing.iter().map(String::as_str).zip(fetched).collect();
+            if let Some(cache) = &self.embedding_cache {
+                let mut cache = cache.lock().unwrap();
+                for (text, embedding) in &fetched {
+                    cache.put(key(text), embedding.clone());
+                }
+            }
+            for (text, embedding) in texts.iter().zip(embeddings.iter_mut()) {
+                if embedding.is_none() {
+                    *embedding = fetched.get(text.as_str()).cloned();
+                }
+            }
+        }
+        Ok(embeddings.into_iter().map(|embedding| embedding.expect("every text is cached or fetched")).collect())
+    }
+
+    /// Embed `texts` with one batch call to the provider, bypassing the cache, like
+    /// `fetch_embedding`
+    #[tracing::instrument(skip_all, fields(provider = self.provider.name(), model = %model, texts = texts.len()))]
+    async fn fetch_embeddings(
+        &self,
+        texts: &[String],
+        model: &str,
+        dimensions: Option<usize>,
+        input_type: InputType,
+    ) -> Result<Vec<Vec<f64>>, ProviderError> {
+        let _phase = PhaseTimer::start(Phase::Provider);
+        let _permit = self.provider_permits.acquire().await.expect("provider semaphore is never closed");
+        let call = ProviderCall::start(self.provider.name());
+        let result = self.provider.embed_batch(&self.client, texts, model, dimensions, input_type).await;
+        let elapsed_ms = call.finish().as_millis() as u64;
+        match &result {
+            Ok(embeddings) => tracing::info!(embeddings = embeddings.len(), elapsed_ms, "embeddings generated"),
+            Err(e) => tracing::error!(error = %e, elapsed_ms, "embedding request failed"),
+        }
+        result
+    }
 
-        Ok(embedding_vec)
+    /// Embed `text` with a provider call, bypassing the cache. Waits for a permit first when
+    /// the concurrency limit is reached.
+    #[tracing::instrument(skip_all, fields(provider = self.provider.name(), model = %model, text_len = text.len()))]
+    async fn fetch_embedding(
+        &self,
+        text: &str,
+        model: &str,
+        dimensions: Option<usize>,
+        input_type: InputType,
+    ) -> Result<Vec<f64>, ProviderError> {
+        // Waiting for a permit counts too, as it is time lost to the provider being busy
+        let _phase = PhaseTimer::start(Phase::Provider);
+        let _permit = self.provider_permits.acquire().await.expect("provider semaphore is never closed");
+        let call = ProviderCall::start(self.provider.name());
+        let result = self.provider.embed(&self.client, text, model, dimensions, input_type).await;
+        let elapsed_ms = call.finish().as_millis() as u64;
+        match &result {
+            Ok(embedding) => tracing::info!(dimensions = embedding.len(), elapsed_ms, "embedding generated"),
+            Err(e) => tracing::error!(error = %e, elapsed_ms, "embedding request failed"),
+        }
+        result
     }
 
+    #[tracing::instrument(skip_all, fields(
+        embedding_types = ?filter.embedding_types,
+        model_filter = filter.model.as_deref(),
+        text_len = text.len(),
+        top_k,
+    ))]
     pub async fn compare_embeddings(
         &self,
         text: &str,
-        embedding: &Vec<f64>,
+        embedding: &[f64],
         top_k: Option<usize>,
         include_embeddings: bool,
-        embedding_type: Option<String>,
+        filter: CompareFilter,
+        min_similarity: Option<f64>,
+    ) -> Result<Vec<ComparisonResult>, Box<dyn std::error::Error>> {
+        let _phase = PhaseTimer::start(Phase::Store);
+        let start = Instant::now();
+        ::metrics::counter!(metrics::COMPARES_TOTAL).increment(1);
+        let options = RankOptions {
+            top_k,
+            per_type_k: None,
+            include_embeddings,
+            filter,
+            min_similarity,
+            tie_break: self.tie_break,
+            parallel: self.parallel_compare && self.stored_vectors() >= PARALLEL_COMPARE_MIN_ENTRIES,
+        };
+        let normalized_query = normalize_vector(embedding);
+        let query = Query {
+            similarity: self.similarity_function(options.filter.metric.as_deref())?,
+            ..Query::new(text, embedding, normalized_query.as_deref(), &options)
+        };
+
+        // The index and the projection only yield enough candidates for `top_k` results, which deduplication
+        // could thin out and boosting or keyword scores could reorder, so it is skipped then. Both
+        // rank by cosine similarity, so another metric scans too, and their nearest records may
+        // all fall outside a candidate set.
+        let use_index = options.filter.dedup_threshold.is_none()
+            && options.filter.type_boosts.is_none()
+            && options.filter.hybrid_alpha.is_none()
+            && options.filter.metric.is_none()
+            && options.filter.candidates.is_none();
+        if let (Some(k), Some(index), true) = (top_k, &self.ann_index, use_index) {
+            if let Some(results) = query.search_index(&index.lock().unwrap(), k) {
+                count_compared(query.compared.into_inner());
+                tracing::info!(results = results.len(), elapsed_ms = start.elapsed().as_millis() as u64, "comparison finished using index");
+                return Ok(results);
+            }
+        }
+
+        if let (Some(k), Some(projection), true) = (top_k, self.projection().filter(|_| self.use_projection), use_index) {
+            if let Some(projected_query) = projection.project(embedding) {
+                let results = self.compare_projected(&query, &projected_query, k)?;
+                count_compared(query.compared.into_inner());
+                tracing::info!(results = results.len(), elapsed_ms = start.elapsed().as_millis() as u64, "comparison finished using projection");
+                return Ok(results);
+            }
+        }
+
+        // Records are streamed from the data file, so besides the results kept only the keys
+        // of the records seen so far are held in memory
+        let similarities = rank_with(&query, self.entries()?, &options);
+        count_compared(query.compared.into_inner());
+        tracing::info!(results = similarities.len(), elapsed_ms = start.elapsed().as_millis() as u64, "comparison finished");
+        Ok(similarities)
+    }
+
+    /// The stored entries whose text is exactly `text` and which pass `filter`, with
+    /// similarity 1, ranked as by `compare_embeddings`. No query vector is needed, so the
+    /// provider isn't called. `filter.exclude_self` would leave nothing and is ignored.
+    pub fn find_exact_matches(
+        &self,
+        text: &str,
+        top_k: Option<usize>,
+        include_embeddings: bool,
+        filter: CompareFilter,
     ) -> Result<Vec<ComparisonResult>, Box<dyn std::error::Error>> {
-        let content = std::fs::read_to_string(&Self::get_data_path())?;
-        let mut similarities = Vec::new();
-        let mut seen = std::collections::HashSet::new();
+        let _phase = PhaseTimer::start(Phase::Store);
+        ::metrics::counter!(metrics::COMPARES_TOTAL).increment(1);
+        let options = RankOptions { top_k, include_embeddings, filter, tie_break: self.tie_break, ..RankOptions::default() };
+        let query = Query::new(text, &[], None, &options);
+        let matches = skip_repeats(self.entries()?).filter_map(|entry| query.exact(&entry));
+        Ok(rank_scored(matches, &options))
+    }
+
+    /// The result `compare_embeddings` would return for `query` once its text is embedded
+    /// with `filter.query_model` at `dimensions`, found without embedding it. That is only
+    /// known when a single result is asked for and a record of the same text and model is
+    /// stored: it scores exactly 1, which no cosine similarity exceeds. `None` when there is
+    /// no such record, or when the query could rank another record first or leave it out,
+    /// e.g. with type boosts, another metric or an embedding type filter; the text then has
+    /// to be embedded after all.
+    pub fn best_exact_match(
+        &self,
+        query: &CompareQuery,
+        dimensions: Option<usize>,
+    ) -> Result<Option<ComparisonResult>, Box<dyn std::error::Error>> {
+        let filter = &query.filter;
+        let Some(model) = filter.query_model.as_deref() else {
+            return Ok(None);
+        };
+        let answers_query = query.top_k == Some(1)
+            && query.per_type_k.is_none()
+            && query.min_similarity.is_none_or(|min| min <= 1.0)
+            && filter.embedding_types.is_none()
+            && !filter.exclude_self
+            && filter.type_boosts.is_none()
+            && filter.hybrid_alpha.is_none()
+            && filter.metric.is_none();
+        // The stored vector must have the length the query's would, to be compared at all
+        let Some(length) = dimensions.or_else(|| self.provider.model_dimensions(model)).filter(|_| answers_query) else {
+            return Ok(None);
+        };
+        if !self.with_content_index(|index| index.may_hold_text(&query.text))? {
+            return Ok(None);
+        }
+
+        let _phase = PhaseTimer::start(Phase::Store);
+        let options = RankOptions {
+            top_k: Some(1),
+            include_embeddings: query.include_embeddings,
+            filter: filter.clone(),
+            tie_break: self.tie_break,
+            ..RankOptions::default()
+        };
+        let exact = Query::new(&query.text, &[], None, &options);
+        let matches = skip_repeats(self.entries()?)
+            .filter(|entry| {
+                let stored_length = entry["dimensions"].as_u64().map(|dimensions| dimensions as usize);
+                stored_length.or_else(|| record_embedding(entry).map(|embedding| embedding.len())) == Some(length)
+            })
+            .filter_map(|entry| exact.exact(&entry));
+        let best = rank_scored(matches, &options).into_iter().next();
+        if best.is_some() {
+            ::metrics::counter!(metrics::COMPARES_TOTAL).increment(1);
+        }
+        Ok(best)
+    }
 
-        // First, collect all valid entries
-        let entries: Vec<_> = content.lines()
-            .filter_map(|line| serde_json::from_str::<serde_json::Value>(line).ok())
+    /// Answer several comparisons with a single read of the store rather than one read each.
+    /// Every query is ranked over all the records as by `compare_embeddings`, or
+    /// `compare_embeddings_by_type` with `per_type_k` (its results then come grouped by type,
+    /// in order of the type names), or `find_exact_matches` without a vector. The ANN index
+    /// and the projection aren't used, and the records are held in memory until every query
+    /// is scored.
+    pub async fn compare_embeddings_batch(
+        &self,
+        queries: &[CompareQuery],
+    ) -> Result<Vec<Vec<ComparisonResult>>, Box<dyn std::error::Error>> {
+        let _phase = PhaseTimer::start(Phase::Store);
+        let start = Instant::now();
+        ::metrics::counter!(metrics::COMPARES_TOTAL).increment(queries.len() as u64);
+        let similarities = queries
+            .iter()
+            .map(|compare| self.similarity_function(compare.filter.metric.as_deref()))
+            .collect::<Result<Vec<_>, _>>()?;
+        let entries = self.load_all()?;
+        let parallel = self.parallel_compare && entries.len() >= PARALLEL_COMPARE_MIN_ENTRIES;
+
+        let results = queries
+            .iter()
+            .zip(similarities)
+            .map(|(compare, similarity)| {
+                let options = RankOptions {
+                    top_k: compare.top_k,
+                    per_type_k: compare.per_type_k,
+                    include_embeddings: compare.include_embeddings,
+                    filter: compare.filter.clone(),
+                    min_similarity: compare.min_similarity,
+                    tie_break: self.tie_break,
+                    parallel,
+                };
+                match &compare.embedding {
+                    Some(embedding) => {
+                        let normalized_query = normalize_vector(embedding);
+                        let query = Query {
+                            similarity,
+                            ..Query::new(&compare.text, embedding, normalized_query.as_deref(), &options)
+                        };
+                        let results = rank_with(&query, &entries, &options);
+                        count_compared(query.compared.into_inner());
+                        results
+                    }
+                    None => {
+                        let query = Query::new(&compare.text, &[], None, &options);
+                        rank_scored(skip_repeats(entries.iter()).filter_map(|entry| query.exact(entry)), &options)
+                    }
+                }
+            })
             .collect();
+        tracing::info!(queries = queries.len(), elapsed_ms = start.elapsed().as_millis() as u64, "batch comparison finished");
+        Ok(results)
+    }
 
-        // Then process them
-        for entry in entries {
-            let stored_text = entry["text"].as_str().unwrap_or_default();
-            let stored_type = entry["embedding_type"].as_str().unwrap_or_default();
-            
-            // Skip if we've already processed this text+type combination
-            let key = format!("{}:{}", stored_text, stored_type);
-            if !seen.insert(key) {
-                continue;
+    /// Compare like `compare_embeddings`, but keep the best `per_type_k` results of each
+    /// embedding type rather than the best overall, so one large type can't crowd out the
+    /// others. Results are keyed by type; types without a match are left out. Every record is
+    /// scored, as deduplication and boosts apply within each type.
+    pub async fn compare_embeddings_by_type(
+        &self,
+        text: &str,
+        embedding: &[f64],
+        per_type_k: usize,
+        include_embeddings: bool,
+        filter: CompareFilter,
+        min_similarity: Option<f64>,
+    ) -> Result<BTreeMap<String, Vec<ComparisonResult>>, Box<dyn std::error::Error>> {
+        let _phase = PhaseTimer::start(Phase::Store);
+        let start = Instant::now();
+        ::metrics::counter!(metrics::COMPARES_TOTAL).increment(1);
+        let options = RankOptions {
+            top_k: None,
+            per_type_k: Some(per_type_k),
+            include_embeddings,
+            filter,
+            min_similarity,
+            tie_break: self.tie_break,
+            parallel: self.parallel_compare && self.stored_vectors() >= PARALLEL_COMPARE_MIN_ENTRIES,
+        };
+        let normalized_query = normalize_vector(embedding);
+        let query = Query {
+            similarity: self.similarity_function(options.filter.metric.as_deref())?,
+            ..Query::new(text, embedding, normalized_query.as_deref(), &options)
+        };
+
+        let mut results_by_type: BTreeMap<String, Vec<ComparisonResult>> = BTreeMap::new();
+        for result in rank_with(&query, self.entries()?, &options) {
+            results_by_type.entry(result.embedding_type.clone()).or_default().push(result);
+        }
+        count_compared(query.compared.into_inner());
+        tracing::info!(types = results_by_type.len(), elapsed_ms = start.elapsed().as_millis() as u64, "comparison by type finished");
+        Ok(results_by_type)
+    }
+
+    /// Rank the stored records by their similarity to `projected_query` in the projected space,
+    /// then rerank the best candidates with their full vectors, as the ANN index does
+    fn compare_projected(
+        &self,
+        query: &Query,
+        projected_query: &[f64],
+        k: usize,
+    ) -> Result<Vec<ComparisonResult>, Box<dyn std::error::Error>> {
+        let candidates = (k * ANN_CANDIDATES_PER_RESULT).max(ANN_MIN_CANDIDATES);
+        // Each candidate keeps its record, so only the candidates' full vectors are decoded
+        let scored = skip_repeats(self.entries()?)
+            .filter_map(|entry| Some((query.score_projected(&entry, projected_query)?, entry)));
+        let best = top_k_sorted_by(scored, candidates, |a, b| self.tie_break.rank(&a.0, &b.0));
+        let mut results: Vec<ComparisonResult> = best.iter().filter_map(|(_, entry)| query.score(entry)).collect();
+        results.sort_by(|a, b| self.tie_break.rank(a, b));
+        results.truncate(k);
+        Ok(results)
+    }
+
+    /// The stored embedding for an exact text and type match, if any
+    pub fn find_stored_embedding(&self, text: &str, embedding_type: &str) -> Result<Option<Vec<f64>>, Box<dyn std::error::Error>> {
+        let hash = content_hash(text, embedding_type);
+        if !self.with_content_index(|index| index.hashes.contains(&hash))? {
+            return Ok(None);
+        }
+        Ok(self.entries()?
+            .find(|entry| record_hash(entry) == hash)
+            .and_then(|entry| record_embedding(&entry)))
+    }
+
+    /// Return one page of stored entries, optionally restricted to an embedding type and a
+    /// range of creation times, along with the total number of matching entries. Offsets past
+    /// the end yield an empty page.
+    pub async fn list_embeddings(
+        &self,
+        offset: usize,
+        limit: usize,
+        embedding_type: Option<String>,
+        created: CreatedRange,
+        include_embeddings: bool,
+    ) -> Result<(Vec<StoredItem>, usize), Box<dyn std::error::Error>> {
+        let entries: Vec<_> = self.load_all()?
+            .into_iter()
+            .filter(|entry| match embedding_type {
+                Some(ref target_type) => entry["embedding_type"].as_str() == Some(target_type.as_str()),
+                None => true,
+            })
+            .filter(|entry| created.contains(entry))
+            .collect();
+        let total = entries.len();
+
+        let items = entries.into_iter()
+            .skip(offset)
+            .take(limit)
+            .map(|entry| stored_item(&entry, include_embeddings))
+            .collect();
+
+        Ok((items, total))
+    }
+
+    /// Count the stored records of `embedding_type` and `model`, either being optional. Without
+    /// filters this is the cached count; otherwise the data file is scanned once, without
+    /// decoding any vectors.
+    pub fn count_embeddings(&self, embedding_type: Option<&str>, model: Option<&str>) -> Result<usize, Box<dyn std::error::Error>> {
+        if embedding_type.is_none() && model.is_none() {
+            return Ok(self.stored_vectors());
+        }
+        Ok(self.entries()?
+            .filter(|entry| embedding_type.is_none_or(|t| entry["embedding_type"].as_str() == Some(t)))
+            .filter(|entry| model.is_none_or(|m| entry["model"].as_str() == Some(m)))
+            .count())
+    }
+
+    /// Count the stored records by type, model and dimension in one pass over the data file
+    pub async fn stats(&self) -> std::io::Result<StatsResponse> {
+        let mut stats = StatsResponse {
+            total: 0,
+            by_type: HashMap::new(),
+            by_model: HashMap::new(),
+            dimensions: HashMap::new(),
+        };
+        let mut entries = self.stream_entries().await?;
+        while let Some(entry) = entries.next().await {
+            stats.total += 1;
+            let embedding_type = entry["embedding_type"].as_str().unwrap_or_default();
+            *stats.by_type.entry(embedding_type.to_string()).or_default() += 1;
+            let model = entry["model"].as_str().unwrap_or_default();
+            *stats.by_model.entry(model.to_string()).or_default() += 1;
+            if let Some(dimensions) = record_dimensions(&entry) {
+                *stats.dimensions.entry(dimensions).or_default() += 1;
             }
+        }
+        Ok(stats)
+    }
 
-            // Skip self-comparison
-            if stored_text == text && embedding_type.as_ref().map(|t| t.as_str()) == Some(stored_type) {
+    /// Group the stored vectors, optionally of one type, into at most `k` clusters by cosine
+    /// similarity. Vectors must share a length to be compared, so those whose length differs
+    /// from the first selected vector's are skipped. Clusters are returned largest first,
+    /// members closest to their centroid first.
+    pub async fn cluster_embeddings(
+        &self,
+        k: usize,
+        embedding_type: Option<&str>,
+        max_iters: usize,
+    ) -> Result<ClusterResponse, Box<dyn std::error::Error>> {
+        let mut texts = Vec::new();
+        let mut vectors = Vec::new();
+        let mut skipped = 0;
+        for entry in self.load_all()? {
+            if embedding_type.is_some_and(|t| entry["embedding_type"].as_str() != Some(t)) {
+                continue;
+            }
+            let Some(vector) = record_embedding(&entry) else {
+                skipped += 1;
+                continue;
+            };
+            if vectors.first().is_some_and(|first: &Vec<f64>| first.len() != vector.len()) {
+                skipped += 1;
                 continue;
             }
+            texts.push(entry["text"].as_str().unwrap_or_default().to_string());
+            vectors.push(vector);
+        }
+
+        let result = kmeans(&vectors, k, max_iters);
+        let mut clusters: Vec<Vec<(String, f64)>> = vec![Vec::new(); result.centroids.len()];
+        for ((text, vector), cluster) in texts.into_iter().zip(&vectors).zip(&result.assignments) {
+            let similarity = centroid_similarity(vector, &result.centroids[*cluster]);
+            clusters[*cluster].push((text, similarity));
+        }
 
-            // Apply type filter if specified
-            if let Some(ref target_type) = embedding_type {
-                if stored_type != target_type {
+        let mut clusters: Vec<Cluster> = clusters
+            .into_iter()
+            .filter(|members| !members.is_empty())
+            .map(|mut members| {
+                members.sort_by(|a, b| descending_similarity(a.1, b.1));
+                let mean_similarity = members.iter().map(|(_, s)| s).sum::<f64>() / members.len() as f64;
+                Cluster {
+                    centroid_summary: CentroidSummary {
+                        nearest_text: members[0].0.clone(),
+                        mean_similarity,
+                    },
+                    members: members.into_iter().map(|(text, _)| text).collect(),
+                }
+            })
+            .collect();
+        clusters.sort_by_key(|cluster| std::cmp::Reverse(cluster.members.len()));
+
+        Ok(ClusterResponse {
+            clusters,
+            skipped,
+            iterations: result.iterations,
+        })
+    }
+
+    /// Stream stored records one line at a time without loading the whole file, skipping
+    /// malformed lines, followed by the buffered ones. Binary and compressed files are read in
+    /// one go.
+    pub async fn stream_entries(
+        &self,
+    ) -> std::io::Result<Pin<Box<dyn Stream<Item = serde_json::Value> + Send>>> {
+        if self.storage_format != StorageFormat::Jsonl {
+            let entries = self.load_all().map_err(|e| std::io::Error::other(e.to_string()))?;
+            return Ok(Box::pin(tokio_stream::iter(entries)));
+        }
+        // Opened under the buffer's lock, as in `entries`, so flushes don't repeat records
+        let (file, buffered) = {
+            let records = self.write_buffer.as_ref().map(|buffer| buffer.records.lock().unwrap());
+            let file = match fs::File::open(&self.data_path) {
+                Ok(file) => Some(file),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+                Err(e) => return Err(e),
+            };
+            (file, records.map(|records| records.clone()).unwrap_or_default())
+        };
+        let buffered = tokio_stream::iter(buffered);
+        let Some(file) = file else {
+            return Ok(Box::pin(buffered));
+        };
+        let length = file.metadata()?.len();
+        let lines = LinesStream::new(BufReader::new(tokio::fs::File::from_std(file).take(length)).lines());
+        Ok(Box::pin(
+            lines
+                .filter_map(|line| line.ok().and_then(|line| serde_json::from_str(&line).ok()))
+                .chain(buffered),
+        ))
+    }
+
+    /// Bulk-load a JSONL dump (e.g. from `/export`). Each line needs a `text`, a non-empty
+    /// numeric `embedding` and an `embedding_type`; invalid lines and entries already stored
+    /// (or repeated within the dump) are counted and skipped rather than aborting the import.
+    /// With `STRICT_DIMENSIONS`, records whose vector length differs from their type's, as
+    /// stored or as first seen in the dump, are skipped as invalid.
+    /// Past `max_stored_entries`, the oldest records are dropped as for a single store.
+    pub async fn import_embeddings(&self, content: &str) -> Result<ImportResponse, Box<dyn std::error::Error>> {
+        let lock = self.write_lock(&self.data_path);
+        let _guard = lock.lock().await;
+        // Ahead of the imported records, so the file keeps the order they were stored in
+        self.flush_buffer()?;
+
+        let mut seen = HashSet::new();
+        let mut records = Vec::new();
+        let mut skipped_duplicates = 0;
+        let mut skipped_invalid = 0;
+        // Dimensions of the types first seen in this dump
+        let mut imported_dimensions: HashMap<String, usize> = HashMap::new();
+
+        for line in content.lines().filter(|line| !line.trim().is_empty()) {
+            let Some(mut record) = parse_import_record(line, self.storage_precision) else {
+                skipped_invalid += 1;
+                continue;
+            };
+            self.add_projected(&mut record);
+            let hash = record_hash(&record);
+            let (is_duplicate, stored_dimensions) = self.with_content_index(|index| {
+                let embedding_type = record["embedding_type"].as_str().unwrap_or_default();
+                (index.hashes.contains(&hash), index.dimensions.get(embedding_type).copied())
+            })?;
+            if is_duplicate || seen.contains(&hash) {
+                skipped_duplicates += 1;
+                continue;
+            }
+            if let Some(dimensions) = record_dimensions(&record) {
+                let embedding_type = record["embedding_type"].as_str().unwrap_or_default();
+                let expected = stored_dimensions.or_else(|| imported_dimensions.get(embedding_type).copied());
+                if self.check_dimensions(embedding_type, expected, dimensions).is_err() {
+                    skipped_invalid += 1;
                     continue;
                 }
+                imported_dimensions.entry(embedding_type.to_string()).or_insert(dimensions);
             }
+            seen.insert(hash);
+            records.push(record);
+        }
 
-            // Get and compare embeddings
-            if let Ok(stored_embedding) = serde_json::from_value::<Vec<f64>>(entry["embedding"].clone()) {
-                let similarity = cosine_similarity(embedding, &stored_embedding);
-                
-                let result = ComparisonResult {
-                    text: stored_text.to_string(),
-                    similarity,
-                    embedding: if include_embeddings {
-                        Some(stored_embedding)
-                    } else {
-                        None
-                    },
-                    embedding_type: stored_type.to_string(),
-                };
-                similarities.push(result);
+        self.ensure_data_dir()?;
+        let imported = records.len();
+        let over_limit = self.max_stored_entries.is_some_and(|max| self.stored_vectors() + imported > max);
+        let evicted = if over_limit {
+            self.store_evicting_oldest(records)?.len()
+        } else {
+            append_records(&self.data_path, self.storage_format, &records)?;
+            self.with_content_index(|index| {
+                for record in &records {
+                    index.insert(record);
+                }
+            })?;
+            if let Some(index) = &self.ann_index {
+                let mut index = index.lock().unwrap();
+                for record in records {
+                    index.insert(record);
+                }
             }
+            self.set_stored_vectors(self.stored_vectors() + imported);
+            0
+        };
+        ::metrics::counter!(metrics::STORES_TOTAL).increment(imported as u64);
+
+        Ok(ImportResponse {
+            imported,
+            skipped_duplicates,
+            skipped_invalid,
+            evicted,
+        })
+    }
+
+    /// Store a text's embedding along with optional `metadata`, failing if the text is
+    /// already stored under the type (whatever its metadata). Returns the records evicted to
+    /// stay within `MAX_STORED_ENTRIES`, oldest first.
+    #[tracing::instrument(skip_all, fields(model = %model_name, embedding_type = %embedding_type, text_len = text.len()))]
+    pub async fn save_embedding(
+        &self,
+        text: &str,
+        embedding: &[f64],
+        model_name: &str,
+        embedding_type: &str,
+        metadata: Option<&serde_json::Value>,
+    ) -> Result<Vec<StoredItem>, Box<dyn std::error::Error>> {
+        let _phase = PhaseTimer::start(Phase::Store);
+        let start = Instant::now();
+        // Hold the lock across the duplicate check and the append so concurrent stores of the
+        // same text can't both pass the check
+        let lock = self.write_lock(&self.data_path);
+        let _guard = lock.lock().await;
+
+        check_data_path(&self.data_path)?;
+        let hash = content_hash(text, embedding_type);
+        let (is_duplicate, stored_dimensions) = self.with_content_index(|index| {
+            (index.hashes.contains(&hash), index.dimensions.get(embedding_type).copied())
+        })?;
+        if is_duplicate {
+            let e = StoreError::Duplicate { embedding_type: embedding_type.to_string() };
+            ::metrics::counter!(metrics::DUPLICATES_TOTAL).increment(1);
+            tracing::warn!(error = %e, elapsed_ms = start.elapsed().as_millis() as u64, "embedding not stored");
+            return Err(e.into());
         }
+        self.check_dimensions(embedding_type, stored_dimensions, embedding.len())?;
 
-        // Sort by similarity
-        similarities.sort_by(|a, b| b.similarity.partial_cmp(&a.similarity).unwrap());
+        self.ensure_data_dir()?;
+        let (stored, normalized) = self.encode_embedding(embedding);
+        let mut record = build_record(text, &stored, model_name, embedding_type, normalized, metadata);
+        record["created_at"] = timestamp_now().into();
+        self.add_projected(&mut record);
+        let full = self.max_stored_entries.is_some_and(|max| self.stored_vectors() >= max);
+        let result = if full {
+            self.store_evicting_oldest(vec![record])
+        } else {
+            self.store_appending(record).map(|()| Vec::new())
+        };
+        let elapsed_ms = start.elapsed().as_millis() as u64;
+        match &result {
+            Ok(evicted) => {
+                ::metrics::counter!(metrics::STORES_TOTAL).increment(1);
+                tracing::info!(elapsed_ms, evicted = evicted.len(), "embedding stored");
+            }
+            Err(e) => tracing::warn!(error = %e, elapsed_ms, "embedding not stored"),
+        }
+        result
+    }
 
-        // Apply top_k filter
-        if let Some(k) = top_k {
-            similarities.truncate(k);
+    /// Append `record` to the data file, or to the write buffer, and the in-memory indexes
+    fn store_appending(&self, record: serde_json::Value) -> Result<(), Box<dyn std::error::Error>> {
+        match &self.write_buffer {
+            Some(buffer) => {
+                let mut records = buffer.records.lock().unwrap();
+                records.push(record.clone());
+                let full = records.len() >= buffer.max_records;
+                drop(records);
+                if full {
+                    self.flush_buffer()?;
+                }
+            }
+            None => append_records(&self.data_path, self.storage_format, std::slice::from_ref(&record))?,
         }
+        self.with_content_index(|index| index.insert(&record))?;
+        if let Some(index) = &self.ann_index {
+            index.lock().unwrap().insert(record);
+        }
+        self.set_stored_vectors(self.stored_vectors() + 1);
+        Ok(())
+    }
 
-        if similarities.is_empty() && embedding_type.is_none() {
-            return Err("No similar embeddings found".into());
+    /// Append `records` and drop the oldest records so at most `max_stored_entries` remain,
+    /// in one atomic rewrite of the data file. Callers hold the write lock.
+    fn store_evicting_oldest(&self, records: Vec<serde_json::Value>) -> Result<Vec<StoredItem>, Box<dyn std::error::Error>> {
+        let max = self.max_stored_entries.unwrap_or(usize::MAX);
+        self.flush_buffer()?;
+        let mut entries = load_entries(&self.data_path, self.storage_format)?;
+        entries.extend(records);
+        let excess = entries.len().saturating_sub(max);
+        let evicted: Vec<StoredItem> = entries.drain(..excess).map(|entry| stored_item(&entry, false)).collect();
+        replace_records(&self.data_path, self.storage_format, &entries)?;
+        for item in &evicted {
+            tracing::info!(text_len = item.text.len(), embedding_type = %item.embedding_type, "evicted oldest embedding");
         }
+        self.reset_indexes(entries);
+        Ok(evicted)
+    }
 
-        Ok(similarities)
+    /// Replace the record of `old_text` with one for `new_text` under the same type, keeping
+    /// its position and metadata. The data file is swapped in one rename, so readers see
+    /// either version.
+    #[tracing::instrument(skip_all, fields(model = %model_name, embedding_type = %embedding_type))]
+    pub async fn update_embedding(
+        &self,
+        old_text: &str,
+        new_text: &str,
+        embedding: &[f64],
+        model_name: &str,
+        embedding_type: &str,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let lock = self.write_lock(&self.data_path);
+        let _guard = lock.lock().await;
+
+        let old_hash = content_hash(old_text, embedding_type);
+        let new_hash = content_hash(new_text, embedding_type);
+        let (old_exists, new_exists) = self.with_content_index(|index| {
+            (index.hashes.contains(&old_hash), index.hashes.contains(&new_hash))
+        })?;
+        if !old_exists {
+            return Err(StoreError::NotFound { embedding_type: embedding_type.to_string() }.into());
+        }
+        if new_exists && new_hash != old_hash {
+            return Err(StoreError::Duplicate { embedding_type: embedding_type.to_string() }.into());
+        }
+
+        self.flush_buffer()?;
+        let mut entries = load_entries(&self.data_path, self.storage_format)?;
+        let position = entries.iter().position(|entry| record_hash(entry) == old_hash).unwrap_or(entries.len());
+        let metadata = entries.get(position).and_then(record_metadata);
+        let created_at = entries.get(position).map(|entry| entry["created_at"].clone());
+        entries.retain(|entry| record_hash(entry) != old_hash);
+        let remaining = ContentIndex::build(&entries);
+        self.check_dimensions(embedding_type, remaining.dimensions.get(embedding_type).copied(), embedding.len())?;
+
+        let (stored, normalized) = self.encode_embedding(embedding);
+        let mut record = build_record(new_text, &stored, model_name, embedding_type, normalized, metadata.as_ref());
+        if let Some(created_at) = created_at.filter(|created_at| !created_at.is_null()) {
+            record["created_at"] = created_at;
+        }
+        self.add_projected(&mut record);
+        entries.insert(position.min(entries.len()), record);
+        replace_records(&self.data_path, self.storage_format, &entries)?;
+
+        self.reset_indexes(entries);
+        tracing::info!("embedding updated");
+        Ok(())
     }
 
-    pub async fn save_embedding(
+    /// Re-embed every record not yet stored with `model` from its original text, e.g. after
+    /// switching models. Records are embedded `batch_size` at a time and each batch is written
+    /// back before the next one starts, so an interrupted run keeps its progress and a rerun
+    /// resumes where it stopped. The write lock is only held while a batch is written.
+    /// `progress` is called after each record is embedded.
+    #[tracing::instrument(skip(self, progress))]
+    pub async fn reembed_all(
         &self,
-        text: &str,
-        embedding: &Vec<f64>,
         model_name: &str,
+        batch_size: usize,
+        progress: impl Fn(ReembedProgress) + Send + Sync,
+    ) -> Result<ReembedResponse, Box<dyn std::error::Error>> {
+        let entries = self.load_all()?;
+        let total = entries.len();
+        let pending: Vec<(String, String, Option<serde_json::Value>)> = entries
+            .iter()
+            .filter(|entry| entry["model"].as_str() != Some(model_name))
+            .filter_map(|entry| {
+                let text = entry["text"].as_str()?;
+                let embedding_type = entry["embedding_type"].as_str()?;
+                Some((text.to_string(), embedding_type.to_string(), record_metadata(entry)))
+            })
+            .collect();
+        drop(entries);
+
+        let mut reembedded = 0;
+        let mut done = 0;
+        for batch in pending.chunks(batch_size.max(1)) {
+            let mut records = HashMap::new();
+            for (text, embedding_type, metadata) in batch {
+                let embedding = self.get_embedding(text, model_name, None, InputType::Document).await
+                    .map_err(|e| format!(
+                        "re-embedding stopped after {} of {} records: {}",
+                        reembedded, pending.len(), e
+                    ))?;
+                let (stored, normalized) = self.encode_embedding(&embedding);
+                let mut record = build_record(text, &stored, model_name, embedding_type, normalized, metadata.as_ref());
+                self.add_projected(&mut record);
+                records.insert(record_hash(&record), record);
+                done += 1;
+                progress(ReembedProgress { done, total: pending.len() });
+            }
+
+            let lock = self.write_lock(&self.data_path);
+            let _guard = lock.lock().await;
+            // Re-read under the lock so records stored, updated or cleared meanwhile are kept
+            self.flush_buffer()?;
+            let mut entries = load_entries(&self.data_path, self.storage_format)?;
+            for entry in entries.iter_mut() {
+                if entry["model"].as_str() == Some(model_name) {
+                    continue;
+                }
+                if let Some(mut record) = records.remove(&record_hash(entry)) {
+                    // Re-embedding doesn't make the entry newer
+                    if !entry["created_at"].is_null() {
+                        record["created_at"] = entry["created_at"].clone();
+                    }
+                    *entry = record;
+                    reembedded += 1;
+                }
+            }
+            replace_records(&self.data_path, self.storage_format, &entries)?;
+
+            self.reset_indexes(entries);
+            tracing::info!(reembedded, pending = pending.len(), "re-embedding progress");
+        }
+
+        Ok(ReembedResponse {
+            reembedded,
+            skipped: total - pending.len(),
+            total,
+        })
+    }
+
+    /// Check a new vector's length against the `expected` one of its type, failing in strict
+    /// mode and warning otherwise
+    fn check_dimensions(
+        &self,
         embedding_type: &str,
+        expected: Option<usize>,
+        dimensions: usize,
     ) -> Result<(), Box<dyn std::error::Error>> {
-        save_embedding_to_jsonl(text, embedding, &Self::get_data_path(), model_name, embedding_type).await
+        let Some(expected) = expected.filter(|expected| *expected != dimensions) else {
+            return Ok(());
+        };
+        let error = StoreError::DimensionMismatch { embedding_type: embedding_type.to_string(), stored: expected, got: dimensions };
+        if self.strict_dimensions {
+            tracing::warn!(error = %error, "embedding not stored");
+            return Err(error.into());
+        }
+        tracing::warn!("{}; comparisons across dimensions will be skipped", error);
+        Ok(())
+    }
+
+    /// Encode a vector at the store's precision, normalizing it first if configured. Also
+    /// returns whether it was normalized.
+    fn encode_embedding(&self, embedding: &[f64]) -> (StoredEmbedding, bool) {
+        let normalized = if self.normalize_embeddings {
+            normalize_vector(embedding)
+        } else {
+            None
+        };
+        let stored = StoredEmbedding::encode(normalized.as_deref().unwrap_or(embedding), self.storage_precision);
+        (stored, normalized.is_some())
+    }
+}
+
+/// A comparison request, scored against stored records
+struct Query<'a> {
+    text: &'a str,
+    embedding: &'a [f64],
+    /// The query scaled to unit length, if it has a non-zero norm
+    normalized: Option<&'a [f64]>,
+    include_embeddings: bool,
+    embedding_types: Option<&'a [String]>,
+    model: Option<&'a str>,
+    exclude_self: bool,
+    metadata: Option<&'a serde_json::Map<String, serde_json::Value>>,
+    candidates: Option<&'a HashSet<String>>,
+    created: CreatedRange,
+    /// Lowest similarity a result may have. Every metric grows with closeness, so this is a
+    /// lower bound.
+    min_similarity: Option<f64>,
+    tie_break: TieBreak,
+    /// The registered function to score with instead of cosine similarity
+    similarity: Option<&'a SimilarityFn>,
+    /// The model `embedding` was embedded from `text` with, if it was
+    query_model: Option<&'a str>,
+    /// Stored vectors scored so far, counting an index or projection candidate's rerank
+    /// separately
+    compared: AtomicUsize,
+}
+
+impl<'a> Query<'a> {
+    fn new(text: &'a str, embedding: &'a [f64], normalized: Option<&'a [f64]>, options: &'a RankOptions) -> Self {
+        let filter = &options.filter;
+        Query {
+            text,
+            embedding,
+            normalized,
+            // Deduplication compares results with each other, so it needs their vectors
+            include_embeddings: options.include_embeddings || filter.dedup_threshold.is_some(),
+            embedding_types: filter.embedding_types.as_deref(),
+            model: filter.model.as_deref(),
+            exclude_self: filter.exclude_self,
+            metadata: filter.metadata.as_ref(),
+            candidates: filter.candidates.as_ref(),
+            created: filter.created,
+            min_similarity: options.min_similarity,
+            tie_break: options.tie_break,
+            similarity: None,
+            query_model: filter.query_model.as_deref(),
+            compared: AtomicUsize::new(0),
+        }
+    }
+}
+
+impl Query<'_> {
+    /// Whether a stored record passes the filters, isn't the query itself and has a vector
+    /// of the query's length
+    fn accepts(&self, entry: &serde_json::Value) -> bool {
+        let stored_text = entry["text"].as_str().unwrap_or_default();
+        let stored_type = entry["embedding_type"].as_str().unwrap_or_default();
+
+        let type_matches = self.embedding_types.map(|types| types.iter().any(|t| t == stored_type));
+
+        // Skip self-comparison: within the filtered types by default, under any type with
+        // `exclude_self`
+        if stored_text == self.text && (self.exclude_self || type_matches == Some(true)) {
+            return false;
+        }
+
+        // Vectors of another length (another model or `dimensions`) aren't comparable
+        self.passes_filters(entry)
+            && entry["dimensions"].as_u64().is_none_or(|dimensions| dimensions as usize == self.embedding.len())
+    }
+
+    /// Whether a stored record passes the candidate, type, model, metadata and creation time
+    /// filters
+    fn passes_filters(&self, entry: &serde_json::Value) -> bool {
+        let stored_type = entry["embedding_type"].as_str().unwrap_or_default();
+
+        // Apply the candidate set if specified
+        if self.candidates.is_some_and(|candidates| !candidates.contains(entry["text"].as_str().unwrap_or_default())) {
+            return false;
+        }
+
+        // Apply type filter if specified
+        if self.embedding_types.is_some_and(|types| !types.iter().any(|t| t == stored_type)) {
+            return false;
+        }
+
+        // Apply model filter if specified
+        if self.model.is_some_and(|target_model| entry["model"].as_str() != Some(target_model)) {
+            return false;
+        }
+
+        // Apply metadata filter if specified; records without metadata never match it
+        if let Some(filter) = self.metadata {
+            let stored = entry["metadata"].as_object();
+            if !filter.iter().all(|(key, value)| stored.and_then(|stored| stored.get(key)) == Some(value)) {
+                return false;
+            }
+        }
+
+        // Apply the creation time filter if specified
+        self.created.contains(entry)
+    }
+
+    /// Whether a stored record is the query's own text embedded by the query's model, so its
+    /// vector is the query's up to rounding
+    fn embeds_query_text(&self, entry: &serde_json::Value) -> bool {
+        !self.text.is_empty()
+            && entry["text"].as_str() == Some(self.text)
+            && self.query_model.is_some_and(|model| entry["model"].as_str() == Some(model))
+    }
+
+    /// Exact similarity against one stored record, or `None` if the record isn't accepted,
+    /// has an undefined similarity or scores below `min_similarity`
+    fn score(&self, entry: &serde_json::Value) -> Option<ComparisonResult> {
+        if !self.accepts(entry) {
+            return None;
+        }
+        self.compared.fetch_add(1, AtomicOrdering::Relaxed);
+        let stored_text = entry["text"].as_str().unwrap_or_default();
+        let stored_type = entry["embedding_type"].as_str().unwrap_or_default();
+
+        let stored_embedding = record_embedding(entry)?;
+        // The same text embedded the same way scores exactly 1, where the cosine of its
+        // vectors could come out a rounding error short of it
+        let exact_match = self.embeds_query_text(entry) && stored_embedding.len() == self.embedding.len();
+        // Normalized records only need a dot product; older records get the full cosine
+        let similarity = match (self.similarity, exact_match, self.normalized, entry["normalized"].as_bool()) {
+            (Some(similarity), ..) => Some(similarity(self.embedding, &stored_embedding))
+                .filter(|score| !score.is_nan() && stored_embedding.len() == self.embedding.len()),
+            (None, true, _, _) => Some(1.0),
+            (None, false, Some(query), Some(true)) => dot_product(query, &stored_embedding),
+            _ => cosine_similarity(self.embedding, &stored_embedding),
+        }?;
+        if self.min_similarity.is_some_and(|min| similarity.is_nan() || similarity < min) {
+            return None;
+        }
+
+        Some(ComparisonResult {
+            text: stored_text.to_string(),
+            similarity,
+            embedding: if self.include_embeddings {
+                Some(stored_embedding)
+            } else {
+                None
+            },
+            embedding_type: stored_type.to_string(),
+            model: entry["model"].as_str().unwrap_or_default().to_string(),
+            metadata: record_metadata(entry),
+            created_at: record_created_at(entry),
+            adjusted_similarity: None,
+            lexical_score: None,
+            exact_match,
+        })
+    }
+
+    /// A record whose text is exactly the query's and which passes the filters, as a result
+    /// with similarity 1, whatever its vector. Unlike `score`, records of the query's own text
+    /// are what this looks for, so they aren't left out.
+    fn exact(&self, entry: &serde_json::Value) -> Option<ComparisonResult> {
+        let stored_text = entry["text"].as_str()?;
+        if stored_text != self.text || !self.passes_filters(entry) {
+            return None;
+        }
+        if self.query_model.is_some_and(|model| entry["model"].as_str() != Some(model)) {
+            return None;
+        }
+        Some(ComparisonResult {
+            text: stored_text.to_string(),
+            similarity: 1.0,
+            embedding: if self.include_embeddings { record_embedding(entry) } else { None },
+            embedding_type: entry["embedding_type"].as_str().unwrap_or_default().to_string(),
+            model: entry["model"].as_str().unwrap_or_default().to_string(),
+            metadata: record_metadata(entry),
+            created_at: record_created_at(entry),
+            adjusted_similarity: None,
+            lexical_score: None,
+            exact_match: true,
+        })
+    }
+
+    /// Similarity in the projected space, for records stored with a projected vector of the
+    /// projected query's length; others are scored exactly. The filters apply as in `score`,
+    /// but `min_similarity` is left to the exact rerank.
+    fn score_projected(&self, entry: &serde_json::Value, projected_query: &[f64]) -> Option<ComparisonResult> {
+        let stored: Option<Vec<f64>> = entry["projected"]
+            .as_array()
+            .and_then(|values| values.iter().map(serde_json::Value::as_f64).collect())
+            .filter(|stored: &Vec<f64>| stored.len() == projected_query.len());
+        // Exact matches are scored exactly, to get their similarity of 1
+        let exact_match = self.embeds_query_text(entry);
+        let Some(stored) = stored.filter(|_| !exact_match) else {
+            return self.score(entry);
+        };
+        if !self.accepts(entry) {
+            return None;
+        }
+        self.compared.fetch_add(1, AtomicOrdering::Relaxed);
+        Some(ComparisonResult {
+            text: entry["text"].as_str().unwrap_or_default().to_string(),
+            similarity: cosine_similarity(projected_query, &stored)?,
+            embedding: None,
+            embedding_type: entry["embedding_type"].as_str().unwrap_or_default().to_string(),
+            model: entry["model"].as_str().unwrap_or_default().to_string(),
+            metadata: None,
+            created_at: None,
+            adjusted_similarity: None,
+            lexical_score: None,
+            exact_match: false,
+        })
+    }
+
+    /// Fetch candidates from the index and rerank them exactly. Returns `None` when the
+    /// candidates don't yield `k` results (or any at all) after filtering, so the caller can
+    /// fall back to a full scan, unless the candidates already reach below `min_similarity`
+    /// and so cover every entry that could pass it.
+    fn search_index(&self, index: &AnnIndex, k: usize) -> Option<Vec<ComparisonResult>> {
+        let candidates = (k * ANN_CANDIDATES_PER_RESULT).max(ANN_MIN_CANDIDATES);
+        let found = index.graph.search(self.embedding, candidates, candidates);
+        let below_threshold = match (self.min_similarity, found.last()) {
+            (Some(min), Some((_, worst))) => *worst < min,
+            _ => false,
+        };
+        let mut seen = HashSet::new();
+        let mut results: Vec<ComparisonResult> = found
+            .into_iter()
+            .map(|(id, _)| &index.records[id])
+            .filter(|entry| seen.insert((entry["text"].as_str(), entry["embedding_type"].as_str())))
+            .filter_map(|entry| self.score(entry))
+            .collect();
+
+        if !below_threshold && (results.is_empty() || (results.len() < k && candidates < index.records.len())) {
+            return None;
+        }
+        results.sort_by(|a, b| self.tie_break.rank(a, b));
+        results.truncate(k);
+        Some(results)
+    }
+}
+
+/// Score stored records, as read from a data file, against a query vector and rank them,
+/// the way `EmbeddingService::compare_embeddings` does when it scans the whole store: the
+/// filters, `min_similarity`, type boosts, deduplication and `top_k` of `options` apply, and
+/// repeats of a text+type combination after its first copy are skipped. `text` is the query's
+/// text, which records are left out for as by `/compare`; pass `""` to keep them all. Of the
+/// metrics, only the built-in ones are known here; other names score by cosine similarity.
+/// This is synchronous and does no I/O, so it can be benchmarked and tested without a provider.
+pub fn rank_candidates<R>(
+    candidates: impl IntoIterator<Item = R>,
+    text: &str,
+    query: &[f64],
+    options: &RankOptions,
+) -> Vec<ComparisonResult>
+where
+    R: Borrow<serde_json::Value> + Send + Sync,
+{
+    let normalized_query = normalize_vector(query);
+    let builtins = builtin_similarity_functions();
+    let query = Query {
+        similarity: options.filter.metric.as_deref().and_then(|name| builtins.get(name)),
+        ..Query::new(text, query, normalized_query.as_deref(), options)
+    };
+    rank_with(&query, candidates, options)
+}
+
+fn rank_with<R>(query: &Query, candidates: impl IntoIterator<Item = R>, options: &RankOptions) -> Vec<ComparisonResult>
+where
+    R: Borrow<serde_json::Value> + Send + Sync,
+{
+    // Repeats are skipped sequentially, so the first copy wins however the scoring below is
+    // spread out
+    let mut candidates = skip_repeats(candidates.into_iter());
+    if let Some(alpha) = options.filter.hybrid_alpha {
+        return rank_scored(score_hybrid(query, candidates, alpha).into_iter(), options);
+    }
+    if !options.parallel {
+        return rank_scored(candidates.filter_map(|entry| query.score(entry.borrow())), options);
+    }
+    // In parallel, a chunk of records is scored at a time and collected in order, so ties
+    // rank the same as in the sequential path
+    let chunks = std::iter::from_fn(move || {
+        let chunk: Vec<R> = candidates.by_ref().take(PARALLEL_COMPARE_CHUNK).collect();
+        (!chunk.is_empty()).then_some(chunk)
+    });
+    rank_scored(
+        chunks.flat_map(|chunk| chunk.par_iter().filter_map(|entry| query.score(entry.borrow())).collect::<Vec<_>>()),
+        options,
+    )
+}
+
+/// Score records as `Query::score` does, then blend each similarity with the BM25 score of
+/// the record's text against the query's, divided by the best among the results so it runs
+/// from 0 to 1. Term statistics are gathered over every record, so all of them are held at
+/// once.
+fn score_hybrid<R: Borrow<serde_json::Value>>(query: &Query, candidates: impl Iterator<Item = R>, alpha: f64) -> Vec<ComparisonResult> {
+    let candidates: Vec<R> = candidates.collect();
+    let corpus = Bm25Corpus::new(candidates.iter().map(|entry| entry.borrow()["text"].as_str().unwrap_or_default()));
+    let scored: Vec<(ComparisonResult, f64)> = candidates
+        .iter()
+        .zip(corpus.scores(query.text))
+        .filter_map(|(entry, lexical)| Some((query.score(entry.borrow())?, lexical)))
+        .collect();
+    let best = scored.iter().map(|(_, lexical)| *lexical).fold(0.0, f64::max);
+    scored
+        .into_iter()
+        .map(|(mut result, lexical)| {
+            let lexical = if best > 0.0 { lexical / best } else { 0.0 };
+            result.lexical_score = Some(lexical);
+            result.adjusted_similarity = Some(alpha * result.similarity + (1.0 - alpha) * lexical);
+            result
+        })
+        .collect()
+}
+
+/// Apply the type boosts, ranking, deduplication and `top_k` of `options` to scored results
+fn rank_scored(scored: impl Iterator<Item = ComparisonResult>, options: &RankOptions) -> Vec<ComparisonResult> {
+    if let Some(k) = options.per_type_k {
+        let mut by_type: BTreeMap<String, Vec<ComparisonResult>> = BTreeMap::new();
+        for result in scored {
+            by_type.entry(result.embedding_type.clone()).or_default().push(result);
+        }
+        let options = RankOptions { top_k: Some(k), per_type_k: None, ..options.clone() };
+        return by_type.into_values().flat_map(|results| rank_scored(results.into_iter(), &options)).collect();
+    }
+
+    let filter = &options.filter;
+    let scored = scored.map(|mut result| {
+        if let Some(boosts) = &filter.type_boosts {
+            let boost = boosts.get(&result.embedding_type).copied().unwrap_or(1.0);
+            result.adjusted_similarity = Some(result.ranking_score() * boost);
+        }
+        result
+    });
+
+    // Rank by (adjusted) similarity, highest first, then by the tie-break. With `top_k`,
+    // only the best `k` are kept, unless deduplication may need to look further down the
+    // ranking
+    let rank = |a: &ComparisonResult, b: &ComparisonResult| options.tie_break.rank(a, b);
+    let mut similarities = match (options.top_k, filter.dedup_threshold) {
+        (Some(k), None) => top_k_sorted_by(scored, k, rank),
+        _ => {
+            let mut similarities: Vec<ComparisonResult> = scored.collect();
+            similarities.sort_by(rank);
+            similarities
+        }
+    };
+
+    if let Some(threshold) = filter.dedup_threshold {
+        similarities = drop_near_duplicates(similarities, threshold, options.top_k);
+        if !options.include_embeddings {
+            for result in &mut similarities {
+                result.embedding = None;
+            }
+        }
+    }
+
+    // Apply top_k filter
+    if let Some(k) = options.top_k {
+        similarities.truncate(k);
+    }
+    similarities
+}
+
+/// The records in order, without repeats of a text+type combination after its first copy
+fn skip_repeats<R: Borrow<serde_json::Value>>(entries: impl Iterator<Item = R>) -> impl Iterator<Item = R> {
+    let mut seen = HashSet::new();
+    entries.filter(move |entry| {
+        let entry = entry.borrow();
+        let key = format!("{}:{}", entry["text"].as_str().unwrap_or_default(), entry["embedding_type"].as_str().unwrap_or_default());
+        seen.insert(key)
+    })
+}
+
+/// Where the projection fitted for a data file is saved
+fn projection_path(data_path: &str) -> String {
+    format!("{}.projection.json", data_path)
+}
+
+/// The projection saved for a data file, if there is a readable one
+fn load_projection(data_path: &str) -> Option<Arc<Projection>> {
+    let path = projection_path(data_path);
+    let contents = fs::read(&path).ok()?;
+    match serde_json::from_slice(&contents) {
+        Ok(projection) => Some(Arc::new(projection)),
+        Err(e) => {
+            tracing::warn!(path, error = %e, "ignoring unreadable projection");
+            None
+        }
+    }
+}
+
+/// The listing of a stored record, with its vector only if `include_embedding`
+fn stored_item(entry: &serde_json::Value, include_embedding: bool) -> StoredItem {
+    StoredItem {
+        text: entry["text"].as_str().unwrap_or_default().to_string(),
+        model: entry["model"].as_str().unwrap_or_default().to_string(),
+        embedding_type: entry["embedding_type"].as_str().unwrap_or_default().to_string(),
+        embedding: if include_embedding {
+            record_embedding(entry)
+        } else {
+            None
+        },
+        metadata: record_metadata(entry),
+        created_at: record_created_at(entry),
+    }
+}
+
+/// Close the least recently used namespaces that no request holds until there is room to
+/// open one more, writing out their buffered stores first. While every open namespace is in
+/// use, or one can't be flushed, the limit is exceeded instead until they are released.
+fn close_idle_namespaces(namespaces: &mut LruCache<String, Arc<EmbeddingService>>) {
+    while namespaces.len() >= MAX_OPEN_NAMESPACES {
+        let Some((name, service)) = namespaces.iter().rev().find(|(_, service)| Arc::strong_count(service) == 1) else {
+            return;
+        };
+        if let Err(e) = service.flush_buffer() {
+            tracing::error!(path = %service.data_path, error = %e, "failed to flush buffered stores of idle namespace");
+            return;
+        }
+        let name = name.clone();
+        namespaces.pop(&name);
+    }
+}
+
+/// Count the readable records in a data file, leaving out malformed lines as loading does;
+/// a missing file has none
+fn count_entries(path: &str, format: StorageFormat) -> usize {
+    load_entries(path, format).map_or(0, |entries| entries.len())
+}
+
+/// Count the records in a data file, without parsing JSONL lines, so malformed lines count
+/// too; a missing file has none
+fn count_records(path: &str, format: StorageFormat) -> usize {
+    use std::io::BufRead;
+    if format != StorageFormat::Jsonl {
+        return count_entries(path, format);
+    }
+    fs::File::open(path)
+        .map(|file| {
+            std::io::BufReader::new(file)
+                .lines()
+                .map_while(Result::ok)
+                .filter(|line| !line.trim().is_empty())
+                .count()
+        })
+        .unwrap_or(0)
+}
+
+/// Walk ranked results best first, dropping each one whose cosine similarity to a result
+/// already kept is above `threshold`, so near-identical texts only appear once. Stops once
+/// `limit` results are kept. Results need their embeddings.
+fn drop_near_duplicates(
+    ranked: Vec<ComparisonResult>,
+    threshold: f64,
+    limit: Option<usize>,
+) -> Vec<ComparisonResult> {
+    let mut kept: Vec<ComparisonResult> = Vec::new();
+    for result in ranked {
+        if limit.is_some_and(|limit| kept.len() >= limit) {
+            break;
+        }
+        let embedding = result.embedding.as_deref().unwrap_or_default();
+        let is_duplicate = kept.iter().any(|other| {
+            cosine_similarity(embedding, other.embedding.as_deref().unwrap_or_default())
+                .is_some_and(|similarity| similarity > threshold)
+        });
+        if !is_duplicate {
+            kept.push(result);
+        }
+    }
+    kept
+}
+
+/// Validate one line of an import, returning the record to store if it is well-formed. The
+/// embedding is re-encoded at the store's precision whatever precision the dump used.
+fn parse_import_record(line: &str, precision: StoragePrecision) -> Option<serde_json::Value> {
+    let entry: serde_json::Value = serde_json::from_str(line).ok()?;
+    let text = entry["text"].as_str()?;
+    let embedding_type = entry["embedding_type"].as_str()?;
+    let embedding = record_embedding(&entry)?;
+    if embedding.is_empty() {
+        return None;
+    }
+    let model = entry["model"].as_str().unwrap_or("unknown");
+
+    let mut record = serde_json::json!({
+        "text": text,
+        "model": model,
+        "embedding_type": embedding_type,
+        "hash": content_hash(text, embedding_type)
+    });
+    if let Some(metadata) = record_metadata(&entry) {
+        record["metadata"] = metadata;
+    }
+    // Kept only if readable, so time filters can rely on it
+    if record_created_at(&entry).is_some() {
+        record["created_at"] = entry["created_at"].clone();
     }
-} 
\ No newline at end of file
+    StoredEmbedding::encode(&embedding, precision).write_to(&mut record);
+    Some(record)
+}
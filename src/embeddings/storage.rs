@@ -1,47 +1,704 @@
+use chrono::{DateTime, SecondsFormat, Utc};
+use flate2::read::MultiGzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use half::bf16;
+use serde::{Deserialize, Serialize};
 use std::fs::OpenOptions;
-use std::io::Write;
-use serde_json;
+use std::io::{BufRead, Read, Seek, SeekFrom, Write};
 
+/// Layout of the data file
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum StorageFormat {
+    /// One JSON record per line
+    #[default]
+    Jsonl,
+    /// Length-prefixed bincode records
+    Bincode,
+    /// Gzip-compressed JSON lines, each append written as its own gzip member
+    JsonlGzip,
+}
 
-pub async fn save_embedding_to_jsonl(
-    text: &str, 
-    embedding: &Vec<f64>,
-    output_file: &str,
-    model_name: &str,
-    embedding_type: &str,
-) -> Result<(), Box<dyn std::error::Error>> {
-    let file_exists = std::path::Path::new(output_file).exists();
-    let mut existing_entries = Vec::new();
-    
-    if file_exists {
-        let content = std::fs::read_to_string(output_file)?;
-        for line in content.lines() {
-            let entry: serde_json::Value = serde_json::from_str(line)?;
-            existing_entries.push(entry);
+impl StorageFormat {
+    /// Read `STORAGE_FORMAT` (`jsonl`, `jsonl.gz` or `bincode`), defaulting to `jsonl`.
+    /// `COMPRESS_STORAGE=true` selects `jsonl.gz` too.
+    pub fn from_env() -> Self {
+        match std::env::var("STORAGE_FORMAT").unwrap_or_default().to_lowercase().as_str() {
+            "bincode" | "bin" => StorageFormat::Bincode,
+            "jsonl.gz" | "gzip" | "gz" => StorageFormat::JsonlGzip,
+            _ if std::env::var("COMPRESS_STORAGE").is_ok_and(|v| v == "true") => StorageFormat::JsonlGzip,
+            _ => StorageFormat::Jsonl,
         }
     }
 
-    let is_duplicate = existing_entries.iter().any(|entry| {
-        entry["text"].as_str() == Some(text) && 
-        entry["embedding_type"].as_str() == Some(embedding_type)
-    });
+    /// Switch JSONL to its compressed form when the data file is named `*.gz`
+    pub fn for_path(self, path: &str) -> Self {
+        match self {
+            StorageFormat::Jsonl if path.ends_with(".gz") => StorageFormat::JsonlGzip,
+            format => format,
+        }
+    }
+
+    /// File extension of data files in this format
+    pub fn extension(&self) -> &'static str {
+        match self {
+            StorageFormat::Jsonl => "jsonl",
+            StorageFormat::Bincode => "bin",
+            StorageFormat::JsonlGzip => "jsonl.gz",
+        }
+    }
+
+    /// Where data is stored when `DATA_PATH` is unset
+    pub fn default_data_path(&self) -> &'static str {
+        match self {
+            StorageFormat::Jsonl => "data/embeddings.jsonl",
+            StorageFormat::Bincode => "data/embeddings.bin",
+            StorageFormat::JsonlGzip => "data/embeddings.jsonl.gz",
+        }
+    }
+}
+
+/// How embedding values are written to the data file
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum StoragePrecision {
+    /// Full `f64` values
+    #[default]
+    F64,
+    /// Values rounded to `f32`, which roughly halves the text per value
+    F32,
+    /// Values rounded to bfloat16: the `f32` exponent range with an 8-bit mantissa, about 3
+    /// significant digits, and 2 bytes per value in the binary format
+    Bf16,
+    /// Integers in `-127..=127` times a per-vector `scale`
+    Int8,
+}
 
-    if is_duplicate {
-        return Err(format!("duplicate text entry for type {}", embedding_type).into());
+impl StoragePrecision {
+    /// Read `STORAGE_PRECISION` (`f64`, `f32`, `bf16` or `int8`), defaulting to `f64`
+    pub fn from_env() -> Self {
+        std::env::var("STORAGE_PRECISION")
+            .ok()
+            .and_then(|v| Self::parse(&v))
+            .unwrap_or_default()
     }
 
-    let record = serde_json::json!({
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.to_lowercase().as_str() {
+            "f64" => Some(StoragePrecision::F64),
+            "f32" => Some(StoragePrecision::F32),
+            "bf16" => Some(StoragePrecision::Bf16),
+            "int8" => Some(StoragePrecision::Int8),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            StoragePrecision::F64 => "f64",
+            StoragePrecision::F32 => "f32",
+            StoragePrecision::Bf16 => "bf16",
+            StoragePrecision::Int8 => "int8",
+        }
+    }
+}
+
+/// An embedding as it is written to a record: the raw stored values plus what is needed to
+/// turn them back into floats
+#[derive(Clone, Debug, PartialEq)]
+pub struct StoredEmbedding {
+    pub precision: StoragePrecision,
+    /// Multiplier applied to int8 values; `None` for float precisions
+    pub scale: Option<f64>,
+    pub values: Vec<f64>,
+}
+
+impl StoredEmbedding {
+    pub fn encode(embedding: &[f64], precision: StoragePrecision) -> Self {
+        match precision {
+            StoragePrecision::F64 => Self { precision, scale: None, values: embedding.to_vec() },
+            StoragePrecision::F32 => Self {
+                precision,
+                scale: None,
+                // Go through the shortest f32 decimal so the JSON isn't padded with f64 digits
+                values: embedding
+                    .iter()
+                    .map(|&x| (x as f32).to_string().parse().unwrap_or(x))
+                    .collect(),
+            },
+            StoragePrecision::Bf16 => Self {
+                precision,
+                scale: None,
+                values: embedding.iter().map(|&x| shortest_bf16_decimal(bf16::from_f64(x))).collect(),
+            },
+            StoragePrecision::Int8 => {
+                let max_abs = embedding.iter().fold(0.0_f64, |max, x| max.max(x.abs()));
+                let scale = if max_abs > 0.0 && max_abs.is_finite() { max_abs / 127.0 } else { 1.0 };
+                Self {
+                    precision,
+                    scale: Some(scale),
+                    values: embedding
+                        .iter()
+                        .map(|x| (x / scale).round().clamp(-127.0, 127.0))
+                        .collect(),
+                }
+            }
+        }
+    }
+
+    /// Read the embedding of a stored record. Records without a `precision` are `f64`.
+    pub fn from_record(entry: &serde_json::Value) -> Option<Self> {
+        let precision = match entry["precision"].as_str() {
+            Some(precision) => StoragePrecision::parse(precision)?,
+            None => StoragePrecision::F64,
+        };
+        let scale = match precision {
+            StoragePrecision::Int8 => Some(entry["scale"].as_f64()?),
+            _ => None,
+        };
+        let values = serde_json::from_value(entry["embedding"].clone()).ok()?;
+        Some(Self { precision, scale, values })
+    }
+
+    /// The embedding as floats, dequantizing int8 values. bf16 values are returned exactly as
+    /// the bfloat16 numbers their decimals stand for.
+    pub fn to_vec(&self) -> Vec<f64> {
+        match (self.precision, self.scale) {
+            (StoragePrecision::Bf16, _) => self.values.iter().map(|&x| bf16::from_f64(x).to_f64()).collect(),
+            (_, Some(scale)) => self.values.iter().map(|q| q * scale).collect(),
+            (_, None) => self.values.clone(),
+        }
+    }
+
+    /// Write the values into `record`, with `precision` and `scale` when not plain `f64`
+    pub fn write_to(&self, record: &mut serde_json::Value) {
+        record["embedding"] = match self.precision {
+            StoragePrecision::Int8 => self.values.iter().map(|&q| q as i64).collect::<Vec<_>>().into(),
+            _ => self.values.clone().into(),
+        };
+        if self.precision != StoragePrecision::F64 {
+            record["precision"] = self.precision.as_str().into();
+        }
+        if let Some(scale) = self.scale {
+            record["scale"] = scale.into();
+        }
+        record["dimensions"] = self.values.len().into();
+    }
+}
+
+/// The shortest decimal that reads back as `value`, so bf16 records aren't padded with the
+/// digits of the exact binary value. Four significant digits always suffice.
+fn shortest_bf16_decimal(value: bf16) -> f64 {
+    let exact = value.to_f64();
+    (0..4)
+        .filter_map(|precision| format!("{:.*e}", precision, exact).parse::<f64>().ok())
+        .find(|&decimal| bf16::from_f64(decimal) == value)
+        .unwrap_or(exact)
+}
+
+/// The dequantized embedding of a stored record, if it has a readable one
+pub fn record_embedding(entry: &serde_json::Value) -> Option<Vec<f64>> {
+    StoredEmbedding::from_record(entry).map(|embedding| embedding.to_vec())
+}
+
+/// Hash identifying a text within an embedding type, stored with each record so duplicates
+/// can be found without comparing full texts
+pub fn content_hash(text: &str, embedding_type: &str) -> String {
+    let mut hasher = blake3::Hasher::new();
+    // Length-prefix the type so ("ab", "c") and ("a", "bc") can't collide
+    hasher.update(&(embedding_type.len() as u64).to_le_bytes());
+    hasher.update(embedding_type.as_bytes());
+    hasher.update(text.as_bytes());
+    hasher.finalize().to_hex().to_string()
+}
+
+/// The content hash of a stored record, computed from its text and type for records
+/// written before hashes were stored
+pub fn record_hash(entry: &serde_json::Value) -> String {
+    match entry["hash"].as_str() {
+        Some(hash) => hash.to_string(),
+        None => content_hash(
+            entry["text"].as_str().unwrap_or_default(),
+            entry["embedding_type"].as_str().unwrap_or_default(),
+        ),
+    }
+}
+
+/// Dimension of a stored record's vector
+pub fn record_dimensions(entry: &serde_json::Value) -> Option<usize> {
+    match entry["dimensions"].as_u64() {
+        Some(dimensions) => Some(dimensions as usize),
+        // Records written before dimensions were recorded
+        None => entry["embedding"].as_array().map(|values| values.len()),
+    }
+}
+
+/// The metadata attached to a record, if any
+pub fn record_metadata(entry: &serde_json::Value) -> Option<serde_json::Value> {
+    entry.get("metadata").filter(|metadata| !metadata.is_null()).cloned()
+}
+
+/// When a record was stored, if it has a readable `created_at`. Records written before
+/// timestamps were recorded have none.
+pub fn record_created_at(entry: &serde_json::Value) -> Option<DateTime<Utc>> {
+    let created_at = entry["created_at"].as_str()?;
+    DateTime::parse_from_rfc3339(created_at).ok().map(|created_at| created_at.with_timezone(&Utc))
+}
+
+/// The current time as stored in a record's `created_at`: RFC 3339 in UTC, to the millisecond
+pub fn timestamp_now() -> String {
+    Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true)
+}
+
+/// The record stored for a text and its embedding. The hash covers only the text and type,
+/// so metadata never makes an otherwise duplicate record distinct.
+pub fn build_record(
+    text: &str,
+    embedding: &StoredEmbedding,
+    model_name: &str,
+    embedding_type: &str,
+    normalized: bool,
+    metadata: Option<&serde_json::Value>,
+) -> serde_json::Value {
+    let mut record = serde_json::json!({
         "text": text,
-        "embedding": embedding,
         "model": model_name,
-        "embedding_type": embedding_type
+        "embedding_type": embedding_type,
+        "normalized": normalized,
+        "hash": content_hash(text, embedding_type)
     });
+    if let Some(metadata) = metadata.filter(|metadata| !metadata.is_null()) {
+        record["metadata"] = metadata.clone();
+    }
+    embedding.write_to(&mut record);
+    record
+}
+
+/// Read every readable record from a data file in the given format. A missing file is
+/// treated as an empty store.
+pub fn load_entries(input_file: &str, format: StorageFormat) -> Result<Vec<serde_json::Value>, Box<dyn std::error::Error>> {
+    match format {
+        StorageFormat::Jsonl => load_entries_from_jsonl(input_file),
+        StorageFormat::Bincode => load_entries_from_bincode(input_file),
+        StorageFormat::JsonlGzip => load_entries_from_gzip(input_file),
+    }
+}
+
+/// Iterate over the records of a data file in the given format, like `load_entries`, without
+/// holding them all in memory. JSONL files are read one line at a time; binary and compressed
+/// files are still loaded whole first.
+pub fn iter_entries(
+    input_file: &str,
+    format: StorageFormat,
+) -> Result<Box<dyn Iterator<Item = serde_json::Value> + Send>, Box<dyn std::error::Error>> {
+    match format {
+        StorageFormat::Jsonl => Ok(Box::new(iter_entries_from_jsonl(input_file)?)),
+        StorageFormat::Bincode | StorageFormat::JsonlGzip => Ok(Box::new(load_entries(input_file, format)?.into_iter())),
+    }
+}
+
+/// Append already-validated records to a data file in the given format
+pub fn append_records(
+    output_file: &str,
+    format: StorageFormat,
+    records: &[serde_json::Value],
+) -> Result<(), Box<dyn std::error::Error>> {
+    match format {
+        StorageFormat::Jsonl => append_records_to_jsonl(output_file, records),
+        StorageFormat::Bincode => append_records_to_bincode(output_file, records),
+        StorageFormat::JsonlGzip => append_records_to_gzip(output_file, records),
+    }
+}
+
+/// Replace the contents of a data file with `records`. They are written to a temporary file
+/// that is then renamed over the original, so readers see either the old or the new contents.
+pub fn replace_records(
+    output_file: &str,
+    format: StorageFormat,
+    records: &[serde_json::Value],
+) -> Result<(), Box<dyn std::error::Error>> {
+    let temp_file = format!("{}.tmp", output_file);
+    if std::path::Path::new(&temp_file).exists() {
+        std::fs::remove_file(&temp_file)?;
+    }
+    if let Err(e) = append_records(&temp_file, format, records) {
+        let _ = std::fs::remove_file(&temp_file);
+        return Err(e);
+    }
+    if records.is_empty() {
+        // Nothing was written, but the result should still be an empty store
+        std::fs::File::create(&temp_file)?;
+    }
+    std::fs::rename(&temp_file, output_file)?;
+    Ok(())
+}
 
+/// Read every parseable record from a JSONL file, skipping malformed lines (e.g. one cut short
+/// by an interrupted write) with a warning. A missing file is treated as an empty store.
+pub fn load_entries_from_jsonl(input_file: &str) -> Result<Vec<serde_json::Value>, Box<dyn std::error::Error>> {
+    if !std::path::Path::new(input_file).exists() {
+        return Ok(Vec::new());
+    }
+    let content = std::fs::read_to_string(input_file)?;
+    Ok(parse_jsonl(input_file, &content))
+}
+
+/// Read the records of a JSONL file one line at a time, so only the current line is held in
+/// memory. Malformed lines are skipped with a warning, as in `load_entries_from_jsonl`; a line
+/// that can't be read at all (e.g. invalid UTF-8) ends the iteration with a warning. A missing
+/// file is treated as an empty store. Only the records in the file when it is opened are read,
+/// not those appended while iterating.
+pub fn iter_entries_from_jsonl(
+    input_file: &str,
+) -> Result<impl Iterator<Item = serde_json::Value> + Send, Box<dyn std::error::Error>> {
+    let file = match std::fs::File::open(input_file) {
+        Ok(file) => Some(file),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+        Err(e) => return Err(e.into()),
+    };
+    let path = input_file.to_string();
+    Ok(file
+        .into_iter()
+        .flat_map(|file| {
+            let length = file.metadata().map_or(u64::MAX, |metadata| metadata.len());
+            std::io::BufReader::new(file.take(length)).lines()
+        })
+        .enumerate()
+        .map_while({
+            let path = path.clone();
+            move |(number, line)| match line {
+                Ok(line) => Some((number, line)),
+                Err(e) => {
+                    tracing::warn!(path = %path, line = number + 1, error = %e, "stopping at unreadable line");
+                    None
+                }
+            }
+        })
+        .filter_map(move |(number, line)| parse_jsonl_line(&path, number, &line)))
+}
+
+/// Parse the records of JSONL content read from `input_file`, warning about malformed lines
+fn parse_jsonl(input_file: &str, content: &str) -> Vec<serde_json::Value> {
+    content.lines()
+        .enumerate()
+        .filter_map(|(number, line)| parse_jsonl_line(input_file, number, line))
+        .collect()
+}
+
+/// The record on line `number` (from 0) of a JSONL file; `None` for a blank line, or with a
+/// warning for a malformed one
+fn parse_jsonl_line(input_file: &str, number: usize, line: &str) -> Option<serde_json::Value> {
+    if line.trim().is_empty() {
+        return None;
+    }
+    match serde_json::from_str::<serde_json::Value>(line) {
+        Ok(entry) => Some(entry),
+        Err(e) => {
+            tracing::warn!(path = input_file, line = number + 1, error = %e, "skipping malformed record");
+            None
+        }
+    }
+}
+
+/// Append already-validated records to a JSONL file in one write. If the file ends in a line
+/// cut short by an interrupted write, the records start on a new line rather than extending it.
+pub fn append_records_to_jsonl(
+    output_file: &str,
+    records: &[serde_json::Value],
+) -> Result<(), Box<dyn std::error::Error>> {
+    if records.is_empty() {
+        return Ok(());
+    }
     let mut file = OpenOptions::new()
         .create(true)
+        .read(true)
         .append(true)
         .open(output_file)?;
 
-    writeln!(file, "{}", record.to_string())?;
+    let mut buffer = String::new();
+    let length = file.metadata()?.len();
+    if length > 0 {
+        let mut last = [0u8; 1];
+        file.seek(SeekFrom::Start(length - 1))?;
+        file.read_exact(&mut last)?;
+        if last[0] != b'\n' {
+            buffer.push('\n');
+        }
+    }
+    for record in records {
+        buffer.push_str(&record.to_string());
+        buffer.push('\n');
+    }
+    file.write_all(buffer.as_bytes())?;
     Ok(())
-} 
\ No newline at end of file
+}
+
+/// Read every parseable record from a gzip-compressed JSONL file made of one or more gzip
+/// members. A missing file is treated as an empty store. If a member is cut short by an
+/// interrupted write, the records decompressed before it are kept with a warning; members
+/// after it can't be found again, so the file should be rewritten with `repair_data`.
+pub fn load_entries_from_gzip(input_file: &str) -> Result<Vec<serde_json::Value>, Box<dyn std::error::Error>> {
+    if !std::path::Path::new(input_file).exists() {
+        return Ok(Vec::new());
+    }
+    let mut decoder = MultiGzDecoder::new(std::io::BufReader::new(std::fs::File::open(input_file)?));
+    let mut bytes = Vec::new();
+    let mut chunk = [0u8; 64 * 1024];
+    loop {
+        match decoder.read(&mut chunk) {
+            Ok(0) => break,
+            Ok(read) => bytes.extend_from_slice(&chunk[..read]),
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(e) => {
+                tracing::warn!(path = input_file, decompressed = bytes.len(), error = %e, "ignoring unreadable end of compressed data file");
+                break;
+            }
+        }
+    }
+    Ok(parse_jsonl(input_file, &String::from_utf8_lossy(&bytes)))
+}
+
+/// Append records to a gzip-compressed JSONL file as a new gzip member, so earlier members
+/// are neither read nor rewritten. Gzip readers decompress consecutive members as one stream.
+pub fn append_records_to_gzip(
+    output_file: &str,
+    records: &[serde_json::Value],
+) -> Result<(), Box<dyn std::error::Error>> {
+    if records.is_empty() {
+        return Ok(());
+    }
+    let mut buffer = String::new();
+    for record in records {
+        buffer.push_str(&record.to_string());
+        buffer.push('\n');
+    }
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(buffer.as_bytes())?;
+    let member = encoder.finish()?;
+
+    let mut file = OpenOptions::new().create(true).append(true).open(output_file)?;
+    file.write_all(&member)?;
+    Ok(())
+}
+
+/// Header row of the CSV export
+pub const CSV_HEADER: &str = "text,model,embedding_type,embedding";
+
+/// Render a stored record as one CSV row, with the (dequantized) embedding kept as a single
+/// JSON array field
+pub fn record_to_csv_row(entry: &serde_json::Value) -> String {
+    [
+        entry["text"].as_str().unwrap_or_default().to_string(),
+        entry["model"].as_str().unwrap_or_default().to_string(),
+        entry["embedding_type"].as_str().unwrap_or_default().to_string(),
+        serde_json::Value::from(record_embedding(entry).unwrap_or_default()).to_string(),
+    ]
+    .iter()
+    .map(|field| format!("\"{}\"", field.replace('"', "\"\"")))
+    .collect::<Vec<_>>()
+    .join(",")
+}
+
+/// Values of an embedding in the binary format, at their stored width
+#[derive(Serialize, Deserialize)]
+enum BinaryValues {
+    F64(Vec<f64>),
+    F32(Vec<f32>),
+    Int8(Vec<i8>),
+    /// The bits of each bfloat16 value
+    Bf16(Vec<u16>),
+}
+
+/// One record of the binary format. Records are converted to and from the JSON shape used
+/// everywhere else at the file boundary. The creation time and metadata, when present, follow
+/// the encoded record within the same length-prefixed payload as a NUL byte and a JSON object
+/// of those fields, so records without them keep the layout files were written with before
+/// they existed. Files from before timestamps hold the bare metadata JSON there instead.
+#[derive(Serialize, Deserialize)]
+struct BinaryRecord {
+    text: String,
+    model: String,
+    embedding_type: String,
+    normalized: bool,
+    scale: Option<f64>,
+    values: BinaryValues,
+}
+
+impl BinaryRecord {
+    fn from_value(entry: &serde_json::Value) -> Option<Self> {
+        let embedding = StoredEmbedding::from_record(entry)?;
+        let values = match embedding.precision {
+            StoragePrecision::F64 => BinaryValues::F64(embedding.values),
+            StoragePrecision::F32 => BinaryValues::F32(embedding.values.iter().map(|&x| x as f32).collect()),
+            StoragePrecision::Int8 => BinaryValues::Int8(embedding.values.iter().map(|&q| q as i8).collect()),
+            StoragePrecision::Bf16 => BinaryValues::Bf16(embedding.values.iter().map(|&x| bf16::from_f64(x).to_bits()).collect()),
+        };
+        Some(Self {
+            text: entry["text"].as_str()?.to_string(),
+            model: entry["model"].as_str().unwrap_or_default().to_string(),
+            embedding_type: entry["embedding_type"].as_str().unwrap_or_default().to_string(),
+            normalized: entry["normalized"].as_bool().unwrap_or(false),
+            scale: embedding.scale,
+            values,
+        })
+    }
+
+    fn into_value(self) -> serde_json::Value {
+        let (precision, values) = match self.values {
+            BinaryValues::F64(values) => (StoragePrecision::F64, values),
+            // Through the shortest decimal, so the value matches what the JSONL format stores
+            BinaryValues::F32(values) => (
+                StoragePrecision::F32,
+                values.iter().map(|x| x.to_string().parse().unwrap_or(*x as f64)).collect(),
+            ),
+            BinaryValues::Int8(values) => (StoragePrecision::Int8, values.iter().map(|&q| q as f64).collect()),
+            BinaryValues::Bf16(values) => (
+                StoragePrecision::Bf16,
+                values.iter().map(|&bits| shortest_bf16_decimal(bf16::from_bits(bits))).collect(),
+            ),
+        };
+        let mut record = serde_json::json!({
+            "text": self.text,
+            "model": self.model,
+            "embedding_type": self.embedding_type,
+            "normalized": self.normalized
+        });
+        StoredEmbedding { precision, scale: self.scale, values }.write_to(&mut record);
+        record
+    }
+}
+
+/// Read every record from a binary data file. A missing file is treated as an empty store;
+/// a record cut short by an interrupted write ends the read with a warning.
+pub fn load_entries_from_bincode(input_file: &str) -> Result<Vec<serde_json::Value>, Box<dyn std::error::Error>> {
+    if !std::path::Path::new(input_file).exists() {
+        return Ok(Vec::new());
+    }
+    let bytes = std::fs::read(input_file)?;
+    let mut entries = Vec::new();
+    let mut offset = 0;
+    while offset < bytes.len() {
+        let Some(length) = bytes.get(offset..offset + 4) else {
+            tracing::warn!(path = input_file, offset, "ignoring truncated record at end of data file");
+            break;
+        };
+        let length = u32::from_le_bytes(length.try_into().unwrap()) as usize;
+        let Some(payload) = bytes.get(offset + 4..offset + 4 + length) else {
+            tracing::warn!(path = input_file, offset, "ignoring truncated record at end of data file");
+            break;
+        };
+        let mut reader = std::io::Cursor::new(payload);
+        match bincode::deserialize_from::<_, BinaryRecord>(&mut reader) {
+            Ok(record) => {
+                let mut entry = record.into_value();
+                let trailing = &payload[reader.position() as usize..];
+                if let Err(e) = read_binary_extras(trailing, &mut entry) {
+                    tracing::warn!(path = input_file, offset, error = %e, "ignoring unreadable record metadata");
+                }
+                entries.push(entry);
+            }
+            Err(e) => tracing::warn!(path = input_file, offset, error = %e, "skipping unreadable record"),
+        }
+        offset += 4 + length;
+    }
+    Ok(entries)
+}
+
+/// Append records to a binary data file, each prefixed with its length. A truncated record
+/// left by an interrupted write is cut off first so it can't swallow the new ones.
+pub fn append_records_to_bincode(
+    output_file: &str,
+    records: &[serde_json::Value],
+) -> Result<(), Box<dyn std::error::Error>> {
+    if records.is_empty() {
+        return Ok(());
+    }
+    let mut buffer = Vec::new();
+    for record in records {
+        let extras = binary_extras(record);
+        let record = BinaryRecord::from_value(record).ok_or("record has no readable text or embedding")?;
+        let mut payload = bincode::serialize(&record)?;
+        if let Some(extras) = extras {
+            payload.push(0);
+            payload.extend_from_slice(extras.to_string().as_bytes());
+        }
+        buffer.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+        buffer.extend_from_slice(&payload);
+    }
+
+    let mut file = OpenOptions::new()
+        .create(true)
+        .read(true)
+        .write(true)
+        .truncate(false)
+        .open(output_file)?;
+    let valid_length = complete_records_length(&mut file)?;
+    if valid_length < file.metadata()?.len() {
+        tracing::warn!(path = output_file, valid_length, "truncating partial record at end of data file");
+        file.set_len(valid_length)?;
+    }
+    file.seek(SeekFrom::Start(valid_length))?;
+    file.write_all(&buffer)?;
+    Ok(())
+}
+
+/// The fields of a record kept after its bincode encoding, if it has any
+fn binary_extras(entry: &serde_json::Value) -> Option<serde_json::Value> {
+    let mut extras = serde_json::Map::new();
+    if let Some(created_at) = entry["created_at"].as_str() {
+        extras.insert("created_at".to_string(), created_at.into());
+    }
+    if let Some(metadata) = record_metadata(entry) {
+        extras.insert("metadata".to_string(), metadata);
+    }
+    if entry["projected"].is_array() {
+        extras.insert("projected".to_string(), entry["projected"].clone());
+    }
+    (!extras.is_empty()).then_some(serde_json::Value::Object(extras))
+}
+
+/// Copy the fields kept after a record's bincode encoding into `entry`
+fn read_binary_extras(trailing: &[u8], entry: &mut serde_json::Value) -> serde_json::Result<()> {
+    match trailing.split_first() {
+        None => {}
+        Some((0, extras)) => {
+            let mut extras: serde_json::Map<String, serde_json::Value> = serde_json::from_slice(extras)?;
+            for field in ["created_at", "metadata", "projected"] {
+                if let Some(value) = extras.remove(field) {
+                    entry[field] = value;
+                }
+            }
+        }
+        // Metadata as written before the extras object; JSON never starts with a NUL byte
+        Some(_) => entry["metadata"] = serde_json::from_slice(trailing)?,
+    }
+    Ok(())
+}
+
+/// Length of the prefix of a binary data file made of complete records, found by following
+/// the length prefixes without decoding the records
+fn complete_records_length(file: &mut std::fs::File) -> std::io::Result<u64> {
+    let file_length = file.metadata()?.len();
+    let mut offset = 0;
+    let mut length = [0u8; 4];
+    while offset + 4 <= file_length {
+        file.seek(SeekFrom::Start(offset))?;
+        file.read_exact(&mut length)?;
+        let end = offset + 4 + u32::from_le_bytes(length) as u64;
+        if end > file_length {
+            break;
+        }
+        offset = end;
+    }
+    Ok(offset)
+}
+
+/// Convert a JSONL data file to the binary format, returning the number of records written.
+/// Malformed lines are skipped and an existing binary file is appended to.
+pub fn migrate_jsonl_to_bincode(jsonl_file: &str, bincode_file: &str) -> Result<usize, Box<dyn std::error::Error>> {
+    let records: Vec<_> = load_entries_from_jsonl(jsonl_file)?
+        .into_iter()
+        .filter(|record| BinaryRecord::from_value(record).is_some())
+        .collect();
+    append_records_to_bincode(bincode_file, &records)?;
+    Ok(records.len())
+}
@@ -0,0 +1,84 @@
+use crate::ErrorResponse;
+use axum::{
+    extract::{Request, State},
+    http::{header, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+use std::sync::Arc;
+
+/// Paths served without a token: liveness/readiness probes and the API docs
+const EXEMPT_PATHS: [&str; 4] = ["/health", "/ready", "/swagger-ui", "/api-docs"];
+
+/// The token a request was authenticated with, added to its extensions by
+/// `require_api_key`. Only present when `API_AUTH_TOKEN` is set and the token matched it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AuthenticatedToken(pub String);
+
+/// Shared token that callers must present, from `API_AUTH_TOKEN`. With no token configured
+/// every request is allowed.
+pub struct ApiAuth {
+    token: Option<String>,
+}
+
+impl ApiAuth {
+    pub fn new(token: Option<String>) -> Self {
+        Self { token: token.filter(|t| !t.is_empty()) }
+    }
+
+    pub fn from_env() -> Self {
+        let auth = Self::new(std::env::var("API_AUTH_TOKEN").ok());
+        if auth.token.is_none() {
+            tracing::warn!("API_AUTH_TOKEN is not set, all endpoints are open to anyone who can reach the server");
+        }
+        auth
+    }
+
+    /// The configured token, if the request carries it as `Authorization: Bearer <token>` or
+    /// `X-API-Key`
+    fn validated_token(&self, request: &Request) -> Option<String> {
+        let expected = self.token.as_ref()?;
+        let headers = request.headers();
+        let bearer = headers
+            .get(header::AUTHORIZATION)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.strip_prefix("Bearer "));
+        let api_key = headers.get("x-api-key").and_then(|v| v.to_str().ok());
+        [bearer, api_key]
+            .into_iter()
+            .flatten()
+            .any(|token| constant_time_eq(token.trim().as_bytes(), expected.as_bytes()))
+            .then(|| expected.clone())
+    }
+}
+
+/// Middleware rejecting requests without a valid token with `401 Unauthorized`. Requests
+/// with a valid token get it as an `AuthenticatedToken` extension.
+pub async fn require_api_key(State(auth): State<Arc<ApiAuth>>, mut request: Request, next: Next) -> Response {
+    if let Some(token) = auth.validated_token(&request) {
+        request.extensions_mut().insert(AuthenticatedToken(token));
+        return next.run(request).await;
+    }
+    let path = request.uri().path();
+    let exempt = EXEMPT_PATHS
+        .iter()
+        .any(|prefix| path == *prefix || path.starts_with(&format!("{}/", prefix)));
+    if exempt || auth.token.is_none() {
+        return next.run(request).await;
+    }
+
+    tracing::warn!(path, "rejected request without a valid API token");
+    (
+        StatusCode::UNAUTHORIZED,
+        [(header::WWW_AUTHENTICATE, "Bearer")],
+        Json(ErrorResponse { error: "missing or invalid API token".to_string() }),
+    )
+        .into_response()
+}
+
+/// Compare without returning early on the first differing byte, so response timing doesn't
+/// leak how much of a guessed token was right
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |diff, (x, y)| diff | (x ^ y)) == 0
+}
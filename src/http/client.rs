@@ -1,16 +1,90 @@
 use reqwest::{Client, Method, Request, Url};
 use std::collections::HashMap;
+use std::fmt;
+use std::time::Duration;
+
+pub const DEFAULT_HTTP_TIMEOUT_SECS: u64 = 30;
+
+#[derive(Debug)]
+pub enum HttpError {
+    /// The request didn't complete within `HTTP_TIMEOUT_SECS`
+    Timeout,
+    /// The request couldn't be built, e.g. from a malformed URL
+    InvalidRequest(String),
+    Request(reqwest::Error),
+}
+
+impl fmt::Display for HttpError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HttpError::Timeout => write!(f, "HTTP request timed out"),
+            HttpError::InvalidRequest(message) => write!(f, "invalid HTTP request: {}", message),
+            HttpError::Request(e) => write!(f, "HTTP request failed: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for HttpError {}
+
+impl From<reqwest::Error> for HttpError {
+    fn from(e: reqwest::Error) -> Self {
+        if e.is_timeout() {
+            HttpError::Timeout
+        } else {
+            HttpError::Request(e)
+        }
+    }
+}
+
+/// A response read to completion
+#[derive(Debug)]
+pub struct HttpResponse {
+    pub status: u16,
+    /// Header names in lowercase. A header sent more than once keeps its last value.
+    pub headers: HashMap<String, String>,
+    pub body: String,
+}
+
+impl HttpResponse {
+    /// Whether the status is 2xx
+    pub fn is_success(&self) -> bool {
+        (200..300).contains(&self.status)
+    }
+
+    /// A header value by case-insensitive name
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(&name.to_lowercase()).map(String::as_str)
+    }
+}
+
+/// Build a client to be shared across requests so connections (and TLS sessions) are pooled
+/// instead of re-established per call. The total request timeout comes from `HTTP_TIMEOUT_SECS`.
+pub fn build_client() -> Client {
+    let timeout_secs = std::env::var("HTTP_TIMEOUT_SECS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(DEFAULT_HTTP_TIMEOUT_SECS);
+    build_client_with_timeout(Duration::from_secs(timeout_secs))
+}
+
+/// `build_client` with the given total request timeout instead of `HTTP_TIMEOUT_SECS`
+pub fn build_client_with_timeout(timeout: Duration) -> Client {
+    Client::builder()
+        .timeout(timeout)
+        .pool_idle_timeout(Duration::from_secs(90))
+        .build()
+        .expect("Failed to build HTTP client")
+}
 
 pub async fn make_http_request(
+    client: &Client,
     method: Method,
     url: &str,
     headers: Option<HashMap<String, String>>,
     query_params: Option<HashMap<String, String>>,
     body: Option<String>,
-) -> Result<String, reqwest::Error> {
-    let client = Client::new();
-
-    let url = Url::parse(url).unwrap();
+) -> Result<HttpResponse, HttpError> {
+    let url = Url::parse(url).map_err(|e| HttpError::InvalidRequest(format!("invalid URL {}: {}", url, e)))?;
     let url = if let Some(query_params) = query_params {
         let mut url = url.clone();
         url.query_pairs_mut()
@@ -24,10 +98,11 @@ pub async fn make_http_request(
 
     if let Some(headers) = headers {
         for (key, value) in headers {
-            request.headers_mut().insert(
-                reqwest::header::HeaderName::from_bytes(key.as_bytes()).unwrap(),
-                reqwest::header::HeaderValue::from_str(&value).unwrap(),
-            );
+            let name = reqwest::header::HeaderName::from_bytes(key.as_bytes())
+                .map_err(|e| HttpError::InvalidRequest(format!("invalid header name {}: {}", key, e)))?;
+            let value = reqwest::header::HeaderValue::from_str(&value)
+                .map_err(|e| HttpError::InvalidRequest(format!("invalid value for header {}: {}", key, e)))?;
+            request.headers_mut().insert(name, value);
         }
     }
 
@@ -36,7 +111,13 @@ pub async fn make_http_request(
     }
 
     let response = client.execute(request).await?;
-    let response_body = response.text().await?;
+    let status = response.status().as_u16();
+    let headers = response
+        .headers()
+        .iter()
+        .filter_map(|(name, value)| Some((name.as_str().to_string(), value.to_str().ok()?.to_string())))
+        .collect();
+    let body = response.text().await?;
 
-    Ok(response_body)
-} 
\ No newline at end of file
+    Ok(HttpResponse { status, headers, body })
+}
@@ -0,0 +1,52 @@
+use axum::http::{header, HeaderName, HeaderValue, Method};
+use tower_http::cors::{AllowOrigin, CorsLayer};
+
+/// CORS policy for browser clients. `allowed_origins` is a comma-separated list of origins,
+/// or `*` for any origin. When it is unset, any origin is allowed in development, while in
+/// production no cross-origin requests are allowed until origins are listed.
+pub fn cors_layer(allowed_origins: Option<&str>, production: bool) -> CorsLayer {
+    let layer = CorsLayer::new()
+        .allow_methods([Method::GET, Method::POST])
+        .allow_headers([
+            header::AUTHORIZATION,
+            header::CONTENT_TYPE,
+            HeaderName::from_static("x-api-key"),
+            HeaderName::from_static("idempotency-key"),
+        ]);
+
+    let allowed_origins = allowed_origins.map(str::trim).filter(|origins| !origins.is_empty());
+    match allowed_origins {
+        Some("*") => layer.allow_origin(AllowOrigin::any()),
+        Some(origins) => {
+            let origins: Vec<HeaderValue> = origins
+                .split(',')
+                .map(|origin| origin.trim().trim_end_matches('/'))
+                .filter(|origin| !origin.is_empty())
+                .filter_map(|origin| match HeaderValue::from_str(origin) {
+                    Ok(value) => Some(value),
+                    Err(_) => {
+                        tracing::warn!(origin, "ignoring invalid origin in CORS_ALLOWED_ORIGINS");
+                        None
+                    }
+                })
+                .collect();
+            layer.allow_origin(AllowOrigin::list(origins))
+        }
+        None if production => layer,
+        None => layer.allow_origin(AllowOrigin::any()),
+    }
+}
+
+/// The policy from `CORS_ALLOWED_ORIGINS`, treating `APP_ENV=production` as production
+pub fn cors_from_env() -> CorsLayer {
+    let allowed_origins = std::env::var("CORS_ALLOWED_ORIGINS").ok();
+    let production = std::env::var("APP_ENV").is_ok_and(|env| env.trim().eq_ignore_ascii_case("production"));
+    if allowed_origins.is_none() {
+        if production {
+            tracing::warn!("CORS_ALLOWED_ORIGINS is not set, browsers on other origins can't call the API");
+        } else {
+            tracing::info!("CORS_ALLOWED_ORIGINS is not set, allowing any origin outside production");
+        }
+    }
+    cors_layer(allowed_origins.as_deref(), production)
+}
@@ -0,0 +1,109 @@
+use crate::RequestErrorResponse;
+use async_trait::async_trait;
+use axum::{
+    body::Bytes,
+    extract::{FromRequest, Request},
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::de::DeserializeOwned;
+
+/// JSON body extractor like `axum::Json`, but a body that doesn't fit the request type is
+/// rejected with a `RequestErrorResponse` naming the offending field and what it should hold,
+/// rather than a plain-text message.
+pub struct ValidJson<T>(pub T);
+
+/// Why a JSON body was rejected, rendered as a `RequestErrorResponse`
+pub struct JsonRejection {
+    pub status: StatusCode,
+    pub body: RequestErrorResponse,
+}
+
+impl JsonRejection {
+    fn new(status: StatusCode, error: impl Into<String>) -> Self {
+        Self { status, body: RequestErrorResponse { error: error.into(), field: None, expected: None } }
+    }
+}
+
+impl IntoResponse for JsonRejection {
+    fn into_response(self) -> Response {
+        (self.status, Json(self.body)).into_response()
+    }
+}
+
+#[async_trait]
+impl<T, S> FromRequest<S> for ValidJson<T>
+where
+    T: DeserializeOwned,
+    S: Send + Sync,
+{
+    type Rejection = JsonRejection;
+
+    async fn from_request(request: Request, state: &S) -> Result<Self, Self::Rejection> {
+        if !is_json(request.headers()) {
+            return Err(JsonRejection::new(
+                StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                "expected a request with `Content-Type: application/json`",
+            ));
+        }
+        let bytes = Bytes::from_request(request, state)
+            .await
+            .map_err(|e| JsonRejection::new(e.status(), e.body_text()))?;
+        parse(&bytes).map(ValidJson)
+    }
+}
+
+/// Deserialize a JSON body, tracking the path to the value that failed
+fn parse<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, JsonRejection> {
+    let mut deserializer = serde_json::Deserializer::from_slice(bytes);
+    let value = serde_path_to_error::deserialize(&mut deserializer).map_err(describe)?;
+    deserializer
+        .end()
+        .map_err(|e| JsonRejection::new(StatusCode::BAD_REQUEST, format!("invalid JSON: {}", e)))?;
+    Ok(value)
+}
+
+/// Turn a deserialization error into a message naming the field. Malformed JSON is a
+/// `400 Bad Request`; well-formed JSON of the wrong shape is a `422 Unprocessable Entity`,
+/// as with `axum::Json`.
+fn describe(error: serde_path_to_error::Error<serde_json::Error>) -> JsonRejection {
+    let path = error.path().to_string();
+    let inner = error.into_inner();
+    if !inner.is_data() {
+        return JsonRejection::new(StatusCode::BAD_REQUEST, format!("invalid JSON: {}", inner));
+    }
+
+    // serde's messages read "missing field `text`" or "invalid type: integer `5`, expected a
+    // string", followed by serde_json's position
+    let message = inner.to_string();
+    let message = match message.rfind(" at line ") {
+        Some(position) => &message[..position],
+        None => message.as_str(),
+    };
+    let parent = Some(path.as_str()).filter(|path| *path != ".");
+    let (field, expected) = match message.strip_prefix("missing field `").and_then(|rest| rest.split_once('`')) {
+        Some((missing, _)) => {
+            let field = parent.map_or_else(|| missing.to_string(), |parent| format!("{}.{}", parent, missing));
+            (Some(field), Some("a value; the field is required".to_string()))
+        }
+        None => (
+            parent.map(str::to_string),
+            message.split_once(", expected ").map(|(_, expected)| expected.to_string()),
+        ),
+    };
+    let error = match &field {
+        Some(field) if !message.starts_with("missing field") => format!("{}: {}", field, message),
+        _ => message.to_string(),
+    };
+    JsonRejection { status: StatusCode::UNPROCESSABLE_ENTITY, body: RequestErrorResponse { error, field, expected } }
+}
+
+/// Whether the content type is `application/json` or a `+json` type, as `axum::Json` accepts
+fn is_json(headers: &HeaderMap) -> bool {
+    let Some(content_type) = headers.get(header::CONTENT_TYPE).and_then(|value| value.to_str().ok()) else {
+        return false;
+    };
+    let essence = content_type.split(';').next().unwrap_or_default().trim().to_ascii_lowercase();
+    essence == "application/json" || (essence.starts_with("application/") && essence.ends_with("+json"))
+}
@@ -0,0 +1,19 @@
+/// Largest request body accepted when `MAX_BODY_BYTES` is unset; the same as axum's own
+/// default, which the configured limit replaces
+pub const DEFAULT_MAX_BODY_BYTES: usize = 2 * 1024 * 1024;
+
+/// Largest request body in bytes, from `MAX_BODY_BYTES`. Bodies over it are rejected with
+/// `413 Payload Too Large` before they are buffered.
+pub fn max_body_bytes_from_env() -> usize {
+    std::env::var("MAX_BODY_BYTES")
+        .ok()
+        .and_then(|v| v.trim().parse().ok())
+        .filter(|bytes| *bytes > 0)
+        .unwrap_or(DEFAULT_MAX_BODY_BYTES)
+}
+
+/// Bytes needed for a request carrying one text of `max_text_chars` characters: up to four
+/// bytes per character once UTF-8 encoded, with room for the other JSON fields
+pub fn body_bytes_for_text(max_text_chars: usize) -> usize {
+    max_text_chars.saturating_mul(4).saturating_add(4096)
+}
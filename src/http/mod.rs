@@ -1 +1,7 @@
+pub mod auth;
 pub mod client;
+pub mod cors;
+pub mod json;
+pub mod limit;
+pub mod rate_limit;
+pub mod slow_requests;
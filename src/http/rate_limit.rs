@@ -0,0 +1,102 @@
+use crate::http::auth::AuthenticatedToken;
+use crate::ErrorResponse;
+use axum::{
+    extract::{ConnectInfo, Request, State},
+    http::{header, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+/// Number of tracked clients above which idle (fully refilled) buckets are dropped
+const MAX_TRACKED_CLIENTS: usize = 10_000;
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+/// Token-bucket rate limiter allowing each client `RATE_LIMIT_PER_MINUTE` requests per minute,
+/// with bursts up to the same number. Clients are keyed by API token when `require_api_key`
+/// validated one, otherwise by IP address, so made-up tokens can't buy a fresh budget.
+pub struct RateLimiter {
+    per_minute: Option<u32>,
+    buckets: Mutex<HashMap<String, Bucket>>,
+}
+
+impl RateLimiter {
+    /// A limiter allowing `per_minute` requests per client; `None` disables limiting
+    pub fn new(per_minute: Option<u32>) -> Self {
+        Self {
+            per_minute: per_minute.filter(|n| *n > 0),
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn from_env() -> Self {
+        Self::new(std::env::var("RATE_LIMIT_PER_MINUTE").ok().and_then(|v| v.parse().ok()))
+    }
+
+    /// Take a token for `client`, or return how many seconds until one is available
+    fn check(&self, client: &str) -> Result<(), u64> {
+        let Some(per_minute) = self.per_minute else {
+            return Ok(());
+        };
+        let capacity = per_minute as f64;
+        let refill_per_sec = capacity / 60.0;
+        let now = Instant::now();
+
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.len() >= MAX_TRACKED_CLIENTS {
+            buckets.retain(|_, bucket| {
+                bucket.tokens + now.duration_since(bucket.updated).as_secs_f64() * refill_per_sec < capacity
+            });
+        }
+        let bucket = buckets
+            .entry(client.to_string())
+            .or_insert(Bucket { tokens: capacity, updated: now });
+        bucket.tokens = (bucket.tokens + now.duration_since(bucket.updated).as_secs_f64() * refill_per_sec).min(capacity);
+        bucket.updated = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(((1.0 - bucket.tokens) / refill_per_sec).ceil() as u64)
+        }
+    }
+}
+
+/// The key a request is limited under: its API token if it was validated, else its IP
+/// address. Tokens are ignored when auth is off, as nothing checks them then.
+fn client_key(request: &Request) -> String {
+    if let Some(AuthenticatedToken(token)) = request.extensions().get::<AuthenticatedToken>() {
+        return format!("token:{}", token);
+    }
+    match request.extensions().get::<ConnectInfo<SocketAddr>>() {
+        Some(ConnectInfo(addr)) => format!("ip:{}", addr.ip()),
+        None => "unknown".to_string(),
+    }
+}
+
+/// Middleware answering `429 Too Many Requests` with a `Retry-After` header once a client
+/// has used up its requests
+pub async fn rate_limit(State(limiter): State<Arc<RateLimiter>>, request: Request, next: Next) -> Response {
+    let client = client_key(&request);
+    match limiter.check(&client) {
+        Ok(()) => next.run(request).await,
+        Err(retry_after) => {
+            tracing::warn!(path = request.uri().path(), retry_after, "rate limit exceeded");
+            (
+                StatusCode::TOO_MANY_REQUESTS,
+                [(header::RETRY_AFTER, retry_after.to_string())],
+                Json(ErrorResponse { error: "rate limit exceeded".to_string() }),
+            )
+                .into_response()
+        }
+    }
+}
@@ -0,0 +1,165 @@
+use axum::{
+    extract::{Request, State},
+    middleware::Next,
+    response::Response,
+};
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Requests taking at least this long are logged when `SLOW_REQUEST_MS` is unset
+pub const DEFAULT_SLOW_REQUEST_MS: u64 = 1000;
+
+/// Where a request can spend its time, besides the handler's own work
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Phase {
+    /// Waiting for and calling the embedding provider
+    Provider,
+    /// Scanning or writing the store
+    Store,
+}
+
+/// Time the current request has spent in each phase, in nanoseconds, and the stored vectors
+/// it compared against
+#[derive(Default)]
+struct PhaseTimings {
+    provider: AtomicU64,
+    store: AtomicU64,
+    compared: AtomicU64,
+}
+
+impl PhaseTimings {
+    fn add(&self, phase: Phase, elapsed: Duration) {
+        let total = match phase {
+            Phase::Provider => &self.provider,
+            Phase::Store => &self.store,
+        };
+        total.fetch_add(elapsed.as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    fn get(&self, phase: Phase) -> Duration {
+        let total = match phase {
+            Phase::Provider => &self.provider,
+            Phase::Store => &self.store,
+        };
+        Duration::from_nanos(total.load(Ordering::Relaxed))
+    }
+}
+
+tokio::task_local! {
+    static TIMINGS: Arc<PhaseTimings>;
+}
+
+/// What a future measured by `measure_phases` spent its time on
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PhaseDurations {
+    pub provider: Duration,
+    pub store: Duration,
+    /// Stored vectors compared against, as counted with `count_compared`
+    pub compared: u64,
+}
+
+/// Run `future`, measuring the time it spends in each phase. Inside a request timed by
+/// `log_slow_requests` the phases count towards the request too.
+pub async fn measure_phases<F: Future>(future: F) -> (F::Output, PhaseDurations) {
+    let timings = Arc::new(PhaseTimings::default());
+    let output = TIMINGS.scope(timings.clone(), future).await;
+    let durations = PhaseDurations {
+        provider: timings.get(Phase::Provider),
+        store: timings.get(Phase::Store),
+        compared: timings.compared.load(Ordering::Relaxed),
+    };
+    TIMINGS
+        .try_with(|outer| {
+            outer.add(Phase::Provider, durations.provider);
+            outer.add(Phase::Store, durations.store);
+            outer.compared.fetch_add(durations.compared, Ordering::Relaxed);
+        })
+        .ok();
+    (output, durations)
+}
+
+/// Count `vectors` stored vectors compared against by the current request. Outside a
+/// measured request it does nothing.
+pub fn count_compared(vectors: usize) {
+    TIMINGS.try_with(|timings| timings.compared.fetch_add(vectors as u64, Ordering::Relaxed)).ok();
+}
+
+/// Times one phase of the current request, adding the time to it when dropped. Outside a
+/// request timed by `log_slow_requests` or `measure_phases` it does nothing.
+pub struct PhaseTimer {
+    phase: Phase,
+    start: Instant,
+}
+
+impl PhaseTimer {
+    pub fn start(phase: Phase) -> Self {
+        Self { phase, start: Instant::now() }
+    }
+}
+
+impl Drop for PhaseTimer {
+    fn drop(&mut self) {
+        let elapsed = self.start.elapsed();
+        TIMINGS.try_with(|timings| timings.add(self.phase, elapsed)).ok();
+    }
+}
+
+/// How long a request may take before `log_slow_requests` warns about it
+pub struct SlowRequestLog {
+    threshold: Option<Duration>,
+}
+
+impl SlowRequestLog {
+    /// Warn about requests taking at least `threshold_ms`; 0 disables the log
+    pub fn new(threshold_ms: u64) -> Self {
+        Self { threshold: (threshold_ms > 0).then(|| Duration::from_millis(threshold_ms)) }
+    }
+
+    pub fn from_env() -> Self {
+        Self::new(
+            std::env::var("SLOW_REQUEST_MS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(DEFAULT_SLOW_REQUEST_MS),
+        )
+    }
+}
+
+/// Middleware logging a warning for each request slower than the threshold, with the time it
+/// spent calling the provider and in the store, so a slow provider can be told apart from a
+/// slow scan
+pub async fn log_slow_requests(State(log): State<Arc<SlowRequestLog>>, request: Request, next: Next) -> Response {
+    let Some(threshold) = log.threshold else {
+        return next.run(request).await;
+    };
+    let method = request.method().clone();
+    let path = request.uri().path().to_string();
+    let start = Instant::now();
+    let (response, PhaseDurations { provider, store, .. }) = measure_phases(next.run(request)).await;
+    let elapsed = start.elapsed();
+    if elapsed < threshold {
+        return response;
+    }
+
+    let other = elapsed.saturating_sub(provider + store);
+    let slowest_phase = if provider >= store && provider >= other {
+        "provider"
+    } else if store >= other {
+        "store"
+    } else {
+        "other"
+    };
+    tracing::warn!(
+        method = %method,
+        path,
+        status = response.status().as_u16(),
+        elapsed_ms = elapsed.as_millis() as u64,
+        provider_ms = provider.as_millis() as u64,
+        store_ms = store.as_millis() as u64,
+        slowest_phase,
+        "slow request"
+    );
+    response
+}
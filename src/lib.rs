@@ -2,35 +2,182 @@ pub mod http;
 pub mod embeddings;
 pub mod utils;
 
-use axum::{Json, extract::State};
+use axum::{Json, body::Body, extract::{Query, State}, http::{header, HeaderMap, StatusCode}, response::{IntoResponse, Response}};
+use axum::extract::ws::{Message, WebSocket, WebSocketUpgrade};
+use axum::response::sse::{Event, KeepAlive, Sse};
+use chrono::{DateTime, Utc};
+use std::collections::{BTreeMap, HashMap};
+use std::convert::Infallible;
 use std::sync::Arc;
-use utoipa::ToSchema;
+use std::time::Instant;
+use tokio_stream::wrappers::UnboundedReceiverStream;
+use tokio_stream::StreamExt;
+use utoipa::{IntoParams, ToSchema};
 
-pub use crate::embeddings::service::EmbeddingService;
+use crate::embeddings::idempotency::{Claim, IdempotencyError, MAX_IDEMPOTENCY_KEY_LEN};
+use crate::embeddings::provider::{InputType, ProviderError};
+use crate::http::json::ValidJson;
+use crate::http::slow_requests::{measure_phases, PhaseDurations};
+use crate::embeddings::storage::{record_to_csv_row, CSV_HEADER};
+use crate::utils::similarity::{cosine_similarity, dot_product, euclidean_distance, round_significant, round_to};
+
+pub use crate::embeddings::service::{rank_candidates, CompareFilter, CompareQuery, CreatedRange, EmbeddingService, FitProjectionError, RankOptions, StoreError, TieBreak};
 
 #[derive(serde::Deserialize, serde::Serialize, ToSchema)]
 pub struct EmbeddingRequest {
     /// The text to generate an embedding for
     pub text: String,
-    /// Optional model name, defaults to "text-embedding-3-large"
+    /// Optional model name, defaults to the provider's default model. Must be in the allowlist
     pub model: Option<String>,
-    /// The type of embedding (e.g., "user", "title", etc.)
-    pub embedding_type: String,
+    /// The type of embedding (e.g., "user", "title", etc.), defaults to `DEFAULT_EMBEDDING_TYPE`
+    pub embedding_type: Option<String>,
+    /// Optional shorter output length, for models that support it (`text-embedding-3-*`)
+    pub dimensions: Option<usize>,
+    /// Arbitrary JSON kept with the entry and returned with it, e.g. a source URL or author.
+    /// It doesn't affect duplicate detection, which only looks at `text` and `embedding_type`
+    #[schema(value_type = Option<Object>)]
+    pub metadata: Option<serde_json::Value>,
+    /// Compute and return the embedding without storing it. Defaults to false
+    pub dry_run: Option<bool>,
+    /// Store the entry in this namespace, a separate store with its own data file, instead of
+    /// the default one. Letters, digits, `-` and `_` only
+    pub namespace: Option<String>,
+    /// Round the returned embedding's components to this many significant digits, to shrink
+    /// the response. The stored vector keeps full precision. Defaults to no rounding
+    pub float_precision: Option<u32>,
+    /// What to do with a text over `MAX_TEXT_CHARS`: reject it (`none`, the default), or cut
+    /// it down to the limit by dropping its `start` or its `end`
+    pub truncation: Option<Truncation>,
+    /// Add a `meta` object to the response with the request's timings, provider and model.
+    /// Defaults to false. It doesn't change what is stored, so it isn't part of what an
+    /// `Idempotency-Key` identifies
+    #[serde(skip_serializing)]
+    pub verbose: Option<bool>,
 }
 
-#[derive(serde::Serialize, ToSchema)]
+/// How a text longer than the configured limit is shortened before it is embedded
+#[derive(serde::Deserialize, serde::Serialize, Clone, Copy, Debug, Default, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Truncation {
+    /// Don't shorten it; the request is rejected
+    #[default]
+    None,
+    /// Drop characters from the start, keeping the end of the text
+    Start,
+    /// Drop characters from the end, keeping the start of the text
+    End,
+}
+
+impl Truncation {
+    /// `text` cut down to at most `max_chars` characters, or as it is with `None`
+    pub fn apply(self, text: &str, max_chars: usize) -> &str {
+        let chars = text.chars().count();
+        if chars <= max_chars {
+            return text;
+        }
+        match self {
+            Truncation::None => text,
+            Truncation::Start => text.char_indices().nth(chars - max_chars).map_or(text, |(i, _)| &text[i..]),
+            Truncation::End => text.char_indices().nth(max_chars).map_or(text, |(i, _)| &text[..i]),
+        }
+    }
+}
+
+#[derive(serde::Deserialize, ToSchema)]
+pub struct UpdateRequest {
+    /// The stored text to replace
+    pub old_text: String,
+    /// The text to embed and store in its place
+    pub new_text: String,
+    /// The type both texts are stored under, defaults to `DEFAULT_EMBEDDING_TYPE`
+    pub embedding_type: Option<String>,
+    /// Optional model name, defaults to the provider's default model. Must be in the allowlist
+    pub model: Option<String>,
+}
+
+#[derive(serde::Serialize, Clone, ToSchema)]
 pub struct StoreResponse {
     /// The generated embedding vector
     pub embedding: Vec<f64>,
     /// Whether the embedding was successfully stored
     pub stored: bool,
+    /// Length of the embedding vector
+    pub dimensions: usize,
+    /// Oldest entries dropped to stay within `MAX_STORED_ENTRIES`; omitted when none were
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub evicted: Vec<StoredItem>,
+    /// Whether the text was cut down by `truncation` before it was embedded and stored;
+    /// omitted when it wasn't
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub truncated: bool,
+    /// Timings, provider and model of the request; only with `verbose`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub meta: Option<ResponseMeta>,
+}
+
+/// How a request was served, returned as `meta` with `verbose`
+#[derive(serde::Serialize, Clone, Debug, ToSchema)]
+pub struct ResponseMeta {
+    /// Time the server took to handle the request, in milliseconds
+    pub took_ms: u64,
+    /// Of `took_ms`, the time spent waiting for and calling the embedding provider; 0 when
+    /// the provider wasn't called, e.g. on a cache hit
+    pub provider_ms: u64,
+    /// Of `took_ms`, the time spent scanning or writing the store
+    pub store_ms: u64,
+    /// The embedding provider the service uses
+    pub provider: String,
+    /// Model the text was embedded with; omitted when nothing was embedded
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    /// Stored vectors the query was scored against, counting an index or projection
+    /// candidate's rerank separately; compares only
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub candidates_scanned: Option<u64>,
+}
+
+impl ResponseMeta {
+    fn new(service: &EmbeddingService, start: Instant, phases: PhaseDurations, model: Option<String>) -> Self {
+        ResponseMeta {
+            took_ms: start.elapsed().as_millis() as u64,
+            provider_ms: phases.provider.as_millis() as u64,
+            store_ms: phases.store.as_millis() as u64,
+            provider: service.provider().name().to_string(),
+            model,
+            candidates_scanned: None,
+        }
+    }
 }
 
 #[derive(serde::Deserialize, ToSchema)]
-pub struct CompareRequest {
-    /// The text to compare with stored embeddings
+pub struct EmbedRequest {
+    /// The text to generate an embedding for
     pub text: String,
-    /// Optional model name, defaults to "text-embedding-3-large"
+    /// Optional model name, defaults to the provider's default model. Must be in the allowlist
+    pub model: Option<String>,
+    /// Optional shorter output length, for models that support it (`text-embedding-3-*`)
+    pub dimensions: Option<usize>,
+}
+
+#[derive(serde::Serialize, ToSchema)]
+pub struct EmbedResponse {
+    /// The generated embedding vector
+    pub embedding: Vec<f64>,
+    /// Model that generated it
+    pub model: String,
+    /// Length of the embedding vector
+    pub dimensions: usize,
+}
+
+#[derive(serde::Deserialize, ToSchema)]
+pub struct CompareRequest {
+    /// The text to compare with stored embeddings. Required unless `embedding` is given, in
+    /// which case it is only used to leave out entries with the same text
+    pub text: Option<String>,
+    /// A precomputed query vector, e.g. from another provider or a client-side cache, to use
+    /// instead of embedding `text`. Its length must match the stored vectors compared against
+    pub embedding: Option<Vec<f64>>,
+    /// Optional model name, defaults to the provider's default model. Must be in the allowlist
     pub model: Option<String>,
     /// Number of top results to return, defaults to all
     pub top_k: Option<usize>,
@@ -38,12 +185,203 @@ pub struct CompareRequest {
     pub include_embeddings: Option<bool>,
     /// The type of embedding to compare against (e.g., "user", "title", etc.)
     pub embedding_type: Option<String>,
+    /// Several types to compare against; combined with `embedding_type` if both are given
+    pub embedding_types: Option<Vec<String>>,
+    /// Optional shorter output length for the query; only entries of the same length are compared
+    pub dimensions: Option<usize>,
+    /// Only return results with at least this cosine similarity, applied before `top_k`
+    pub min_similarity: Option<f64>,
+    /// Only compare against entries stored with this model. Unlike `model`, which embeds the
+    /// query, this restricts the stored candidates
+    pub model_filter: Option<String>,
+    /// Whether to return the embedding computed for `text`
+    pub include_query_embedding: Option<bool>,
+    /// Round each similarity to this many decimal places, after ranking. Defaults to no rounding
+    pub round_to: Option<u32>,
+    /// Exclude entries whose text equals `text` under any type. By default they are only
+    /// excluded within the requested embedding types, and kept when no type is given
+    pub exclude_self: Option<bool>,
+    /// Drop results whose cosine similarity to a better-ranked result is above this, before
+    /// `top_k` is applied, so near-identical texts don't crowd out the rest
+    pub dedup_threshold: Option<f64>,
+    /// Only compare against entries whose metadata has all of these keys with exactly these
+    /// values, e.g. `{"source": "docs", "lang": "en"}`. Entries without metadata are skipped
+    #[schema(value_type = Option<Object>)]
+    pub filter: Option<serde_json::Map<String, serde_json::Value>>,
+    /// Factors to multiply the similarity of entries of some types by before ranking, e.g.
+    /// `{"title": 1.1}`; other types keep a factor of 1. The raw `similarity` is still
+    /// returned, with the product as `adjusted_similarity`
+    pub type_boosts: Option<HashMap<String, f64>>,
+    /// Only compare against entries stored at or after this RFC 3339 time. Entries stored
+    /// before timestamps were recorded are skipped by either time filter
+    pub created_after: Option<DateTime<Utc>>,
+    /// Only compare against entries stored before this RFC 3339 time
+    pub created_before: Option<DateTime<Utc>>,
+    /// Compare against the entries of this namespace instead of the default store
+    pub namespace: Option<String>,
+    /// Round the components of returned embeddings, including `query_embedding`, to this many
+    /// significant digits. Defaults to no rounding
+    pub float_precision: Option<u32>,
+    /// Return only the closest match, as `best`, instead of the `results` list; `top_k` is
+    /// then ignored. Defaults to false
+    pub best_only: Option<bool>,
+    /// Only return these fields of each result, e.g. `["text", "similarity"]`. Asking for
+    /// `embedding` includes the embeddings. Defaults to every field
+    pub fields: Option<Vec<ResultField>>,
+    /// Return the best `per_type_k` results of each embedding type, as `results_by_type`,
+    /// instead of the best `top_k` overall; `top_k` is then ignored
+    pub per_type_k: Option<usize>,
+    /// Only return the entries whose text is exactly `text`, without embedding it, so the
+    /// provider isn't called. Without it the provider is only skipped when the single best
+    /// result is asked for and an exact match is sure to be it. Defaults to false
+    pub exact_only: Option<bool>,
+    /// Rank by `hybrid_alpha * similarity + (1 - hybrid_alpha) * lexical_score`, mixing in
+    /// how well each entry's text matches the keywords of `text`. Between 0 and 1; 1 ranks by
+    /// similarity alone. Defaults to ranking by similarity
+    pub hybrid_alpha: Option<f64>,
+    /// Similarity function to score with: `cosine`, `dot`, `euclidean` or one registered on
+    /// the service. Defaults to cosine similarity
+    pub metric: Option<String>,
+    /// Only score the stored entries whose text is one of these, e.g. a shortlist from a
+    /// cheaper first stage. Texts that aren't stored are skipped
+    pub candidates: Option<Vec<String>>,
+    /// Add a `meta` object to the response with the request's timings, provider, model and
+    /// the number of vectors compared. Defaults to false; not supported by `/compare/batch`
+    pub verbose: Option<bool>,
+}
+
+/// A field of a `ComparisonResult`, for picking the fields `/compare` returns
+#[derive(serde::Deserialize, Clone, Copy, Debug, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ResultField {
+    Text,
+    Similarity,
+    Embedding,
+    EmbeddingType,
+    Model,
+    Metadata,
+    CreatedAt,
+    AdjustedSimilarity,
+    ExactMatch,
+    LexicalScore,
+}
+
+impl ResultField {
+    /// The field's key in a serialized result
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ResultField::Text => "text",
+            ResultField::Similarity => "similarity",
+            ResultField::Embedding => "embedding",
+            ResultField::EmbeddingType => "embedding_type",
+            ResultField::Model => "model",
+            ResultField::Metadata => "metadata",
+            ResultField::CreatedAt => "created_at",
+            ResultField::AdjustedSimilarity => "adjusted_similarity",
+            ResultField::ExactMatch => "exact_match",
+            ResultField::LexicalScore => "lexical_score",
+        }
+    }
+}
+
+#[derive(serde::Deserialize, ToSchema)]
+pub struct CompareByIdRequest {
+    /// The stored text to find neighbours of
+    pub text: String,
+    /// The type it is stored under, defaults to `DEFAULT_EMBEDDING_TYPE`; only entries of this
+    /// type are compared against
+    pub embedding_type: Option<String>,
+    /// Number of top results to return, defaults to all
+    pub top_k: Option<usize>,
+    /// Whether to include embeddings in the response
+    pub include_embeddings: Option<bool>,
+    /// Only return results with at least this cosine similarity, applied before `top_k`
+    pub min_similarity: Option<f64>,
+    /// Round the components of returned embeddings to this many significant digits. Defaults
+    /// to no rounding
+    pub float_precision: Option<u32>,
 }
 
 #[derive(serde::Serialize, ToSchema)]
 pub struct CompareResponse {
-    /// List of comparison results, sorted by similarity
+    /// List of comparison results, sorted by similarity. Empty when nothing matches
     pub results: Vec<ComparisonResult>,
+    /// The embedding computed for the query text, if requested
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub query_embedding: Option<Vec<f64>>,
+}
+
+#[derive(serde::Serialize, ToSchema)]
+pub struct BestMatchResponse {
+    /// The closest match, or `null` when nothing matches
+    pub best: Option<ComparisonResult>,
+    /// The embedding computed for the query text, if requested
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub query_embedding: Option<Vec<f64>>,
+}
+
+#[derive(serde::Serialize, ToSchema)]
+pub struct CompareByTypeResponse {
+    /// The results of each embedding type with a match, ranked within the type
+    pub results_by_type: BTreeMap<String, Vec<ComparisonResult>>,
+    /// The embedding computed for the query text, if requested
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub query_embedding: Option<Vec<f64>>,
+}
+
+/// Most queries a `/compare/batch` request may hold
+pub const MAX_COMPARE_BATCH_QUERIES: usize = 100;
+
+#[derive(serde::Deserialize, ToSchema)]
+pub struct CompareBatchRequest {
+    /// The comparisons to run, each with the fields of a `/compare` request
+    pub queries: Vec<CompareRequest>,
+}
+
+#[derive(serde::Serialize, ToSchema)]
+pub struct CompareBatchResponse {
+    /// What `/compare` would answer to each query, in the order of `queries`
+    pub results: Vec<CompareOutput>,
+}
+
+/// Response of `/compare`: the ranked results, only the best one with `best_only`, or the
+/// results of each type with `per_type_k`
+#[derive(serde::Serialize, ToSchema)]
+#[serde(untagged)]
+pub enum CompareOutput {
+    Results(CompareResponse),
+    Best(BestMatchResponse),
+    ByType(CompareByTypeResponse),
+    /// Either of the above with the results cut down to the requested `fields`
+    #[schema(value_type = Object)]
+    Selected(serde_json::Value),
+}
+
+impl CompareOutput {
+    /// Keep only `fields` in each result, leaving the rest of the response as it is
+    fn select_fields(self, fields: &[ResultField]) -> Self {
+        let mut response = serde_json::to_value(self).expect("responses serialize to JSON");
+        let keep = |result: &mut serde_json::Value| {
+            if let Some(result) = result.as_object_mut() {
+                result.retain(|key, _| fields.iter().any(|field| field.as_str() == key));
+            }
+        };
+        if let Some(serde_json::Value::Array(results)) = response.get_mut("results") {
+            results.iter_mut().for_each(keep);
+        } else if let Some(best) = response.get_mut("best") {
+            keep(best);
+        } else if let Some(serde_json::Value::Object(results_by_type)) = response.get_mut("results_by_type") {
+            results_by_type.values_mut().filter_map(serde_json::Value::as_array_mut).flatten().for_each(keep);
+        }
+        CompareOutput::Selected(response)
+    }
+
+    /// Add `meta` next to the results
+    fn with_meta(self, meta: ResponseMeta) -> Self {
+        let mut response = serde_json::to_value(self).expect("responses serialize to JSON");
+        response["meta"] = serde_json::to_value(meta).expect("responses serialize to JSON");
+        CompareOutput::Selected(response)
+    }
 }
 
 #[derive(serde::Serialize, ToSchema)]
@@ -57,122 +395,1578 @@ pub struct ComparisonResult {
     pub embedding: Option<Vec<f64>>,
     /// The type of the embedding
     pub embedding_type: String,
+    /// The model that produced the stored embedding; vectors of different models aren't
+    /// comparable, even when their lengths match
+    pub model: String,
+    /// The metadata stored with the entry, if any
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<Object>)]
+    pub metadata: Option<serde_json::Value>,
+    /// When the entry was stored; null for entries stored before timestamps were recorded
+    pub created_at: Option<DateTime<Utc>>,
+    /// The score results are ranked by instead of `similarity`, present when `type_boosts` or
+    /// `hybrid_alpha` is given: the blend of `similarity` and `lexical_score` with
+    /// `hybrid_alpha`, then times the boost for the entry's type
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub adjusted_similarity: Option<f64>,
+    /// How well the entry's text matches the query's keywords, from 0 to 1 for the best
+    /// match, present when `hybrid_alpha` is given
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub lexical_score: Option<f64>,
+    /// Whether the entry's text is exactly the query's, embedded by the model that embedded
+    /// it; its similarity is then 1. Omitted when it isn't
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub exact_match: bool,
+}
+
+impl ComparisonResult {
+    /// The score results are ranked by
+    pub fn ranking_score(&self) -> f64 {
+        self.adjusted_similarity.unwrap_or(self.similarity)
+    }
+}
+
+#[derive(serde::Deserialize, ToSchema)]
+pub struct SimilarityRequest {
+    /// The query text, always embedded
+    pub text_a: String,
+    /// The text to score the query against
+    pub text_b: Option<String>,
+    /// When set, `text_b` is looked up among the stored entries of this type instead of
+    /// being embedded
+    pub embedding_type: Option<String>,
+    /// Optional model name, defaults to the provider's default model. Must be in the allowlist
+    pub model: Option<String>,
+}
+
+#[derive(serde::Serialize, ToSchema)]
+pub struct SimilarityResponse {
+    /// Cosine similarity between the two texts
+    pub similarity: f64,
+}
+
+/// Most texts `/matrix` accepts in one request, bounding both the provider calls and the
+/// size of the matrix
+pub const MAX_MATRIX_TEXTS: usize = 200;
+
+/// How a pair of embeddings is scored
+#[derive(serde::Deserialize, Clone, Copy, Debug, Default, PartialEq, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum SimilarityMetric {
+    /// Cosine similarity, from -1 to 1
+    #[default]
+    Cosine,
+    /// Dot product, the same as cosine for normalized embeddings
+    Dot,
+    /// `1 / (1 + distance)` for the Euclidean distance, from 0 to 1, so higher is closer like
+    /// the others
+    Euclidean,
+}
+
+impl SimilarityMetric {
+    /// Score two vectors, or `None` when the metric is undefined for them
+    pub fn score(self, a: &[f64], b: &[f64]) -> Option<f64> {
+        match self {
+            SimilarityMetric::Cosine => cosine_similarity(a, b),
+            SimilarityMetric::Dot => dot_product(a, b),
+            SimilarityMetric::Euclidean => euclidean_distance(a, b).map(|distance| 1.0 / (1.0 + distance)),
+        }
+    }
+}
+
+#[derive(serde::Deserialize, ToSchema)]
+pub struct MatrixRequest {
+    /// The texts to score against each other, at most `MAX_MATRIX_TEXTS`
+    pub texts: Vec<String>,
+    /// Optional model name, defaults to the provider's default model. Must be in the allowlist
+    pub model: Option<String>,
+    /// How pairs are scored, defaults to cosine
+    pub metric: Option<SimilarityMetric>,
+}
+
+#[derive(serde::Serialize, ToSchema)]
+pub struct MatrixResponse {
+    /// The texts, in request order, labelling both the rows and the columns
+    pub labels: Vec<String>,
+    /// `matrix[i][j]` scores `labels[i]` against `labels[j]`. Null where the metric is
+    /// undefined, e.g. cosine with a zero vector
+    pub matrix: Vec<Vec<Option<f64>>>,
+}
+
+#[derive(serde::Serialize, ToSchema)]
+pub struct ModelInfo {
+    /// Model name to pass as `model`
+    pub name: String,
+    /// Length of the vectors the model produces, if known
+    pub dimensions: Option<usize>,
+    /// Provider serving the model
+    pub provider: String,
+    /// Whether this model is used when a request doesn't name one
+    pub default: bool,
+}
+
+#[derive(serde::Serialize, ToSchema)]
+pub struct ModelsResponse {
+    /// Models the server accepts
+    pub models: Vec<ModelInfo>,
+    /// Whether model names outside `models` are accepted too (no allowlist is configured)
+    pub any_model: bool,
+}
+
+#[derive(serde::Serialize, ToSchema)]
+pub struct StatsResponse {
+    /// Number of stored records
+    pub total: usize,
+    /// Records per `embedding_type`
+    pub by_type: HashMap<String, usize>,
+    /// Records per model
+    pub by_model: HashMap<String, usize>,
+    /// Records per vector dimension
+    pub dimensions: HashMap<usize, usize>,
+}
+
+#[derive(serde::Deserialize, IntoParams)]
+pub struct CountQuery {
+    /// Only count entries of this type
+    pub embedding_type: Option<String>,
+    /// Only count entries embedded with this model
+    pub model: Option<String>,
+}
+
+#[derive(serde::Serialize, ToSchema)]
+pub struct CountResponse {
+    /// Number of stored entries matching the filters
+    pub count: usize,
+}
+
+#[derive(serde::Serialize, ToSchema)]
+pub struct ReindexResponse {
+    /// Number of records read back from the data files
+    pub reloaded: usize,
+}
+
+#[derive(serde::Deserialize, ToSchema)]
+pub struct FitProjectionRequest {
+    /// Length of the projected vectors; below the stored vectors' length
+    pub dimensions: usize,
+}
+
+#[derive(serde::Serialize, ToSchema)]
+pub struct FitProjectionResponse {
+    /// Length of the vectors the projection was fitted to, the most common stored length
+    pub source_dimensions: usize,
+    /// Length of the projected vectors
+    pub dimensions: usize,
+    /// Number of stored vectors the projection was fitted to
+    pub vectors: usize,
+    /// Share of the vectors' squared norm the projection keeps, from 0 to 1. The closer to 1,
+    /// the closer projected similarities are to the exact ones
+    pub explained_variance: f64,
+}
+
+#[derive(serde::Deserialize, Default, ToSchema)]
+pub struct ClearRequest {
+    /// Only delete records of this type. Without it every record is deleted
+    pub embedding_type: Option<String>,
+    /// Clear this namespace instead of the default store; other stores are left alone
+    pub namespace: Option<String>,
+}
+
+/// Iterations `/cluster` runs when no `max_iters` is given
+pub const DEFAULT_CLUSTER_MAX_ITERS: usize = 100;
+
+#[derive(serde::Deserialize, ToSchema)]
+pub struct ClusterRequest {
+    /// Number of clusters to form; capped at the number of stored vectors
+    pub k: usize,
+    /// Only cluster entries of this type, defaults to all types
+    pub embedding_type: Option<String>,
+    /// Most assignment passes to run before stopping, defaults to 100
+    pub max_iters: Option<usize>,
+}
+
+#[derive(serde::Serialize, ToSchema)]
+pub struct CentroidSummary {
+    /// The member closest to the cluster's centroid, i.e. its most typical text
+    pub nearest_text: String,
+    /// Average cosine similarity of the members to the centroid; higher is tighter
+    pub mean_similarity: f64,
+}
+
+#[derive(serde::Serialize, ToSchema)]
+pub struct Cluster {
+    /// Describes the cluster's centroid through its members
+    pub centroid_summary: CentroidSummary,
+    /// Texts in the cluster, closest to the centroid first
+    pub members: Vec<String>,
+}
+
+#[derive(serde::Serialize, ToSchema)]
+pub struct ClusterResponse {
+    /// Non-empty clusters, largest first
+    pub clusters: Vec<Cluster>,
+    /// Entries left out because their vector is unreadable or of another length
+    pub skipped: usize,
+    /// Assignment passes run before the clusters settled or `max_iters` was hit
+    pub iterations: usize,
 }
 
 #[derive(serde::Serialize, ToSchema)]
 pub struct ClearResponse {
-    /// Whether the data was successfully cleared
+    /// Always true; a clear that fails gets an error response instead
     pub success: bool,
+    /// Number of records deleted, when clearing a single type
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub removed: Option<usize>,
+}
+
+/// Page size used by `/list` when no `limit` is given
+pub const DEFAULT_LIST_LIMIT: usize = 100;
+
+#[derive(serde::Deserialize, IntoParams)]
+pub struct ListQuery {
+    /// Number of entries to skip, defaults to 0
+    pub offset: Option<usize>,
+    /// Maximum number of entries to return, defaults to 100
+    pub limit: Option<usize>,
+    /// Only list entries of this type
+    pub embedding_type: Option<String>,
+    /// Only list entries stored at or after this RFC 3339 time. Entries stored before
+    /// timestamps were recorded are skipped by either time filter
+    pub created_after: Option<DateTime<Utc>>,
+    /// Only list entries stored before this RFC 3339 time
+    pub created_before: Option<DateTime<Utc>>,
+    /// Whether to include embeddings in the response
+    pub include_embeddings: Option<bool>,
+    /// Round the components of included embeddings to this many significant digits. Defaults
+    /// to no rounding
+    pub float_precision: Option<u32>,
+}
+
+#[derive(serde::Serialize, Clone, Debug, ToSchema)]
+pub struct StoredItem {
+    /// The stored text
+    pub text: String,
+    /// The model that produced the embedding
+    pub model: String,
+    /// The type of the embedding
+    pub embedding_type: String,
+    /// The embedding vector, if requested
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub embedding: Option<Vec<f64>>,
+    /// The metadata stored with the entry, if any
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<Object>)]
+    pub metadata: Option<serde_json::Value>,
+    /// When the entry was stored; null for entries stored before timestamps were recorded
+    pub created_at: Option<DateTime<Utc>>,
+}
+
+#[derive(serde::Serialize, ToSchema)]
+pub struct ListResponse {
+    /// The requested page of stored entries, in insertion order
+    pub items: Vec<StoredItem>,
+    /// Total number of entries matching the filter
+    pub total: usize,
+}
+
+#[derive(serde::Deserialize, IntoParams)]
+pub struct ExportQuery {
+    /// Output format, `jsonl` (default) or `csv`
+    pub format: Option<String>,
+}
+
+#[derive(serde::Serialize, ToSchema)]
+pub struct ImportResponse {
+    /// Number of records added to the store
+    pub imported: usize,
+    /// Number of records skipped because the text and type were already stored
+    pub skipped_duplicates: usize,
+    /// Number of lines skipped because they were not valid records
+    pub skipped_invalid: usize,
+    /// Number of the oldest records dropped to stay within `MAX_STORED_ENTRIES`
+    pub evicted: usize,
+}
+
+/// Records re-embedded per batch when `/reembed` doesn't set `batch_size`
+pub const DEFAULT_REEMBED_BATCH_SIZE: usize = 100;
+
+#[derive(serde::Deserialize, ToSchema)]
+pub struct ReembedRequest {
+    /// The model to re-embed with, defaults to the provider's default model. Must be in the allowlist
+    pub model: Option<String>,
+    /// Records embedded and written back per batch, defaults to 100
+    pub batch_size: Option<usize>,
+}
+
+#[derive(serde::Serialize, ToSchema)]
+pub struct ReembedProgress {
+    /// Records embedded so far
+    pub done: usize,
+    /// Records to embed, i.e. those not yet stored with the model
+    pub total: usize,
+}
+
+#[derive(serde::Serialize, ToSchema)]
+pub struct ReembedResponse {
+    /// Number of records re-embedded by this call
+    pub reembedded: usize,
+    /// Number of records skipped because they were already stored with the model
+    pub skipped: usize,
+    /// Number of records in the store when the call started
+    pub total: usize,
+}
+
+#[derive(serde::Serialize, ToSchema)]
+pub struct HealthResponse {
+    /// Always "ok" while the process is serving requests
+    pub status: String,
+}
+
+#[derive(serde::Serialize, ToSchema)]
+pub struct ReadinessResponse {
+    /// "ready" or "not_ready"
+    pub status: String,
+    /// What is preventing the service from being ready, if anything
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub problems: Vec<String>,
 }
 
+#[derive(serde::Serialize, ToSchema)]
+pub struct ErrorResponse {
+    /// Description of what was wrong with the request
+    pub error: String,
+}
+
+/// A request body that isn't valid JSON or doesn't match the request's fields
+#[derive(serde::Serialize, ToSchema)]
+pub struct RequestErrorResponse {
+    /// Description of what was wrong with the body
+    pub error: String,
+    /// Path to the offending field, e.g. `embedding_type` or `filter.lang`, when known
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub field: Option<String>,
+    /// What the field should hold, e.g. `a string`, when known
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expected: Option<String>,
+}
+
+/// Error returned by a handler, rendered as an `ErrorResponse` with the given status
+pub struct ApiError {
+    pub status: StatusCode,
+    pub message: String,
+}
+
+impl ApiError {
+    pub fn bad_request(message: impl Into<String>) -> Self {
+        Self { status: StatusCode::BAD_REQUEST, message: message.into() }
+    }
+
+    pub fn forbidden(message: impl Into<String>) -> Self {
+        Self { status: StatusCode::FORBIDDEN, message: message.into() }
+    }
+
+    pub fn not_found(message: impl Into<String>) -> Self {
+        Self { status: StatusCode::NOT_FOUND, message: message.into() }
+    }
+
+    pub fn conflict(message: impl Into<String>) -> Self {
+        Self { status: StatusCode::CONFLICT, message: message.into() }
+    }
+
+    pub fn internal(message: impl Into<String>) -> Self {
+        Self { status: StatusCode::INTERNAL_SERVER_ERROR, message: message.into() }
+    }
+}
+
+impl From<ProviderError> for ApiError {
+    fn from(e: ProviderError) -> Self {
+        match e {
+            ProviderError::NotConfigured(message) => Self::internal(format!("provider not configured: {}", message)),
+            e => Self::internal(format!("Failed to get embedding: {}", e)),
+        }
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        (self.status, Json(ErrorResponse { error: self.message })).into_response()
+    }
+}
+
+/// Rejects a `float_precision` of 0, which would leave no digits to return
+fn check_float_precision(float_precision: Option<u32>) -> Result<(), ApiError> {
+    match float_precision {
+        Some(0) => Err(ApiError::bad_request("float_precision must be at least 1")),
+        _ => Ok(()),
+    }
+}
+
+/// Round a returned embedding to `float_precision` significant digits, if one is given
+fn round_embedding(embedding: Vec<f64>, float_precision: Option<u32>) -> Vec<f64> {
+    match float_precision {
+        Some(digits) => embedding.into_iter().map(|value| round_significant(value, digits)).collect(),
+        None => embedding,
+    }
+}
+
+/// Round the similarities of `results` to `round_to` decimal places and their embeddings to
+/// `float_precision` significant digits, where given
+fn round_results(results: Vec<ComparisonResult>, round_to_places: Option<u32>, float_precision: Option<u32>) -> Vec<ComparisonResult> {
+    let results = match round_to_places {
+        Some(places) => results
+            .into_iter()
+            .map(|result| ComparisonResult {
+                similarity: round_to(result.similarity, places),
+                adjusted_similarity: result.adjusted_similarity.map(|adjusted| round_to(adjusted, places)),
+                ..result
+            })
+            .collect(),
+        None => results,
+    };
+    round_result_embeddings(results, float_precision)
+}
+
+/// `round_embedding` for the embeddings included with compare results
+fn round_result_embeddings(results: Vec<ComparisonResult>, float_precision: Option<u32>) -> Vec<ComparisonResult> {
+    if float_precision.is_none() {
+        return results;
+    }
+    results
+        .into_iter()
+        .map(|result| ComparisonResult {
+            embedding: result.embedding.map(|embedding| round_embedding(embedding, float_precision)),
+            ..result
+        })
+        .collect()
+}
+
+/// Header a client sets on `/store` so a retried request isn't stored twice
+pub const IDEMPOTENCY_KEY_HEADER: &str = "idempotency-key";
+
 /// Store a new text embedding
 #[utoipa::path(
     post,
     path = "/store",
     request_body = EmbeddingRequest,
+    params(
+        ("Idempotency-Key" = Option<String>, Header, description = "Optional key identifying this store across retries. A repeat of the request with the same key gets the first response back instead of embedding the text again")
+    ),
     responses(
-        (status = 200, description = "Embedding successfully stored", body = StoreResponse),
-        (status = 500, description = "Failed to generate or store embedding")
+        (status = 200, description = "Embedding successfully stored, or the response first given to this Idempotency-Key", body = StoreResponse),
+        (status = 400, description = "Text is empty or longer than MAX_TEXT_CHARS without truncation, model is not in the allowlist, dimensions is too large for the model, the dimension doesn't match the type with STRICT_DIMENSIONS, namespace is not a valid name, float_precision is 0, or Idempotency-Key is empty, too long or not visible ASCII", body = ErrorResponse),
+        (status = 409, description = "The first request with this Idempotency-Key is still in progress", body = ErrorResponse),
+        (status = 422, description = "Body is missing a field or has one of the wrong type, or the Idempotency-Key was used with a different request", body = RequestErrorResponse),
+        (status = 429, description = "Rate limit exceeded", body = ErrorResponse),
+        (status = 500, description = "Failed to generate or store embedding", body = ErrorResponse)
     ),
     tag = "embeddings"
 )]
 pub async fn store_embedding(
     State(embedding_service): State<Arc<EmbeddingService>>,
-    Json(payload): Json<EmbeddingRequest>,
-) -> Json<StoreResponse> {
-    let mut model = payload.model.unwrap_or_else(|| "text-embedding-3-large".to_string());
-    // check if model is one of text-embedding-3-large, text-embedding-3-small, text-embedding-3-base
-    // else default to text-embedding-3-large
-    if model != "text-embedding-3-large" && model != "text-embedding-3-small" && model != "text-embedding-3-base" {
-        model = "text-embedding-3-large".to_string();
+    headers: HeaderMap,
+    ValidJson(payload): ValidJson<EmbeddingRequest>,
+) -> Result<Json<StoreResponse>, ApiError> {
+    let start = Instant::now();
+    let embedding_service = embedding_service.namespace(payload.namespace.as_deref())
+        .map_err(ApiError::bad_request)?;
+    let verbose = payload.verbose.unwrap_or(false);
+    let model = embedding_service.resolve_model(payload.model.clone()).ok();
+    let (response, phases) = measure_phases(store_once(&embedding_service, &headers, payload)).await;
+    let mut response = response?;
+    if verbose {
+        response.meta = Some(ResponseMeta::new(&embedding_service, start, phases, model));
+    }
+    Ok(Json(response))
+}
+
+/// Store the text of a `/store` request, unless its `Idempotency-Key` was already handled,
+/// in which case the first response is returned again
+async fn store_once(embedding_service: &EmbeddingService, headers: &HeaderMap, payload: EmbeddingRequest) -> Result<StoreResponse, ApiError> {
+    let guard = match (idempotency_key(headers)?, embedding_service.idempotency_cache()) {
+        (Some(key), Some(cache)) => {
+            // The whole request identifies what the key was used for, so a different request
+            // reusing it is refused rather than answered with an unrelated response
+            let fingerprint = serde_json::to_string(&payload).unwrap_or_default();
+            match cache.claim(key, &fingerprint) {
+                Ok(Claim::Replay(response)) => {
+                    tracing::info!(key, "replaying store for idempotency key");
+                    return Ok(response);
+                }
+                Ok(Claim::New(guard)) => Some(guard),
+                Err(e @ IdempotencyError::InProgress) => return Err(ApiError::conflict(e.to_string())),
+                Err(e @ IdempotencyError::Mismatch) => {
+                    return Err(ApiError { status: StatusCode::UNPROCESSABLE_ENTITY, message: e.to_string() })
+                }
+            }
+        }
+        _ => None,
+    };
+
+    let response = store(embedding_service, payload).await?;
+    if let Some(guard) = guard {
+        guard.complete(response.clone());
     }
+    Ok(response)
+}
+
+/// The `Idempotency-Key` header, if the request has one
+fn idempotency_key(headers: &HeaderMap) -> Result<Option<&str>, ApiError> {
+    let Some(value) = headers.get(IDEMPOTENCY_KEY_HEADER) else {
+        return Ok(None);
+    };
+    value
+        .to_str()
+        .ok()
+        .filter(|key| !key.is_empty() && key.len() <= MAX_IDEMPOTENCY_KEY_LEN)
+        .map(Some)
+        .ok_or_else(|| ApiError::bad_request(format!(
+            "Idempotency-Key must be 1 to {} visible ASCII characters", MAX_IDEMPOTENCY_KEY_LEN
+        )))
+}
+
+/// Embed and store the text of a `/store` request
+async fn store(embedding_service: &EmbeddingService, payload: EmbeddingRequest) -> Result<StoreResponse, ApiError> {
+    // The limit approximates the model's token budget, so a truncated text is what the
+    // provider can take. It is stored as truncated, so the stored text is what was embedded
+    let text = payload.truncation.unwrap_or_default().apply(&payload.text, embedding_service.max_text_chars());
+    let truncated = text.len() < payload.text.len();
+    embedding_service.validate_text("text", text)
+        .map_err(ApiError::bad_request)?;
+    check_float_precision(payload.float_precision)?;
+    let model = embedding_service.resolve_model(payload.model)
+        .map_err(ApiError::bad_request)?;
+    let dimensions = embedding_service.resolve_dimensions(&model, payload.dimensions)
+        .map_err(ApiError::bad_request)?;
+    let embedding_type = embedding_service.resolve_embedding_type(payload.embedding_type);
     // Get embedding
-    let embedding_vec = embedding_service.get_embedding(&payload.text, &model).await
-        .expect("Failed to get embedding");
+    let embedding_vec = embedding_service.get_embedding(text, &model, dimensions, InputType::Document).await
+        .map_err(ApiError::from)?;
+
+    if payload.dry_run.unwrap_or(false) {
+        return Ok(StoreResponse {
+            dimensions: embedding_vec.len(),
+            embedding: round_embedding(embedding_vec, payload.float_precision),
+            stored: false,
+            evicted: Vec::new(),
+            truncated,
+            meta: None,
+        });
+    }
 
     // Save the new embedding
     let store_result = embedding_service.save_embedding(
-        &payload.text,
+        text,
         &embedding_vec,
         &model,
-        &payload.embedding_type
+        &embedding_type,
+        payload.metadata.as_ref()
     ).await;
 
     // Check if it was actually stored (not a duplicate)
-    let stored = match store_result {
-        Ok(_) => true,
-        Err(e) => {
-            if e.to_string().contains("duplicate") {
-                false
-            } else {
-                panic!("Failed to store embedding: {}", e)
-            }
-        }
+    let (stored, evicted) = match store_result {
+        Ok(evicted) => (true, evicted),
+        Err(e) => match e.downcast_ref::<StoreError>() {
+            Some(StoreError::Duplicate { .. }) => (false, Vec::new()),
+            Some(StoreError::DimensionMismatch { .. }) => return Err(ApiError::bad_request(e.to_string())),
+            _ => return Err(ApiError::internal(format!("Failed to store embedding: {}", e))),
+        },
     };
 
-    Json(StoreResponse {
-        embedding: embedding_vec,
+    Ok(StoreResponse {
+        dimensions: embedding_vec.len(),
+        embedding: round_embedding(embedding_vec, payload.float_precision),
         stored,
+        evicted,
+        truncated,
+        meta: None,
     })
 }
 
-/// Compare text with stored embeddings
+/// Generate a text's embedding and return it, without reading or writing the store
 #[utoipa::path(
     post,
-    path = "/compare",
-    request_body = CompareRequest,
+    path = "/embed",
+    request_body = EmbedRequest,
     responses(
-        (status = 200, description = "Comparison results", body = CompareResponse),
-        (status = 500, description = "Failed to generate embedding or compare")
+        (status = 200, description = "The text's embedding", body = EmbedResponse),
+        (status = 400, description = "Text is empty or longer than MAX_TEXT_CHARS, model is not in the allowlist, or dimensions is too large for the model", body = ErrorResponse),
+        (status = 422, description = "Body is missing a field or has one of the wrong type", body = RequestErrorResponse),
+        (status = 429, description = "Rate limit exceeded", body = ErrorResponse),
+        (status = 500, description = "Failed to generate embedding", body = ErrorResponse)
     ),
     tag = "embeddings"
 )]
-pub async fn compare_embedding(
+pub async fn embed(
     State(embedding_service): State<Arc<EmbeddingService>>,
-    Json(payload): Json<CompareRequest>,
-) -> Json<CompareResponse> {
-    let mut model = payload.model.unwrap_or_else(|| "text-embedding-3-large".to_string());
-    // check if model is one of text-embedding-3-large, text-embedding-3-small, text-embedding-3-base
-    // else default to text-embedding-3-large
-    if model != "text-embedding-3-large" && model != "text-embedding-3-small" && model != "text-embedding-3-base" {
-        model = "text-embedding-3-large".to_string();
+    ValidJson(payload): ValidJson<EmbedRequest>,
+) -> Result<Json<EmbedResponse>, ApiError> {
+    embedding_service.validate_text("text", &payload.text)
+        .map_err(ApiError::bad_request)?;
+    let model = embedding_service.resolve_model(payload.model)
+        .map_err(ApiError::bad_request)?;
+    let dimensions = embedding_service.resolve_dimensions(&model, payload.dimensions)
+        .map_err(ApiError::bad_request)?;
+    let embedding = embedding_service.get_embedding(&payload.text, &model, dimensions, InputType::Document).await
+        .map_err(ApiError::from)?;
+    Ok(Json(EmbedResponse { dimensions: embedding.len(), embedding, model }))
+}
+
+/// Re-embed an edited text, replacing its stored entry
+#[utoipa::path(
+    post,
+    path = "/update",
+    request_body = UpdateRequest,
+    responses(
+        (status = 200, description = "Entry replaced with the new text's embedding", body = StoreResponse),
+        (status = 400, description = "new_text is empty or longer than MAX_TEXT_CHARS, model is not in the allowlist, or the dimension doesn't match the type with STRICT_DIMENSIONS", body = ErrorResponse),
+        (status = 404, description = "old_text is not stored with the given type", body = ErrorResponse),
+        (status = 409, description = "new_text is already stored with the given type", body = ErrorResponse),
+        (status = 429, description = "Rate limit exceeded", body = ErrorResponse),
+        (status = 500, description = "Failed to generate or store embedding", body = ErrorResponse)
+    ),
+    tag = "embeddings"
+)]
+pub async fn update_embedding(
+    State(embedding_service): State<Arc<EmbeddingService>>,
+    Json(payload): Json<UpdateRequest>,
+) -> Result<Json<StoreResponse>, ApiError> {
+    embedding_service.validate_text("new_text", &payload.new_text)
+        .map_err(ApiError::bad_request)?;
+    let model = embedding_service.resolve_model(payload.model)
+        .map_err(ApiError::bad_request)?;
+    let embedding_type = embedding_service.resolve_embedding_type(payload.embedding_type);
+    let embedding_vec = embedding_service.get_embedding(&payload.new_text, &model, None, InputType::Document).await
+        .map_err(ApiError::from)?;
+
+    embedding_service.update_embedding(
+        &payload.old_text,
+        &payload.new_text,
+        &embedding_vec,
+        &model,
+        &embedding_type
+    ).await
+        .map_err(|e| match e.downcast_ref::<StoreError>() {
+            Some(StoreError::NotFound { .. }) => ApiError::not_found(e.to_string()),
+            Some(StoreError::Duplicate { .. }) => ApiError::conflict(e.to_string()),
+            Some(StoreError::DimensionMismatch { .. }) => ApiError::bad_request(e.to_string()),
+            None => ApiError::internal(format!("Failed to update embedding: {}", e)),
+        })?;
+
+    Ok(Json(StoreResponse {
+        dimensions: embedding_vec.len(),
+        embedding: embedding_vec,
+        stored: true,
+        evicted: Vec::new(),
+        truncated: false,
+        meta: None,
+    }))
+}
+
+/// Re-embed all stored texts with another model, e.g. after upgrading. Billable for every
+/// record not yet stored with the model; rerun after a failure to resume.
+#[utoipa::path(
+    post,
+    path = "/reembed",
+    request_body = ReembedRequest,
+    responses(
+        (status = 200, description = "Re-embedding summary", body = ReembedResponse),
+        (status = 400, description = "Model is not in the allowlist, or batch_size is 0", body = ErrorResponse),
+        (status = 500, description = "Failed to generate or store an embedding; finished batches are kept", body = ErrorResponse)
+    ),
+    tag = "embeddings"
+)]
+pub async fn reembed_all(
+    State(embedding_service): State<Arc<EmbeddingService>>,
+    Json(payload): Json<ReembedRequest>,
+) -> Result<Json<ReembedResponse>, ApiError> {
+    let (model, batch_size) = reembed_params(&embedding_service, payload)?;
+    let summary = embedding_service.reembed_all(&model, batch_size, |_| {}).await
+        .map_err(|e| ApiError::internal(format!("Failed to re-embed: {}", e)))?;
+    Ok(Json(summary))
+}
+
+/// Like `/reembed`, but streams server-sent events while it runs: a `progress` event
+/// (`ReembedProgress`) after each record, then a `summary` event (`ReembedResponse`) or an
+/// `error` event (`ErrorResponse`). The job finishes even if the client disconnects.
+#[utoipa::path(
+    post,
+    path = "/reembed/stream",
+    request_body = ReembedRequest,
+    responses(
+        (status = 200, description = "Event stream of progress events, then a summary or error event", content_type = "text/event-stream"),
+        (status = 400, description = "Model is not in the allowlist, or batch_size is 0", body = ErrorResponse)
+    ),
+    tag = "embeddings"
+)]
+pub async fn reembed_all_stream(
+    State(embedding_service): State<Arc<EmbeddingService>>,
+    Json(payload): Json<ReembedRequest>,
+) -> Result<Sse<impl tokio_stream::Stream<Item = Result<Event, Infallible>>>, ApiError> {
+    let (model, batch_size) = reembed_params(&embedding_service, payload)?;
+
+    let (events, receiver) = tokio::sync::mpsc::unbounded_channel();
+    tokio::spawn(async move {
+        let progress = |progress: ReembedProgress| {
+            let _ = events.send(Event::default().event("progress").json_data(progress));
+        };
+        let result = embedding_service.reembed_all(&model, batch_size, progress).await;
+        let event = match result {
+            Ok(summary) => Event::default().event("summary").json_data(summary),
+            Err(e) => Event::default()
+                .event("error")
+                .json_data(ErrorResponse { error: format!("Failed to re-embed: {}", e) }),
+        };
+        let _ = events.send(event);
+    });
+
+    let stream = UnboundedReceiverStream::new(receiver)
+        .map(|event| Ok(event.expect("events serialize to JSON")));
+    Ok(Sse::new(stream).keep_alive(KeepAlive::default()))
+}
+
+/// The model and batch size a re-embedding request asks for, validated
+fn reembed_params(embedding_service: &EmbeddingService, payload: ReembedRequest) -> Result<(String, usize), ApiError> {
+    let model = embedding_service.resolve_model(payload.model)
+        .map_err(ApiError::bad_request)?;
+    let batch_size = payload.batch_size.unwrap_or(DEFAULT_REEMBED_BATCH_SIZE);
+    if batch_size == 0 {
+        return Err(ApiError::bad_request("batch_size must be at least 1"));
+    }
+    Ok((model, batch_size))
+}
+
+/// Compare text with stored embeddings
+#[utoipa::path(
+    post,
+    path = "/compare",
+    request_body = CompareRequest,
+    responses(
+        (status = 200, description = "Comparison results, only the best match with best_only, or the results of each type with per_type_k", body = CompareOutput),
+        (status = 400, description = "Text is empty or longer than MAX_TEXT_CHARS, model is not in the allowlist, dimensions is too large for the model, embedding_types is empty, embedding doesn't match the stored dimensions, namespace is not a valid name, float_precision or per_type_k is 0, or per_type_k is combined with best_only", body = ErrorResponse),
+        (status = 422, description = "Body is missing a field or has one of the wrong type, or fields names an unknown field", body = RequestErrorResponse),
+        (status = 429, description = "Rate limit exceeded", body = ErrorResponse),
+        (status = 500, description = "Failed to generate embedding or compare", body = ErrorResponse)
+    ),
+    tag = "embeddings"
+)]
+pub async fn compare_embedding(
+    State(embedding_service): State<Arc<EmbeddingService>>,
+    ValidJson(payload): ValidJson<CompareRequest>,
+) -> Result<Json<CompareOutput>, ApiError> {
+    run_compare(&embedding_service, payload).await.map(Json)
+}
+
+/// Answer one compare request; shared by `/compare` and `/ws/compare`
+async fn run_compare(embedding_service: &Arc<EmbeddingService>, payload: CompareRequest) -> Result<CompareOutput, ApiError> {
+    let start = Instant::now();
+    let verbose = payload.verbose.unwrap_or(false);
+    let compare = PreparedCompare::new(embedding_service, payload)?;
+    let model = compare.embed_with.as_ref().map(|(model, _)| model.clone());
+    let (output, phases) = measure_phases(compare.run()).await;
+    let output = output?;
+    if !verbose {
+        return Ok(output);
     }
-    let include_embeddings = payload.include_embeddings.unwrap_or(false);
+    let meta = ResponseMeta { candidates_scanned: Some(phases.compared), ..ResponseMeta::new(embedding_service, start, phases, model) };
+    Ok(output.with_meta(meta))
+}
+
+fn compare_error(e: Box<dyn std::error::Error>) -> ApiError {
+    ApiError::internal(format!("Failed to compare embeddings: {}", e))
+}
+
+/// A validated compare request, with the store it runs against
+struct PreparedCompare {
+    /// The service of the request's namespace, or `None` for a namespace with no store yet,
+    /// which has nothing to compare against
+    service: Option<Arc<EmbeddingService>>,
+    /// The comparison to run. Its `embedding` is still `None` while the text awaits embedding
+    query: CompareQuery,
+    /// The model and output length to embed the text with, unless the request gave a vector
+    /// or only wants exact matches
+    embed_with: Option<(String, Option<usize>)>,
+    best_only: bool,
+    include_query_embedding: bool,
+    round_to: Option<u32>,
+    float_precision: Option<u32>,
+    fields: Option<Vec<ResultField>>,
+}
+
+impl PreparedCompare {
+    /// Check a compare request, resolving its namespace and how its query vector is obtained
+    fn new(embedding_service: &Arc<EmbeddingService>, payload: CompareRequest) -> Result<Self, ApiError> {
+        let service = embedding_service.existing_namespace(payload.namespace.as_deref())
+            .map_err(ApiError::bad_request)?;
+        let text = payload.text.unwrap_or_default();
+        if payload.embedding.is_none() || !text.is_empty() {
+            embedding_service.validate_text("text", &text)
+                .map_err(ApiError::bad_request)?;
+        }
+        check_float_precision(payload.float_precision)?;
+        let include_embeddings = payload.include_embeddings.unwrap_or(false)
+            || payload.fields.as_ref().is_some_and(|fields| fields.contains(&ResultField::Embedding));
+        let embedding_types = match (payload.embedding_types, payload.embedding_type) {
+            (None, None) => None,
+            (types, single) => {
+                let types: Vec<String> = types.unwrap_or_default().into_iter().chain(single).collect();
+                if types.is_empty() {
+                    return Err(ApiError::bad_request("embedding_types must not be empty"));
+                }
+                Some(types)
+            }
+        };
+        if let Some((embedding_type, boost)) = payload.type_boosts.iter().flatten().find(|(_, boost)| !boost.is_finite() || **boost < 0.0) {
+            return Err(ApiError::bad_request(format!("type_boosts for {} must be a non-negative number, got {}", embedding_type, boost)));
+        }
+        if let Some(alpha) = payload.hybrid_alpha.filter(|alpha| !(0.0..=1.0).contains(alpha)) {
+            return Err(ApiError::bad_request(format!("hybrid_alpha must be between 0 and 1, got {}", alpha)));
+        }
+        if let Some(metric) = payload.metric.as_ref().filter(|metric| !embedding_service.similarity_functions().contains(metric)) {
+            return Err(ApiError::bad_request(format!(
+                "unknown metric {}, expected one of {}",
+                metric,
+                embedding_service.similarity_functions().join(", ")
+            )));
+        }
+        let best_only = payload.best_only.unwrap_or(false);
+        match payload.per_type_k {
+            Some(0) => return Err(ApiError::bad_request("per_type_k must be at least 1")),
+            Some(_) if best_only => return Err(ApiError::bad_request("best_only and per_type_k can't be used together")),
+            _ => {}
+        }
+        let exact_only = payload.exact_only.unwrap_or(false);
+        if exact_only {
+            if text.is_empty() {
+                return Err(ApiError::bad_request("text is required with exact_only"));
+            }
+            if payload.exclude_self.unwrap_or(false) || payload.per_type_k.is_some() {
+                return Err(ApiError::bad_request("exact_only can't be used with exclude_self or per_type_k"));
+            }
+        }
 
-    // Get embedding for the input text
-    let embedding_vec = embedding_service.get_embedding(&payload.text, &model).await
-        .expect("Failed to get embedding");
+        let mut filter = CompareFilter {
+            embedding_types,
+            model: payload.model_filter,
+            exclude_self: payload.exclude_self.unwrap_or(false),
+            dedup_threshold: payload.dedup_threshold,
+            metadata: payload.filter.filter(|filter| !filter.is_empty()),
+            type_boosts: payload.type_boosts,
+            hybrid_alpha: payload.hybrid_alpha,
+            metric: payload.metric,
+            candidates: payload.candidates.map(|candidates| candidates.into_iter().collect()),
+            created: CreatedRange {
+                after: payload.created_after,
+                before: payload.created_before,
+            },
+            query_model: None,
+        };
 
-    // Compare with stored embeddings
+        let mut embed_with = None;
+        let embedding = match payload.embedding {
+            _ if exact_only => None,
+            Some(embedding) => {
+                if embedding.is_empty() || embedding.iter().any(|value| !value.is_finite()) {
+                    return Err(ApiError::bad_request("embedding must be a non-empty list of finite numbers"));
+                }
+                let stored = match &service {
+                    Some(service) => service.stored_dimensions(filter.embedding_types.as_deref())
+                        .map_err(|e| ApiError::internal(format!("Failed to read embeddings: {}", e)))?,
+                    None => Vec::new(),
+                };
+                if !stored.is_empty() && !stored.contains(&embedding.len()) {
+                    return Err(ApiError::bad_request(format!(
+                        "embedding has {} dimensions, but the stored vectors compared against have {:?}",
+                        embedding.len(), stored
+                    )));
+                }
+                Some(embedding)
+            }
+            None => {
+                let model = embedding_service.resolve_model(payload.model)
+                    .map_err(ApiError::bad_request)?;
+                let dimensions = embedding_service.resolve_dimensions(&model, payload.dimensions)
+                    .map_err(ApiError::bad_request)?;
+                filter.query_model = Some(model.clone());
+                embed_with = Some((model, dimensions));
+                None
+            }
+        };
+
+        Ok(Self {
+            service,
+            query: CompareQuery {
+                text,
+                embedding,
+                // Only one result is needed, so the comparison keeps a single best candidate
+                top_k: if best_only { Some(1) } else { payload.top_k },
+                per_type_k: payload.per_type_k,
+                include_embeddings,
+                filter,
+                min_similarity: payload.min_similarity,
+            },
+            embed_with,
+            best_only,
+            include_query_embedding: payload.include_query_embedding.unwrap_or(false),
+            round_to: payload.round_to,
+            float_precision: payload.float_precision,
+            fields: payload.fields,
+        })
+    }
+
+    /// Embed the query's text if needed, then rank the stored entries against it. A namespace
+    /// with no store yet has no entries, so nothing is embedded.
+    async fn run(mut self) -> Result<CompareOutput, ApiError> {
+        let Some(service) = self.service.clone() else {
+            return Ok(self.output(Vec::new()));
+        };
+        if let Some((model, dimensions)) = &self.embed_with {
+            // A stored copy of the text that is sure to rank first answers the query without
+            // calling the provider
+            if !self.include_query_embedding {
+                if let Some(best) = service.best_exact_match(&self.query, *dimensions).map_err(compare_error)? {
+                    return Ok(self.output(vec![best]));
+                }
+            }
+            let embedding = service.get_embedding(&self.query.text, model, *dimensions, InputType::Query).await
+                .map_err(ApiError::from)?;
+            self.query.embedding = Some(embedding);
+        }
+
+        let query = &self.query;
+        let results = match (&query.embedding, query.per_type_k) {
+            // Exact matches are found by their text alone, so the provider isn't called
+            (None, _) => service.find_exact_matches(&query.text, query.top_k, query.include_embeddings, query.filter.clone()),
+            (Some(embedding), Some(per_type_k)) => service
+                .compare_embeddings_by_type(&query.text, embedding, per_type_k, query.include_embeddings, query.filter.clone(), query.min_similarity)
+                .await
+                .map(|results_by_type| results_by_type.into_values().flatten().collect()),
+            (Some(embedding), None) => service
+                .compare_embeddings(&query.text, embedding, query.top_k, query.include_embeddings, query.filter.clone(), query.min_similarity)
+                .await,
+        }
+        .map_err(compare_error)?;
+        Ok(self.output(results))
+    }
+
+    /// Shape the ranked results of the query into the response the request asked for
+    fn output(&self, results: Vec<ComparisonResult>) -> CompareOutput {
+        let results = round_results(results, self.round_to, self.float_precision);
+        let query_embedding = self.query.embedding.clone()
+            .filter(|_| self.include_query_embedding)
+            .map(|embedding| round_embedding(embedding, self.float_precision));
+        let output = if self.query.per_type_k.is_some() {
+            let mut results_by_type: BTreeMap<String, Vec<ComparisonResult>> = BTreeMap::new();
+            for result in results {
+                results_by_type.entry(result.embedding_type.clone()).or_default().push(result);
+            }
+            CompareOutput::ByType(CompareByTypeResponse { results_by_type, query_embedding })
+        } else if self.best_only {
+            CompareOutput::Best(BestMatchResponse { best: results.into_iter().next(), query_embedding })
+        } else {
+            CompareOutput::Results(CompareResponse { results, query_embedding })
+        };
+        match &self.fields {
+            Some(fields) => output.select_fields(fields),
+            None => output,
+        }
+    }
+}
+
+/// Run several compare requests at once
+#[utoipa::path(
+    post,
+    path = "/compare/batch",
+    request_body = CompareBatchRequest,
+    responses(
+        (status = 200, description = "The response to each query, in order", body = CompareBatchResponse),
+        (status = 400, description = "queries is empty or holds more than MAX_COMPARE_BATCH_QUERIES, or a query is invalid as for /compare; the message starts with its position, e.g. queries[2]", body = ErrorResponse),
+        (status = 422, description = "Body is missing a field or has one of the wrong type", body = RequestErrorResponse),
+        (status = 429, description = "Rate limit exceeded", body = ErrorResponse),
+        (status = 500, description = "Failed to generate embeddings or compare", body = ErrorResponse)
+    ),
+    tag = "embeddings"
+)]
+pub async fn compare_batch(
+    State(embedding_service): State<Arc<EmbeddingService>>,
+    ValidJson(payload): ValidJson<CompareBatchRequest>,
+) -> Result<Json<CompareBatchResponse>, ApiError> {
+    if payload.queries.is_empty() {
+        return Err(ApiError::bad_request("queries must not be empty"));
+    }
+    if payload.queries.len() > MAX_COMPARE_BATCH_QUERIES {
+        return Err(ApiError::bad_request(format!(
+            "queries holds {} queries, more than the limit of {}",
+            payload.queries.len(), MAX_COMPARE_BATCH_QUERIES
+        )));
+    }
+    if let Some(i) = payload.queries.iter().position(|query| query.verbose.unwrap_or(false)) {
+        return Err(ApiError::bad_request(format!("queries[{}]: verbose is not supported in a batch", i)));
+    }
+    let mut compares = payload.queries.into_iter()
+        .enumerate()
+        .map(|(i, query)| PreparedCompare::new(&embedding_service, query)
+            .map_err(|e| ApiError { message: format!("queries[{}]: {}", i, e.message), ..e }))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    // The texts to embed with the same model and length go to the provider in one call
+    let mut to_embed: BTreeMap<(String, Option<usize>), Vec<usize>> = BTreeMap::new();
+    for (i, compare) in compares.iter().enumerate().filter(|(_, compare)| compare.service.is_some()) {
+        if let Some(embed_with) = &compare.embed_with {
+            to_embed.entry(embed_with.clone()).or_default().push(i);
+        }
+    }
+    for ((model, dimensions), indexes) in to_embed {
+        let texts: Vec<String> = indexes.iter().map(|&i| compares[i].query.text.clone()).collect();
+        let embeddings = embedding_service.get_embeddings(&texts, &model, dimensions, InputType::Query).await
+            .map_err(ApiError::from)?;
+        for (i, embedding) in indexes.into_iter().zip(embeddings) {
+            compares[i].query.embedding = Some(embedding);
+        }
+    }
+
+    // The queries against each namespace are scored together, with one read of its store,
+    // and a namespace with no store yet has no results
+    let mut by_store: Vec<(Arc<EmbeddingService>, Vec<usize>)> = Vec::new();
+    let mut outputs: Vec<Option<CompareOutput>> = compares.iter().map(|_| None).collect();
+    for (i, compare) in compares.iter().enumerate() {
+        let Some(service) = &compare.service else {
+            outputs[i] = Some(compare.output(Vec::new()));
+            continue;
+        };
+        match by_store.iter_mut().find(|(stored, _)| Arc::ptr_eq(stored, service)) {
+            Some((_, indexes)) => indexes.push(i),
+            None => by_store.push((service.clone(), vec![i])),
+        }
+    }
+    for (service, indexes) in by_store {
+        let queries: Vec<CompareQuery> = indexes.iter().map(|&i| compares[i].query.clone()).collect();
+        let results = service.compare_embeddings_batch(&queries).await
+            .map_err(compare_error)?;
+        for (i, results) in indexes.into_iter().zip(results) {
+            outputs[i] = Some(compares[i].output(results));
+        }
+    }
+    let results = outputs.into_iter().map(|output| output.expect("every query belongs to a store")).collect();
+    Ok(Json(CompareBatchResponse { results }))
+}
+
+/// Compare a stored entry with the other entries of its type, using its stored vector, so
+/// the provider isn't called
+#[utoipa::path(
+    post,
+    path = "/compare_by_id",
+    request_body = CompareByIdRequest,
+    responses(
+        (status = 200, description = "Comparison results, without the entry itself", body = CompareResponse),
+        (status = 400, description = "float_precision is 0", body = ErrorResponse),
+        (status = 404, description = "text is not stored with the given type", body = ErrorResponse),
+        (status = 500, description = "Failed to read or compare embeddings", body = ErrorResponse)
+    ),
+    tag = "embeddings"
+)]
+pub async fn compare_by_id(
+    State(embedding_service): State<Arc<EmbeddingService>>,
+    Json(payload): Json<CompareByIdRequest>,
+) -> Result<Json<CompareResponse>, ApiError> {
+    check_float_precision(payload.float_precision)?;
+    let embedding_type = embedding_service.resolve_embedding_type(payload.embedding_type);
+    let embedding = embedding_service.find_stored_embedding(&payload.text, &embedding_type)
+        .map_err(|e| ApiError::internal(format!("Failed to read embeddings: {}", e)))?
+        .ok_or_else(|| ApiError::not_found(format!("no stored embedding for '{}' with type {}", payload.text, embedding_type)))?;
+
+    // Scoring within the entry's own type skips the entry itself
     let results = embedding_service.compare_embeddings(
         &payload.text,
-        &embedding_vec,
+        &embedding,
         payload.top_k,
-        include_embeddings,
-        payload.embedding_type
+        payload.include_embeddings.unwrap_or(false),
+        CompareFilter {
+            embedding_types: Some(vec![embedding_type]),
+            ..CompareFilter::default()
+        },
+        payload.min_similarity
     ).await
-        .expect("Failed to compare embeddings");
+        .map_err(|e| ApiError::internal(format!("Failed to compare embeddings: {}", e)))?;
 
-    Json(CompareResponse {
-        results
+    let results = round_result_embeddings(results, payload.float_precision);
+    Ok(Json(CompareResponse { results, query_embedding: None }))
+}
+
+/// Compare over a WebSocket, e.g. for search-as-you-type. Each text frame holds a
+/// `CompareRequest` and is answered with a `CompareResponse` frame (a `BestMatchResponse` one
+/// with `best_only`), or an `ErrorResponse` frame if the request is malformed or fails; the
+/// socket stays open either way.
+#[utoipa::path(
+    get,
+    path = "/ws/compare",
+    responses(
+        (status = 101, description = "Switched to the WebSocket protocol"),
+        (status = 429, description = "Rate limit exceeded", body = ErrorResponse)
+    ),
+    tag = "embeddings"
+)]
+pub async fn compare_ws(
+    State(embedding_service): State<Arc<EmbeddingService>>,
+    ws: WebSocketUpgrade,
+) -> Response {
+    ws.on_upgrade(move |socket| handle_compare_socket(socket, embedding_service))
+}
+
+async fn handle_compare_socket(mut socket: WebSocket, embedding_service: Arc<EmbeddingService>) {
+    // Requests on one socket are answered in order, one at a time
+    while let Some(Ok(message)) = socket.recv().await {
+        let reply = match message {
+            Message::Text(text) => match serde_json::from_str::<CompareRequest>(&text) {
+                Ok(payload) => match run_compare(&embedding_service, payload).await {
+                    Ok(response) => serde_json::to_string(&response),
+                    Err(e) => serde_json::to_string(&ErrorResponse { error: e.message }),
+                },
+                Err(e) => serde_json::to_string(&ErrorResponse { error: format!("invalid compare request: {}", e) }),
+            },
+            Message::Binary(_) => serde_json::to_string(&ErrorResponse { error: "expected a text frame".to_string() }),
+            Message::Close(_) => break,
+            // Pings are answered automatically
+            Message::Ping(_) | Message::Pong(_) => continue,
+        };
+        let reply = reply.expect("responses serialize to JSON");
+        if socket.send(Message::Text(reply)).await.is_err() {
+            break;
+        }
+    }
+}
+
+/// Score a query against one other text, either embedded on the fly or looked up in the store
+#[utoipa::path(
+    post,
+    path = "/similarity",
+    request_body = SimilarityRequest,
+    responses(
+        (status = 200, description = "Similarity between the two texts", body = SimilarityResponse),
+        (status = 400, description = "Missing text_b or model not in the allowlist", body = ErrorResponse),
+        (status = 404, description = "text_b is not stored with the given type", body = ErrorResponse),
+        (status = 429, description = "Rate limit exceeded", body = ErrorResponse),
+        (status = 500, description = "Failed to generate embedding or read the store", body = ErrorResponse)
+    ),
+    tag = "embeddings"
+)]
+pub async fn similarity(
+    State(embedding_service): State<Arc<EmbeddingService>>,
+    Json(payload): Json<SimilarityRequest>,
+) -> Result<Json<SimilarityResponse>, ApiError> {
+    let model = embedding_service.resolve_model(payload.model)
+        .map_err(ApiError::bad_request)?;
+    let text_b = payload.text_b
+        .ok_or_else(|| ApiError::bad_request("text_b is required"))?;
+
+    let embedding_a = embedding_service.get_embedding(&payload.text_a, &model, None, InputType::Query).await
+        .map_err(ApiError::from)?;
+
+    let embedding_b = match payload.embedding_type {
+        Some(embedding_type) => embedding_service.find_stored_embedding(&text_b, &embedding_type)
+            .map_err(|e| ApiError::internal(format!("Failed to read embeddings: {}", e)))?
+            .ok_or_else(|| ApiError::not_found(format!("no stored embedding for '{}' with type {}", text_b, embedding_type)))?,
+        None => embedding_service.get_embedding(&text_b, &model, None, InputType::Document).await
+            .map_err(ApiError::from)?,
+    };
+
+    let similarity = cosine_similarity(&embedding_a, &embedding_b)
+        .ok_or_else(|| ApiError::bad_request("similarity is undefined for these embeddings (zero vector or dimension mismatch)"))?;
+    Ok(Json(SimilarityResponse { similarity }))
+}
+
+/// Score every pair of the given texts against each other, without touching the store
+#[utoipa::path(
+    post,
+    path = "/matrix",
+    request_body = MatrixRequest,
+    responses(
+        (status = 200, description = "Pairwise scores of the texts", body = MatrixResponse),
+        (status = 400, description = "No texts, more than MAX_MATRIX_TEXTS, an empty or overlong text, or model not in the allowlist", body = ErrorResponse),
+        (status = 429, description = "Rate limit exceeded", body = ErrorResponse),
+        (status = 500, description = "Failed to generate an embedding", body = ErrorResponse)
+    ),
+    tag = "embeddings"
+)]
+pub async fn similarity_matrix(
+    State(embedding_service): State<Arc<EmbeddingService>>,
+    Json(payload): Json<MatrixRequest>,
+) -> Result<Json<MatrixResponse>, ApiError> {
+    if payload.texts.is_empty() {
+        return Err(ApiError::bad_request("texts must not be empty"));
+    }
+    if payload.texts.len() > MAX_MATRIX_TEXTS {
+        return Err(ApiError::bad_request(format!(
+            "texts has {} entries, over the limit of {}",
+            payload.texts.len(), MAX_MATRIX_TEXTS
+        )));
+    }
+    for (i, text) in payload.texts.iter().enumerate() {
+        embedding_service.validate_text(&format!("texts[{}]", i), text)
+            .map_err(ApiError::bad_request)?;
+    }
+    let model = embedding_service.resolve_model(payload.model)
+        .map_err(ApiError::bad_request)?;
+    let metric = payload.metric.unwrap_or_default();
+
+    // Embedded in one provider call; cached and repeated texts aren't sent
+    let embeddings = embedding_service.get_embeddings(&payload.texts, &model, None, InputType::Document).await?;
+
+    // Every metric is symmetric, so only the upper triangle is computed and then mirrored
+    let n = embeddings.len();
+    let mut matrix = vec![vec![None; n]; n];
+    for i in 0..n {
+        for j in i..n {
+            let score = metric.score(&embeddings[i], &embeddings[j]);
+            matrix[i][j] = score;
+            matrix[j][i] = score;
+        }
+    }
+
+    Ok(Json(MatrixResponse { labels: payload.texts, matrix }))
+}
+
+/// List the models the server accepts
+#[utoipa::path(
+    get,
+    path = "/models",
+    responses(
+        (status = 200, description = "Accepted models and their output dimensions", body = ModelsResponse)
+    ),
+    tag = "embeddings"
+)]
+pub async fn list_models(
+    State(embedding_service): State<Arc<EmbeddingService>>,
+) -> Json<ModelsResponse> {
+    Json(ModelsResponse {
+        models: embedding_service.models(),
+        any_model: !embedding_service.has_model_allowlist(),
     })
 }
 
-/// Clear all stored embeddings
+/// Summarize the stored records by type, model and dimension
+#[utoipa::path(
+    get,
+    path = "/stats",
+    responses(
+        (status = 200, description = "Counts of the stored records", body = StatsResponse),
+        (status = 500, description = "Failed to read stored embeddings", body = ErrorResponse)
+    ),
+    tag = "embeddings"
+)]
+pub async fn stats(
+    State(embedding_service): State<Arc<EmbeddingService>>,
+) -> Result<Json<StatsResponse>, ApiError> {
+    let stats = embedding_service.stats().await
+        .map_err(|e| ApiError::internal(format!("Failed to read embeddings: {}", e)))?;
+    Ok(Json(stats))
+}
+
+/// Count the stored entries, optionally only those of a type and/or model, without comparing
+/// anything
+#[utoipa::path(
+    get,
+    path = "/count",
+    params(CountQuery),
+    responses(
+        (status = 200, description = "Number of matching entries", body = CountResponse),
+        (status = 500, description = "Failed to read stored embeddings", body = ErrorResponse)
+    ),
+    tag = "embeddings"
+)]
+pub async fn count_embeddings(
+    State(embedding_service): State<Arc<EmbeddingService>>,
+    Query(query): Query<CountQuery>,
+) -> Result<Json<CountResponse>, ApiError> {
+    let count = embedding_service.count_embeddings(query.embedding_type.as_deref(), query.model.as_deref())
+        .map_err(|e| ApiError::internal(format!("Failed to read embeddings: {}", e)))?;
+    Ok(Json(CountResponse { count }))
+}
+
+/// Group stored embeddings into clusters of similar texts with k-means
+#[utoipa::path(
+    post,
+    path = "/cluster",
+    request_body = ClusterRequest,
+    responses(
+        (status = 200, description = "Clusters of stored texts", body = ClusterResponse),
+        (status = 400, description = "k is 0", body = ErrorResponse),
+        (status = 500, description = "Failed to read stored embeddings", body = ErrorResponse)
+    ),
+    tag = "embeddings"
+)]
+pub async fn cluster_embeddings(
+    State(embedding_service): State<Arc<EmbeddingService>>,
+    Json(payload): Json<ClusterRequest>,
+) -> Result<Json<ClusterResponse>, ApiError> {
+    if payload.k == 0 {
+        return Err(ApiError::bad_request("k must be at least 1"));
+    }
+    let max_iters = payload.max_iters.unwrap_or(DEFAULT_CLUSTER_MAX_ITERS);
+    let response = embedding_service
+        .cluster_embeddings(payload.k, payload.embedding_type.as_deref(), max_iters)
+        .await
+        .map_err(|e| ApiError::internal(format!("Failed to cluster embeddings: {}", e)))?;
+    Ok(Json(response))
+}
+
+/// Reload the stored records from disk and rebuild the in-memory indexes and counts, for the
+/// default store and every namespace in use. Needed after the data files were edited or
+/// restored while the server was running, which it otherwise only notices on restart.
+#[utoipa::path(
+    post,
+    path = "/reindex",
+    responses(
+        (status = 200, description = "Stores reloaded", body = ReindexResponse),
+        (status = 500, description = "Failed to read the stored records", body = ErrorResponse)
+    ),
+    tag = "embeddings"
+)]
+pub async fn reindex(
+    State(embedding_service): State<Arc<EmbeddingService>>,
+) -> Result<Json<ReindexResponse>, ApiError> {
+    let reloaded = embedding_service.reindex()
+        .map_err(|e| ApiError::internal(format!("Failed to reindex embeddings: {}", e)))?;
+    Ok(Json(ReindexResponse { reloaded }))
+}
+
+/// Fit a PCA projection of the stored vectors to fewer dimensions and store every record's
+/// projected vector alongside the full one. With `USE_PROJECTION=true`, `top_k` comparisons
+/// then rank candidates by their projected vectors and rerank only those exactly.
+#[utoipa::path(
+    post,
+    path = "/fit_projection",
+    request_body = FitProjectionRequest,
+    responses(
+        (status = 200, description = "Projection fitted and stored vectors projected", body = FitProjectionResponse),
+        (status = 400, description = "No vectors are stored, too few of them, or dimensions is not below their length", body = ErrorResponse),
+        (status = 500, description = "Failed to read or rewrite the stored records", body = ErrorResponse)
+    ),
+    tag = "embeddings"
+)]
+pub async fn fit_projection(
+    State(embedding_service): State<Arc<EmbeddingService>>,
+    Json(payload): Json<FitProjectionRequest>,
+) -> Result<Json<FitProjectionResponse>, ApiError> {
+    let response = embedding_service.fit_projection(payload.dimensions).await.map_err(|e| match e {
+        FitProjectionError::InvalidInput(message) => ApiError::bad_request(message),
+        FitProjectionError::Store(e) => ApiError::internal(format!("Failed to fit projection: {}", e)),
+    })?;
+    Ok(Json(response))
+}
+
+/// Clear all stored embeddings, or only those of one type
 #[utoipa::path(
     post,
     path = "/clear",
+    request_body(content = Option<ClearRequest>, description = "Optional; without a body everything is cleared"),
     responses(
         (status = 200, description = "Data successfully cleared", body = ClearResponse),
-        (status = 500, description = "Failed to clear data")
+        (status = 400, description = "The body is not a valid ClearRequest or namespace is not a valid name", body = ErrorResponse),
+        (status = 403, description = "Clearing is disabled with DISABLE_CLEAR", body = ErrorResponse),
+        (status = 500, description = "Failed to clear data", body = ErrorResponse)
     ),
     tag = "embeddings"
 )]
 pub async fn clear_embeddings(
     State(embedding_service): State<Arc<EmbeddingService>>,
-) -> Json<ClearResponse> {
-    let result = embedding_service.clear_data();
-    Json(ClearResponse {
-        success: result.is_ok(),
+    body: String,
+) -> Result<Json<ClearResponse>, ApiError> {
+    if embedding_service.clear_disabled() {
+        return Err(ApiError::forbidden("destructive operations disabled"));
+    }
+    // An empty body keeps the original wipe-everything behavior; a body that doesn't parse is
+    // rejected rather than treated as empty, since that would clear every type
+    let request: ClearRequest = if body.trim().is_empty() {
+        ClearRequest::default()
+    } else {
+        serde_json::from_str(&body)
+            .map_err(|e| ApiError::bad_request(format!("invalid clear request: {}", e)))?
+    };
+    // A namespace with no store yet has nothing to clear, and isn't opened just to find that out
+    let Some(embedding_service) = embedding_service.existing_namespace(request.namespace.as_deref())
+        .map_err(ApiError::bad_request)?
+    else {
+        return Ok(Json(ClearResponse { success: true, removed: request.embedding_type.map(|_| 0) }));
+    };
+
+    let removed = match request.embedding_type {
+        Some(embedding_type) => Some(embedding_service.clear_type(&embedding_type).await
+            .map_err(|e| ApiError::internal(format!("Failed to clear data: {}", e)))?),
+        None => {
+            embedding_service.clear_data().await
+                .map_err(|e| ApiError::internal(format!("Failed to clear data: {}", e)))?;
+            None
+        }
+    };
+    Ok(Json(ClearResponse { success: true, removed }))
+}
+
+/// List stored embeddings, one page at a time
+#[utoipa::path(
+    get,
+    path = "/list",
+    params(ListQuery),
+    responses(
+        (status = 200, description = "A page of stored embeddings", body = ListResponse),
+        (status = 400, description = "float_precision is 0", body = ErrorResponse),
+        (status = 500, description = "Failed to read stored embeddings", body = ErrorResponse)
+    ),
+    tag = "embeddings"
+)]
+pub async fn list_embeddings(
+    State(embedding_service): State<Arc<EmbeddingService>>,
+    Query(query): Query<ListQuery>,
+) -> Result<Json<ListResponse>, ApiError> {
+    check_float_precision(query.float_precision)?;
+    let (items, total) = embedding_service.list_embeddings(
+        query.offset.unwrap_or(0),
+        query.limit.unwrap_or(DEFAULT_LIST_LIMIT),
+        query.embedding_type,
+        CreatedRange {
+            after: query.created_after,
+            before: query.created_before,
+        },
+        query.include_embeddings.unwrap_or(false)
+    ).await
+        .map_err(|e| ApiError::internal(format!("Failed to list embeddings: {}", e)))?;
+    let items = match query.float_precision {
+        Some(_) => items
+            .into_iter()
+            .map(|item| StoredItem {
+                embedding: item.embedding.map(|embedding| round_embedding(embedding, query.float_precision)),
+                ..item
+            })
+            .collect(),
+        None => items,
+    };
+
+    Ok(Json(ListResponse {
+        items,
+        total,
+    }))
+}
+
+/// Download all stored embeddings as JSONL or CSV
+#[utoipa::path(
+    get,
+    path = "/export",
+    params(ExportQuery),
+    responses(
+        (status = 200, description = "Stored embeddings, streamed as an attachment", content(
+            (String = "application/x-ndjson"),
+            (String = "text/csv")
+        )),
+        (status = 400, description = "Unknown export format", body = ErrorResponse),
+        (status = 500, description = "Failed to read stored embeddings", body = ErrorResponse)
+    ),
+    tag = "embeddings"
+)]
+pub async fn export_embeddings(
+    State(embedding_service): State<Arc<EmbeddingService>>,
+    Query(query): Query<ExportQuery>,
+) -> Result<Response, ApiError> {
+    let format = query.format.unwrap_or_else(|| "jsonl".to_string());
+    if format != "jsonl" && format != "csv" {
+        return Err(ApiError::bad_request(format!("unknown export format '{}', expected jsonl or csv", format)));
+    }
+
+    let entries = embedding_service.stream_entries().await
+        .map_err(|e| ApiError::internal(format!("Failed to read embeddings: {}", e)))?;
+
+    // Records are streamed straight from the data file, one line at a time
+    let (content_type, body) = if format == "csv" {
+        let rows = entries.map(|entry| format!("{}\n", record_to_csv_row(&entry)));
+        let lines = tokio_stream::once(format!("{}\n", CSV_HEADER)).chain(rows);
+        ("text/csv", Body::from_stream(lines.map(Ok::<_, Infallible>)))
+    } else {
+        let lines = entries.map(|entry| format!("{}\n", entry));
+        ("application/x-ndjson", Body::from_stream(lines.map(Ok::<_, Infallible>)))
+    };
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, content_type.to_string()),
+            (header::CONTENT_DISPOSITION, format!("attachment; filename=\"embeddings.{}\"", format)),
+        ],
+        body,
+    ).into_response())
+}
+
+/// Bulk-load embeddings from a JSONL dump
+#[utoipa::path(
+    post,
+    path = "/import",
+    request_body(content = String, description = "JSONL records with text, embedding, embedding_type and optional model", content_type = "application/x-ndjson"),
+    responses(
+        (status = 200, description = "Import summary", body = ImportResponse),
+        (status = 500, description = "Failed to import embeddings", body = ErrorResponse)
+    ),
+    tag = "embeddings"
+)]
+pub async fn import_embeddings(
+    State(embedding_service): State<Arc<EmbeddingService>>,
+    body: String,
+) -> Result<Json<ImportResponse>, ApiError> {
+    let summary = embedding_service.import_embeddings(&body).await
+        .map_err(|e| ApiError::internal(format!("Failed to import embeddings: {}", e)))?;
+    Ok(Json(summary))
+}
+
+/// Liveness probe
+#[utoipa::path(
+    get,
+    path = "/health",
+    security(()),
+    responses(
+        (status = 200, description = "The server is alive", body = HealthResponse)
+    ),
+    tag = "health"
+)]
+pub async fn health_check() -> Json<HealthResponse> {
+    Json(HealthResponse {
+        status: "ok".to_string(),
     })
-} 
\ No newline at end of file
+}
+
+/// Readiness probe: checks the provider configuration and that the data directory is writable
+#[utoipa::path(
+    get,
+    path = "/ready",
+    security(()),
+    responses(
+        (status = 200, description = "The service is ready for traffic", body = ReadinessResponse),
+        (status = 503, description = "The service is not ready", body = ReadinessResponse)
+    ),
+    tag = "health"
+)]
+pub async fn readiness_check(
+    State(embedding_service): State<Arc<EmbeddingService>>,
+) -> (StatusCode, Json<ReadinessResponse>) {
+    match embedding_service.check_readiness().await {
+        Ok(()) => (StatusCode::OK, Json(ReadinessResponse {
+            status: "ready".to_string(),
+            problems: Vec::new(),
+        })),
+        Err(problems) => (StatusCode::SERVICE_UNAVAILABLE, Json(ReadinessResponse {
+            status: "not_ready".to_string(),
+            problems,
+        })),
+    }
+}
+
+/// Prometheus metrics
+#[utoipa::path(
+    get,
+    path = "/metrics",
+    responses(
+        (status = 200, description = "Metrics in the Prometheus text format", body = String, content_type = "text/plain")
+    ),
+    tag = "health"
+)]
+pub async fn metrics_endpoint() -> impl IntoResponse {
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        crate::utils::metrics::render(),
+    )
+}
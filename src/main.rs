@@ -1,40 +1,175 @@
-use axum::{routing::post, Router};
+use axum::{extract::DefaultBodyLimit, routing::{get, post}, Router};
 use dotenv::dotenv;
+use std::net::SocketAddr;
 use std::sync::Arc;
 use tokio::net::TcpListener;
-use utoipa::OpenApi;
+use tower_http::limit::RequestBodyLimitLayer;
+use tower_http::trace::{DefaultMakeSpan, DefaultOnResponse, TraceLayer};
+use tracing::Level;
+use tracing_subscriber::EnvFilter;
+use utoipa::openapi::security::{ApiKey, ApiKeyValue, HttpAuthScheme, HttpBuilder, SecurityScheme};
+use utoipa::{Modify, OpenApi};
 use utoipa_swagger_ui::SwaggerUi;
 
 use rust_embedding::{
+    embeddings::config::Config,
     embeddings::service::EmbeddingService,
+    http::auth::{require_api_key, ApiAuth},
+    http::cors::cors_from_env,
+    http::limit::{body_bytes_for_text, max_body_bytes_from_env},
+    http::rate_limit::{rate_limit, RateLimiter},
+    http::slow_requests::{log_slow_requests, SlowRequestLog},
     store_embedding,
+    embed,
+    update_embedding,
+    reembed_all,
+    reembed_all_stream,
     compare_embedding,
+    compare_batch,
+    compare_by_id,
+    compare_ws,
+    similarity,
+    similarity_matrix,
+    list_models,
+    stats,
+    count_embeddings,
+    cluster_embeddings,
+    reindex,
+    fit_projection,
     clear_embeddings,
+    list_embeddings,
+    export_embeddings,
+    import_embeddings,
+    health_check,
+    readiness_check,
+    metrics_endpoint,
     EmbeddingRequest,
+    Truncation,
+    UpdateRequest,
+    ReembedRequest,
+    ReembedProgress,
+    ReembedResponse,
     CompareRequest,
+    CompareByIdRequest,
     StoreResponse,
+    ResponseMeta,
+    EmbedRequest,
+    EmbedResponse,
     CompareResponse,
+    BestMatchResponse,
+    CompareByTypeResponse,
+    CompareBatchRequest,
+    CompareBatchResponse,
+    CompareOutput,
+    ResultField,
+    SimilarityRequest,
+    SimilarityResponse,
+    SimilarityMetric,
+    MatrixRequest,
+    MatrixResponse,
+    ModelInfo,
+    ModelsResponse,
+    StatsResponse,
+    CountResponse,
+    ClusterRequest,
+    CentroidSummary,
+    Cluster,
+    ClusterResponse,
+    ReindexResponse,
+    FitProjectionRequest,
+    FitProjectionResponse,
+    ClearRequest,
     ClearResponse,
+    ErrorResponse,
+    RequestErrorResponse,
+    ListResponse,
+    StoredItem,
+    ImportResponse,
+    HealthResponse,
+    ReadinessResponse,
 };
 
 #[derive(OpenApi)]
 #[openapi(
     paths(
         rust_embedding::store_embedding,
+        rust_embedding::embed,
+        rust_embedding::update_embedding,
+        rust_embedding::reembed_all,
+        rust_embedding::reembed_all_stream,
         rust_embedding::compare_embedding,
-        rust_embedding::clear_embeddings
+        rust_embedding::compare_batch,
+        rust_embedding::compare_by_id,
+        rust_embedding::compare_ws,
+        rust_embedding::similarity,
+        rust_embedding::similarity_matrix,
+        rust_embedding::list_models,
+        rust_embedding::stats,
+        rust_embedding::count_embeddings,
+        rust_embedding::cluster_embeddings,
+        rust_embedding::reindex,
+        rust_embedding::fit_projection,
+        rust_embedding::clear_embeddings,
+        rust_embedding::list_embeddings,
+        rust_embedding::export_embeddings,
+        rust_embedding::import_embeddings,
+        rust_embedding::health_check,
+        rust_embedding::readiness_check,
+        rust_embedding::metrics_endpoint
     ),
     components(
         schemas(
             EmbeddingRequest,
+            Truncation,
+            UpdateRequest,
+            ReembedRequest,
+            ReembedProgress,
+            ReembedResponse,
             CompareRequest,
+            CompareByIdRequest,
             StoreResponse,
+            ResponseMeta,
+            EmbedRequest,
+            EmbedResponse,
             CompareResponse,
-            ClearResponse
+            BestMatchResponse,
+            CompareByTypeResponse,
+            CompareBatchRequest,
+            CompareBatchResponse,
+            CompareOutput,
+            ResultField,
+            SimilarityRequest,
+            SimilarityResponse,
+            SimilarityMetric,
+            MatrixRequest,
+            MatrixResponse,
+            ModelInfo,
+            ModelsResponse,
+            StatsResponse,
+            CountResponse,
+            ClusterRequest,
+            CentroidSummary,
+            Cluster,
+            ClusterResponse,
+            ReindexResponse,
+            FitProjectionRequest,
+            FitProjectionResponse,
+            ClearRequest,
+            ClearResponse,
+            ErrorResponse,
+            RequestErrorResponse,
+            ListResponse,
+            StoredItem,
+            ImportResponse,
+            HealthResponse,
+            ReadinessResponse
         )
     ),
+    modifiers(&SecurityAddon),
+    security(("bearer" = []), ("api_key" = [])),
     tags(
-        (name = "embeddings", description = "Embedding management endpoints")
+        (name = "embeddings", description = "Embedding management endpoints"),
+        (name = "health", description = "Liveness and readiness probes")
     ),
     info(
         title = "Embeddings API",
@@ -44,23 +179,130 @@ use rust_embedding::{
 )]
 struct ApiDoc;
 
+/// Documents the `API_AUTH_TOKEN` schemes so the token can be entered in Swagger UI
+struct SecurityAddon;
+
+impl Modify for SecurityAddon {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme(
+            "bearer",
+            SecurityScheme::Http(HttpBuilder::new().scheme(HttpAuthScheme::Bearer).build()),
+        );
+        components.add_security_scheme(
+            "api_key",
+            SecurityScheme::ApiKey(ApiKey::Header(ApiKeyValue::new("X-API-Key"))),
+        );
+    }
+}
+
 #[tokio::main]
 async fn main() {
     dotenv().ok();
 
-    let embedding_service = Arc::new(EmbeddingService::new());
+    // Log level is controlled by RUST_LOG, e.g. RUST_LOG=rust_embedding=debug,tower_http=info
+    tracing_subscriber::fmt()
+        .with_env_filter(EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info")))
+        .init();
+    rust_embedding::utils::metrics::init();
+
+    // With PRELOAD_ON_START=true this reads the store before the listener is bound below
+    let embedding_service = match EmbeddingService::try_new_with_config(Config::from_env()) {
+        Ok(service) => Arc::new(service),
+        Err(e) => {
+            tracing::error!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    // With FLUSH_INTERVAL_MS set, buffered stores are written out in the background
+    embedding_service.spawn_flush_task();
+    let auth = Arc::new(ApiAuth::from_env());
+    // Only the endpoints that call the (billable) embedding provider are rate limited
+    let limiter = Arc::new(RateLimiter::from_env());
+    let limited = || axum::middleware::from_fn_with_state(limiter.clone(), rate_limit);
+    let max_body_bytes = max_body_bytes_from_env();
+    if max_body_bytes < body_bytes_for_text(embedding_service.max_text_chars()) {
+        tracing::warn!(
+            max_body_bytes,
+            max_text_chars = embedding_service.max_text_chars(),
+            "MAX_BODY_BYTES may reject texts that MAX_TEXT_CHARS allows",
+        );
+    }
     
     let app = Router::new()
         .merge(SwaggerUi::new("/swagger-ui").url("/api-docs/openapi.json", ApiDoc::openapi()))
-        .route("/store", post(store_embedding))
-        .route("/compare", post(compare_embedding))
+        .route("/store", post(store_embedding).layer(limited()))
+        .route("/embed", post(embed).layer(limited()))
+        .route("/update", post(update_embedding).layer(limited()))
+        .route("/reembed", post(reembed_all))
+        .route("/reembed/stream", post(reembed_all_stream))
+        .route("/compare", post(compare_embedding).layer(limited()))
+        .route("/compare/batch", post(compare_batch).layer(limited()))
+        .route("/compare_by_id", post(compare_by_id))
+        .route("/ws/compare", get(compare_ws).layer(limited()))
+        .route("/similarity", post(similarity).layer(limited()))
+        .route("/matrix", post(similarity_matrix).layer(limited()))
+        .route("/models", get(list_models))
+        .route("/stats", get(stats))
+        .route("/count", get(count_embeddings))
+        .route("/cluster", post(cluster_embeddings))
+        .route("/reindex", post(reindex))
+        .route("/fit_projection", post(fit_projection))
         .route("/clear", post(clear_embeddings))
-        .with_state(embedding_service);
+        .route("/list", get(list_embeddings))
+        .route("/export", get(export_embeddings))
+        .route("/import", post(import_embeddings))
+        .route("/health", get(health_check))
+        .route("/ready", get(readiness_check))
+        .route("/metrics", get(metrics_endpoint))
+        // Replaces axum's fixed 2 MiB limit on buffered bodies with the configured one
+        .layer(DefaultBodyLimit::disable())
+        .layer(RequestBodyLimitLayer::new(max_body_bytes))
+        .layer(axum::middleware::from_fn_with_state(auth, require_api_key))
+        // Outside the auth check, so preflight requests (which carry no token) are answered
+        .layer(cors_from_env())
+        .layer(axum::middleware::from_fn_with_state(Arc::new(SlowRequestLog::from_env()), log_slow_requests))
+        .layer(
+            TraceLayer::new_for_http()
+                .make_span_with(DefaultMakeSpan::new().level(Level::INFO))
+                .on_response(DefaultOnResponse::new().level(Level::INFO).latency_unit(tower_http::LatencyUnit::Millis)),
+        )
+        .with_state(embedding_service.clone());
 
     let port = std::env::var("PORT").unwrap_or_else(|_| "3000".to_string());
     let addr = format!("0.0.0.0:{}", port);
     let listener = TcpListener::bind(addr).await.unwrap();
-    println!("Server running on http://0.0.0.0:{}", port);
-    println!("API documentation available at http://0.0.0.0:{}/swagger-ui/", port);
-    axum::serve(listener, app).await.unwrap();
+    tracing::info!("Server running on http://0.0.0.0:{}", port);
+    tracing::info!("API documentation available at http://0.0.0.0:{}/swagger-ui/", port);
+    axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>())
+        .with_graceful_shutdown(shutdown_signal())
+        .await
+        .unwrap();
+
+    // Stores still buffered would be lost on exit
+    match embedding_service.flush().await {
+        Ok(flushed) => tracing::info!(flushed, "buffered stores flushed on shutdown"),
+        Err(e) => tracing::error!(error = %e, "failed to flush buffered stores on shutdown"),
+    }
+}
+
+/// Resolves on Ctrl+C or, on Unix, SIGTERM, letting in-flight requests finish
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c().await.expect("failed to listen for Ctrl+C");
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("failed to listen for SIGTERM")
+            .recv()
+            .await;
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
+    }
+    tracing::info!("shutting down");
 } 
@@ -0,0 +1,102 @@
+use crate::utils::similarity::{dot_product, normalize_vector};
+
+/// Outcome of a k-means run
+pub struct KMeans {
+    /// Cluster of each input vector, in input order
+    pub assignments: Vec<usize>,
+    /// Unit-length centroid of each cluster
+    pub centroids: Vec<Vec<f64>>,
+    /// Assignment passes made before the clusters stopped changing or `max_iters` was hit
+    pub iterations: usize,
+}
+
+/// Spherical k-means: group vectors by cosine similarity into at most `k` clusters. `k` is
+/// capped at the number of vectors. Centroids start from a deterministic farthest-point
+/// pick, so the same input always gives the same clusters. Vectors must share one length;
+/// zero vectors, which have no direction, all land in the first cluster.
+pub fn kmeans(vectors: &[Vec<f64>], k: usize, max_iters: usize) -> KMeans {
+    let k = k.min(vectors.len());
+    if k == 0 {
+        return KMeans { assignments: vec![0; vectors.len()], centroids: Vec::new(), iterations: 0 };
+    }
+    let dimensions = vectors[0].len();
+    let points: Vec<Vec<f64>> = vectors
+        .iter()
+        .map(|v| normalize_vector(v).unwrap_or_else(|| vec![0.0; dimensions]))
+        .collect();
+
+    let mut centroids = initial_centroids(&points, k);
+    let mut assignments = vec![usize::MAX; points.len()];
+    let mut iterations = 0;
+    while iterations < max_iters.max(1) {
+        iterations += 1;
+        let mut changed = false;
+        for (point, assignment) in points.iter().zip(assignments.iter_mut()) {
+            let nearest = nearest_centroid(point, &centroids);
+            if *assignment != nearest {
+                *assignment = nearest;
+                changed = true;
+            }
+        }
+        if !changed {
+            break;
+        }
+
+        for (cluster, centroid) in centroids.iter_mut().enumerate() {
+            let mut sum = vec![0.0; dimensions];
+            for (point, _) in points.iter().zip(&assignments).filter(|(_, a)| **a == cluster) {
+                for (total, value) in sum.iter_mut().zip(point) {
+                    *total += value;
+                }
+            }
+            // An empty cluster, or one whose members cancel out, keeps its old centroid
+            if let Some(mean) = normalize_vector(&sum) {
+                *centroid = mean;
+            }
+        }
+    }
+
+    KMeans { assignments, centroids, iterations }
+}
+
+/// Cosine similarity of a vector to a unit-length centroid; zero for a zero vector
+pub fn centroid_similarity(point: &[f64], centroid: &[f64]) -> f64 {
+    match normalize_vector(point) {
+        Some(point) => dot_product(&point, centroid).unwrap_or(0.0),
+        None => 0.0,
+    }
+}
+
+/// Start from the first point, then repeatedly add the point least similar to every centroid
+/// picked so far, which spreads the starting centroids across the data
+fn initial_centroids(points: &[Vec<f64>], k: usize) -> Vec<Vec<f64>> {
+    let mut centroids = vec![points[0].clone()];
+    let mut closest: Vec<f64> = points.iter().map(|p| similarity(p, &points[0])).collect();
+    while centroids.len() < k {
+        let (next, _) = closest
+            .iter()
+            .enumerate()
+            .min_by(|(_, a), (_, b)| a.total_cmp(b))
+            .expect("points is not empty");
+        let centroid = points[next].clone();
+        for (best, point) in closest.iter_mut().zip(points) {
+            *best = best.max(similarity(point, &centroid));
+        }
+        centroids.push(centroid);
+    }
+    centroids
+}
+
+fn nearest_centroid(point: &[f64], centroids: &[Vec<f64>]) -> usize {
+    centroids
+        .iter()
+        .enumerate()
+        // Reversed `min_by` so ties go to the first centroid
+        .min_by(|(_, a), (_, b)| similarity(point, b).total_cmp(&similarity(point, a)))
+        .map(|(cluster, _)| cluster)
+        .unwrap_or(0)
+}
+
+fn similarity(a: &[f64], b: &[f64]) -> f64 {
+    dot_product(a, b).unwrap_or(f64::NEG_INFINITY)
+}
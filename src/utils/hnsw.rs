@@ -0,0 +1,223 @@
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BinaryHeap, HashSet};
+
+/// Hierarchical Navigable Small World graph for approximate nearest-neighbor search by cosine
+/// similarity. Vectors are identified by their insertion order.
+pub struct HnswIndex {
+    /// Unit-length copies of the inserted vectors, so distance is `1 - dot`
+    vectors: Vec<Vec<f64>>,
+    /// `neighbors[node][layer]` lists the node's links on that layer
+    neighbors: Vec<Vec<Vec<usize>>>,
+    entry_point: Option<usize>,
+    max_layer: usize,
+    /// Links per node on the upper layers; layer 0 allows twice as many
+    m: usize,
+    ef_construction: usize,
+    level_multiplier: f64,
+    rng_state: u64,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+struct Candidate {
+    distance: f64,
+    id: usize,
+}
+
+impl Eq for Candidate {}
+
+impl PartialOrd for Candidate {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Candidate {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.distance.total_cmp(&other.distance).then(self.id.cmp(&other.id))
+    }
+}
+
+impl Default for HnswIndex {
+    fn default() -> Self {
+        Self::new(16, 100)
+    }
+}
+
+impl HnswIndex {
+    pub fn new(m: usize, ef_construction: usize) -> Self {
+        Self {
+            vectors: Vec::new(),
+            neighbors: Vec::new(),
+            entry_point: None,
+            max_layer: 0,
+            m,
+            ef_construction,
+            level_multiplier: 1.0 / (m as f64).ln(),
+            rng_state: 0x2545_f491_4f6c_dd1d,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.vectors.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.vectors.is_empty()
+    }
+
+    /// Add a vector, returning its id
+    pub fn insert(&mut self, vector: &[f64]) -> usize {
+        let id = self.vectors.len();
+        self.vectors.push(unit(vector));
+        let level = self.random_level();
+        self.neighbors.push(vec![Vec::new(); level + 1]);
+
+        let Some(mut entry) = self.entry_point else {
+            self.entry_point = Some(id);
+            self.max_layer = level;
+            return id;
+        };
+
+        // Descend greedily through the layers above the new node's level
+        for layer in (level + 1..=self.max_layer).rev() {
+            entry = self.search_layer(id, &[entry], 1, layer)[0].id;
+        }
+
+        let mut entries = vec![entry];
+        for layer in (0..=level.min(self.max_layer)).rev() {
+            let found = self.search_layer(id, &entries, self.ef_construction, layer);
+            let max_links = self.max_links(layer);
+            let selected: Vec<usize> = found.iter().take(max_links).map(|c| c.id).collect();
+
+            for &neighbor in &selected {
+                self.neighbors[neighbor][layer].push(id);
+                if self.neighbors[neighbor][layer].len() > max_links {
+                    self.prune(neighbor, layer, max_links);
+                }
+            }
+            self.neighbors[id][layer] = selected;
+            entries = found.iter().map(|c| c.id).collect();
+        }
+
+        if level > self.max_layer {
+            self.max_layer = level;
+            self.entry_point = Some(id);
+        }
+        id
+    }
+
+    /// Approximate `k` nearest neighbors of `query` as `(id, cosine similarity)`, best first.
+    /// A larger `ef` explores more of the graph, trading speed for recall.
+    pub fn search(&self, query: &[f64], k: usize, ef: usize) -> Vec<(usize, f64)> {
+        let Some(mut entry) = self.entry_point else {
+            return Vec::new();
+        };
+        let query = unit(query);
+
+        for layer in (1..=self.max_layer).rev() {
+            entry = self.search_layer_for(&query, &[entry], 1, layer)[0].id;
+        }
+        self.search_layer_for(&query, &[entry], ef.max(k), 0)
+            .into_iter()
+            .take(k)
+            .map(|c| (c.id, 1.0 - c.distance))
+            .collect()
+    }
+
+    fn search_layer(&self, node: usize, entries: &[usize], ef: usize, layer: usize) -> Vec<Candidate> {
+        self.search_layer_for(&self.vectors[node], entries, ef, layer)
+    }
+
+    /// Best-first search of one layer, returning up to `ef` nodes sorted nearest first
+    fn search_layer_for(&self, query: &[f64], entries: &[usize], ef: usize, layer: usize) -> Vec<Candidate> {
+        let mut visited: HashSet<usize> = entries.iter().copied().collect();
+        let mut to_visit = BinaryHeap::new();
+        let mut found = BinaryHeap::new();
+
+        for &id in entries {
+            let candidate = Candidate { distance: self.distance(query, id), id };
+            to_visit.push(Reverse(candidate));
+            found.push(candidate);
+        }
+        while found.len() > ef {
+            found.pop();
+        }
+
+        while let Some(Reverse(current)) = to_visit.pop() {
+            let furthest = found.peek().map_or(f64::INFINITY, |c| c.distance);
+            if current.distance > furthest && found.len() >= ef {
+                break;
+            }
+            let Some(links) = self.neighbors[current.id].get(layer) else {
+                continue;
+            };
+            for &neighbor in links {
+                if !visited.insert(neighbor) {
+                    continue;
+                }
+                let candidate = Candidate { distance: self.distance(query, neighbor), id: neighbor };
+                let furthest = found.peek().map_or(f64::INFINITY, |c| c.distance);
+                if found.len() < ef || candidate.distance < furthest {
+                    to_visit.push(Reverse(candidate));
+                    found.push(candidate);
+                    if found.len() > ef {
+                        found.pop();
+                    }
+                }
+            }
+        }
+
+        found.into_sorted_vec()
+    }
+
+    /// Keep only a node's `max_links` closest links on a layer
+    fn prune(&mut self, node: usize, layer: usize, max_links: usize) {
+        let mut links: Vec<Candidate> = self.neighbors[node][layer]
+            .iter()
+            .map(|&id| Candidate { distance: self.distance(&self.vectors[node], id), id })
+            .collect();
+        links.sort();
+        self.neighbors[node][layer] = links.into_iter().take(max_links).map(|c| c.id).collect();
+    }
+
+    fn max_links(&self, layer: usize) -> usize {
+        if layer == 0 {
+            self.m * 2
+        } else {
+            self.m
+        }
+    }
+
+    fn distance(&self, query: &[f64], id: usize) -> f64 {
+        let other = &self.vectors[id];
+        if query.len() != other.len() {
+            // Vectors of another dimensionality are never neighbors
+            return f64::INFINITY;
+        }
+        let distance = 1.0 - query.iter().zip(other).map(|(a, b)| a * b).sum::<f64>();
+        if distance.is_nan() {
+            f64::INFINITY
+        } else {
+            distance
+        }
+    }
+
+    /// Draw a level from the exponentially decaying distribution, using xorshift so builds are
+    /// reproducible
+    fn random_level(&mut self) -> usize {
+        self.rng_state ^= self.rng_state << 13;
+        self.rng_state ^= self.rng_state >> 7;
+        self.rng_state ^= self.rng_state << 17;
+        let uniform = (self.rng_state >> 11) as f64 / (1u64 << 53) as f64;
+        (-(uniform.max(f64::MIN_POSITIVE)).ln() * self.level_multiplier) as usize
+    }
+}
+
+fn unit(vector: &[f64]) -> Vec<f64> {
+    let norm: f64 = vector.iter().map(|x| x * x).sum::<f64>().sqrt();
+    if norm == 0.0 || !norm.is_finite() {
+        vector.to_vec()
+    } else {
+        vector.iter().map(|x| x / norm).collect()
+    }
+}
@@ -0,0 +1,94 @@
+use std::collections::HashMap;
+
+/// BM25 term frequency saturation: further occurrences of a term add less and less
+pub const BM25_K1: f64 = 1.2;
+
+/// BM25 length normalization: 0 ignores a text's length, 1 scales fully by it
+pub const BM25_B: f64 = 0.75;
+
+/// Split text into lowercase terms at every character that isn't alphanumeric
+pub fn tokenize(text: &str) -> Vec<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|term| !term.is_empty())
+        .map(str::to_lowercase)
+        .collect()
+}
+
+/// How many times each term occurs
+pub fn term_frequencies(terms: &[String]) -> HashMap<&str, usize> {
+    let mut frequencies = HashMap::new();
+    for term in terms {
+        *frequencies.entry(term.as_str()).or_insert(0) += 1;
+    }
+    frequencies
+}
+
+/// Term statistics of a set of texts, for scoring each of them against a query with BM25
+pub struct Bm25Corpus {
+    /// The terms of each text
+    texts: Vec<Vec<String>>,
+    /// Number of texts each term occurs in
+    document_frequencies: HashMap<String, usize>,
+    /// Average number of terms per text
+    average_length: f64,
+}
+
+impl Bm25Corpus {
+    pub fn new<'a>(texts: impl IntoIterator<Item = &'a str>) -> Self {
+        let texts: Vec<Vec<String>> = texts.into_iter().map(tokenize).collect();
+        let mut document_frequencies = HashMap::new();
+        for terms in &texts {
+            for term in term_frequencies(terms).into_keys() {
+                *document_frequencies.entry(term.to_string()).or_insert(0) += 1;
+            }
+        }
+        let total_terms: usize = texts.iter().map(Vec::len).sum();
+        let average_length = if texts.is_empty() { 0.0 } else { total_terms as f64 / texts.len() as f64 };
+        Self { texts, document_frequencies, average_length }
+    }
+
+    /// Number of texts in the corpus
+    pub fn len(&self) -> usize {
+        self.texts.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.texts.is_empty()
+    }
+
+    /// Inverse document frequency of a term: rare terms weigh more than common ones. Never
+    /// negative, even for a term in every text.
+    fn idf(&self, term: &str) -> f64 {
+        let containing = self.document_frequencies.get(term).copied().unwrap_or(0) as f64;
+        let total = self.texts.len() as f64;
+        ((total - containing + 0.5) / (containing + 0.5) + 1.0).ln()
+    }
+
+    /// BM25 score of each text against `query`, in the order the texts were given. 0 for texts
+    /// sharing no term with it.
+    pub fn scores(&self, query: &str) -> Vec<f64> {
+        let query_terms = tokenize(query);
+        let query_terms: Vec<(&str, f64)> = term_frequencies(&query_terms)
+            .into_keys()
+            .map(|term| (term, self.idf(term)))
+            .collect();
+        self.texts
+            .iter()
+            .map(|terms| {
+                let frequencies = term_frequencies(terms);
+                let length_norm = if self.average_length > 0.0 {
+                    1.0 - BM25_B + BM25_B * terms.len() as f64 / self.average_length
+                } else {
+                    1.0
+                };
+                query_terms
+                    .iter()
+                    .map(|(term, idf)| {
+                        let frequency = frequencies.get(term).copied().unwrap_or(0) as f64;
+                        idf * frequency * (BM25_K1 + 1.0) / (frequency + BM25_K1 * length_norm)
+                    })
+                    .sum()
+            })
+            .collect()
+    }
+}
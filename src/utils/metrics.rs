@@ -0,0 +1,35 @@
+use metrics_exporter_prometheus::{PrometheusBuilder, PrometheusHandle};
+use std::sync::OnceLock;
+
+pub const STORES_TOTAL: &str = "embeddings_stored_total";
+pub const DUPLICATES_TOTAL: &str = "embeddings_duplicates_skipped_total";
+pub const COMPARES_TOTAL: &str = "embeddings_compares_total";
+pub const STORE_READS_TOTAL: &str = "embeddings_store_reads_total";
+pub const PROVIDER_REQUEST_SECONDS: &str = "embedding_provider_request_duration_seconds";
+pub const PROVIDER_CANCELLED_TOTAL: &str = "embedding_provider_requests_cancelled_total";
+pub const STORED_VECTORS: &str = "embeddings_stored_vectors";
+
+static HANDLE: OnceLock<PrometheusHandle> = OnceLock::new();
+
+/// Install the global Prometheus recorder. Safe to call more than once; only the first call
+/// installs it. Metrics recorded before this is called are dropped.
+pub fn init() -> &'static PrometheusHandle {
+    HANDLE.get_or_init(|| {
+        let handle = PrometheusBuilder::new()
+            .install_recorder()
+            .expect("Failed to install Prometheus recorder");
+        ::metrics::describe_counter!(STORES_TOTAL, "Embeddings written to the store");
+        ::metrics::describe_counter!(DUPLICATES_TOTAL, "Stores skipped because the text and type were already stored");
+        ::metrics::describe_counter!(COMPARES_TOTAL, "Comparisons run against the store");
+        ::metrics::describe_counter!(STORE_READS_TOTAL, "Reads of every stored record, by comparisons, listings and other scans");
+        ::metrics::describe_histogram!(PROVIDER_REQUEST_SECONDS, ::metrics::Unit::Seconds, "Latency of embedding provider requests");
+        ::metrics::describe_counter!(PROVIDER_CANCELLED_TOTAL, "Embedding provider requests abandoned before they returned, e.g. because the client disconnected");
+        ::metrics::describe_gauge!(STORED_VECTORS, "Number of vectors currently in the store");
+        handle
+    })
+}
+
+/// Render all metrics in the Prometheus text format, or an empty string if `init` was never called
+pub fn render() -> String {
+    HANDLE.get().map(|handle| handle.render()).unwrap_or_default()
+}
@@ -1 +1,7 @@
+pub mod clustering;
+pub mod hnsw;
+pub mod lexical;
+pub mod metrics;
+pub mod paths;
+pub mod projection;
 pub mod similarity;
\ No newline at end of file
@@ -0,0 +1,24 @@
+/// Longest file name component accepted, the limit of common filesystems
+pub const MAX_PATH_COMPONENT_LEN: usize = 255;
+
+/// Check that user input is safe to use as a single file name inside a directory the server
+/// controls, returning it unchanged. Only ASCII letters, digits, `-`, `_` and `.` are allowed,
+/// and it may not start with `.`, so separators, `..`, absolute and drive paths, hidden files
+/// and look-alike Unicode (fullwidth or division slashes, bidi overrides, zero-width
+/// characters) are all rejected rather than stripped, since stripping could map two inputs to
+/// the same file.
+pub fn sanitize_path_component(input: &str) -> Result<&str, String> {
+    if input.is_empty() {
+        return Err("must not be empty".to_string());
+    }
+    if input.len() > MAX_PATH_COMPONENT_LEN {
+        return Err(format!("must be at most {} characters", MAX_PATH_COMPONENT_LEN));
+    }
+    if let Some(c) = input.chars().find(|c| !(c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))) {
+        return Err(format!("must not contain {:?}", c));
+    }
+    if input.starts_with('.') {
+        return Err("must not start with '.'".to_string());
+    }
+    Ok(input)
+}
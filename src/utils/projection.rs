@@ -0,0 +1,148 @@
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// Subspace iterations `fit` runs when no count is given. Each one costs two passes over the
+/// vectors; a dozen is plenty once the leading directions stand out from the noise.
+pub const DEFAULT_FIT_ITERATIONS: usize = 12;
+
+/// A linear map from vectors of `source_dimensions()` down to `dimensions()`, onto the
+/// directions that carry most of the stored vectors' energy. The rows of `components` are
+/// orthonormal, so dot products and cosines between projected vectors approximate those of
+/// the originals, and match them exactly for vectors within the spanned subspace.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Projection {
+    /// One unit-length row per output dimension, each of the source length
+    pub components: Vec<Vec<f64>>,
+}
+
+impl Projection {
+    /// Fit a projection to `dimensions` outputs by PCA over `vectors`, which must share one
+    /// length. This is the uncentered form (a truncated SVD): the mean isn't subtracted first,
+    /// since similarity is measured from the origin, not from the mean. The top singular
+    /// subspace is found by subspace iteration, so the full covariance matrix is never built.
+    pub fn fit(vectors: &[Vec<f64>], dimensions: usize, iterations: usize) -> Result<Self, String> {
+        let Some(source_dimensions) = vectors.first().map(Vec::len) else {
+            return Err("cannot fit projection: no vectors".to_string());
+        };
+        if vectors.iter().any(|v| v.len() != source_dimensions) {
+            return Err("cannot fit projection: vectors differ in length".to_string());
+        }
+        if dimensions == 0 || dimensions >= source_dimensions {
+            return Err(format!(
+                "cannot fit projection: dimensions must be between 1 and {}, got {}",
+                source_dimensions.saturating_sub(1),
+                dimensions
+            ));
+        }
+        if vectors.len() < dimensions {
+            return Err(format!(
+                "cannot fit projection: {} dimensions need at least as many vectors, got {}",
+                dimensions,
+                vectors.len()
+            ));
+        }
+
+        let mut seed = 0x9E37_79B9_7F4A_7C15u64;
+        let mut components: Vec<Vec<f64>> = (0..dimensions).map(|_| random_vector(source_dimensions, &mut seed)).collect();
+        orthonormalize(&mut components, &mut seed);
+        for _ in 0..iterations.max(1) {
+            // Multiply the basis by XᵀX without forming it: first X·Vᵀ, then Xᵀ·(X·Vᵀ)
+            let coordinates: Vec<Vec<f64>> = vectors
+                .par_iter()
+                .map(|vector| components.iter().map(|component| dot(vector, component)).collect())
+                .collect();
+            components = (0..dimensions)
+                .into_par_iter()
+                .map(|j| {
+                    let mut row = vec![0.0; source_dimensions];
+                    for (vector, coordinates) in vectors.iter().zip(&coordinates) {
+                        let weight = coordinates[j];
+                        for (r, x) in row.iter_mut().zip(vector) {
+                            *r += weight * x;
+                        }
+                    }
+                    row
+                })
+                .collect();
+            orthonormalize(&mut components, &mut seed);
+        }
+        Ok(Self { components })
+    }
+
+    /// Length of the vectors this projects
+    pub fn source_dimensions(&self) -> usize {
+        self.components.first().map_or(0, Vec::len)
+    }
+
+    /// Length of the projected vectors
+    pub fn dimensions(&self) -> usize {
+        self.components.len()
+    }
+
+    /// Project one vector, or `None` if it isn't of the source length
+    pub fn project(&self, vector: &[f64]) -> Option<Vec<f64>> {
+        (vector.len() == self.source_dimensions())
+            .then(|| self.components.iter().map(|component| dot(vector, component)).collect())
+    }
+
+    /// Share of the vectors' total squared norm kept by the projection, from 0 to 1. Cosines
+    /// in the projected space are close to the originals when this is near 1.
+    pub fn explained_variance(&self, vectors: &[Vec<f64>]) -> f64 {
+        let (kept, total) = vectors
+            .iter()
+            .filter_map(|vector| Some((self.project(vector)?, vector)))
+            .fold((0.0, 0.0), |(kept, total), (projected, vector)| {
+                (kept + dot(&projected, &projected), total + dot(vector, vector))
+            });
+        if total == 0.0 {
+            0.0
+        } else {
+            kept / total
+        }
+    }
+}
+
+fn dot(a: &[f64], b: &[f64]) -> f64 {
+    a.iter().zip(b).map(|(x, y)| x * y).sum()
+}
+
+/// A deterministic pseudo-random vector with values in [-1, 1)
+fn random_vector(length: usize, seed: &mut u64) -> Vec<f64> {
+    (0..length)
+        .map(|_| {
+            *seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            (*seed >> 11) as f64 / (1u64 << 52) as f64 - 1.0
+        })
+        .collect()
+}
+
+/// Make the rows orthonormal by modified Gram-Schmidt. A row that vanishes, because the data
+/// has fewer independent directions than rows, is replaced by a random direction so the
+/// basis keeps its size.
+fn orthonormalize(rows: &mut [Vec<f64>], seed: &mut u64) {
+    for i in 0..rows.len() {
+        for _attempt in 0..8 {
+            let (done, rest) = rows.split_at_mut(i);
+            let row = &mut rest[0];
+            let original_norm = dot(row, row).sqrt();
+            // Twice, since one pass loses orthogonality when the row is close to the others
+            for _ in 0..2 {
+                for previous in done.iter() {
+                    let overlap = dot(row, previous);
+                    for (r, p) in row.iter_mut().zip(previous) {
+                        *r -= overlap * p;
+                    }
+                }
+            }
+            // Relative to where it started, since XᵀX scales rows by the vectors' energy
+            let norm = dot(row, row).sqrt();
+            if norm > 1e-10 * original_norm {
+                for r in row.iter_mut() {
+                    *r /= norm;
+                }
+                break;
+            }
+            *row = random_vector(row.len(), seed);
+        }
+    }
+}
@@ -1,6 +1,145 @@
-pub fn cosine_similarity(a: &[f64], b: &[f64]) -> f64 {
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap};
+use std::sync::Arc;
+
+/// Scores a query vector against a stored one, higher meaning closer, or NaN where the score
+/// is undefined
+pub type SimilarityFn = Arc<dyn Fn(&[f64], &[f64]) -> f64 + Send + Sync>;
+
+/// The similarity functions a service starts with, by name: `cosine`, `dot`, and `euclidean`
+/// turned into a similarity as `1 / (1 + distance)`, so that higher is closer for each
+pub fn builtin_similarity_functions() -> HashMap<String, SimilarityFn> {
+    let functions: [(&str, SimilarityFn); 3] = [
+        ("cosine", Arc::new(|a, b| cosine_similarity(a, b).unwrap_or(f64::NAN))),
+        ("dot", Arc::new(|a, b| dot_product(a, b).unwrap_or(f64::NAN))),
+        ("euclidean", Arc::new(|a, b| euclidean_distance(a, b).map_or(f64::NAN, |distance| 1.0 / (1.0 + distance)))),
+    ];
+    functions.into_iter().map(|(name, function)| (name.to_string(), function)).collect()
+}
+
+/// Cosine similarity between two vectors, or `None` when it is undefined: the vectors differ
+/// in length (e.g. embeddings from different models) or either has zero magnitude.
+pub fn cosine_similarity(a: &[f64], b: &[f64]) -> Option<f64> {
+    if a.len() != b.len() {
+        return None;
+    }
     let dot_product: f64 = a.iter().zip(b.iter()).map(|(x, y)| x * y).sum();
     let norm_a: f64 = a.iter().map(|x| x * x).sum::<f64>().sqrt();
     let norm_b: f64 = b.iter().map(|x| x * x).sum::<f64>().sqrt();
-    dot_product / (norm_a * norm_b)
-} 
\ No newline at end of file
+    if norm_a == 0.0 || norm_b == 0.0 {
+        return None;
+    }
+    Some(dot_product / (norm_a * norm_b))
+}
+
+/// Dot product of two vectors, or `None` if they differ in length. For unit-length vectors
+/// this equals their cosine similarity without recomputing magnitudes.
+pub fn dot_product(a: &[f64], b: &[f64]) -> Option<f64> {
+    if a.len() != b.len() {
+        return None;
+    }
+    Some(a.iter().zip(b.iter()).map(|(x, y)| x * y).sum())
+}
+
+/// Euclidean (L2) distance between two vectors, or `None` if they differ in length
+pub fn euclidean_distance(a: &[f64], b: &[f64]) -> Option<f64> {
+    if a.len() != b.len() {
+        return None;
+    }
+    Some(a.iter().zip(b.iter()).map(|(x, y)| (x - y) * (x - y)).sum::<f64>().sqrt())
+}
+
+/// Order similarities from highest to lowest with NaN (e.g. from overflowing stored values)
+/// always last, so a single bad entry can't break the ranking
+pub fn descending_similarity(a: f64, b: f64) -> Ordering {
+    match (a.is_nan(), b.is_nan()) {
+        (true, true) => Ordering::Equal,
+        (true, false) => Ordering::Greater,
+        (false, true) => Ordering::Less,
+        (false, false) => b.total_cmp(&a),
+    }
+}
+
+/// The `k` items with the highest `score`, best first. Gives the same order as a stable sort
+/// by `descending_similarity` followed by truncating to `k`, ties included, but keeps only `k`
+/// items in a heap: O(n log k) instead of O(n log n).
+pub fn top_k_by<T>(items: impl IntoIterator<Item = T>, k: usize, score: impl Fn(&T) -> f64) -> Vec<T> {
+    top_k_sorted_by(items, k, |a, b| descending_similarity(score(a), score(b)))
+}
+
+/// The first `k` items in the order given by `compare`, in that order. Same result as a stable
+/// `sort_by(compare)` followed by truncating to `k`, with only `k` items kept in a heap.
+pub fn top_k_sorted_by<T>(
+    items: impl IntoIterator<Item = T>,
+    k: usize,
+    compare: impl Fn(&T, &T) -> Ordering,
+) -> Vec<T> {
+    if k == 0 {
+        return Vec::new();
+    }
+    let mut heap = BinaryHeap::with_capacity(k + 1);
+    for (index, item) in items.into_iter().enumerate() {
+        heap.push(Ranked { index, item, compare: &compare });
+        if heap.len() > k {
+            heap.pop();
+        }
+    }
+    heap.into_sorted_vec().into_iter().map(|ranked| ranked.item).collect()
+}
+
+/// An item with its rank: later in `compare`'s order, then later in the input, compares
+/// greater, so the top of the max-heap is the worst item kept
+struct Ranked<'a, T, F> {
+    index: usize,
+    item: T,
+    compare: &'a F,
+}
+
+impl<T, F: Fn(&T, &T) -> Ordering> Ord for Ranked<'_, T, F> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.compare)(&self.item, &other.item).then(self.index.cmp(&other.index))
+    }
+}
+
+impl<T, F: Fn(&T, &T) -> Ordering> PartialOrd for Ranked<'_, T, F> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<T, F: Fn(&T, &T) -> Ordering> PartialEq for Ranked<'_, T, F> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl<T, F: Fn(&T, &T) -> Ordering> Eq for Ranked<'_, T, F> {}
+
+/// Scale a vector to unit (L2) length, or `None` if it has zero magnitude
+pub fn normalize_vector(v: &[f64]) -> Option<Vec<f64>> {
+    let norm: f64 = v.iter().map(|x| x * x).sum::<f64>().sqrt();
+    if norm == 0.0 || !norm.is_finite() {
+        return None;
+    }
+    Some(v.iter().map(|x| x / norm).collect())
+}
+
+/// Round to `places` decimal places. Values already finer than f64 can show are unchanged.
+pub fn round_to(value: f64, places: u32) -> f64 {
+    if places >= f64::DIGITS + 2 {
+        return value;
+    }
+    let factor = 10f64.powi(places as i32);
+    (value * factor).round() / factor
+}
+
+/// Round to `digits` significant digits, so the value serializes with at most that many.
+/// Zero, non-finite values and values already finer than f64 can show are unchanged.
+pub fn round_significant(value: f64, digits: u32) -> f64 {
+    if digits > f64::DIGITS + 1 || value == 0.0 || !value.is_finite() {
+        return value;
+    }
+    // Going through the decimal text rounds exactly as it is printed, which scaling by a
+    // power of ten doesn't for very large or small values
+    format!("{:.*e}", digits.max(1) as usize - 1, value).parse().unwrap_or(value)
+}
@@ -0,0 +1,72 @@
+use rust_embedding::utils::hnsw::HnswIndex;
+use rust_embedding::utils::similarity::cosine_similarity;
+use std::collections::HashSet;
+
+/// Deterministic vectors with components in [-1, 1), from a linear congruential generator
+fn synthetic_vectors(count: usize, dimensions: usize, seed: u64) -> Vec<Vec<f64>> {
+    let mut state = seed;
+    (0..count)
+        .map(|_| {
+            (0..dimensions)
+                .map(|_| {
+                    state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+                    (state >> 11) as f64 / (1u64 << 52) as f64 - 1.0
+                })
+                .collect()
+        })
+        .collect()
+}
+
+fn brute_force_top_k(vectors: &[Vec<f64>], query: &[f64], k: usize) -> Vec<usize> {
+    let mut scored: Vec<(usize, f64)> = vectors
+        .iter()
+        .enumerate()
+        .map(|(id, v)| (id, cosine_similarity(query, v).unwrap()))
+        .collect();
+    scored.sort_by(|a, b| b.1.total_cmp(&a.1));
+    scored.into_iter().take(k).map(|(id, _)| id).collect()
+}
+
+#[test]
+fn test_hnsw_recall_against_brute_force() {
+    const K: usize = 10;
+    let vectors = synthetic_vectors(1000, 32, 7);
+    let queries = synthetic_vectors(50, 32, 99);
+
+    let mut index = HnswIndex::default();
+    for vector in &vectors {
+        index.insert(vector);
+    }
+    assert_eq!(index.len(), vectors.len());
+
+    let mut hits = 0;
+    for query in &queries {
+        let expected: HashSet<usize> = brute_force_top_k(&vectors, query, K).into_iter().collect();
+        let found = index.search(query, K, 64);
+        assert_eq!(found.len(), K);
+        hits += found.iter().filter(|(id, _)| expected.contains(id)).count();
+    }
+
+    let recall = hits as f64 / (queries.len() * K) as f64;
+    assert!(recall >= 0.9, "recall@{} was {}", K, recall);
+}
+
+#[test]
+fn test_hnsw_returns_similarities_best_first() {
+    let mut index = HnswIndex::default();
+    index.insert(&[1.0, 0.0]);
+    index.insert(&[0.0, 1.0]);
+    index.insert(&[1.0, 1.0]);
+
+    let found = index.search(&[2.0, 0.1], 3, 10);
+    let ids: Vec<usize> = found.iter().map(|(id, _)| *id).collect();
+    assert_eq!(ids, vec![0, 2, 1]);
+    assert!((found[0].1 - cosine_similarity(&[2.0, 0.1], &[1.0, 0.0]).unwrap()).abs() < 1e-9);
+}
+
+#[test]
+fn test_hnsw_empty_index() {
+    let index = HnswIndex::default();
+    assert!(index.is_empty());
+    assert!(index.search(&[1.0, 0.0], 5, 10).is_empty());
+}
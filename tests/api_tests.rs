@@ -1,35 +1,144 @@
-use rust_embedding::embeddings;
-use rust_embedding::{store_embedding, compare_embedding, clear_embeddings};
-use axum::{Router, routing::post};
-use std::sync::Arc;
+use rust_embedding::embeddings::provider::{
+    CohereProvider, EmbeddingProvider, InputType, MockProvider, OllamaProvider, ProviderError,
+};
+use rust_embedding::embeddings::config::{Config, ConfigError};
+use rust_embedding::embeddings::service::{CompareFilter, CreatedRange, EmbeddingService, TieBreak, MAX_OPEN_NAMESPACES};
+use rust_embedding::http::auth::{require_api_key, ApiAuth};
+use rust_embedding::http::cors::cors_layer;
+use tower_http::limit::RequestBodyLimitLayer;
+use rust_embedding::http::rate_limit::{rate_limit, RateLimiter};
+use rust_embedding::embeddings::storage::{append_records, build_record, StorageFormat, StoragePrecision, StoredEmbedding};
+use rust_embedding::utils::similarity::{cosine_similarity, euclidean_distance};
+use rust_embedding::{store_embedding, embed, update_embedding, reembed_all, reembed_all_stream, compare_embedding, compare_batch, compare_by_id, compare_ws, similarity, similarity_matrix, list_models, stats, count_embeddings, cluster_embeddings, reindex, fit_projection, clear_embeddings, list_embeddings, export_embeddings, import_embeddings, health_check, readiness_check, metrics_endpoint};
+use axum::{Json, Router, extract::State, routing::{get, post}};
+use std::net::SocketAddr;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering as AtomicOrdering};
+use std::sync::{Arc, Mutex};
 use tokio::net::TcpListener;
-use reqwest;
 use serde_json::{json, Value};
+use tempfile::TempPath;
 
-async fn spawn_app() -> String {
-    let embedding_service = Arc::new(embeddings::service::EmbeddingService::new());
-    
-    let app = Router::new()
+/// A fresh, uniquely named data file that is deleted when dropped
+fn temp_data_path() -> TempPath {
+    tempfile::Builder::new()
+        .suffix(".jsonl")
+        .tempfile()
+        .unwrap()
+        .into_temp_path()
+}
+
+/// Spawn the app with the offline mock provider and a fresh data file
+async fn spawn_app() -> (String, TempPath) {
+    let data_path = temp_data_path();
+    let base_url = spawn_app_with_service(EmbeddingService::with_provider_and_data_path(
+        Box::new(MockProvider::default()),
+        data_path.to_str().unwrap(),
+    )).await;
+    (base_url, data_path)
+}
+
+async fn spawn_app_with_service(embedding_service: EmbeddingService) -> String {
+    serve(app_router(embedding_service)).await
+}
+
+fn app_router(embedding_service: EmbeddingService) -> Router {
+    rust_embedding::utils::metrics::init();
+    let embedding_service = Arc::new(embedding_service);
+
+    Router::new()
         .route("/store", post(store_embedding))
+        .route("/embed", post(embed))
+        .route("/update", post(update_embedding))
+        .route("/reembed", post(reembed_all))
+        .route("/reembed/stream", post(reembed_all_stream))
         .route("/compare", post(compare_embedding))
+        .route("/compare/batch", post(compare_batch))
+        .route("/compare_by_id", post(compare_by_id))
+        .route("/ws/compare", get(compare_ws))
+        .route("/similarity", post(similarity))
+        .route("/matrix", post(similarity_matrix))
+        .route("/models", get(list_models))
+        .route("/stats", get(stats))
+        .route("/count", get(count_embeddings))
+        .route("/cluster", post(cluster_embeddings))
+        .route("/reindex", post(reindex))
+        .route("/fit_projection", post(fit_projection))
         .route("/clear", post(clear_embeddings))
-        .with_state(embedding_service);
+        .route("/list", get(list_embeddings))
+        .route("/export", get(export_embeddings))
+        .route("/import", post(import_embeddings))
+        .route("/health", get(health_check))
+        .route("/ready", get(readiness_check))
+        .route("/metrics", get(metrics_endpoint))
+        .with_state(embedding_service)
+}
 
+async fn serve(app: Router) -> String {
     // Bind to a random available port
     let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
     let addr = listener.local_addr().unwrap();
     let base_url = format!("http://{}", addr);
 
     tokio::spawn(async move {
-        axum::serve(listener, app).await.unwrap();
+        axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>()).await.unwrap();
     });
 
     base_url
 }
 
+/// Deterministic embedding for the fake provider: a byte histogram, so texts sharing
+/// characters end up close to each other
+fn fake_embedding(text: &str) -> Vec<f64> {
+    let mut embedding = vec![0.0; 16];
+    for byte in text.bytes() {
+        embedding[(byte % 16) as usize] += 1.0;
+    }
+    embedding
+}
+
+/// Spawn a server speaking Ollama's `/api/embeddings` protocol, recording the requested models
+async fn spawn_fake_ollama() -> (String, Arc<Mutex<Vec<String>>>) {
+    let models = Arc::new(Mutex::new(Vec::new()));
+
+    let app = Router::new()
+        .route("/api/embeddings", post(|State(models): State<Arc<Mutex<Vec<String>>>>, Json(body): Json<Value>| async move {
+            models.lock().unwrap().push(body["model"].as_str().unwrap().to_string());
+            Json(json!({ "embedding": fake_embedding(body["prompt"].as_str().unwrap()) }))
+        }))
+        .with_state(models.clone());
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    tokio::spawn(async move {
+        axum::serve(listener, app).await.unwrap();
+    });
+
+    (format!("http://{}", addr), models)
+}
+
+/// Spawn the app backed by a fake Ollama provider, returning the models it was asked for
+async fn spawn_ollama_app() -> (String, Arc<Mutex<Vec<String>>>, TempPath) {
+    spawn_ollama_app_with(|service| service).await
+}
+
+/// Like `spawn_ollama_app`, letting the test adjust the service before it is served
+async fn spawn_ollama_app_with(
+    configure: impl FnOnce(EmbeddingService) -> EmbeddingService,
+) -> (String, Arc<Mutex<Vec<String>>>, TempPath) {
+    let (ollama_url, models) = spawn_fake_ollama().await;
+    let data_path = temp_data_path();
+    let base_url = spawn_app_with_service(configure(EmbeddingService::with_provider_and_data_path(
+        Box::new(OllamaProvider::new(ollama_url)),
+        data_path.to_str().unwrap(),
+    ))).await;
+    (base_url, models, data_path)
+}
+
 #[tokio::test]
 async fn test_store_embedding() {
-    let base_url = spawn_app().await;
+    let (base_url, _data_path) = spawn_app().await;
     
     let client = reqwest::Client::new();
     let response = client
@@ -53,7 +162,7 @@ async fn test_store_embedding() {
 
 #[tokio::test]
 async fn test_compare_embeddings() {
-    let base_url = spawn_app().await;
+    let (base_url, _data_path) = spawn_app().await;
     let client = reqwest::Client::new();
 
     // Clear any existing data
@@ -114,6 +223,7 @@ async fn test_compare_embeddings() {
     // Verify all results are of type "user"
     for result in results {
         assert_eq!(result["embedding_type"].as_str().unwrap(), "user");
+        assert_eq!(result["model"], "text-embedding-3-large");
         assert!(stored_texts.contains(&result["text"].as_str().unwrap()));
     }
 
@@ -184,7 +294,7 @@ async fn test_compare_embeddings() {
 
 #[tokio::test]
 async fn test_default_model() {
-    let base_url = spawn_app().await;
+    let (base_url, _data_path) = spawn_app().await;
     
     let client = reqwest::Client::new();
     let response = client
@@ -205,7 +315,7 @@ async fn test_default_model() {
 
 #[tokio::test]
 async fn test_duplicate_prevention() {
-    let base_url = spawn_app().await;
+    let (base_url, _data_path) = spawn_app().await;
     let client = reqwest::Client::new();
 
     // Clear any existing data
@@ -254,4 +364,3770 @@ async fn test_duplicate_prevention() {
         .unwrap();
     assert!(different_type_response.status().is_success());
     assert!(different_type_response.json::<Value>().await.unwrap()["stored"].as_bool().unwrap());
-} 
\ No newline at end of file
+}
+
+#[tokio::test]
+async fn test_ollama_provider_keeps_model_name() {
+    let (base_url, models, _data_path) = spawn_ollama_app().await;
+    let client = reqwest::Client::new();
+
+    let response = client
+        .post(format!("{}/store", base_url))
+        .json(&json!({
+            "text": "Hello local world",
+            "model": "nomic-embed-text",
+            "embedding_type": "test"
+        }))
+        .send()
+        .await
+        .unwrap();
+
+    assert!(response.status().is_success());
+    let body: Value = response.json().await.unwrap();
+    assert_eq!(body["embedding"].as_array().unwrap().len(), 16);
+
+    // The Ollama model name must reach the provider untouched
+    assert_eq!(*models.lock().unwrap(), vec!["nomic-embed-text".to_string()]);
+}
+
+#[tokio::test]
+async fn test_unknown_model_rejected() {
+    let (base_url, _data_path) = spawn_app().await;
+    let client = reqwest::Client::new();
+
+    for endpoint in ["store", "compare"] {
+        let response = client
+            .post(format!("{}/{}", base_url, endpoint))
+            .json(&json!({
+                "text": "Some text",
+                "model": "text-embedding-ada-002",
+                "embedding_type": "test"
+            }))
+            .send()
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), reqwest::StatusCode::BAD_REQUEST);
+        let body: Value = response.json().await.unwrap();
+        assert!(body["error"].as_str().unwrap().contains("text-embedding-ada-002"));
+    }
+}
+
+#[tokio::test]
+async fn test_compare_survives_nan_similarity() {
+    let (base_url, _, data_path) = spawn_ollama_app().await;
+    let client = reqwest::Client::new();
+
+    // "ab" embeds to a vector with ones in dimensions 1 and 2
+    let mut exact = vec![0.0; 16];
+    exact[1] = 1.0;
+    exact[2] = 1.0;
+    let mut partial = vec![0.0; 16];
+    partial[1] = 1.0;
+    // The dot product with this one overflows to infinity, making the cosine NaN
+    let mut overflow = vec![0.0; 16];
+    overflow[1] = 1e308;
+    overflow[2] = 1e308;
+
+    let records: Vec<String> = [("overflow", overflow), ("partial", partial), ("exact", exact)]
+        .into_iter()
+        .map(|(text, embedding)| json!({
+            "text": text,
+            "embedding": embedding,
+            "model": "nomic-embed-text",
+            "embedding_type": "test"
+        }).to_string())
+        .collect();
+    std::fs::write(&data_path, records.join("\n") + "\n").unwrap();
+
+    let response = client
+        .post(format!("{}/compare", base_url))
+        .json(&json!({
+            "text": "ab",
+            "embedding_type": "test"
+        }))
+        .send()
+        .await
+        .unwrap();
+
+    assert!(response.status().is_success());
+    let body: Value = response.json().await.unwrap();
+    let texts: Vec<&str> = body["results"].as_array().unwrap()
+        .iter()
+        .map(|r| r["text"].as_str().unwrap())
+        .collect();
+    assert_eq!(texts, vec!["exact", "partial", "overflow"]);
+
+}
+
+#[tokio::test]
+async fn test_list_embeddings_pagination() {
+    let (base_url, _, _data_path) = spawn_ollama_app().await;
+    let client = reqwest::Client::new();
+
+    for (text, embedding_type) in [("first", "user"), ("second", "title"), ("third", "user")] {
+        let response = client
+            .post(format!("{}/store", base_url))
+            .json(&json!({ "text": text, "embedding_type": embedding_type }))
+            .send()
+            .await
+            .unwrap();
+        assert!(response.status().is_success());
+    }
+
+    let body: Value = client
+        .get(format!("{}/list?offset=1&limit=1", base_url))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(body["total"], 3);
+    let items = body["items"].as_array().unwrap();
+    assert_eq!(items.len(), 1);
+    assert_eq!(items[0]["text"], "second");
+    assert_eq!(items[0]["embedding_type"], "title");
+    assert_eq!(items[0]["model"], "nomic-embed-text");
+    assert!(items[0].get("embedding").is_none());
+
+    let body: Value = client
+        .get(format!("{}/list?embedding_type=user&include_embeddings=true", base_url))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(body["total"], 2);
+    let items = body["items"].as_array().unwrap();
+    assert_eq!(items.len(), 2);
+    assert!(items.iter().all(|item| item["embedding"].as_array().unwrap().len() == 16));
+
+    // An offset past the end is an empty page, not an error
+    let response = client
+        .get(format!("{}/list?offset=1000000", base_url))
+        .send()
+        .await
+        .unwrap();
+    assert!(response.status().is_success());
+    let body: Value = response.json().await.unwrap();
+    assert_eq!(body["total"], 3);
+    assert!(body["items"].as_array().unwrap().is_empty());
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_concurrent_duplicate_stores() {
+    let (base_url, _, data_path) = spawn_ollama_app().await;
+    let client = reqwest::Client::new();
+
+    let mut stores = tokio::task::JoinSet::new();
+    for _ in 0..20 {
+        let client = client.clone();
+        let url = format!("{}/store", base_url);
+        stores.spawn(async move {
+            let response = client
+                .post(url)
+                .json(&json!({ "text": "Same text", "embedding_type": "test" }))
+                .send()
+                .await
+                .unwrap();
+            assert!(response.status().is_success());
+            response.json::<Value>().await.unwrap()["stored"].as_bool().unwrap()
+        });
+    }
+
+    let mut stored_count = 0;
+    while let Some(stored) = stores.join_next().await {
+        if stored.unwrap() {
+            stored_count += 1;
+        }
+    }
+
+    assert_eq!(stored_count, 1);
+    let content = std::fs::read_to_string(&data_path).unwrap();
+    assert_eq!(content.lines().count(), 1);
+}
+
+#[tokio::test]
+async fn test_export_embeddings() {
+    let (base_url, _, _data_path) = spawn_ollama_app().await;
+    let client = reqwest::Client::new();
+
+    for text in ["plain text", "text with \"quotes\", and commas"] {
+        client
+            .post(format!("{}/store", base_url))
+            .json(&json!({ "text": text, "embedding_type": "test" }))
+            .send()
+            .await
+            .unwrap();
+    }
+
+    let response = client.get(format!("{}/export", base_url)).send().await.unwrap();
+    assert!(response.status().is_success());
+    assert_eq!(response.headers()["content-type"], "application/x-ndjson");
+    assert!(response.headers()["content-disposition"].to_str().unwrap().starts_with("attachment"));
+    let body = response.text().await.unwrap();
+    let records: Vec<Value> = body.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+    assert_eq!(records.len(), 2);
+    assert_eq!(records[0]["text"], "plain text");
+    assert_eq!(records[0]["embedding"].as_array().unwrap().len(), 16);
+
+    let response = client.get(format!("{}/export?format=csv", base_url)).send().await.unwrap();
+    assert!(response.status().is_success());
+    assert_eq!(response.headers()["content-type"], "text/csv");
+    let body = response.text().await.unwrap();
+    let lines: Vec<&str> = body.lines().collect();
+    assert_eq!(lines.len(), 3);
+    assert_eq!(lines[0], "text,model,embedding_type,embedding");
+    assert!(lines[2].starts_with("\"text with \"\"quotes\"\", and commas\",\"nomic-embed-text\",\"test\",\"["));
+
+    let response = client.get(format!("{}/export?format=xml", base_url)).send().await.unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn test_import_embeddings() {
+    let (base_url, _, data_path) = spawn_ollama_app().await;
+    let client = reqwest::Client::new();
+
+    client
+        .post(format!("{}/store", base_url))
+        .json(&json!({ "text": "already stored", "embedding_type": "test" }))
+        .send()
+        .await
+        .unwrap();
+
+    let dump = [
+        json!({ "text": "imported", "embedding": [0.1, 0.2], "model": "m", "embedding_type": "test" }).to_string(),
+        json!({ "text": "already stored", "embedding": [0.1, 0.2], "model": "m", "embedding_type": "test" }).to_string(),
+        json!({ "text": "imported", "embedding": [0.3, 0.4], "model": "m", "embedding_type": "test" }).to_string(),
+        json!({ "text": "no type", "embedding": [0.1] }).to_string(),
+        json!({ "text": "empty", "embedding": [], "embedding_type": "test" }).to_string(),
+        json!({ "text": "not numbers", "embedding": ["a"], "embedding_type": "test" }).to_string(),
+        "{\"text\": \"truncated".to_string(),
+        json!({ "text": "imported", "embedding": [0.5], "embedding_type": "other" }).to_string(),
+    ].join("\n");
+
+    let response = client
+        .post(format!("{}/import", base_url))
+        .body(dump)
+        .send()
+        .await
+        .unwrap();
+    assert!(response.status().is_success());
+    let body: Value = response.json().await.unwrap();
+    assert_eq!(body["imported"], 2);
+    assert_eq!(body["skipped_duplicates"], 2);
+    assert_eq!(body["skipped_invalid"], 4);
+    assert_eq!(body["evicted"], 0);
+
+    let content = std::fs::read_to_string(&data_path).unwrap();
+    assert_eq!(content.lines().count(), 3);
+}
+
+#[tokio::test]
+async fn test_normalized_storage() {
+    let (base_url, _, data_path) = spawn_ollama_app_with(|service| service.with_normalize_embeddings(true)).await;
+    let client = reqwest::Client::new();
+
+    for text in ["aaab", "abcdef"] {
+        client
+            .post(format!("{}/store", base_url))
+            .json(&json!({ "text": text, "embedding_type": "test" }))
+            .send()
+            .await
+            .unwrap();
+    }
+
+    // Records are stored at unit length and flagged as such
+    let content = std::fs::read_to_string(&data_path).unwrap();
+    for line in content.lines() {
+        let record: Value = serde_json::from_str(line).unwrap();
+        assert_eq!(record["normalized"], true);
+        let norm: f64 = record["embedding"].as_array().unwrap()
+            .iter()
+            .map(|v| v.as_f64().unwrap().powi(2))
+            .sum::<f64>()
+            .sqrt();
+        assert!((norm - 1.0).abs() < 1e-9);
+    }
+
+    // Append a legacy un-normalized record, which must still be scored with the full cosine
+    let legacy = json!({
+        "text": "legacy",
+        "embedding": [0.0, 3.0, 3.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0],
+        "model": "nomic-embed-text",
+        "embedding_type": "test"
+    });
+    std::fs::write(&data_path, format!("{}{}\n", content, legacy)).unwrap();
+
+    let body: Value = client
+        .post(format!("{}/compare", base_url))
+        .json(&json!({ "text": "ab", "embedding_type": "test" }))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    let results = body["results"].as_array().unwrap();
+    assert_eq!(results.len(), 3);
+    // "ab" and "legacy" point in the same direction
+    assert_eq!(results[0]["text"], "legacy");
+    assert!((results[0]["similarity"].as_f64().unwrap() - 1.0).abs() < 1e-9);
+    // "aaab" is [3, 1] in the same two dimensions: cosine 4 / (sqrt(2) * sqrt(10))
+    let expected = 4.0 / (2.0_f64.sqrt() * 10.0_f64.sqrt());
+    let aaab = results.iter().find(|r| r["text"] == "aaab").unwrap();
+    assert!((aaab["similarity"].as_f64().unwrap() - expected).abs() < 1e-9);
+}
+
+#[tokio::test]
+async fn test_health_and_readiness() {
+    let (base_url, _, _data_path) = spawn_ollama_app().await;
+    let client = reqwest::Client::new();
+
+    let response = client.get(format!("{}/health", base_url)).send().await.unwrap();
+    assert!(response.status().is_success());
+    assert_eq!(response.json::<Value>().await.unwrap()["status"], "ok");
+
+    let response = client.get(format!("{}/ready", base_url)).send().await.unwrap();
+    assert!(response.status().is_success());
+    assert_eq!(response.json::<Value>().await.unwrap()["status"], "ready");
+}
+
+#[tokio::test]
+async fn test_not_ready_when_data_directory_cannot_be_created() {
+    let (ollama_url, _) = spawn_fake_ollama().await;
+    let data_dir = tempfile::tempdir().unwrap();
+    // A missing directory is created, but not where a file is in the way
+    std::fs::write(data_dir.path().join("blocked"), b"").unwrap();
+    let data_path = data_dir.path().join("blocked").join("embeddings.jsonl");
+    let base_url = spawn_app_with_service(EmbeddingService::with_provider_and_data_path(
+        Box::new(OllamaProvider::new(ollama_url)),
+        data_path.to_str().unwrap(),
+    )).await;
+    let client = reqwest::Client::new();
+
+    let response = client.get(format!("{}/ready", base_url)).send().await.unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::SERVICE_UNAVAILABLE);
+    let body: Value = response.json().await.unwrap();
+    assert_eq!(body["status"], "not_ready");
+    assert!(body["problems"][0].as_str().unwrap().contains("not writable"));
+}
+
+#[tokio::test]
+async fn test_preload_on_start() {
+    let data_path = temp_data_path();
+    let records: Vec<String> = ["one", "two", "three"]
+        .iter()
+        .map(|text| json!({ "text": text, "embedding": [1.0, 0.0, 0.0], "model": "test", "embedding_type": "test" }).to_string())
+        .collect();
+    std::fs::write(&data_path, records.join("\n") + "\n").unwrap();
+
+    let lazy = EmbeddingService::with_provider_and_data_path(Box::new(MockProvider::default()), data_path.to_str().unwrap());
+    assert!(!lazy.is_index_loaded());
+
+    let preloaded = EmbeddingService::with_provider_and_data_path(Box::new(MockProvider::default()), data_path.to_str().unwrap())
+        .with_preload_on_start(true);
+    assert!(preloaded.is_index_loaded());
+    assert_eq!(preloaded.stored_vectors(), 3);
+    assert!(preloaded.check_readiness().await.is_ok());
+
+    // A store that can't be read keeps the service from reporting ready
+    let data_dir = tempfile::tempdir().unwrap();
+    let unreadable = EmbeddingService::with_provider_and_data_path(Box::new(MockProvider::default()), data_dir.path().to_str().unwrap())
+        .with_preload_on_start(true);
+    assert!(!unreadable.is_index_loaded());
+    let problems = unreadable.check_readiness().await.unwrap_err();
+    assert!(problems[0].contains("has not been preloaded"));
+}
+
+#[tokio::test]
+async fn test_service_from_explicit_config() {
+    let data_path = temp_data_path();
+    let service = EmbeddingService::new_with_config(Config {
+        allowed_models: Some(vec!["text-embedding-3-small".to_string()]),
+        max_text_chars: 20,
+        default_embedding_type: "notes".to_string(),
+        max_stored_entries: 2,
+        embedding_cache_size: 0,
+        ..Config::new(Box::new(MockProvider::default()), data_path.to_str().unwrap())
+    });
+
+    assert_eq!(service.data_path(), data_path.to_str().unwrap());
+    assert_eq!(service.max_text_chars(), 20);
+    assert!(service.validate_text("text", &"a".repeat(21)).is_err());
+    assert_eq!(service.resolve_embedding_type(None), "notes");
+    assert!(service.resolve_model(Some("text-embedding-3-small".to_string())).is_ok());
+    assert!(service.resolve_model(Some("text-embedding-3-large".to_string())).is_err());
+
+    let base_url = spawn_app_with_service(service).await;
+    let client = reqwest::Client::new();
+    for text in ["one", "two", "three"] {
+        let response = client
+            .post(format!("{}/store", base_url))
+            .json(&json!({ "text": text, "model": "text-embedding-3-small" }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::OK);
+    }
+    // The oldest entry was evicted, and the rest stored under the configured default type
+    let body: Value = client.get(format!("{}/list", base_url)).send().await.unwrap().json().await.unwrap();
+    let items: Vec<(&str, &str)> = body["items"].as_array().unwrap()
+        .iter()
+        .map(|item| (item["text"].as_str().unwrap(), item["embedding_type"].as_str().unwrap()))
+        .collect();
+    assert_eq!(items, [("two", "notes"), ("three", "notes")]);
+}
+
+#[tokio::test]
+async fn test_store_write_error_is_500() {
+    // The data directory can't be created, as nothing can be made under /proc
+    let service = EmbeddingService::with_provider_and_data_path(Box::new(MockProvider::default()), "/proc/self/no-such-dir/embeddings.jsonl");
+    let base_url = spawn_app_with_service(service).await;
+
+    let response = reqwest::Client::new()
+        .post(format!("{}/store", base_url))
+        .json(&json!({ "text": "hello", "embedding_type": "test" }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::INTERNAL_SERVER_ERROR);
+    let body: Value = response.json().await.unwrap();
+    assert!(body["error"].as_str().unwrap().starts_with("Failed to store embedding"), "{}", body);
+}
+
+#[tokio::test]
+async fn test_data_path_directory_is_a_clear_error() {
+    let data_dir = tempfile::tempdir().unwrap();
+    let dir_path = data_dir.path().to_str().unwrap();
+
+    let error = EmbeddingService::try_new_with_config(Config::new(Box::new(MockProvider::default()), dir_path))
+        .err()
+        .unwrap();
+    assert_eq!(error, ConfigError::DataPathIsDirectory(dir_path.to_string()));
+    assert!(error.to_string().starts_with("DATA_PATH points to a directory"));
+
+    // A file where a parent directory should be is caught too
+    let file = temp_data_path();
+    let under_file = format!("{}/embeddings.jsonl", file.to_str().unwrap());
+    let error = EmbeddingService::try_new_with_config(Config::new(Box::new(MockProvider::default()), under_file.as_str()))
+        .err()
+        .unwrap();
+    assert!(matches!(error, ConfigError::DataPathUnderFile { .. }));
+
+    // Services built without the check report it on the first store instead of an OS error
+    let base_url = spawn_app_with_service(EmbeddingService::with_provider_and_data_path(Box::new(MockProvider::default()), dir_path)).await;
+    let client = reqwest::Client::new();
+    let response = client
+        .post(format!("{}/store", base_url))
+        .json(&json!({ "text": "hello", "embedding_type": "test" }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::INTERNAL_SERVER_ERROR);
+    let body: Value = response.json().await.unwrap();
+    assert!(body["error"].as_str().unwrap().contains("DATA_PATH points to a directory"), "{}", body);
+
+    // Reads fail with an error response rather than a panic
+    let response = client.get(format!("{}/list", base_url)).send().await.unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::INTERNAL_SERVER_ERROR);
+    let body: Value = response.json().await.unwrap();
+    assert!(body["error"].as_str().unwrap().starts_with("Failed to list embeddings"), "{}", body);
+    let response = client.post(format!("{}/fit_projection", base_url)).json(&json!({ "dimensions": 4 })).send().await.unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::INTERNAL_SERVER_ERROR);
+    let body: Value = response.json().await.unwrap();
+    assert!(body["error"].as_str().unwrap().starts_with("Failed to fit projection"), "{}", body);
+    let response = client.post(format!("{}/clear", base_url)).json(&json!({ "embedding_type": "test" })).send().await.unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::INTERNAL_SERVER_ERROR);
+    let body: Value = response.json().await.unwrap();
+    assert!(body["error"].as_str().unwrap().starts_with("Failed to clear data"), "{}", body);
+
+    let body: Value = client.get(format!("{}/ready", base_url)).send().await.unwrap().json().await.unwrap();
+    assert!(body.to_string().contains("DATA_PATH points to a directory"), "{}", body);
+}
+
+/// Read a counter's value from the `/metrics` output, treating a missing counter as zero
+async fn read_counter(client: &reqwest::Client, base_url: &str, name: &str) -> f64 {
+    let body = client.get(format!("{}/metrics", base_url)).send().await.unwrap().text().await.unwrap();
+    body.lines()
+        .find(|line| line.starts_with(name) && !line.starts_with('#'))
+        .and_then(|line| line.split_whitespace().last())
+        .map(|value| value.parse().unwrap())
+        .unwrap_or(0.0)
+}
+
+#[tokio::test]
+async fn test_metrics_count_stores() {
+    let (base_url, _, _data_path) = spawn_ollama_app().await;
+    let client = reqwest::Client::new();
+
+    let before = read_counter(&client, &base_url, "embeddings_stored_total").await;
+
+    let response = client
+        .post(format!("{}/store", base_url))
+        .json(&json!({ "text": "counted", "embedding_type": "test" }))
+        .send()
+        .await
+        .unwrap();
+    assert!(response.status().is_success());
+
+    // Other tests share the global recorder, so only require that our store was counted
+    let after = read_counter(&client, &base_url, "embeddings_stored_total").await;
+    assert!(after >= before + 1.0, "store counter went from {} to {}", before, after);
+
+    let body = client.get(format!("{}/metrics", base_url)).send().await.unwrap().text().await.unwrap();
+    assert!(body.contains("embedding_provider_request_duration_seconds"));
+    assert!(body.contains("embeddings_stored_vectors"));
+}
+
+#[tokio::test]
+async fn test_ann_index_matches_brute_force() {
+    let (base_url, _, data_path) = spawn_ollama_app_with(|service| service.with_ann_index(true)).await;
+    let client = reqwest::Client::new();
+
+    let words = ["alpha", "bravo", "charlie", "delta", "echo", "foxtrot", "golf", "hotel", "india", "juliett"];
+    for (i, first) in words.iter().enumerate() {
+        for second in &words[i..] {
+            client
+                .post(format!("{}/store", base_url))
+                .json(&json!({ "text": format!("{} {}", first, second), "embedding_type": "test" }))
+                .send()
+                .await
+                .unwrap();
+        }
+    }
+
+    // One service scanning the file, one whose index is built from it at startup
+    let (ollama_url, _) = spawn_fake_ollama().await;
+    let brute_force_url = spawn_app_with_service(EmbeddingService::with_provider_and_data_path(
+        Box::new(OllamaProvider::new(ollama_url.clone())),
+        data_path.to_str().unwrap(),
+    )).await;
+    let rebuilt_url = spawn_app_with_service(EmbeddingService::with_provider_and_data_path(
+        Box::new(OllamaProvider::new(ollama_url)),
+        data_path.to_str().unwrap(),
+    ).with_ann_index(true)).await;
+
+    for query in ["echo hotel", "zulu", "charlie charlie"] {
+        let mut similarities = Vec::new();
+        for url in [&base_url, &brute_force_url, &rebuilt_url] {
+            let body: Value = client
+                .post(format!("{}/compare", url))
+                .json(&json!({ "text": query, "embedding_type": "test", "top_k": 5 }))
+                .send()
+                .await
+                .unwrap()
+                .json()
+                .await
+                .unwrap();
+            let scores: Vec<f64> = body["results"].as_array().unwrap()
+                .iter()
+                .map(|r| r["similarity"].as_f64().unwrap())
+                .collect();
+            assert_eq!(scores.len(), 5);
+            similarities.push(scores);
+        }
+        assert_eq!(similarities[0], similarities[1], "incremental index differs for {}", query);
+        assert_eq!(similarities[2], similarities[1], "rebuilt index differs for {}", query);
+    }
+}
+
+#[tokio::test]
+async fn test_reindex_picks_up_external_edits() {
+    let data_path = temp_data_path();
+    let base_url = spawn_app_with_service(EmbeddingService::with_provider_and_data_path(
+        Box::new(MockProvider::default()),
+        data_path.to_str().unwrap(),
+    ).with_ann_index(true)).await;
+    let client = reqwest::Client::new();
+
+    client
+        .post(format!("{}/store", base_url))
+        .json(&json!({ "text": "red apple", "embedding_type": "test" }))
+        .send()
+        .await
+        .unwrap();
+    let top_match = || async {
+        let body: Value = client
+            .post(format!("{}/compare", base_url))
+            .json(&json!({ "text": "blue whale", "top_k": 1 }))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        body["results"][0]["text"].as_str().unwrap().to_string()
+    };
+    assert_eq!(top_match().await, "red apple");
+
+    // An operator appends a record behind the server's back
+    let embedding = StoredEmbedding::encode(&MockProvider::embed_text("blue whale", 64), StoragePrecision::F64);
+    let record = build_record("blue whale", &embedding, "text-embedding-3-large", "test", false, None);
+    append_records(data_path.to_str().unwrap(), StorageFormat::Jsonl, &[record]).unwrap();
+    assert_eq!(top_match().await, "red apple");
+
+    let response = client.post(format!("{}/reindex", base_url)).send().await.unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::OK);
+    let body: Value = response.json().await.unwrap();
+    assert_eq!(body["reloaded"], 2);
+
+    assert_eq!(top_match().await, "blue whale");
+    let body: Value = client.get(format!("{}/count", base_url)).send().await.unwrap().json().await.unwrap();
+    assert_eq!(body["count"], 2);
+}
+
+#[tokio::test]
+async fn test_fit_projection() {
+    let (base_url, data_path) = spawn_app().await;
+    let client = reqwest::Client::new();
+    let projection_path = format!("{}.projection.json", data_path.to_str().unwrap());
+
+    let response = client.post(format!("{}/fit_projection", base_url)).json(&json!({ "dimensions": 4 })).send().await.unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::BAD_REQUEST);
+
+    for text in ["red apple", "green pear", "blue whale", "grey seal", "yellow banana"] {
+        client
+            .post(format!("{}/store", base_url))
+            .json(&json!({ "text": text, "embedding_type": "test" }))
+            .send()
+            .await
+            .unwrap();
+    }
+    for dimensions in [0, 64, 6] {
+        let response = client.post(format!("{}/fit_projection", base_url)).json(&json!({ "dimensions": dimensions })).send().await.unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::BAD_REQUEST, "dimensions {}", dimensions);
+    }
+
+    let response = client.post(format!("{}/fit_projection", base_url)).json(&json!({ "dimensions": 4 })).send().await.unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::OK);
+    let body: Value = response.json().await.unwrap();
+    assert_eq!((body["source_dimensions"].as_u64(), body["dimensions"].as_u64(), body["vectors"].as_u64()), (Some(64), Some(4), Some(5)));
+    assert!(std::path::Path::new(&projection_path).exists());
+
+    // Records written afterwards carry their projected vector too
+    client
+        .post(format!("{}/store", base_url))
+        .json(&json!({ "text": "orange fox", "embedding_type": "test" }))
+        .send()
+        .await
+        .unwrap();
+    let stored = std::fs::read_to_string(&data_path).unwrap();
+    for line in stored.lines() {
+        let record: Value = serde_json::from_str(line).unwrap();
+        assert_eq!(record["projected"].as_array().map(Vec::len), Some(4), "{}", record["text"]);
+    }
+
+    client.post(format!("{}/clear", base_url)).send().await.unwrap();
+    assert!(!std::path::Path::new(&projection_path).exists());
+}
+
+#[tokio::test]
+async fn test_similarity_between_two_texts() {
+    let (base_url, _, _data_path) = spawn_ollama_app().await;
+    let client = reqwest::Client::new();
+
+    client
+        .post(format!("{}/store", base_url))
+        .json(&json!({ "text": "aaab", "embedding_type": "test" }))
+        .send()
+        .await
+        .unwrap();
+
+    // "ab" and "aaab" share two dimensions: cosine 4 / (sqrt(2) * sqrt(10))
+    let expected = 4.0 / (2.0_f64.sqrt() * 10.0_f64.sqrt());
+
+    // Embedding both texts on the fly
+    let body: Value = client
+        .post(format!("{}/similarity", base_url))
+        .json(&json!({ "text_a": "ab", "text_b": "aaab" }))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert!((body["similarity"].as_f64().unwrap() - expected).abs() < 1e-9);
+
+    // Against the stored entry
+    let body: Value = client
+        .post(format!("{}/similarity", base_url))
+        .json(&json!({ "text_a": "ab", "text_b": "aaab", "embedding_type": "test" }))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert!((body["similarity"].as_f64().unwrap() - expected).abs() < 1e-9);
+
+    // Stored under another type only
+    let response = client
+        .post(format!("{}/similarity", base_url))
+        .json(&json!({ "text_a": "ab", "text_b": "aaab", "embedding_type": "other" }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::NOT_FOUND);
+
+    let response = client
+        .post(format!("{}/similarity", base_url))
+        .json(&json!({ "text_a": "ab" }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn test_int8_storage() {
+    let (base_url, _, data_path) = spawn_ollama_app_with(|service| {
+        service.with_storage_precision(StoragePrecision::Int8)
+    }).await;
+    let client = reqwest::Client::new();
+
+    client
+        .post(format!("{}/store", base_url))
+        .json(&json!({ "text": "aaab", "embedding_type": "test" }))
+        .send()
+        .await
+        .unwrap();
+
+    let content = std::fs::read_to_string(&data_path).unwrap();
+    let record: Value = serde_json::from_str(content.lines().next().unwrap()).unwrap();
+    assert_eq!(record["precision"], "int8");
+    assert!(record["embedding"].as_array().unwrap().iter().all(|v| v.is_i64()));
+
+    // Scores come from the dequantized vector: "b" counts 1 against a scale step of 3/127
+    let body: Value = client
+        .post(format!("{}/compare", base_url))
+        .json(&json!({ "text": "ab", "embedding_type": "test" }))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    let expected = 4.0 / (2.0_f64.sqrt() * 10.0_f64.sqrt());
+    assert!((body["results"][0]["similarity"].as_f64().unwrap() - expected).abs() < 1e-2);
+}
+
+#[tokio::test]
+async fn test_text_validation() {
+    let data_path = temp_data_path();
+    let base_url = spawn_app_with_service(
+        EmbeddingService::with_provider_and_data_path(Box::new(MockProvider::default()), data_path.to_str().unwrap())
+            .with_max_text_chars(20),
+    ).await;
+    let client = reqwest::Client::new();
+
+    for (text, message) in [
+        ("", "text must not be empty"),
+        ("  \n\t", "text must not be empty"),
+        ("this text is far too long", "text is 25 characters long, over the limit of 20"),
+    ] {
+        for endpoint in ["store", "compare"] {
+            let response = client
+                .post(format!("{}/{}", base_url, endpoint))
+                .json(&json!({ "text": text, "embedding_type": "test" }))
+                .send()
+                .await
+                .unwrap();
+            assert_eq!(response.status(), reqwest::StatusCode::BAD_REQUEST, "{} {:?}", endpoint, text);
+            let body: Value = response.json().await.unwrap();
+            assert_eq!(body["error"], message);
+        }
+    }
+
+    // The limit counts characters, not bytes
+    let response = client
+        .post(format!("{}/store", base_url))
+        .json(&json!({ "text": "é".repeat(20), "embedding_type": "test" }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::OK);
+    assert!(!std::fs::read_to_string(&data_path).unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn test_store_truncation() {
+    let data_path = temp_data_path();
+    let base_url = spawn_app_with_service(
+        EmbeddingService::with_provider_and_data_path(Box::new(MockProvider::default()), data_path.to_str().unwrap())
+            .with_max_text_chars(1000),
+    ).await;
+    let client = reqwest::Client::new();
+    // A long document, with multi-byte characters so cuts have to fall on character boundaries
+    let document = format!("{}{}", "début ".repeat(5000), "the end");
+    let store = |truncation: Value| {
+        client
+            .post(format!("{}/store", base_url))
+            .json(&json!({ "text": document, "embedding_type": "test", "truncation": truncation }))
+            .send()
+    };
+
+    for truncation in [Value::Null, json!("none")] {
+        let response = store(truncation).await.unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::BAD_REQUEST);
+    }
+
+    let response = store(json!("end")).await.unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::OK);
+    let body: Value = response.json().await.unwrap();
+    assert_eq!((body["stored"].as_bool(), body["truncated"].as_bool()), (Some(true), Some(true)));
+    let kept: String = document.chars().take(1000).collect();
+    assert_eq!(body["embedding"], json!(MockProvider::embed_text(&kept, 64)));
+
+    let body: Value = store(json!("start")).await.unwrap().json().await.unwrap();
+    assert_eq!(body["truncated"], true);
+
+    // The stored texts are the parts that were embedded
+    let stored: Vec<String> = std::fs::read_to_string(&data_path)
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str::<Value>(line).unwrap()["text"].as_str().unwrap().to_string())
+        .collect();
+    assert_eq!(stored.len(), 2);
+    assert_eq!(stored[0], kept);
+    assert_eq!(stored[1].chars().count(), 1000);
+    assert!(stored[1].ends_with("the end"));
+
+    // Short texts are left alone, and the response says nothing about truncation
+    let body: Value = client
+        .post(format!("{}/store", base_url))
+        .json(&json!({ "text": "short", "embedding_type": "test", "truncation": "end" }))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert!(body.get("truncated").is_none());
+
+    let response = store(json!("middle")).await.unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::UNPROCESSABLE_ENTITY);
+}
+
+#[tokio::test]
+async fn test_embed_does_not_store() {
+    let (base_url, data_path) = spawn_app().await;
+    let client = reqwest::Client::new();
+    let before = std::fs::read(&data_path).unwrap();
+
+    let response = client
+        .post(format!("{}/embed", base_url))
+        .json(&json!({ "text": "just a vector please" }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::OK);
+    let body: Value = response.json().await.unwrap();
+    assert_eq!(body["dimensions"], 64);
+    assert_eq!(body["model"], "text-embedding-3-large");
+    let embedding: Vec<f64> = serde_json::from_value(body["embedding"].clone()).unwrap();
+    assert_eq!(embedding, MockProvider::embed_text("just a vector please", 64));
+
+    let response = client
+        .post(format!("{}/embed", base_url))
+        .json(&json!({ "text": "shorter", "model": "text-embedding-3-small", "dimensions": 8 }))
+        .send()
+        .await
+        .unwrap();
+    let body: Value = response.json().await.unwrap();
+    assert_eq!(body["dimensions"], 8);
+    assert_eq!(body["embedding"].as_array().unwrap().len(), 8);
+    assert_eq!(body["model"], "text-embedding-3-small");
+
+    let response = client.post(format!("{}/embed", base_url)).json(&json!({ "text": "" })).send().await.unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::BAD_REQUEST);
+
+    assert_eq!(std::fs::read(&data_path).unwrap(), before);
+    let count: Value = client.get(format!("{}/count", base_url)).send().await.unwrap().json().await.unwrap();
+    assert_eq!(count["count"], 0);
+}
+
+/// Provider that counts its calls in flight and remembers the highest count seen
+#[derive(Default)]
+struct CountingProvider {
+    in_flight: Arc<AtomicUsize>,
+    max_in_flight: Arc<AtomicUsize>,
+}
+
+#[async_trait::async_trait]
+impl EmbeddingProvider for CountingProvider {
+    fn name(&self) -> &'static str {
+        "counting"
+    }
+
+    fn default_model(&self) -> &'static str {
+        "counting"
+    }
+
+    async fn embed(
+        &self,
+        _client: &reqwest::Client,
+        text: &str,
+        _model: &str,
+        _dimensions: Option<usize>,
+        _input_type: InputType,
+    ) -> Result<Vec<f64>, ProviderError> {
+        let in_flight = self.in_flight.fetch_add(1, AtomicOrdering::SeqCst) + 1;
+        self.max_in_flight.fetch_max(in_flight, AtomicOrdering::SeqCst);
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        self.in_flight.fetch_sub(1, AtomicOrdering::SeqCst);
+        Ok(fake_embedding(text))
+    }
+}
+
+#[tokio::test]
+async fn test_provider_concurrency_limit() {
+    let provider = CountingProvider::default();
+    let max_in_flight = provider.max_in_flight.clone();
+    let data_path = temp_data_path();
+    let service = Arc::new(
+        EmbeddingService::with_provider_and_data_path(Box::new(provider), data_path.to_str().unwrap())
+            .with_max_concurrent_embeddings(3),
+    );
+
+    let tasks: Vec<_> = (0..24)
+        .map(|i| {
+            let service = service.clone();
+            tokio::spawn(async move {
+                service.get_embedding(&format!("text {}", i), "counting", None, InputType::Document).await
+            })
+        })
+        .collect();
+    for task in tasks {
+        task.await.unwrap().unwrap();
+    }
+
+    assert_eq!(max_in_flight.load(AtomicOrdering::SeqCst), 3);
+}
+
+#[tokio::test]
+async fn test_gzip_storage() {
+    let data_path = tempfile::Builder::new()
+        .suffix(".jsonl.gz")
+        .tempfile()
+        .unwrap()
+        .into_temp_path();
+    let base_url = spawn_app_with_service(EmbeddingService::with_provider_and_data_path(
+        Box::new(MockProvider::default()),
+        data_path.to_str().unwrap(),
+    )).await;
+    let client = reqwest::Client::new();
+
+    let mut stored = HashMap::new();
+    for text in ["the quick brown fox", "a lazy dog"] {
+        let body: Value = client
+            .post(format!("{}/store", base_url))
+            .json(&json!({ "text": text, "embedding_type": "test" }))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        stored.insert(text, body["embedding"].clone());
+    }
+
+    // The file is gzip, not JSON lines
+    let bytes = std::fs::read(&data_path).unwrap();
+    assert_eq!(bytes[..2], [0x1f, 0x8b]);
+
+    let body: Value = client
+        .post(format!("{}/compare", base_url))
+        .json(&json!({ "text": "the quick brown fox jumps", "embedding_type": "test", "include_embeddings": true }))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    let results = body["results"].as_array().unwrap();
+    assert_eq!(results.len(), 2);
+    assert_eq!(results[0]["text"], "the quick brown fox");
+    for result in results {
+        assert_eq!(result["embedding"], stored[result["text"].as_str().unwrap()]);
+    }
+}
+
+#[tokio::test]
+async fn test_bincode_storage() {
+    let (base_url, _, data_path) = spawn_ollama_app_with(|service| {
+        service.with_storage_format(StorageFormat::Bincode)
+    }).await;
+    let client = reqwest::Client::new();
+
+    for text in ["aaab", "abcdef", "aaab"] {
+        client
+            .post(format!("{}/store", base_url))
+            .json(&json!({ "text": text, "embedding_type": "test" }))
+            .send()
+            .await
+            .unwrap();
+    }
+
+    // The file holds binary records, not JSON lines
+    let bytes = std::fs::read(&data_path).unwrap();
+    assert!(serde_json::from_slice::<Value>(&bytes).is_err());
+
+    let body: Value = client
+        .get(format!("{}/list", base_url))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(body["total"], 2);
+
+    let body: Value = client
+        .post(format!("{}/compare", base_url))
+        .json(&json!({ "text": "ab", "embedding_type": "test" }))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    let expected = 4.0 / (2.0_f64.sqrt() * 10.0_f64.sqrt());
+    let aaab = body["results"].as_array().unwrap().iter().find(|r| r["text"] == "aaab").unwrap();
+    assert!((aaab["similarity"].as_f64().unwrap() - expected).abs() < 1e-9);
+
+    let export = client
+        .get(format!("{}/export", base_url))
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert_eq!(export.lines().count(), 2);
+}
+
+/// Spawn the Ollama-backed app behind the auth middleware with the given token
+async fn spawn_app_with_auth(token: Option<&str>) -> (String, TempPath) {
+    let (ollama_url, _) = spawn_fake_ollama().await;
+    let data_path = temp_data_path();
+    let service = EmbeddingService::with_provider_and_data_path(
+        Box::new(OllamaProvider::new(ollama_url)),
+        data_path.to_str().unwrap(),
+    );
+    let auth = Arc::new(ApiAuth::new(token.map(str::to_string)));
+    let base_url = serve(app_router(service).layer(axum::middleware::from_fn_with_state(auth, require_api_key))).await;
+    (base_url, data_path)
+}
+
+#[tokio::test]
+async fn test_auth_accepts_valid_token() {
+    let (base_url, _data_path) = spawn_app_with_auth(Some("secret")).await;
+    let client = reqwest::Client::new();
+
+    let response = client
+        .post(format!("{}/store", base_url))
+        .bearer_auth("secret")
+        .json(&json!({ "text": "hello", "embedding_type": "test" }))
+        .send()
+        .await
+        .unwrap();
+    assert!(response.status().is_success());
+
+    let response = client
+        .get(format!("{}/list", base_url))
+        .header("X-API-Key", "secret")
+        .send()
+        .await
+        .unwrap();
+    assert!(response.status().is_success());
+}
+
+#[tokio::test]
+async fn test_auth_rejects_missing_or_wrong_token() {
+    let (base_url, _data_path) = spawn_app_with_auth(Some("secret")).await;
+    let client = reqwest::Client::new();
+
+    let response = client.post(format!("{}/clear", base_url)).send().await.unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::UNAUTHORIZED);
+    let response = client.post(format!("{}/reindex", base_url)).send().await.unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::UNAUTHORIZED);
+
+    let response = client
+        .post(format!("{}/clear", base_url))
+        .bearer_auth("wrong")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::UNAUTHORIZED);
+    let body: Value = response.json().await.unwrap();
+    assert!(body["error"].is_string());
+
+    // Probes stay open
+    let response = client.get(format!("{}/health", base_url)).send().await.unwrap();
+    assert!(response.status().is_success());
+}
+
+#[tokio::test]
+async fn test_auth_disabled_without_token() {
+    let (base_url, _data_path) = spawn_app_with_auth(None).await;
+    let client = reqwest::Client::new();
+
+    let response = client
+        .post(format!("{}/store", base_url))
+        .json(&json!({ "text": "hello", "embedding_type": "test" }))
+        .send()
+        .await
+        .unwrap();
+    assert!(response.status().is_success());
+}
+
+#[tokio::test]
+async fn test_cors_preflight() {
+    let spawn = |origins: Option<&'static str>, production: bool| async move {
+        let service = EmbeddingService::with_provider_and_data_path(Box::new(MockProvider::default()), temp_data_path().to_str().unwrap());
+        let auth = Arc::new(ApiAuth::new(Some("secret".to_string())));
+        serve(
+            app_router(service)
+                .layer(axum::middleware::from_fn_with_state(auth, require_api_key))
+                .layer(cors_layer(origins, production)),
+        )
+        .await
+    };
+    let client = reqwest::Client::new();
+    let preflight = |base_url: String, origin: &'static str| {
+        client
+            .request(reqwest::Method::OPTIONS, format!("{}/store", base_url))
+            .header("Origin", origin)
+            .header("Access-Control-Request-Method", "POST")
+            .header("Access-Control-Request-Headers", "content-type,authorization")
+            .send()
+    };
+    let allowed_origin = |response: &reqwest::Response| {
+        response.headers().get("access-control-allow-origin").map(|v| v.to_str().unwrap().to_string())
+    };
+
+    // Preflights carry no token but still succeed
+    let base_url = spawn(Some("https://app.example.com, https://admin.example.com"), true).await;
+    let response = preflight(base_url.clone(), "https://app.example.com").await.unwrap();
+    assert_eq!(response.status(), 200);
+    assert_eq!(allowed_origin(&response).as_deref(), Some("https://app.example.com"));
+    let methods = response.headers()["access-control-allow-methods"].to_str().unwrap().to_string();
+    assert!(methods.contains("POST"), "{}", methods);
+    let response = preflight(base_url.clone(), "https://evil.example.com").await.unwrap();
+    assert_eq!(allowed_origin(&response), None);
+
+    // Actual requests get the header too, and still need the token
+    let response = client
+        .post(format!("{}/store", base_url))
+        .header("Origin", "https://admin.example.com")
+        .json(&json!({ "text": "hello", "embedding_type": "test" }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 401);
+    assert_eq!(allowed_origin(&response).as_deref(), Some("https://admin.example.com"));
+
+    // Any origin in development, none in production, unless origins are listed
+    let base_url = spawn(None, false).await;
+    let response = preflight(base_url, "http://localhost:5173").await.unwrap();
+    assert_eq!(allowed_origin(&response).as_deref(), Some("*"));
+    let base_url = spawn(None, true).await;
+    let response = preflight(base_url, "http://localhost:5173").await.unwrap();
+    assert_eq!(allowed_origin(&response), None);
+}
+
+#[tokio::test]
+async fn test_body_size_limit() {
+    let data_path = temp_data_path();
+    let service = EmbeddingService::with_provider_and_data_path(Box::new(MockProvider::default()), data_path.to_str().unwrap());
+    let base_url = serve(
+        app_router(service)
+            .layer(axum::extract::DefaultBodyLimit::disable())
+            .layer(RequestBodyLimitLayer::new(4096)),
+    )
+    .await;
+    let client = reqwest::Client::new();
+
+    let response = client
+        .post(format!("{}/store", base_url))
+        .json(&json!({ "text": "word ".repeat(1000), "embedding_type": "test" }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::PAYLOAD_TOO_LARGE);
+    let response = client
+        .post(format!("{}/import", base_url))
+        .body("x".repeat(10_000))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::PAYLOAD_TOO_LARGE);
+    // Nothing over the limit was stored
+    assert_eq!(std::fs::metadata(&data_path).map(|m| m.len()).unwrap_or(0), 0);
+
+    let response = client
+        .post(format!("{}/store", base_url))
+        .json(&json!({ "text": "word ".repeat(100), "embedding_type": "test" }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200);
+}
+
+#[tokio::test]
+async fn test_rate_limit_returns_429() {
+    let (ollama_url, _) = spawn_fake_ollama().await;
+    let data_path = temp_data_path();
+    let service = Arc::new(EmbeddingService::with_provider_and_data_path(
+        Box::new(OllamaProvider::new(ollama_url)),
+        data_path.to_str().unwrap(),
+    ));
+    let limiter = Arc::new(RateLimiter::new(Some(3)));
+    let app = Router::new()
+        .route("/store", post(store_embedding).layer(axum::middleware::from_fn_with_state(limiter.clone(), rate_limit)))
+        .route("/health", get(health_check))
+        .with_state(service.clone());
+    let base_url = serve(app).await;
+    let client = reqwest::Client::new();
+
+    for i in 0..3 {
+        let response = client
+            .post(format!("{}/store", base_url))
+            .json(&json!({ "text": format!("text {}", i), "embedding_type": "test" }))
+            .send()
+            .await
+            .unwrap();
+        assert!(response.status().is_success());
+    }
+
+    let response = client
+        .post(format!("{}/store", base_url))
+        .json(&json!({ "text": "one too many", "embedding_type": "test" }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::TOO_MANY_REQUESTS);
+    let retry_after: u64 = response.headers()["retry-after"].to_str().unwrap().parse().unwrap();
+    assert!((1..=20).contains(&retry_after), "retry after {}s", retry_after);
+
+    // Without auth nothing checks a token, so sending a new one doesn't buy a fresh budget
+    let response = client
+        .post(format!("{}/store", base_url))
+        .bearer_auth("made-up-token")
+        .json(&json!({ "text": "from another client", "embedding_type": "test" }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::TOO_MANY_REQUESTS);
+    // Unlimited routes are unaffected
+    let response = client.get(format!("{}/health", base_url)).send().await.unwrap();
+    assert!(response.status().is_success());
+
+    // A validated token has its own budget, apart from its IP's
+    let authed_app = Router::new()
+        .route("/store", post(store_embedding).layer(axum::middleware::from_fn_with_state(limiter, rate_limit)))
+        .layer(axum::middleware::from_fn_with_state(Arc::new(ApiAuth::new(Some("secret".to_string()))), require_api_key))
+        .with_state(service);
+    let authed_url = serve(authed_app).await;
+    let response = client
+        .post(format!("{}/store", authed_url))
+        .bearer_auth("secret")
+        .json(&json!({ "text": "from an authenticated client", "embedding_type": "test" }))
+        .send()
+        .await
+        .unwrap();
+    assert!(response.status().is_success());
+}
+
+#[tokio::test]
+async fn test_embedding_cache_avoids_repeat_provider_calls() {
+    let (ollama_url, models) = spawn_fake_ollama().await;
+    let data_path = temp_data_path();
+    let service = EmbeddingService::with_provider_and_data_path(
+        Box::new(OllamaProvider::new(ollama_url)),
+        data_path.to_str().unwrap(),
+    ).with_embedding_cache_size(10);
+
+    let first = service.get_embedding("cached text", "nomic-embed-text", None, InputType::Query).await.unwrap();
+    let second = service.get_embedding("cached text", "nomic-embed-text", None, InputType::Query).await.unwrap();
+    assert_eq!(first, second);
+    assert_eq!(models.lock().unwrap().len(), 1);
+
+    // A different model is a different cache entry
+    service.get_embedding("cached text", "all-minilm", None, InputType::Query).await.unwrap();
+    assert_eq!(models.lock().unwrap().len(), 2);
+}
+
+#[tokio::test]
+async fn test_idempotency_key_replays_store() {
+    // Without the embedding cache, so only the key can save the second provider call
+    let (base_url, models, data_path) = spawn_ollama_app_with(|service| service.with_embedding_cache_size(0)).await;
+    let client = reqwest::Client::new();
+    let store = |key: &'static str, text: &'static str| {
+        client
+            .post(format!("{}/store", base_url))
+            .header("Idempotency-Key", key)
+            .json(&json!({ "text": text, "embedding_type": "test" }))
+            .send()
+    };
+
+    let first = store("retry-1", "flaky network").await.unwrap();
+    assert_eq!(first.status(), reqwest::StatusCode::OK);
+    let first: Value = first.json().await.unwrap();
+    assert_eq!(first["stored"], true);
+    let replay = store("retry-1", "flaky network").await.unwrap();
+    assert_eq!(replay.status(), reqwest::StatusCode::OK);
+    let replay: Value = replay.json().await.unwrap();
+    // The original response, so the client learns its store went through, not that it's a duplicate
+    assert_eq!(replay, first);
+    assert_eq!(models.lock().unwrap().len(), 1);
+    assert_eq!(std::fs::read_to_string(&data_path).unwrap().lines().count(), 1);
+
+    // The key can't be reused for another request
+    let response = store("retry-1", "another text").await.unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(models.lock().unwrap().len(), 1);
+
+    // A new key, or none, stores as usual and reports the duplicate
+    let body: Value = store("retry-2", "flaky network").await.unwrap().json().await.unwrap();
+    assert_eq!(body["stored"], false);
+    assert_eq!(models.lock().unwrap().len(), 2);
+
+    let response = client
+        .post(format!("{}/store", base_url))
+        .header("Idempotency-Key", "")
+        .json(&json!({ "text": "flaky network", "embedding_type": "test" }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn test_strict_dimensions() {
+    let (base_url, _, data_path) = spawn_ollama_app_with(|service| service.with_strict_dimensions(true)).await;
+    let client = reqwest::Client::new();
+
+    // A type already holding 3-dimensional vectors, as if stored with another model. Written
+    // before the first store, since the service reads the file's hashes and dimensions once.
+    let legacy = json!({ "text": "legacy", "embedding": [1.0, 0.0, 0.0], "model": "other", "embedding_type": "small" });
+    std::fs::write(&data_path, format!("{}\n", legacy)).unwrap();
+
+    let body: Value = client
+        .post(format!("{}/store", base_url))
+        .json(&json!({ "text": "first", "embedding_type": "test" }))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(body["dimensions"], 16);
+    let content = std::fs::read_to_string(&data_path).unwrap();
+    let record: Value = serde_json::from_str(content.lines().last().unwrap()).unwrap();
+    assert_eq!(record["dimensions"], 16);
+
+    let response = client
+        .post(format!("{}/store", base_url))
+        .json(&json!({ "text": "mismatched", "embedding_type": "small" }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::BAD_REQUEST);
+    let body: Value = response.json().await.unwrap();
+    assert!(body["error"].as_str().unwrap().contains("dimension mismatch"));
+
+    // Imported records are held to the stored dimension too, or to the first seen for a new type
+    let dump = [
+        json!({ "text": "fits", "embedding": [0.0, 1.0, 0.0], "embedding_type": "small" }),
+        json!({ "text": "too short", "embedding": [0.0, 1.0], "embedding_type": "small" }),
+        json!({ "text": "new type", "embedding": [0.0, 1.0], "embedding_type": "fresh" }),
+        json!({ "text": "new type, longer", "embedding": [0.0, 1.0, 0.0, 0.0], "embedding_type": "fresh" }),
+    ]
+    .map(|record| record.to_string())
+    .join("\n");
+    let body: Value = client.post(format!("{}/import", base_url)).body(dump).send().await.unwrap().json().await.unwrap();
+    assert_eq!(body["imported"], 2);
+    assert_eq!(body["skipped_invalid"], 2);
+
+    // Without the flag the store goes through
+    let (base_url, _, data_path) = spawn_ollama_app().await;
+    std::fs::write(&data_path, format!("{}\n", legacy)).unwrap();
+    let body: Value = client
+        .post(format!("{}/store", base_url))
+        .json(&json!({ "text": "mismatched", "embedding_type": "small" }))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(body["stored"], true);
+}
+
+#[tokio::test]
+async fn test_unsupported_dimensions_rejected() {
+    let (base_url, models, _data_path) = spawn_ollama_app().await;
+    let client = reqwest::Client::new();
+
+    let response = client
+        .post(format!("{}/store", base_url))
+        .json(&json!({ "text": "short", "embedding_type": "test", "dimensions": 8 }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::BAD_REQUEST);
+    // Rejected before calling the provider
+    assert!(models.lock().unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn test_missing_api_key_returns_error() {
+    if std::env::var("COHERE_API_KEY").is_ok() {
+        return;
+    }
+    // Nothing listens on the base URL: the key check fails before any request is made
+    let data_path = temp_data_path();
+    let base_url = spawn_app_with_service(EmbeddingService::with_provider_and_data_path(
+        Box::new(rust_embedding::embeddings::provider::CohereProvider {
+            base_url: "http://127.0.0.1:1".to_string(),
+            api_key: None,
+        }),
+        data_path.to_str().unwrap(),
+    )).await;
+
+    let response = reqwest::Client::new()
+        .post(format!("{}/store", base_url))
+        .json(&json!({ "text": "hello", "embedding_type": "test" }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::INTERNAL_SERVER_ERROR);
+    let body: Value = response.json().await.unwrap();
+    assert!(body["error"].as_str().unwrap().starts_with("provider not configured"), "{}", body);
+}
+
+#[tokio::test]
+async fn test_similarity_matrix() {
+    let (base_url, _, _data_path) = spawn_ollama_app().await;
+    let client = reqwest::Client::new();
+    let matrix = |body: Value| {
+        let client = client.clone();
+        let base_url = base_url.clone();
+        async move {
+            let response = client.post(format!("{}/matrix", base_url)).json(&body).send().await.unwrap();
+            (response.status(), response.json::<Value>().await.unwrap())
+        }
+    };
+    let texts = ["aaaa", "aaab", "zzzz"];
+    let cell = |body: &Value, i: usize, j: usize| body["matrix"][i][j].as_f64().unwrap();
+
+    let (status, body) = matrix(json!({ "texts": texts })).await;
+    assert_eq!(status, 200);
+    assert_eq!(body["labels"], json!(texts));
+    assert_eq!(body["matrix"].as_array().unwrap().len(), 3);
+    for i in 0..3 {
+        assert!((cell(&body, i, i) - 1.0).abs() < 1e-12);
+        for j in 0..3 {
+            assert_eq!(cell(&body, i, j), cell(&body, j, i));
+        }
+    }
+    let expected = cosine_similarity(&fake_embedding("aaaa"), &fake_embedding("aaab")).unwrap();
+    assert!((cell(&body, 0, 1) - expected).abs() < 1e-12);
+    assert!(cell(&body, 0, 1) > cell(&body, 0, 2));
+
+    // Euclidean as 1 / (1 + distance): one on the diagonal, smaller for less similar texts
+    let (_, body) = matrix(json!({ "texts": texts, "metric": "euclidean" })).await;
+    assert_eq!(cell(&body, 1, 1), 1.0);
+    let distance = euclidean_distance(&fake_embedding("aaaa"), &fake_embedding("aaab")).unwrap();
+    assert!((cell(&body, 0, 1) - 1.0 / (1.0 + distance)).abs() < 1e-12);
+    assert!(cell(&body, 0, 1) > cell(&body, 0, 2));
+
+    let (status, _) = matrix(json!({ "texts": [] })).await;
+    assert_eq!(status, reqwest::StatusCode::BAD_REQUEST);
+    let too_many: Vec<String> = (0..201).map(|i| format!("text {}", i)).collect();
+    let (status, body) = matrix(json!({ "texts": too_many })).await;
+    assert_eq!(status, reqwest::StatusCode::BAD_REQUEST);
+    assert!(body["error"].as_str().unwrap().contains("200"), "{}", body);
+}
+
+/// Provider that counts its single and batch calls
+#[derive(Default)]
+struct BatchCountingProvider {
+    embed_calls: Arc<AtomicUsize>,
+    batch_calls: Arc<AtomicUsize>,
+}
+
+#[async_trait::async_trait]
+impl EmbeddingProvider for BatchCountingProvider {
+    fn name(&self) -> &'static str {
+        "batch-counting"
+    }
+
+    fn default_model(&self) -> &'static str {
+        "batch-counting"
+    }
+
+    fn model_dimensions(&self, _model: &str) -> Option<usize> {
+        Some(16)
+    }
+
+    async fn embed(
+        &self,
+        _client: &reqwest::Client,
+        text: &str,
+        _model: &str,
+        _dimensions: Option<usize>,
+        _input_type: InputType,
+    ) -> Result<Vec<f64>, ProviderError> {
+        self.embed_calls.fetch_add(1, AtomicOrdering::SeqCst);
+        Ok(fake_embedding(text))
+    }
+
+    async fn embed_batch(
+        &self,
+        _client: &reqwest::Client,
+        texts: &[String],
+        _model: &str,
+        _dimensions: Option<usize>,
+        _input_type: InputType,
+    ) -> Result<Vec<Vec<f64>>, ProviderError> {
+        self.batch_calls.fetch_add(1, AtomicOrdering::SeqCst);
+        Ok(texts.iter().map(|text| fake_embedding(text)).collect())
+    }
+}
+
+#[tokio::test]
+async fn test_similarity_matrix_embeds_in_one_call() {
+    let provider = BatchCountingProvider::default();
+    let (embed_calls, batch_calls) = (provider.embed_calls.clone(), provider.batch_calls.clone());
+    let data_path = temp_data_path();
+    let base_url = spawn_app_with_service(EmbeddingService::with_provider_and_data_path(Box::new(provider), data_path.to_str().unwrap())).await;
+
+    let texts: Vec<String> = (0..50).map(|i| format!("text {}", i)).collect();
+    let response = reqwest::Client::new()
+        .post(format!("{}/matrix", base_url))
+        .json(&json!({ "texts": texts }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::OK);
+    let body: Value = response.json().await.unwrap();
+    assert_eq!(body["matrix"].as_array().unwrap().len(), 50);
+    assert_eq!(batch_calls.load(AtomicOrdering::SeqCst), 1);
+    assert_eq!(embed_calls.load(AtomicOrdering::SeqCst), 0);
+}
+
+#[tokio::test]
+async fn test_list_models() {
+    // OpenAI with its default allowlist, which needs no network to list
+    let data_path = temp_data_path();
+    let base_url = spawn_app_with_service(EmbeddingService::with_provider_and_data_path(
+        Box::new(rust_embedding::embeddings::provider::OpenAiProvider::new()),
+        data_path.to_str().unwrap(),
+    )).await;
+    let client = reqwest::Client::new();
+
+    let body: Value = client.get(format!("{}/models", base_url)).send().await.unwrap().json().await.unwrap();
+    let models = body["models"].as_array().unwrap();
+    let large = models.iter().find(|m| m["name"] == "text-embedding-3-large").unwrap();
+    assert_eq!(large["dimensions"], 3072);
+    assert_eq!(large["provider"], "openai");
+    assert_eq!(large["default"], true);
+    let small = models.iter().find(|m| m["name"] == "text-embedding-3-small").unwrap();
+    assert_eq!(small["dimensions"], 1536);
+    assert_eq!(body["any_model"], false);
+
+    // Ollama accepts any model, so only the default is listed
+    let (base_url, _, _data_path) = spawn_ollama_app().await;
+    let body: Value = client.get(format!("{}/models", base_url)).send().await.unwrap().json().await.unwrap();
+    assert_eq!(body["models"].as_array().unwrap().len(), 1);
+    assert_eq!(body["models"][0]["name"], "nomic-embed-text");
+    assert_eq!(body["any_model"], true);
+}
+
+#[tokio::test]
+async fn test_compare_against_empty_store() {
+    let (base_url, _, _data_path) = spawn_ollama_app().await;
+    let client = reqwest::Client::new();
+
+    for request in [json!({ "text": "anything" }), json!({ "text": "anything", "embedding_type": "test" })] {
+        let response = client
+            .post(format!("{}/compare", base_url))
+            .json(&request)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::OK);
+        let body: Value = response.json().await.unwrap();
+        assert_eq!(body["results"], json!([]));
+    }
+}
+
+#[tokio::test]
+async fn test_compare_min_similarity() {
+    let (base_url, _, _data_path) = spawn_ollama_app().await;
+    let client = reqwest::Client::new();
+
+    // "abab" points the same way as the query; the others share nothing with it
+    for text in ["abab", "xyz", "ghij"] {
+        client
+            .post(format!("{}/store", base_url))
+            .json(&json!({ "text": text, "embedding_type": "test" }))
+            .send()
+            .await
+            .unwrap();
+    }
+
+    let body: Value = client
+        .post(format!("{}/compare", base_url))
+        .json(&json!({ "text": "ab", "embedding_type": "test", "min_similarity": 0.5, "top_k": 3 }))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    let results = body["results"].as_array().unwrap();
+    assert_eq!(results.len(), 1);
+    assert_eq!(results[0]["text"], "abab");
+
+    // Without the threshold the dissimilar texts come back too
+    let body: Value = client
+        .post(format!("{}/compare", base_url))
+        .json(&json!({ "text": "ab", "embedding_type": "test", "top_k": 3 }))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(body["results"].as_array().unwrap().len(), 3);
+}
+
+#[tokio::test]
+async fn test_compare_best_only() {
+    let (base_url, _, _data_path) = spawn_ollama_app().await;
+    let client = reqwest::Client::new();
+    let compare = |request: Value| {
+        let sent = client.post(format!("{}/compare", base_url)).json(&request).send();
+        async move {
+            let response = sent.await.unwrap();
+            assert_eq!(response.status(), reqwest::StatusCode::OK);
+            response.json::<Value>().await.unwrap()
+        }
+    };
+
+    // Nothing stored yet, so there is no best match
+    let body = compare(json!({ "text": "ab", "embedding_type": "test", "best_only": true })).await;
+    assert_eq!(body, json!({ "best": null }));
+
+    for text in ["abab", "xyz", "ghij"] {
+        client
+            .post(format!("{}/store", base_url))
+            .json(&json!({ "text": text, "embedding_type": "test" }))
+            .send()
+            .await
+            .unwrap();
+    }
+
+    // top_k is ignored, and the result is the first of the full ranking
+    let body = compare(json!({ "text": "ab", "embedding_type": "test", "best_only": true, "top_k": 3 })).await;
+    assert!(body.get("results").is_none());
+    assert_eq!(body["best"]["text"], "abab");
+    let ranked = compare(json!({ "text": "ab", "embedding_type": "test" })).await;
+    assert_eq!(body["best"], ranked["results"][0]);
+
+    let body = compare(json!({ "text": "xx", "embedding_type": "test", "best_only": true, "min_similarity": 0.9 })).await;
+    assert_eq!(body["best"], Value::Null);
+}
+
+#[tokio::test]
+async fn test_compare_per_type_k() {
+    let (base_url, _, _data_path) = spawn_ollama_app().await;
+    let client = reqwest::Client::new();
+    let compare = |request: Value| {
+        let sent = client.post(format!("{}/compare", base_url)).json(&request).send();
+        async move { sent.await.unwrap() }
+    };
+    for (text, embedding_type) in [("abab", "docs"), ("aab", "docs"), ("abb", "docs"), ("xyz", "titles"), ("ghij", "titles"), ("xyzb", "titles")] {
+        client
+            .post(format!("{}/store", base_url))
+            .json(&json!({ "text": text, "embedding_type": embedding_type }))
+            .send()
+            .await
+            .unwrap();
+    }
+
+    // Globally the docs crowd out the titles
+    let body: Value = compare(json!({ "text": "ab", "top_k": 2 })).await.json().await.unwrap();
+    assert!(body["results"].as_array().unwrap().iter().all(|result| result["embedding_type"] == "docs"));
+
+    let response = compare(json!({ "text": "ab", "per_type_k": 2, "top_k": 1 })).await;
+    assert_eq!(response.status(), reqwest::StatusCode::OK);
+    let body: Value = response.json().await.unwrap();
+    assert!(body.get("results").is_none());
+    let results_by_type = body["results_by_type"].as_object().unwrap();
+    assert_eq!(results_by_type.keys().collect::<Vec<_>>(), ["docs", "titles"]);
+    for (embedding_type, results) in results_by_type {
+        let results = results.as_array().unwrap();
+        assert_eq!(results.len(), 2);
+        assert!(results.iter().all(|result| &result["embedding_type"] == embedding_type));
+        // Each type is ranked as if it were compared on its own
+        let alone: Value = compare(json!({ "text": "ab", "embedding_type": embedding_type, "top_k": 2 })).await.json().await.unwrap();
+        assert_eq!(results, alone["results"].as_array().unwrap());
+    }
+
+    let body: Value = compare(json!({ "text": "ab", "per_type_k": 1, "fields": ["text"] })).await.json().await.unwrap();
+    assert_eq!(body["results_by_type"]["titles"], json!([{ "text": "xyzb" }]));
+
+    for request in [json!({ "text": "ab", "per_type_k": 0 }), json!({ "text": "ab", "per_type_k": 2, "best_only": true })] {
+        assert_eq!(compare(request).await.status(), reqwest::StatusCode::BAD_REQUEST);
+    }
+}
+
+#[tokio::test]
+async fn test_compare_exact_match() {
+    let (base_url, models, _data_path) = spawn_ollama_app().await;
+    let client = reqwest::Client::new();
+    let compare = |request: Value| {
+        let sent = client.post(format!("{}/compare", base_url)).json(&request).send();
+        async move { sent.await.unwrap() }
+    };
+    for (text, embedding_type) in [("foo", "test"), ("food", "test"), ("foo", "other")] {
+        client
+            .post(format!("{}/store", base_url))
+            .json(&json!({ "text": text, "embedding_type": embedding_type }))
+            .send()
+            .await
+            .unwrap();
+    }
+
+    let body: Value = compare(json!({ "text": "foo" })).await.json().await.unwrap();
+    let results = body["results"].as_array().unwrap();
+    assert_eq!(results.len(), 3);
+    for result in &results[..2] {
+        assert_eq!(result["text"], "foo");
+        assert_eq!(result["exact_match"], true);
+        assert_eq!(result["similarity"], 1.0);
+    }
+    assert_eq!(results[2]["text"], "food");
+    assert!(results[2].get("exact_match").is_none());
+
+    // Only the stored copies of the text, including within the requested type, and no
+    // provider call
+    let calls = models.lock().unwrap().len();
+    let body: Value = compare(json!({ "text": "foo", "embedding_type": "test", "exact_only": true })).await.json().await.unwrap();
+    assert_eq!(body["results"], json!([{
+        "text": "foo",
+        "similarity": 1.0,
+        "embedding_type": "test",
+        "model": "nomic-embed-text",
+        "created_at": body["results"][0]["created_at"],
+        "exact_match": true,
+    }]));
+    let body: Value = compare(json!({ "text": "fo", "exact_only": true, "best_only": true })).await.json().await.unwrap();
+    assert_eq!(body, json!({ "best": null }));
+    assert_eq!(models.lock().unwrap().len(), calls);
+
+    let response = compare(json!({ "text": "foo", "exact_only": true, "exclude_self": true })).await;
+    assert_eq!(response.status(), reqwest::StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn test_compare_exact_match_needs_embedded_text() {
+    let (base_url, _data_path) = spawn_app().await;
+    let client = reqwest::Client::new();
+    let compare = |request: Value| {
+        let sent = client.post(format!("{}/compare", base_url)).json(&request).send();
+        async move { sent.await.unwrap().json::<Value>().await.unwrap() }
+    };
+    for text in ["red apple", "blue whale"] {
+        let response = client
+            .post(format!("{}/store", base_url))
+            .json(&json!({ "text": text, "embedding_type": "test" }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::OK);
+    }
+
+    // With a caller's vector the text is only a label: the entry sharing it is scored by its
+    // own vector
+    let embedding = MockProvider::embed_text("blue whale", 64);
+    let body = compare(json!({ "text": "red apple", "embedding": embedding })).await;
+    let results = body["results"].as_array().unwrap();
+    assert_eq!(results[0]["text"], "blue whale");
+    assert_eq!(results[1]["text"], "red apple");
+    assert!(results[1].get("exact_match").is_none());
+    assert!(results[1]["similarity"].as_f64().unwrap() < 1.0);
+
+    // Nor is an entry of the text embedded by another model an exact match
+    let body = compare(json!({ "text": "red apple", "model": "text-embedding-3-small" })).await;
+    assert!(body["results"].as_array().unwrap().iter().all(|result| result.get("exact_match").is_none()));
+    let body = compare(json!({ "text": "red apple" })).await;
+    assert_eq!(body["results"][0]["exact_match"], true);
+}
+
+#[tokio::test]
+async fn test_compare_best_exact_match_skips_provider() {
+    let provider = BatchCountingProvider::default();
+    let embed_calls = provider.embed_calls.clone();
+    let data_path = temp_data_path();
+    let base_url = spawn_app_with_service(EmbeddingService::with_provider_and_data_path(Box::new(provider), data_path.to_str().unwrap())).await;
+    let client = reqwest::Client::new();
+    let compare = |request: Value| {
+        let sent = client.post(format!("{}/compare", base_url)).json(&request).send();
+        async move { sent.await.unwrap().json::<Value>().await.unwrap() }
+    };
+    for text in ["foo", "food"] {
+        client.post(format!("{}/store", base_url)).json(&json!({ "text": text })).send().await.unwrap();
+    }
+    let calls = embed_calls.load(AtomicOrdering::SeqCst);
+
+    for request in [json!({ "text": "foo", "best_only": true }), json!({ "text": "foo", "top_k": 1 })] {
+        let body = compare(request).await;
+        let best = body.get("best").unwrap_or(&body["results"][0]);
+        assert_eq!(best["text"], "foo");
+        assert_eq!(best["exact_match"], true);
+    }
+    assert_eq!(embed_calls.load(AtomicOrdering::SeqCst), calls);
+
+    // More results, no stored copy of the text, or a boost that could rank another entry
+    // first all need the query embedded (each text once, as the query vectors are cached)
+    for request in [
+        json!({ "text": "foo", "top_k": 2 }),
+        json!({ "text": "fo", "best_only": true }),
+        json!({ "text": "food", "best_only": true, "type_boosts": { "default": 2.0 } }),
+    ] {
+        compare(request).await;
+    }
+    assert_eq!(embed_calls.load(AtomicOrdering::SeqCst), calls + 3);
+}
+
+#[tokio::test]
+async fn test_compare_hybrid_alpha() {
+    let (base_url, _data_path) = spawn_app().await;
+    let client = reqwest::Client::new();
+    for text in ["invoice payment overdue", "weather forecast for tomorrow"] {
+        client.post(format!("{}/store", base_url)).json(&json!({ "text": text })).send().await.unwrap();
+    }
+    let compare = |request: Value| client.post(format!("{}/compare", base_url)).json(&request).send();
+
+    let body: Value = compare(json!({ "text": "overdue invoice", "hybrid_alpha": 0.5, "fields": ["text", "lexical_score"] }))
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(body["results"][0], json!({ "text": "invoice payment overdue", "lexical_score": 1.0 }));
+    assert_eq!(body["results"][1], json!({ "text": "weather forecast for tomorrow", "lexical_score": 0.0 }));
+
+    let body: Value = compare(json!({ "text": "overdue invoice" })).await.unwrap().json().await.unwrap();
+    assert!(body["results"][0].get("lexical_score").is_none());
+
+    for alpha in [-0.1, 1.5] {
+        let response = compare(json!({ "text": "overdue invoice", "hybrid_alpha": alpha })).await.unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::BAD_REQUEST);
+    }
+}
+
+#[tokio::test]
+async fn test_compare_custom_metric() {
+    let data_path = temp_data_path();
+    // Cosine similarity over the first two dimensions only
+    let service = EmbeddingService::with_provider_and_data_path(Box::new(MockProvider::default()), data_path.to_str().unwrap())
+        .with_similarity_function("first_two", |a, b| {
+            cosine_similarity(&a[..2], &b[..2]).unwrap_or(f64::NAN)
+        });
+    assert_eq!(service.similarity_functions(), vec!["cosine", "dot", "euclidean", "first_two"]);
+    service.save_embedding("far in the tail", &[1.0, 0.0, 0.0, 5.0], "mock", "test", None).await.unwrap();
+    service.save_embedding("close overall", &[0.5, 0.5, 0.0, 0.0], "mock", "test", None).await.unwrap();
+
+    let query = [1.0, 0.0, 0.0, 0.0];
+    let compare = |metric: Option<&str>| {
+        let filter = CompareFilter { metric: metric.map(str::to_string), ..CompareFilter::default() };
+        service.compare_embeddings("query", &query, None, false, filter, None)
+    };
+    let results = compare(None).await.unwrap();
+    assert_eq!(results[0].text, "close overall");
+    let results = compare(Some("first_two")).await.unwrap();
+    assert_eq!(results[0].text, "far in the tail");
+    assert!((results[0].similarity - 1.0).abs() < 1e-9);
+    assert!(compare(Some("manhattan")).await.is_err());
+
+    let base_url = spawn_app_with_service(service).await;
+    let client = reqwest::Client::new();
+    let compare = |request: Value| client.post(format!("{}/compare", base_url)).json(&request).send();
+    let body: Value = compare(json!({ "embedding": query, "metric": "first_two", "fields": ["text"] }))
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(body["results"][0], json!({ "text": "far in the tail" }));
+    let body: Value = compare(json!({ "embedding": query, "metric": "euclidean", "fields": ["text"] }))
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(body["results"][0], json!({ "text": "close overall" }));
+    let response = compare(json!({ "embedding": query, "metric": "manhattan" })).await.unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::BAD_REQUEST);
+    let body: Value = response.json().await.unwrap();
+    assert!(body["error"].as_str().unwrap().contains("first_two"), "{}", body);
+}
+
+#[tokio::test]
+async fn test_compare_candidates() {
+    let (base_url, _data_path) = spawn_app().await;
+    let client = reqwest::Client::new();
+    for text in ["red apple", "green apple", "yellow banana", "orange carrot", "purple grape"] {
+        client.post(format!("{}/store", base_url)).json(&json!({ "text": text })).send().await.unwrap();
+    }
+
+    let response = client
+        .post(format!("{}/compare", base_url))
+        .json(&json!({
+            "text": "red apple",
+            "candidates": ["purple grape", "yellow banana", "never stored"],
+            "fields": ["text"],
+        }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::OK);
+    let body: Value = response.json().await.unwrap();
+    let mut texts: Vec<&str> = body["results"].as_array().unwrap().iter().map(|result| result["text"].as_str().unwrap()).collect();
+    texts.sort();
+    assert_eq!(texts, vec!["purple grape", "yellow banana"]);
+}
+
+#[tokio::test]
+async fn test_verbose_responses() {
+    let (base_url, _data_path) = spawn_app().await;
+    let client = reqwest::Client::new();
+    let post = |path: &str, body: Value| {
+        let sent = client.post(format!("{}{}", base_url, path)).json(&body).send();
+        async move { sent.await.unwrap().json::<Value>().await.unwrap() }
+    };
+    let plausible_timings = |meta: &Value| {
+        let ms = |key: &str| meta[key].as_u64().unwrap_or_else(|| panic!("{} missing from {}", key, meta));
+        assert!(ms("took_ms") >= ms("provider_ms") + ms("store_ms"), "{}", meta);
+        assert!(ms("took_ms") < 10_000, "{}", meta);
+    };
+
+    let body = post("/store", json!({ "text": "first note", "verbose": true })).await;
+    assert_eq!(body["stored"], true);
+    let meta = &body["meta"];
+    plausible_timings(meta);
+    assert_eq!(meta["provider"], "mock");
+    assert_eq!(meta["model"], "text-embedding-3-large");
+    assert!(meta.get("candidates_scanned").is_none());
+    for text in ["second note", "third note"] {
+        let body = post("/store", json!({ "text": text })).await;
+        assert!(body.get("meta").is_none(), "{}", body);
+    }
+
+    let body = post("/compare", json!({ "text": "a note", "top_k": 2, "verbose": true })).await;
+    assert_eq!(body["results"].as_array().unwrap().len(), 2);
+    let meta = &body["meta"];
+    plausible_timings(meta);
+    assert_eq!(meta["provider"], "mock");
+    assert_eq!(meta["model"], "text-embedding-3-large");
+    assert_eq!(meta["candidates_scanned"], 3);
+
+    // Only the candidates are scored, and a given vector isn't embedded with any model
+    let embedding = MockProvider::embed_text("a note", 64);
+    let body = post("/compare", json!({ "embedding": embedding, "candidates": ["first note"], "best_only": true, "verbose": true })).await;
+    assert_eq!(body["best"]["text"], "first note");
+    assert_eq!(body["meta"]["candidates_scanned"], 1);
+    assert_eq!(body["meta"]["provider_ms"], 0);
+    assert!(body["meta"].get("model").is_none());
+
+    let body = post("/compare", json!({ "text": "a note", "top_k": 2 })).await;
+    assert!(body.get("meta").is_none(), "{}", body);
+    let response = client
+        .post(format!("{}/compare/batch", base_url))
+        .json(&json!({ "queries": [{ "text": "a note", "verbose": true }] }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn test_compare_fields() {
+    let (base_url, _, _data_path) = spawn_ollama_app().await;
+    let client = reqwest::Client::new();
+    let compare = |request: Value| {
+        let sent = client.post(format!("{}/compare", base_url)).json(&request).send();
+        async move { sent.await.unwrap() }
+    };
+    for text in ["abab", "xyz"] {
+        client
+            .post(format!("{}/store", base_url))
+            .json(&json!({ "text": text, "embedding_type": "test" }))
+            .send()
+            .await
+            .unwrap();
+    }
+
+    let response = compare(json!({ "text": "ab", "embedding_type": "test", "fields": ["text", "similarity"] })).await;
+    assert_eq!(response.status(), reqwest::StatusCode::OK);
+    let body: Value = response.json().await.unwrap();
+    let results = body["results"].as_array().unwrap();
+    assert_eq!(results.len(), 2);
+    for result in results {
+        let mut keys: Vec<&str> = result.as_object().unwrap().keys().map(String::as_str).collect();
+        keys.sort();
+        assert_eq!(keys, ["similarity", "text"]);
+    }
+    assert_eq!(results[0]["text"], "abab");
+
+    // Asking for the embedding includes it without include_embeddings
+    let response = compare(json!({ "text": "ab", "embedding_type": "test", "best_only": true, "fields": ["embedding"] })).await;
+    let body: Value = response.json().await.unwrap();
+    assert_eq!(body["best"].as_object().unwrap().len(), 1);
+    assert_eq!(body["best"]["embedding"].as_array().unwrap().len(), 16);
+
+    let response = compare(json!({ "text": "ab", "embedding_type": "test", "fields": ["text", "score"] })).await;
+    assert_eq!(response.status(), reqwest::StatusCode::UNPROCESSABLE_ENTITY);
+    let body: Value = response.json().await.unwrap();
+    assert!(body["error"].as_str().unwrap().contains("fields"), "{}", body);
+}
+
+#[tokio::test]
+async fn test_duplicate_check_scales_with_store_size() {
+    let data_path = temp_data_path();
+    let service = EmbeddingService::with_provider_and_data_path(
+        Box::new(OllamaProvider::new("http://127.0.0.1:1".to_string())),
+        data_path.to_str().unwrap(),
+    );
+
+    let count = 5000;
+    let start = std::time::Instant::now();
+    for i in 0..count {
+        let embedding = [i as f64, 1.0, 2.0];
+        service.save_embedding(&format!("text {}", i), &embedding, "test", "test", None).await.unwrap();
+    }
+    let elapsed = start.elapsed();
+    println!("{} inserts in {:?} ({:?} per insert)", count, elapsed, elapsed / count);
+
+    assert_eq!(service.stored_vectors(), count as usize);
+    // Re-reading the whole file for each duplicate check took minutes at this size
+    assert!(elapsed < std::time::Duration::from_secs(30), "inserts took {:?}", elapsed);
+
+    let err = service.save_embedding("text 1234", &[0.0, 1.0, 2.0], "test", "test", None).await.unwrap_err();
+    assert!(err.to_string().contains("duplicate"));
+    // The same text under another type is not a duplicate
+    service.save_embedding("text 1234", &[0.0, 1.0, 2.0], "test", "other", None).await.unwrap();
+
+    // A fresh service over the same file, including records without stored hashes, still
+    // detects duplicates
+    let legacy = json!({ "text": "legacy", "model": "test", "embedding_type": "test", "embedding": [1.0, 2.0, 3.0] });
+    std::fs::OpenOptions::new()
+        .append(true)
+        .open(&data_path)
+        .and_then(|mut file| std::io::Write::write_all(&mut file, format!("{}\n", legacy).as_bytes()))
+        .unwrap();
+    let reopened = EmbeddingService::with_provider_and_data_path(
+        Box::new(OllamaProvider::new("http://127.0.0.1:1".to_string())),
+        data_path.to_str().unwrap(),
+    );
+    for text in ["text 0", "legacy"] {
+        let err = reopened.save_embedding(text, &[0.0, 1.0, 2.0], "test", "test", None).await.unwrap_err();
+        assert!(err.to_string().contains("duplicate"), "{} was stored twice", text);
+    }
+    assert_eq!(reopened.find_stored_embedding("legacy", "test").unwrap(), Some(vec![1.0, 2.0, 3.0]));
+}
+
+#[tokio::test]
+async fn test_compare_ties_are_ordered_by_text() {
+    let data_path = temp_data_path();
+    let service = EmbeddingService::with_provider_and_data_path(Box::new(MockProvider::default()), data_path.to_str().unwrap());
+    // Equal vectors, so every entry ties with the others
+    for text in ["pear", "apple", "fig"] {
+        service.save_embedding(text, &[1.0, 0.0, 0.0], "test", "test", None).await.unwrap();
+    }
+    service.save_embedding("apple", &[1.0, 0.0, 0.0], "test", "other", None).await.unwrap();
+
+    let ranked = |results: Vec<rust_embedding::ComparisonResult>| -> Vec<(String, String)> {
+        results.into_iter().map(|r| (r.text, r.embedding_type)).collect()
+    };
+    let results = service
+        .compare_embeddings("query", &[1.0, 0.0, 0.0], None, false, CompareFilter::default(), None)
+        .await
+        .unwrap();
+    assert_eq!(
+        ranked(results),
+        [("apple", "other"), ("apple", "test"), ("fig", "test"), ("pear", "test")].map(|(t, e)| (t.to_string(), e.to_string()))
+    );
+    // The heap used for top_k keeps the same order
+    let results = service
+        .compare_embeddings("query", &[1.0, 0.0, 0.0], Some(2), false, CompareFilter::default(), None)
+        .await
+        .unwrap();
+    assert_eq!(ranked(results), [("apple", "other"), ("apple", "test")].map(|(t, e)| (t.to_string(), e.to_string())));
+
+    let service = service.with_tie_break(TieBreak::Insertion);
+    let results = service
+        .compare_embeddings("query", &[1.0, 0.0, 0.0], Some(3), false, CompareFilter::default(), None)
+        .await
+        .unwrap();
+    let texts: Vec<String> = results.into_iter().map(|r| r.text).collect();
+    assert_eq!(texts, ["pear", "apple", "fig"]);
+}
+
+#[tokio::test]
+async fn test_count() {
+    let data_path = temp_data_path();
+    let records: Vec<String> = [
+        ("a", "title", "small"),
+        ("b", "title", "large"),
+        ("c", "body", "large"),
+        ("d", "body", "large"),
+        ("e", "body", "small"),
+    ]
+    .iter()
+    .map(|(text, embedding_type, model)| {
+        json!({ "text": text, "embedding": [1.0, 0.0], "model": model, "embedding_type": embedding_type }).to_string()
+    })
+    .collect();
+    std::fs::write(&data_path, records.join("\n") + "\n").unwrap();
+    let base_url = spawn_app_with_service(EmbeddingService::with_provider_and_data_path(
+        Box::new(MockProvider::default()),
+        data_path.to_str().unwrap(),
+    ))
+    .await;
+    let client = reqwest::Client::new();
+
+    for (query, expected) in [
+        ("", 5),
+        ("?embedding_type=title", 2),
+        ("?model=large", 3),
+        ("?embedding_type=body&model=large", 2),
+        ("?embedding_type=body&model=medium", 0),
+        ("?embedding_type=missing", 0),
+    ] {
+        let body: Value = client.get(format!("{}/count{}", base_url, query)).send().await.unwrap().json().await.unwrap();
+        assert_eq!(body, json!({ "count": expected }), "query {:?}", query);
+    }
+
+    client
+        .post(format!("{}/store", base_url))
+        .json(&json!({ "text": "f", "embedding_type": "title" }))
+        .send()
+        .await
+        .unwrap();
+    let body: Value = client.get(format!("{}/count", base_url)).send().await.unwrap().json().await.unwrap();
+    assert_eq!(body["count"], 6);
+    let body: Value = client.get(format!("{}/count?embedding_type=title", base_url)).send().await.unwrap().json().await.unwrap();
+    assert_eq!(body["count"], 3);
+}
+
+#[tokio::test]
+async fn test_stats() {
+    let (base_url, _, data_path) = spawn_ollama_app().await;
+    let client = reqwest::Client::new();
+
+    let body: Value = client.get(format!("{}/stats", base_url)).send().await.unwrap().json().await.unwrap();
+    assert_eq!(body["total"], 0);
+
+    // Written before the first store, which reads the file once
+    let legacy = json!({ "text": "legacy", "embedding": [1.0, 0.0, 0.0], "model": "other", "embedding_type": "title" });
+    std::fs::write(&data_path, format!("{}\n", legacy)).unwrap();
+    for (text, embedding_type) in [("a", "user"), ("b", "user"), ("c", "title")] {
+        client
+            .post(format!("{}/store", base_url))
+            .json(&json!({ "text": text, "embedding_type": embedding_type }))
+            .send()
+            .await
+            .unwrap();
+    }
+
+    let body: Value = client.get(format!("{}/stats", base_url)).send().await.unwrap().json().await.unwrap();
+    assert_eq!(body["total"], 4);
+    assert_eq!(body["by_type"], json!({ "user": 2, "title": 2 }));
+    assert_eq!(body["by_model"], json!({ "nomic-embed-text": 3, "other": 1 }));
+    // The legacy record mixes a second dimension into the "title" type
+    assert_eq!(body["dimensions"], json!({ "16": 3, "3": 1 }));
+}
+
+#[tokio::test]
+async fn test_compare_model_filter() {
+    let (base_url, _, _data_path) = spawn_ollama_app().await;
+    let client = reqwest::Client::new();
+
+    for (text, model, embedding_type) in [
+        ("large a", "large", "test"),
+        ("large b", "large", "other"),
+        ("small a", "small", "test"),
+        ("small b", "small", "other"),
+    ] {
+        client
+            .post(format!("{}/store", base_url))
+            .json(&json!({ "text": text, "model": model, "embedding_type": embedding_type }))
+            .send()
+            .await
+            .unwrap();
+    }
+
+    let compare = |body: Value| {
+        let client = client.clone();
+        let base_url = base_url.clone();
+        async move {
+            let body: Value = client
+                .post(format!("{}/compare", base_url))
+                .json(&body)
+                .send()
+                .await
+                .unwrap()
+                .json()
+                .await
+                .unwrap();
+            let mut texts: Vec<String> = body["results"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|r| r["text"].as_str().unwrap().to_string())
+                .collect();
+            texts.sort();
+            texts
+        }
+    };
+
+    // The query is embedded with the default model either way; the filter picks the candidates
+    assert_eq!(compare(json!({ "text": "query", "model_filter": "large" })).await, ["large a", "large b"]);
+    assert_eq!(compare(json!({ "text": "query", "model_filter": "small" })).await, ["small a", "small b"]);
+    assert_eq!(
+        compare(json!({ "text": "query", "model_filter": "small", "embedding_type": "test" })).await,
+        ["small a"]
+    );
+    assert_eq!(compare(json!({ "text": "query" })).await.len(), 4);
+}
+
+#[tokio::test]
+async fn test_compare_returns_query_embedding() {
+    let (base_url, _, _data_path) = spawn_ollama_app().await;
+    let client = reqwest::Client::new();
+
+    let body: Value = client
+        .post(format!("{}/compare", base_url))
+        .json(&json!({ "text": "query", "include_query_embedding": true }))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(body["query_embedding"], json!(fake_embedding("query")));
+
+    let body: Value = client
+        .post(format!("{}/compare", base_url))
+        .json(&json!({ "text": "query" }))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert!(body.get("query_embedding").is_none());
+}
+
+#[tokio::test]
+async fn test_compare_multiple_embedding_types() {
+    let (base_url, _, _data_path) = spawn_ollama_app().await;
+    let client = reqwest::Client::new();
+
+    for embedding_type in ["user", "title", "internal"] {
+        client
+            .post(format!("{}/store", base_url))
+            .json(&json!({ "text": format!("{} text", embedding_type), "embedding_type": embedding_type }))
+            .send()
+            .await
+            .unwrap();
+    }
+
+    let compare_types = |body: Value| {
+        let client = client.clone();
+        let base_url = base_url.clone();
+        async move {
+            let response = client.post(format!("{}/compare", base_url)).json(&body).send().await.unwrap();
+            let status = response.status();
+            let body: Value = response.json().await.unwrap();
+            let mut types: Vec<String> = body["results"]
+                .as_array()
+                .map(|results| results.iter().map(|r| r["embedding_type"].as_str().unwrap().to_string()).collect())
+                .unwrap_or_default();
+            types.sort();
+            (status, types)
+        }
+    };
+
+    let (_, types) = compare_types(json!({ "text": "query", "embedding_types": ["user", "title"] })).await;
+    assert_eq!(types, ["title", "user"]);
+    // The singular field still works, alone or added to the list
+    let (_, types) = compare_types(json!({ "text": "query", "embedding_type": "internal" })).await;
+    assert_eq!(types, ["internal"]);
+    let (_, types) = compare_types(json!({ "text": "query", "embedding_type": "internal", "embedding_types": ["user"] })).await;
+    assert_eq!(types, ["internal", "user"]);
+    let (_, types) = compare_types(json!({ "text": "query" })).await;
+    assert_eq!(types.len(), 3);
+
+    let (status, _) = compare_types(json!({ "text": "query", "embedding_types": [] })).await;
+    assert_eq!(status, reqwest::StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn test_compare_exclude_self() {
+    let (base_url, _data_path) = spawn_app().await;
+    let client = reqwest::Client::new();
+
+    for (text, embedding_type) in [("red apple", "title"), ("red apple", "tag"), ("green apple", "title")] {
+        client
+            .post(format!("{}/store", base_url))
+            .json(&json!({ "text": text, "embedding_type": embedding_type }))
+            .send()
+            .await
+            .unwrap();
+    }
+
+    let compare = |body: Value| {
+        let client = client.clone();
+        let base_url = base_url.clone();
+        async move {
+            let body: Value = client.post(format!("{}/compare", base_url)).json(&body).send().await.unwrap().json().await.unwrap();
+            body["results"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|r| format!("{}/{}", r["text"].as_str().unwrap(), r["embedding_type"].as_str().unwrap()))
+                .collect::<Vec<_>>()
+        }
+    };
+
+    // By default the query's own entry is only skipped within the requested types
+    let results = compare(json!({ "text": "red apple" })).await;
+    assert_eq!(results.len(), 3);
+    let results = compare(json!({ "text": "red apple", "embedding_types": ["title", "tag"] })).await;
+    assert_eq!(results, ["green apple/title"]);
+
+    // exclude_self skips it under every type
+    let results = compare(json!({ "text": "red apple", "exclude_self": true })).await;
+    assert_eq!(results, ["green apple/title"]);
+    let results = compare(json!({ "text": "red apple", "embedding_type": "title", "exclude_self": true })).await;
+    assert_eq!(results, ["green apple/title"]);
+}
+
+#[tokio::test]
+async fn test_malformed_request_errors_name_the_field() {
+    let (base_url, _data_path) = spawn_app().await;
+    let client = reqwest::Client::new();
+    let send = |path: &'static str, body: Value| {
+        let client = client.clone();
+        let base_url = base_url.clone();
+        async move {
+            let response = client.post(format!("{}{}", base_url, path)).json(&body).send().await.unwrap();
+            (response.status(), response.json::<Value>().await.unwrap())
+        }
+    };
+
+    let (status, body) = send("/store", json!({ "text": "hello", "embedding_type": 5 })).await;
+    assert_eq!(status, reqwest::StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(body["field"], "embedding_type");
+    assert_eq!(body["expected"], "a string");
+    assert_eq!(body["error"], "embedding_type: invalid type: integer `5`, expected a string");
+
+    let (status, body) = send("/store", json!({ "embedding_type": "test" })).await;
+    assert_eq!(status, reqwest::StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(body["field"], "text");
+    assert_eq!(body["error"], "missing field `text`");
+    assert!(body["expected"].is_string());
+
+    let (status, body) = send("/compare", json!({ "text": "hello", "top_k": "ten" })).await;
+    assert_eq!(status, reqwest::StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(body["field"], "top_k");
+    assert_eq!(body["expected"], "usize");
+
+    // Nested fields are named by their path
+    let (_, body) = send("/compare", json!({ "text": "hello", "type_boosts": { "title": "high" } })).await;
+    assert_eq!(body["field"], "type_boosts.title");
+    assert_eq!(body["expected"], "f64");
+
+    // Bodies that aren't JSON at all are a 400, with no field to point at
+    let response = client
+        .post(format!("{}/compare", base_url))
+        .header("Content-Type", "application/json")
+        .body("{\"text\": ")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::BAD_REQUEST);
+    let body: Value = response.json().await.unwrap();
+    assert!(body["error"].as_str().unwrap().starts_with("invalid JSON"), "{}", body);
+    assert!(body.get("field").is_none());
+
+    let response = client.post(format!("{}/store", base_url)).body("{}").send().await.unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::UNSUPPORTED_MEDIA_TYPE);
+}
+
+#[tokio::test]
+async fn test_store_without_type_uses_default() {
+    let (base_url, _data_path) = spawn_app().await;
+    let client = reqwest::Client::new();
+    let store = |body: Value| {
+        let client = client.clone();
+        let base_url = base_url.clone();
+        async move {
+            let body: Value = client.post(format!("{}/store", base_url)).json(&body).send().await.unwrap().json().await.unwrap();
+            body["stored"].as_bool().unwrap()
+        }
+    };
+
+    assert!(store(json!({ "text": "untyped" })).await);
+    // Omitting the type and naming the default are the same entry
+    assert!(!store(json!({ "text": "untyped", "embedding_type": "default" })).await);
+    assert!(store(json!({ "text": "untyped", "embedding_type": "other" })).await);
+
+    let body: Value = client.get(format!("{}/list?embedding_type=default", base_url)).send().await.unwrap().json().await.unwrap();
+    assert_eq!(body["total"], 1);
+    assert_eq!(body["items"][0]["text"], "untyped");
+
+    // The configured default applies instead
+    let data_path = temp_data_path();
+    let base_url = spawn_app_with_service(
+        EmbeddingService::with_provider_and_data_path(Box::new(MockProvider::default()), data_path.to_str().unwrap())
+            .with_default_embedding_type("notes"),
+    ).await;
+    client
+        .post(format!("{}/store", base_url))
+        .json(&json!({ "text": "untyped" }))
+        .send()
+        .await
+        .unwrap();
+    let body: Value = client.get(format!("{}/list", base_url)).send().await.unwrap().json().await.unwrap();
+    assert_eq!(body["items"][0]["embedding_type"], "notes");
+}
+
+#[tokio::test]
+async fn test_store_dry_run() {
+    let (base_url, data_path) = spawn_app().await;
+    let client = reqwest::Client::new();
+    client
+        .post(format!("{}/store", base_url))
+        .json(&json!({ "text": "kept", "embedding_type": "test" }))
+        .send()
+        .await
+        .unwrap();
+    let before = std::fs::read(&data_path).unwrap();
+
+    let body: Value = client
+        .post(format!("{}/store", base_url))
+        .json(&json!({ "text": "preview", "embedding_type": "test", "dry_run": true }))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(body["stored"], false);
+    assert!(!body["embedding"].as_array().unwrap().is_empty());
+    assert_eq!(body["dimensions"], body["embedding"].as_array().unwrap().len());
+    assert_eq!(std::fs::read(&data_path).unwrap(), before);
+
+    // Nothing was recorded, so a real store of the same text goes through
+    let body: Value = client
+        .post(format!("{}/store", base_url))
+        .json(&json!({ "text": "preview", "embedding_type": "test" }))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(body["stored"], true);
+}
+
+#[tokio::test]
+async fn test_store_evicts_oldest_beyond_max_entries() {
+    let data_path = temp_data_path();
+    let base_url = spawn_app_with_service(
+        EmbeddingService::with_provider_and_data_path(Box::new(MockProvider::default()), data_path.to_str().unwrap())
+            .with_max_stored_entries(3),
+    )
+    .await;
+    let client = reqwest::Client::new();
+
+    let mut responses = Vec::new();
+    for text in ["first", "second", "third", "fourth"] {
+        let body: Value = client
+            .post(format!("{}/store", base_url))
+            .json(&json!({ "text": text, "embedding_type": "test" }))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(body["stored"], true);
+        responses.push(body);
+    }
+    assert!(responses[..3].iter().all(|body| body.get("evicted").is_none()));
+    let evicted = responses[3]["evicted"].as_array().unwrap();
+    assert_eq!(evicted.len(), 1);
+    assert_eq!(evicted[0]["text"], "first");
+
+    let body: Value = client
+        .get(format!("{}/list", base_url))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    let texts: Vec<&str> = body["items"].as_array().unwrap().iter().map(|item| item["text"].as_str().unwrap()).collect();
+    assert_eq!(texts, ["second", "third", "fourth"]);
+
+    // An import is held to the same cap
+    let dump = ["fifth", "sixth"]
+        .map(|text| json!({ "text": text, "embedding": MockProvider::embed_text(text, 64), "embedding_type": "test" }).to_string())
+        .join("\n");
+    let body: Value = client.post(format!("{}/import", base_url)).body(dump).send().await.unwrap().json().await.unwrap();
+    assert_eq!(body["imported"], 2);
+    assert_eq!(body["evicted"], 2);
+    let body: Value = client.get(format!("{}/list", base_url)).send().await.unwrap().json().await.unwrap();
+    let texts: Vec<&str> = body["items"].as_array().unwrap().iter().map(|item| item["text"].as_str().unwrap()).collect();
+    assert_eq!(texts, ["fourth", "fifth", "sixth"]);
+    assert_eq!(body["total"], 3);
+}
+
+#[tokio::test]
+async fn test_created_at_time_filters() {
+    let data_path = temp_data_path();
+    let record = |text: &str, created_at: Option<&str>| {
+        let mut record = json!({
+            "text": text,
+            "model": "text-embedding-3-large",
+            "embedding_type": "test",
+            "embedding": MockProvider::embed_text(text, 64),
+        });
+        if let Some(created_at) = created_at {
+            record["created_at"] = created_at.into();
+        }
+        record.to_string()
+    };
+    let lines = [
+        record("legacy note", None),
+        record("morning note", Some("2024-05-01T08:00:00.000Z")),
+        // The same instant as the boundaries below, written with an offset
+        record("noon note", Some("2024-05-01T14:00:00+02:00")),
+        record("evening note", Some("2024-05-01T18:00:00.000Z")),
+    ];
+    std::fs::write(&data_path, lines.join("\n") + "\n").unwrap();
+    let base_url = spawn_app_with_service(EmbeddingService::with_provider_and_data_path(
+        Box::new(MockProvider::default()),
+        data_path.to_str().unwrap(),
+    ))
+    .await;
+    let client = reqwest::Client::new();
+
+    let list = |query: &'static str| {
+        let client = client.clone();
+        let base_url = base_url.clone();
+        async move {
+            let body: Value = client
+                .get(format!("{}/list?{}", base_url, query))
+                .send()
+                .await
+                .unwrap()
+                .json()
+                .await
+                .unwrap();
+            body["items"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|item| item["text"].as_str().unwrap().to_string())
+                .collect::<Vec<_>>()
+        }
+    };
+    // Without a filter every entry is listed, the legacy one with a null timestamp
+    assert_eq!(list("").await, ["legacy note", "morning note", "noon note", "evening note"]);
+    // created_after is inclusive and created_before exclusive
+    assert_eq!(list("created_after=2024-05-01T12:00:00Z").await, ["noon note", "evening note"]);
+    assert_eq!(list("created_before=2024-05-01T12:00:00Z").await, ["morning note"]);
+    assert_eq!(
+        list("created_after=2024-05-01T08:00:00Z&created_before=2024-05-01T18:00:00Z").await,
+        ["morning note", "noon note"]
+    );
+    assert!(list("created_after=2024-05-02T00:00:00Z").await.is_empty());
+
+    let body: Value = client.get(format!("{}/list", base_url)).send().await.unwrap().json().await.unwrap();
+    assert!(body["items"][0]["created_at"].is_null());
+    assert_eq!(body["items"][2]["created_at"], "2024-05-01T12:00:00Z");
+
+    let body: Value = client
+        .post(format!("{}/compare", base_url))
+        .json(&json!({
+            "text": "note",
+            "embedding_type": "test",
+            "created_after": "2024-05-01T08:00:00.001Z",
+            "created_before": "2024-05-01T18:00:00.001Z",
+        }))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    let mut texts: Vec<&str> = body["results"].as_array().unwrap().iter().map(|r| r["text"].as_str().unwrap()).collect();
+    texts.sort();
+    assert_eq!(texts, ["evening note", "noon note"]);
+
+    let response = client
+        .post(format!("{}/compare", base_url))
+        .json(&json!({ "text": "note", "created_after": "yesterday" }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 422);
+
+    // New entries are stamped when stored
+    client
+        .post(format!("{}/store", base_url))
+        .json(&json!({ "text": "fresh note", "embedding_type": "test" }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(list("created_after=2025-01-01T00:00:00Z").await, ["fresh note"]);
+}
+
+#[tokio::test]
+async fn test_store_metadata_round_trip() {
+    let (base_url, _data_path) = spawn_app().await;
+    let client = reqwest::Client::new();
+    let metadata = json!({ "source": "https://example.com/apples", "author": "sam", "page": 3 });
+
+    let response = client
+        .post(format!("{}/store", base_url))
+        .json(&json!({ "text": "red apple", "embedding_type": "fruit", "metadata": metadata }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200);
+    client
+        .post(format!("{}/store", base_url))
+        .json(&json!({ "text": "green pear", "embedding_type": "fruit" }))
+        .send()
+        .await
+        .unwrap();
+
+    // Metadata doesn't make an otherwise identical entry distinct
+    let response = client
+        .post(format!("{}/store", base_url))
+        .json(&json!({ "text": "red apple", "embedding_type": "fruit", "metadata": { "author": "kim" } }))
+        .send()
+        .await
+        .unwrap();
+    let body: Value = response.json().await.unwrap();
+    assert_eq!(body["stored"], false);
+
+    let body: Value = client
+        .post(format!("{}/compare", base_url))
+        .json(&json!({ "text": "apple" }))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    let results = body["results"].as_array().unwrap();
+    assert_eq!(results.len(), 2);
+    for result in results {
+        match result["text"].as_str().unwrap() {
+            "red apple" => assert_eq!(result["metadata"], metadata),
+            _ => assert!(result.get("metadata").is_none()),
+        }
+    }
+
+    let body: Value = client.get(format!("{}/list", base_url)).send().await.unwrap().json().await.unwrap();
+    let listed = body["items"].as_array().unwrap().iter().find(|item| item["text"] == "red apple").unwrap();
+    assert_eq!(listed["metadata"], metadata);
+}
+
+#[tokio::test]
+async fn test_compare_metadata_filter() {
+    let (base_url, _data_path) = spawn_app().await;
+    let client = reqwest::Client::new();
+    let entries = [
+        ("docs apple", "fruit", json!({ "source": "docs", "lang": "en" })),
+        ("docs pomme", "fruit", json!({ "source": "docs", "lang": "fr" })),
+        ("docs apple tag", "tag", json!({ "source": "docs", "lang": "en", "page": 2 })),
+        ("blog apple", "fruit", json!({ "source": "blog", "lang": "en" })),
+        ("plain apple", "fruit", Value::Null),
+    ];
+    for (text, embedding_type, metadata) in entries {
+        client
+            .post(format!("{}/store", base_url))
+            .json(&json!({ "text": text, "embedding_type": embedding_type, "metadata": metadata }))
+            .send()
+            .await
+            .unwrap();
+    }
+
+    let compare = |body: Value| {
+        let client = client.clone();
+        let base_url = base_url.clone();
+        async move {
+            let body: Value = client.post(format!("{}/compare", base_url)).json(&body).send().await.unwrap().json().await.unwrap();
+            let mut texts: Vec<String> = body["results"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|r| r["text"].as_str().unwrap().to_string())
+                .collect();
+            texts.sort();
+            texts
+        }
+    };
+
+    // Every pair must match; extra stored keys are fine
+    let results = compare(json!({ "text": "apple", "filter": { "source": "docs", "lang": "en" } })).await;
+    assert_eq!(results, ["docs apple", "docs apple tag"]);
+
+    // A partial match is excluded, as is an entry without metadata
+    let results = compare(json!({ "text": "apple", "filter": { "source": "docs" } })).await;
+    assert_eq!(results, ["docs apple", "docs apple tag", "docs pomme"]);
+    let results = compare(json!({ "text": "apple", "filter": { "lang": "en", "page": 3 } })).await;
+    assert!(results.is_empty());
+
+    // Combined with the type filter
+    let results = compare(json!({ "text": "apple", "embedding_type": "fruit", "filter": { "lang": "en" } })).await;
+    assert_eq!(results, ["blog apple", "docs apple"]);
+
+    // Without a filter nothing is excluded
+    assert_eq!(compare(json!({ "text": "apple" })).await.len(), 5);
+}
+
+#[tokio::test]
+async fn test_compare_with_provided_embedding() {
+    let (base_url, _data_path) = spawn_app().await;
+    let client = reqwest::Client::new();
+    for text in ["red apple", "red apple pie", "blue whale"] {
+        client
+            .post(format!("{}/store", base_url))
+            .json(&json!({ "text": text, "embedding_type": "fruit" }))
+            .send()
+            .await
+            .unwrap();
+    }
+    let compare = |body: Value| {
+        let client = client.clone();
+        let base_url = base_url.clone();
+        async move { client.post(format!("{}/compare", base_url)).json(&body).send().await.unwrap() }
+    };
+
+    // A vector computed elsewhere is ranked against the store without any text
+    let embedding = MockProvider::embed_text("red apple", 64);
+    let response = compare(json!({ "embedding": embedding, "embedding_type": "fruit" })).await;
+    assert_eq!(response.status(), 200);
+    let body: Value = response.json().await.unwrap();
+    let texts: Vec<&str> = body["results"].as_array().unwrap().iter().map(|r| r["text"].as_str().unwrap()).collect();
+    assert_eq!(texts, ["red apple", "red apple pie", "blue whale"]);
+    assert!((body["results"][0]["similarity"].as_f64().unwrap() - 1.0).abs() < 1e-9);
+
+    // Same ranking as embedding the text, which also leaves out the text itself
+    let body: Value = compare(json!({ "text": "red apple", "embedding": embedding, "embedding_type": "fruit" }))
+        .await
+        .json()
+        .await
+        .unwrap();
+    let texts: Vec<&str> = body["results"].as_array().unwrap().iter().map(|r| r["text"].as_str().unwrap()).collect();
+    assert_eq!(texts, ["red apple pie", "blue whale"]);
+
+    let response = compare(json!({ "embedding": [0.5, 0.5, 0.5], "embedding_type": "fruit" })).await;
+    assert_eq!(response.status(), 400);
+    let body: Value = response.json().await.unwrap();
+    assert!(body["error"].as_str().unwrap().contains("3 dimensions"));
+
+    let response = compare(json!({ "embedding": [], "embedding_type": "fruit" })).await;
+    assert_eq!(response.status(), 400);
+    let response = compare(json!({ "embedding_type": "fruit" })).await;
+    assert_eq!(response.status(), 400);
+}
+
+#[tokio::test]
+async fn test_namespaces_are_isolated() {
+    let data_dir = tempfile::tempdir().unwrap();
+    let data_path = data_dir.path().join("embeddings.jsonl");
+    let base_url = spawn_app_with_service(EmbeddingService::with_provider_and_data_path(
+        Box::new(MockProvider::default()),
+        data_path.to_str().unwrap(),
+    ))
+    .await;
+    let client = reqwest::Client::new();
+    let post = |path: &'static str, body: Value| {
+        let client = client.clone();
+        let base_url = base_url.clone();
+        async move { client.post(format!("{}{}", base_url, path)).json(&body).send().await.unwrap() }
+    };
+    let compare = |namespace: Option<&'static str>| {
+        let post = &post;
+        async move {
+            let body: Value = post("/compare", json!({ "text": "shared words", "namespace": namespace })).await.json().await.unwrap();
+            let mut texts: Vec<String> =
+                body["results"].as_array().unwrap().iter().map(|r| r["text"].as_str().unwrap().to_string()).collect();
+            texts.sort();
+            texts
+        }
+    };
+
+    post("/store", json!({ "text": "default words", "embedding_type": "test" })).await;
+    post("/store", json!({ "text": "alpha words", "embedding_type": "test", "namespace": "team-a" })).await;
+    post("/store", json!({ "text": "beta words", "embedding_type": "test", "namespace": "team_b" })).await;
+    // The same text and type is not a duplicate in another namespace
+    let body: Value = post("/store", json!({ "text": "alpha words", "embedding_type": "test", "namespace": "team_b" }))
+        .await
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(body["stored"], true);
+
+    assert_eq!(compare(None).await, ["default words"]);
+    assert_eq!(compare(Some("team-a")).await, ["alpha words"]);
+    assert_eq!(compare(Some("team_b")).await, ["alpha words", "beta words"]);
+    assert!(data_dir.path().join("team-a.jsonl").exists());
+    assert!(data_dir.path().join("team_b.jsonl").exists());
+
+    // Clearing a namespace leaves the other stores alone
+    let body: Value = post("/clear", json!({ "namespace": "team-a" })).await.json().await.unwrap();
+    assert_eq!(body["success"], true);
+    assert!(compare(Some("team-a")).await.is_empty());
+    assert_eq!(compare(Some("team_b")).await, ["alpha words", "beta words"]);
+    assert_eq!(compare(None).await, ["default words"]);
+
+    for namespace in ["../escape", "a/b", "..", "", "embeddings"] {
+        let response = post("/store", json!({ "text": "sneaky", "embedding_type": "test", "namespace": namespace })).await;
+        assert_eq!(response.status(), 400, "namespace {:?}", namespace);
+        let response = post("/compare", json!({ "text": "sneaky", "namespace": namespace })).await;
+        assert_eq!(response.status(), 400, "namespace {:?}", namespace);
+    }
+    assert!(!data_dir.path().join("escape.jsonl").exists());
+    assert!(!data_dir.path().parent().unwrap().join("escape.jsonl").exists());
+}
+
+#[tokio::test]
+async fn test_namespaces_are_bounded() {
+    let data_dir = tempfile::tempdir().unwrap();
+    let data_path = data_dir.path().join("embeddings.jsonl");
+    let service = Arc::new(
+        EmbeddingService::with_provider_and_data_path(Box::new(MockProvider::default()), data_path.to_str().unwrap())
+            .with_write_buffer(60_000, 1_000),
+    );
+
+    let first = service.namespace(Some("first")).unwrap();
+    first.save_embedding("kept", &[1.0, 0.0], "test", "test", None).await.unwrap();
+    assert!(!data_dir.path().join("first.jsonl").exists());
+    drop(first);
+
+    // Opening more namespaces than are kept open closes the idle one used longest ago,
+    // writing out its buffered store
+    for i in 0..MAX_OPEN_NAMESPACES {
+        service.namespace(Some(&format!("ns{}", i))).unwrap();
+    }
+    let lines = std::fs::read_to_string(data_dir.path().join("first.jsonl")).unwrap();
+    assert_eq!(lines.lines().count(), 1);
+    let first = service.namespace(Some("first")).unwrap();
+    assert_eq!(first.stored_vectors(), 1);
+
+    // Reads and clears of a namespace with no store don't open it
+    assert!(service.existing_namespace(Some("ghost")).unwrap().is_none());
+    let base_url = serve(
+        Router::new()
+            .route("/compare", post(compare_embedding))
+            .route("/clear", post(clear_embeddings))
+            .with_state(service),
+    )
+    .await;
+    let client = reqwest::Client::new();
+    let body: Value = client.post(format!("{}/compare", base_url))
+        .json(&json!({ "text": "anything", "namespace": "ghost", "best_only": true }))
+        .send().await.unwrap().json().await.unwrap();
+    assert_eq!(body, json!({ "best": null }));
+    let body: Value = client.post(format!("{}/clear", base_url))
+        .json(&json!({ "namespace": "ghost", "embedding_type": "test" }))
+        .send().await.unwrap().json().await.unwrap();
+    assert_eq!(body["removed"], 0);
+    assert!(!data_dir.path().join("ghost.jsonl").exists());
+}
+
+#[tokio::test]
+async fn test_compare_by_id() {
+    let (base_url, _data_path) = spawn_app().await;
+    let client = reqwest::Client::new();
+    for (text, embedding_type) in [("red apple", "fruit"), ("red apple pie", "fruit"), ("blue whale", "fruit"), ("red apple", "tag")] {
+        client
+            .post(format!("{}/store", base_url))
+            .json(&json!({ "text": text, "embedding_type": embedding_type }))
+            .send()
+            .await
+            .unwrap();
+    }
+
+    let response = client
+        .post(format!("{}/compare_by_id", base_url))
+        .json(&json!({ "text": "red apple", "embedding_type": "fruit", "top_k": 5 }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200);
+    let body: Value = response.json().await.unwrap();
+    let results = body["results"].as_array().unwrap();
+    // The item itself and entries of other types are left out, nearest first
+    let texts: Vec<&str> = results.iter().map(|r| r["text"].as_str().unwrap()).collect();
+    assert_eq!(texts, ["red apple pie", "blue whale"]);
+    assert!(results.iter().all(|r| r["embedding_type"] == "fruit"));
+    assert!(body.get("query_embedding").is_none());
+
+    // Matches /compare with the same text, minus the item itself
+    let body: Value = client
+        .post(format!("{}/compare", base_url))
+        .json(&json!({ "text": "red apple", "embedding_type": "fruit" }))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    let expected: Vec<&str> = body["results"].as_array().unwrap().iter().map(|r| r["text"].as_str().unwrap()).collect();
+    assert_eq!(texts, expected);
+
+    let response = client
+        .post(format!("{}/compare_by_id", base_url))
+        .json(&json!({ "text": "green pear", "embedding_type": "fruit" }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 404);
+}
+
+#[tokio::test]
+async fn test_compare_type_boosts() {
+    let (base_url, _data_path) = spawn_app().await;
+    let client = reqwest::Client::new();
+    for (text, embedding_type) in [("red apple", "body"), ("red apple pie recipe", "title")] {
+        client
+            .post(format!("{}/store", base_url))
+            .json(&json!({ "text": text, "embedding_type": embedding_type }))
+            .send()
+            .await
+            .unwrap();
+    }
+    let compare = |body: Value| {
+        let client = client.clone();
+        let base_url = base_url.clone();
+        async move {
+            let response = client.post(format!("{}/compare", base_url)).json(&body).send().await.unwrap();
+            (response.status(), response.json::<Value>().await.unwrap())
+        }
+    };
+
+    let (_, body) = compare(json!({ "text": "red apple" })).await;
+    let results = body["results"].as_array().unwrap();
+    assert_eq!(results[0]["text"], "red apple");
+    assert!(results[0].get("adjusted_similarity").is_none());
+
+    // The boosted title outranks the closer body, which keeps a factor of 1
+    let (_, body) = compare(json!({ "text": "red apple", "type_boosts": { "title": 10.0 } })).await;
+    let results = body["results"].as_array().unwrap();
+    assert_eq!(results[0]["text"], "red apple pie recipe");
+    let (title, body_result) = (&results[0], &results[1]);
+    assert!(title["similarity"].as_f64().unwrap() < body_result["similarity"].as_f64().unwrap());
+    assert_eq!(title["adjusted_similarity"].as_f64().unwrap(), title["similarity"].as_f64().unwrap() * 10.0);
+    assert_eq!(body_result["adjusted_similarity"], body_result["similarity"]);
+
+    let (status, _) = compare(json!({ "text": "red apple", "type_boosts": { "title": -1.0 } })).await;
+    assert_eq!(status, reqwest::StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn test_compare_websocket() {
+    use futures_util::{SinkExt, StreamExt};
+    use tokio_tungstenite::tungstenite::Message;
+
+    let (base_url, _data_path) = spawn_app().await;
+    let client = reqwest::Client::new();
+    for text in ["red apple", "green pear"] {
+        client
+            .post(format!("{}/store", base_url))
+            .json(&json!({ "text": text, "embedding_type": "fruit" }))
+            .send()
+            .await
+            .unwrap();
+    }
+
+    let (mut socket, _) = tokio_tungstenite::connect_async(format!("{}/ws/compare", base_url.replace("http://", "ws://")))
+        .await
+        .unwrap();
+    let mut exchange = async |frame: Message| {
+        socket.send(frame).await.unwrap();
+        let reply = socket.next().await.unwrap().unwrap();
+        serde_json::from_str::<Value>(reply.to_text().unwrap()).unwrap()
+    };
+
+    let body = exchange(Message::text(json!({ "text": "red", "top_k": 1 }).to_string())).await;
+    assert_eq!(body["results"].as_array().unwrap().len(), 1);
+    assert_eq!(body["results"][0]["text"], "red apple");
+
+    // Bad frames get an error frame and leave the socket open
+    let body = exchange(Message::text("not json")).await;
+    assert!(body["error"].as_str().unwrap().starts_with("invalid compare request"));
+    let body = exchange(Message::text(json!({ "text": "" }).to_string())).await;
+    assert_eq!(body["error"], "text must not be empty");
+    let body = exchange(Message::binary(vec![1, 2, 3])).await;
+    assert_eq!(body["error"], "expected a text frame");
+
+    let body = exchange(Message::text(json!({ "text": "green", "top_k": 1 }).to_string())).await;
+    assert_eq!(body["results"][0]["text"], "green pear");
+}
+
+#[tokio::test]
+async fn test_compare_dedup_threshold() {
+    let (base_url, _data_path) = spawn_app().await;
+    let client = reqwest::Client::new();
+
+    // The mock provider ignores case, so the first three embed identically
+    for text in ["red apple", "Red Apple", "RED APPLE", "green pear", "blue car"] {
+        client
+            .post(format!("{}/store", base_url))
+            .json(&json!({ "text": text, "embedding_type": "test" }))
+            .send()
+            .await
+            .unwrap();
+    }
+
+    let compare = |body: Value| {
+        let client = client.clone();
+        let base_url = base_url.clone();
+        async move {
+            let body: Value = client.post(format!("{}/compare", base_url)).json(&body).send().await.unwrap().json().await.unwrap();
+            body["results"].as_array().unwrap().clone()
+        }
+    };
+
+    let results = compare(json!({ "text": "red apple pie", "top_k": 2 })).await;
+    assert!(results.iter().all(|r| r["text"].as_str().unwrap().eq_ignore_ascii_case("red apple")));
+
+    // Only one of the near-duplicates survives, and top_k is filled from the rest
+    let results = compare(json!({ "text": "red apple pie", "top_k": 2, "dedup_threshold": 0.99 })).await;
+    assert_eq!(results.len(), 2);
+    assert!(results[0]["text"].as_str().unwrap().eq_ignore_ascii_case("red apple"));
+    assert!(!results[1]["text"].as_str().unwrap().eq_ignore_ascii_case("red apple"));
+    assert!(results.iter().all(|r| r.get("embedding").is_none()));
+
+    let results = compare(json!({ "text": "red apple pie", "dedup_threshold": 0.99 })).await;
+    assert_eq!(results.len(), 3);
+}
+
+#[tokio::test]
+async fn test_store_after_truncated_line() {
+    let data_path = temp_data_path();
+    // A complete record followed by one cut short by a crash mid-append
+    let complete = json!({ "text": "complete", "embedding": [1.0, 0.0, 0.0], "model": "test", "embedding_type": "small" });
+    std::fs::write(&data_path, format!("{}\n{{\"text\": \"trunc", complete)).unwrap();
+    let service = EmbeddingService::with_provider_and_data_path(
+        Box::new(OllamaProvider::new(spawn_fake_ollama().await.0)),
+        data_path.to_str().unwrap(),
+    );
+    // The partial line isn't counted as a stored vector
+    assert_eq!(service.stored_vectors(), 1);
+
+    service.save_embedding("after crash", &[0.0, 1.0, 0.0], "test", "small", None).await.unwrap();
+    assert!(service.save_embedding("complete", &[1.0, 0.0, 0.0], "test", "small", None).await.is_err());
+
+    // The new record wasn't glued onto the partial line
+    let (items, total) = service.list_embeddings(0, 10, None, CreatedRange::default(), false).await.unwrap();
+    assert_eq!(total, 2);
+    assert_eq!(items[1].text, "after crash");
+
+    assert_eq!(service.repair_data().await.unwrap(), 1);
+    assert_eq!(std::fs::read_to_string(&data_path).unwrap().lines().count(), 2);
+    assert_eq!(service.stored_vectors(), 2);
+    assert_eq!(service.repair_data().await.unwrap(), 0);
+}
+
+#[tokio::test]
+async fn test_update_embedding() {
+    let (base_url, _, data_path) = spawn_ollama_app().await;
+    let client = reqwest::Client::new();
+
+    for text in ["first", "draft", "last"] {
+        client
+            .post(format!("{}/store", base_url))
+            .json(&json!({ "text": text, "embedding_type": "test" }))
+            .send()
+            .await
+            .unwrap();
+    }
+
+    let response = client
+        .post(format!("{}/update", base_url))
+        .json(&json!({ "old_text": "draft", "new_text": "final", "embedding_type": "test" }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::OK);
+    let body: Value = response.json().await.unwrap();
+    assert_eq!(body["embedding"], json!(fake_embedding("final")));
+    assert_eq!(body["stored"], true);
+
+    // Replaced in place
+    let content = std::fs::read_to_string(&data_path).unwrap();
+    let texts: Vec<String> = content
+        .lines()
+        .map(|line| serde_json::from_str::<Value>(line).unwrap()["text"].as_str().unwrap().to_string())
+        .collect();
+    assert_eq!(texts, ["first", "final", "last"]);
+
+    // The old text can be stored again, the new one is now a duplicate
+    let body: Value = client
+        .post(format!("{}/store", base_url))
+        .json(&json!({ "text": "final", "embedding_type": "test" }))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(body["stored"], false);
+
+    let response = client
+        .post(format!("{}/update", base_url))
+        .json(&json!({ "old_text": "draft", "new_text": "other", "embedding_type": "test" }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::NOT_FOUND);
+
+    let response = client
+        .post(format!("{}/update", base_url))
+        .json(&json!({ "old_text": "first", "new_text": "last", "embedding_type": "test" }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::CONFLICT);
+}
+
+#[tokio::test]
+async fn test_reembed_all() {
+    let (base_url, models, data_path) = spawn_ollama_app().await;
+    let client = reqwest::Client::new();
+
+    for text in ["alpha", "beta", "gamma"] {
+        client
+            .post(format!("{}/store", base_url))
+            .json(&json!({ "text": text, "model": "old-model", "embedding_type": "test" }))
+            .send()
+            .await
+            .unwrap();
+    }
+    client
+        .post(format!("{}/store", base_url))
+        .json(&json!({ "text": "delta", "model": "new-model", "embedding_type": "test" }))
+        .send()
+        .await
+        .unwrap();
+    models.lock().unwrap().clear();
+
+    let response = client
+        .post(format!("{}/reembed", base_url))
+        .json(&json!({ "model": "new-model", "batch_size": 2 }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::OK);
+    let body: Value = response.json().await.unwrap();
+    assert_eq!(body, json!({ "reembedded": 3, "skipped": 1, "total": 4 }));
+    assert_eq!(*models.lock().unwrap(), ["new-model"; 3]);
+
+    // Rewritten in place with the new model
+    let content = std::fs::read_to_string(&data_path).unwrap();
+    let records: Vec<Value> = content.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+    let texts: Vec<&str> = records.iter().map(|record| record["text"].as_str().unwrap()).collect();
+    assert_eq!(texts, ["alpha", "beta", "gamma", "delta"]);
+    assert!(records.iter().all(|record| record["model"] == "new-model"));
+
+    // Nothing left to do
+    let body: Value = client
+        .post(format!("{}/reembed", base_url))
+        .json(&json!({ "model": "new-model" }))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(body, json!({ "reembedded": 0, "skipped": 4, "total": 4 }));
+
+    let response = client
+        .post(format!("{}/reembed", base_url))
+        .json(&json!({ "model": "new-model", "batch_size": 0 }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn test_reembed_stream() {
+    let (base_url, _, _data_path) = spawn_ollama_app().await;
+    let client = reqwest::Client::new();
+
+    for text in ["alpha", "beta", "gamma"] {
+        client
+            .post(format!("{}/store", base_url))
+            .json(&json!({ "text": text, "model": "old-model", "embedding_type": "test" }))
+            .send()
+            .await
+            .unwrap();
+    }
+
+    let response = client
+        .post(format!("{}/reembed/stream", base_url))
+        .json(&json!({ "model": "new-model", "batch_size": 2 }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::OK);
+    assert_eq!(response.headers()["content-type"], "text/event-stream");
+
+    // The stream ends once the job is done
+    let body = response.text().await.unwrap();
+    let events: Vec<(String, Value)> = body
+        .split("\n\n")
+        .filter_map(|event| {
+            let name = event.lines().find_map(|line| line.strip_prefix("event: "))?;
+            let data = event.lines().find_map(|line| line.strip_prefix("data: "))?;
+            Some((name.to_string(), serde_json::from_str(data).unwrap()))
+        })
+        .collect();
+    assert_eq!(events, [
+        ("progress".to_string(), json!({ "done": 1, "total": 3 })),
+        ("progress".to_string(), json!({ "done": 2, "total": 3 })),
+        ("progress".to_string(), json!({ "done": 3, "total": 3 })),
+        ("summary".to_string(), json!({ "reembedded": 3, "skipped": 0, "total": 3 })),
+    ]);
+
+    let response = client
+        .post(format!("{}/reembed/stream", base_url))
+        .json(&json!({ "batch_size": 0 }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn test_clear_disabled() {
+    let data_path = temp_data_path();
+    let base_url = spawn_app_with_service(
+        EmbeddingService::with_provider_and_data_path(Box::new(MockProvider::default()), data_path.to_str().unwrap())
+            .with_clear_disabled(true),
+    )
+    .await;
+    let client = reqwest::Client::new();
+    client.post(format!("{}/store", base_url)).json(&json!({ "text": "precious" })).send().await.unwrap();
+
+    for body in [json!({}), json!({ "embedding_type": "default" }), json!({ "namespace": "other" })] {
+        let response = client.post(format!("{}/clear", base_url)).json(&body).send().await.unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::FORBIDDEN);
+        let body: Value = response.json().await.unwrap();
+        assert_eq!(body["error"], "destructive operations disabled");
+    }
+    let response = client.post(format!("{}/clear", base_url)).send().await.unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::FORBIDDEN);
+
+    let content = std::fs::read_to_string(&data_path).unwrap();
+    assert_eq!(content.lines().count(), 1);
+    assert!(content.contains("precious"));
+}
+
+#[tokio::test]
+async fn test_clear_single_type() {
+    let (base_url, data_path) = spawn_app().await;
+    let client = reqwest::Client::new();
+
+    for (text, embedding_type) in [("one", "keep"), ("two", "drop"), ("three", "keep"), ("four", "drop")] {
+        client
+            .post(format!("{}/store", base_url))
+            .json(&json!({ "text": text, "embedding_type": embedding_type }))
+            .send()
+            .await
+            .unwrap();
+    }
+
+    let body: Value = client
+        .post(format!("{}/clear", base_url))
+        .json(&json!({ "embedding_type": "drop" }))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(body, json!({ "success": true, "removed": 2 }));
+
+    let content = std::fs::read_to_string(&data_path).unwrap();
+    let texts: Vec<String> = content
+        .lines()
+        .map(|line| serde_json::from_str::<Value>(line).unwrap()["text"].as_str().unwrap().to_string())
+        .collect();
+    assert_eq!(texts, ["one", "three"]);
+
+    // The cleared texts can be stored again, the kept ones are still duplicates
+    let body: Value = client
+        .post(format!("{}/store", base_url))
+        .json(&json!({ "text": "two", "embedding_type": "drop" }))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(body["stored"], true);
+    let body: Value = client
+        .post(format!("{}/store", base_url))
+        .json(&json!({ "text": "one", "embedding_type": "keep" }))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(body["stored"], false);
+
+    // A body that isn't a clear request must not fall back to clearing everything
+    let response = client
+        .post(format!("{}/clear", base_url))
+        .body("{\"embedding_type\": ")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::BAD_REQUEST);
+    assert_eq!(std::fs::read_to_string(&data_path).unwrap().lines().count(), 3);
+
+    // Without a body everything goes
+    let body: Value = client.post(format!("{}/clear", base_url)).send().await.unwrap().json().await.unwrap();
+    assert_eq!(body, json!({ "success": true }));
+}
+
+#[tokio::test]
+async fn test_store_creates_data_directory() {
+    let root = tempfile::tempdir().unwrap();
+    let data_dir = root.path().join("nested").join("data");
+    let data_path = data_dir.join("embeddings.jsonl");
+    let base_url = spawn_app_with_service(EmbeddingService::with_provider_and_data_path(
+        Box::new(MockProvider::default()),
+        data_path.to_str().unwrap(),
+    )).await;
+    let client = reqwest::Client::new();
+
+    let body: Value = client
+        .post(format!("{}/store", base_url))
+        .json(&json!({ "text": "fresh start", "embedding_type": "test" }))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(body["stored"], true);
+    assert!(data_path.is_file());
+
+    // Clearing removes the directories it created, but not the one it found
+    client.post(format!("{}/clear", base_url)).send().await.unwrap();
+    assert!(!root.path().join("nested").exists());
+    assert!(root.path().is_dir());
+}
+
+#[tokio::test]
+async fn test_clear_keeps_existing_data_directory() {
+    let data_dir = tempfile::tempdir().unwrap();
+    let data_path = data_dir.path().join("embeddings.jsonl");
+    let base_url = spawn_app_with_service(EmbeddingService::with_provider_and_data_path(
+        Box::new(MockProvider::default()),
+        data_path.to_str().unwrap(),
+    )).await;
+    let client = reqwest::Client::new();
+
+    client
+        .post(format!("{}/store", base_url))
+        .json(&json!({ "text": "shared", "embedding_type": "test" }))
+        .send()
+        .await
+        .unwrap();
+    client.post(format!("{}/clear", base_url)).send().await.unwrap();
+
+    // The directory is empty now, but the service didn't create it
+    assert!(!data_path.exists());
+    assert!(data_dir.path().is_dir());
+}
+
+#[tokio::test]
+async fn test_cluster_embeddings() {
+    let (base_url, _data_path) = spawn_app().await;
+    let client = reqwest::Client::new();
+
+    let records = [
+        ("apple", json!([1.0, 0.1]), "fruit"),
+        ("pear", json!([0.9, 0.2]), "fruit"),
+        ("plum", json!([1.0, 0.0]), "fruit"),
+        ("car", json!([0.1, 1.0]), "fruit"),
+        ("bus", json!([0.0, 0.9]), "fruit"),
+        ("other type", json!([0.0, 1.0]), "vehicle"),
+        ("other length", json!([1.0, 0.0, 0.0]), "fruit"),
+    ];
+    let dump: String = records
+        .iter()
+        .map(|(text, embedding, embedding_type)| {
+            format!("{}\n", json!({ "text": text, "embedding": embedding, "model": "test", "embedding_type": embedding_type }))
+        })
+        .collect();
+    client.post(format!("{}/import", base_url)).body(dump).send().await.unwrap();
+
+    let response = client
+        .post(format!("{}/cluster", base_url))
+        .json(&json!({ "k": 2, "embedding_type": "fruit" }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::OK);
+    let body: Value = response.json().await.unwrap();
+    assert_eq!(body["skipped"], 1);
+    let clusters = body["clusters"].as_array().unwrap();
+    assert_eq!(clusters.len(), 2);
+    let mut fruits: Vec<&str> = clusters[0]["members"].as_array().unwrap().iter().map(|m| m.as_str().unwrap()).collect();
+    fruits.sort();
+    assert_eq!(fruits, ["apple", "pear", "plum"]);
+    assert_eq!(clusters[1]["members"].as_array().unwrap().len(), 2);
+    let mean_similarity = clusters[0]["centroid_summary"]["mean_similarity"].as_f64().unwrap();
+    assert!(mean_similarity > 0.9 && mean_similarity <= 1.0);
+
+    // k is capped at the number of vectors
+    let body: Value = client
+        .post(format!("{}/cluster", base_url))
+        .json(&json!({ "k": 50, "embedding_type": "vehicle" }))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(body["clusters"], json!([{
+        "centroid_summary": { "nearest_text": "other type", "mean_similarity": 1.0 },
+        "members": ["other type"]
+    }]));
+
+    let response = client.post(format!("{}/cluster", base_url)).json(&json!({ "k": 0 })).send().await.unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn test_compare_round_to() {
+    let (base_url, _, _data_path) = spawn_ollama_app().await;
+    let client = reqwest::Client::new();
+
+    for text in ["abc", "abd", "xyz"] {
+        client
+            .post(format!("{}/store", base_url))
+            .json(&json!({ "text": text, "embedding_type": "test" }))
+            .send()
+            .await
+            .unwrap();
+    }
+
+    let compare = |round_to: Option<u32>| {
+        let client = client.clone();
+        let base_url = base_url.clone();
+        async move {
+            let body: Value = client
+                .post(format!("{}/compare", base_url))
+                .json(&json!({ "text": "abcd", "round_to": round_to }))
+                .send()
+                .await
+                .unwrap()
+                .json()
+                .await
+                .unwrap();
+            body["results"].as_array().unwrap().clone()
+        }
+    };
+
+    let exact = compare(None).await;
+    let rounded = compare(Some(2)).await;
+    assert_eq!(exact.len(), rounded.len());
+    for (exact, rounded) in exact.iter().zip(&rounded) {
+        // Same ranking, each score rounded
+        assert_eq!(exact["text"], rounded["text"]);
+        let similarity = exact["similarity"].as_f64().unwrap();
+        assert_eq!(rounded["similarity"].as_f64().unwrap(), (similarity * 100.0).round() / 100.0);
+    }
+}
+
+/// Significant digits in each component of the first `"embedding":[...]` array in a raw body
+fn embedding_digits(body: &str) -> Vec<usize> {
+    let start = body.find("\"embedding\":[").unwrap() + "\"embedding\":[".len();
+    let end = start + body[start..].find(']').unwrap();
+    body[start..end]
+        .split(',')
+        .map(|number| {
+            let mantissa = number.split(['e', 'E']).next().unwrap();
+            mantissa.trim_start_matches('-').replace('.', "").trim_start_matches('0').len()
+        })
+        .collect()
+}
+
+/// Returns `fake_embedding` in thirds, so components have more digits than any precision asked for
+struct ThirdsProvider;
+
+#[async_trait::async_trait]
+impl EmbeddingProvider for ThirdsProvider {
+    fn name(&self) -> &'static str {
+        "thirds"
+    }
+
+    fn default_model(&self) -> &'static str {
+        "thirds"
+    }
+
+    async fn embed(
+        &self,
+        _client: &reqwest::Client,
+        text: &str,
+        _model: &str,
+        _dimensions: Option<usize>,
+        _input_type: InputType,
+    ) -> Result<Vec<f64>, ProviderError> {
+        Ok(fake_embedding(text).iter().map(|value| (value + 1.0) / 3.0).collect())
+    }
+}
+
+#[tokio::test]
+async fn test_float_precision() {
+    let data_path = temp_data_path();
+    let base_url = spawn_app_with_service(EmbeddingService::with_provider_and_data_path(
+        Box::new(ThirdsProvider),
+        data_path.to_str().unwrap(),
+    )).await;
+    let client = reqwest::Client::new();
+    let thirds = |text: &str| -> Vec<f64> { fake_embedding(text).iter().map(|value| (value + 1.0) / 3.0).collect() };
+
+    let response = client
+        .post(format!("{}/store", base_url))
+        .json(&json!({ "text": "red apple", "embedding_type": "test", "float_precision": 6 }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::OK);
+    let body = response.text().await.unwrap();
+    let digits = embedding_digits(&body);
+    assert_eq!(digits.len(), 16);
+    assert!(digits.iter().all(|&digits| digits <= 6), "{}", body);
+
+    // The stored vector keeps full precision
+    let full = thirds("red apple");
+    let body = client
+        .get(format!("{}/list?include_embeddings=true", base_url))
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert!(embedding_digits(&body).iter().any(|&digits| digits > 6), "{}", body);
+    let listed: Value = serde_json::from_str(&body).unwrap();
+    let listed: Vec<f64> = serde_json::from_value(listed["items"][0]["embedding"].clone()).unwrap();
+    assert_eq!(listed, full);
+
+    let body = client
+        .get(format!("{}/list?include_embeddings=true&float_precision=3", base_url))
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert!(embedding_digits(&body).iter().all(|&digits| digits <= 3), "{}", body);
+
+    let body: Value = client
+        .post(format!("{}/compare", base_url))
+        .json(&json!({
+            "text": "green apple",
+            "include_embeddings": true,
+            "include_query_embedding": true,
+            "float_precision": 4
+        }))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    let four_digits = |vector: Vec<f64>| -> Vec<f64> {
+        vector.iter().map(|value| format!("{:.3e}", value).parse().unwrap()).collect()
+    };
+    let result: Vec<f64> = serde_json::from_value(body["results"][0]["embedding"].clone()).unwrap();
+    assert_eq!(result, four_digits(full));
+    let query: Vec<f64> = serde_json::from_value(body["query_embedding"].clone()).unwrap();
+    assert_eq!(query, four_digits(thirds("green apple")));
+
+    let response = client
+        .post(format!("{}/store", base_url))
+        .json(&json!({ "text": "pear", "float_precision": 0 }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::BAD_REQUEST);
+}
+
+/// Provider that records the text and input type of each call
+#[derive(Default)]
+struct RecordingProvider {
+    calls: Arc<Mutex<Vec<(String, InputType)>>>,
+}
+
+#[async_trait::async_trait]
+impl EmbeddingProvider for RecordingProvider {
+    fn name(&self) -> &'static str {
+        "recording"
+    }
+
+    fn default_model(&self) -> &'static str {
+        "recording"
+    }
+
+    async fn embed(
+        &self,
+        _client: &reqwest::Client,
+        text: &str,
+        _model: &str,
+        _dimensions: Option<usize>,
+        input_type: InputType,
+    ) -> Result<Vec<f64>, ProviderError> {
+        self.calls.lock().unwrap().push((text.to_string(), input_type));
+        Ok(fake_embedding(text))
+    }
+}
+
+#[tokio::test]
+async fn test_input_type_reaches_provider() {
+    let provider = RecordingProvider::default();
+    let calls = provider.calls.clone();
+    let data_path = temp_data_path();
+    // Without the cache, so every embedding reaches the provider
+    let base_url = spawn_app_with_service(
+        EmbeddingService::with_provider_and_data_path(Box::new(provider), data_path.to_str().unwrap())
+            .with_embedding_cache_size(0),
+    ).await;
+    let client = reqwest::Client::new();
+
+    let requests = [
+        ("store", json!({ "text": "stored", "embedding_type": "test" })),
+        ("update", json!({ "old_text": "stored", "new_text": "edited", "embedding_type": "test" })),
+        ("compare", json!({ "text": "query" })),
+        ("similarity", json!({ "text_a": "first", "text_b": "second" })),
+        // Another model, since records already embedded with the target one are skipped
+        ("reembed", json!({ "model": "recording-v2" })),
+    ];
+    for (endpoint, request) in requests {
+        let response = client.post(format!("{}/{}", base_url, endpoint)).json(&request).send().await.unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::OK, "{}", endpoint);
+    }
+
+    // Stored texts are documents, the texts searched with are queries
+    let expected = [
+        ("stored", InputType::Document),
+        ("edited", InputType::Document),
+        ("query", InputType::Query),
+        ("first", InputType::Query),
+        ("second", InputType::Document),
+        ("edited", InputType::Document),
+    ];
+    let expected: Vec<(String, InputType)> = expected.iter().map(|(text, input_type)| (text.to_string(), *input_type)).collect();
+    assert_eq!(*calls.lock().unwrap(), expected);
+}
+
+/// Spawn a server speaking Cohere's `/embed` protocol, recording the `input_type` of each call
+async fn spawn_fake_cohere() -> (String, Arc<Mutex<Vec<String>>>) {
+    let input_types = Arc::new(Mutex::new(Vec::new()));
+    let app = Router::new()
+        .route(
+            "/embed",
+            post(|State(input_types): State<Arc<Mutex<Vec<String>>>>, Json(body): Json<Value>| async move {
+                input_types.lock().unwrap().push(body["input_type"].as_str().unwrap_or_default().to_string());
+                let embeddings: Vec<Vec<f64>> = body["texts"]
+                    .as_array()
+                    .unwrap()
+                    .iter()
+                    .map(|text| fake_embedding(text.as_str().unwrap()))
+                    .collect();
+                Json(json!({ "id": "test", "embeddings": embeddings, "texts": body["texts"] }))
+            }),
+        )
+        .with_state(input_types.clone());
+    (serve(app).await, input_types)
+}
+
+#[tokio::test]
+async fn test_cohere_provider() {
+    let (cohere_url, input_types) = spawn_fake_cohere().await;
+    let data_path = temp_data_path();
+    let base_url = spawn_app_with_service(EmbeddingService::with_provider_and_data_path(
+        Box::new(CohereProvider::new().with_base_url(cohere_url).with_api_key("test-key")),
+        data_path.to_str().unwrap(),
+    )).await;
+    let client = reqwest::Client::new();
+
+    let body: Value = client
+        .post(format!("{}/store", base_url))
+        .json(&json!({ "text": "stored text", "embedding_type": "test" }))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(body["embedding"], json!(fake_embedding("stored text")));
+
+    let body: Value = client
+        .post(format!("{}/compare", base_url))
+        .json(&json!({ "text": "query text", "model": "embed-multilingual-v3.0" }))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(body["results"][0]["text"], "stored text");
+
+    // Stores embed documents and compares embed queries
+    assert_eq!(*input_types.lock().unwrap(), ["search_document", "search_query"]);
+
+    // Cohere's model names are accepted, OpenAI's are not
+    let body: Value = client.get(format!("{}/models", base_url)).send().await.unwrap().json().await.unwrap();
+    assert_eq!(body["models"][0]["name"], "embed-english-v3.0");
+    assert_eq!(body["models"][0]["dimensions"], 1024);
+    let response = client
+        .post(format!("{}/compare", base_url))
+        .json(&json!({ "text": "query text", "model": "text-embedding-3-large" }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::BAD_REQUEST);
+}
@@ -0,0 +1,110 @@
+use axum::{routing::post, Router};
+use rust_embedding::embeddings::provider::{EmbeddingProvider, InputType, MockProvider, ProviderError};
+use rust_embedding::embeddings::service::EmbeddingService;
+use rust_embedding::store_embedding;
+use rust_embedding::utils::metrics::{self, PROVIDER_CANCELLED_TOTAL};
+use serde_json::json;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::TcpListener;
+use tokio::sync::Notify;
+
+/// How long the provider takes to answer, well past the client's timeout
+const PROVIDER_DELAY: Duration = Duration::from_secs(2);
+
+/// A provider that takes `PROVIDER_DELAY` to answer, recording whether its call started,
+/// completed or was dropped part way
+#[derive(Default)]
+struct SlowProvider {
+    started: Arc<Notify>,
+    completed: Arc<AtomicBool>,
+    dropped: Arc<AtomicBool>,
+}
+
+/// Sets its flag when dropped, unless disarmed
+struct DropFlag(Option<Arc<AtomicBool>>);
+
+impl Drop for DropFlag {
+    fn drop(&mut self) {
+        if let Some(flag) = self.0.take() {
+            flag.store(true, Ordering::SeqCst);
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl EmbeddingProvider for SlowProvider {
+    fn name(&self) -> &'static str {
+        "slow"
+    }
+
+    fn default_model(&self) -> &'static str {
+        "slow"
+    }
+
+    async fn embed(
+        &self,
+        _client: &reqwest::Client,
+        text: &str,
+        _model: &str,
+        _dimensions: Option<usize>,
+        _input_type: InputType,
+    ) -> Result<Vec<f64>, ProviderError> {
+        let mut on_drop = DropFlag(Some(self.dropped.clone()));
+        self.started.notify_one();
+        tokio::time::sleep(PROVIDER_DELAY).await;
+        on_drop.0 = None;
+        self.completed.store(true, Ordering::SeqCst);
+        Ok(MockProvider::embed_text(text, 16))
+    }
+}
+
+/// Provider calls cancelled so far, from the metrics. This file holds a single test, since
+/// the counter is global.
+fn cancelled_calls() -> u64 {
+    metrics::render()
+        .lines()
+        .find_map(|line| line.strip_prefix(PROVIDER_CANCELLED_TOTAL)?.split_whitespace().last()?.parse().ok())
+        .unwrap_or(0)
+}
+
+#[tokio::test]
+async fn test_client_disconnect_cancels_provider_call() {
+    metrics::init();
+    let data_path = tempfile::NamedTempFile::new().unwrap().into_temp_path();
+    let provider = SlowProvider::default();
+    let (started, completed, dropped) = (provider.started.clone(), provider.completed.clone(), provider.dropped.clone());
+    let service = EmbeddingService::with_provider_and_data_path(Box::new(provider), data_path.to_str().unwrap());
+
+    let app = Router::new().route("/store", post(store_embedding)).with_state(Arc::new(service));
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let base_url = format!("http://{}", listener.local_addr().unwrap());
+    tokio::spawn(async move {
+        axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>()).await.unwrap();
+    });
+
+    // The client gives up while the provider is still working, closing the connection
+    let request = tokio::spawn(
+        reqwest::Client::new()
+            .post(format!("{}/store", base_url))
+            .json(&json!({ "text": "abandoned", "embedding_type": "test" }))
+            .send(),
+    );
+    started.notified().await;
+    request.abort();
+
+    let deadline = tokio::time::Instant::now() + PROVIDER_DELAY / 2;
+    while !dropped.load(Ordering::SeqCst) {
+        assert!(tokio::time::Instant::now() < deadline, "the provider call outlived the request");
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    assert_eq!(cancelled_calls(), 1);
+
+    // Nothing is left running to finish the call later
+    tokio::time::sleep(PROVIDER_DELAY).await;
+    assert!(!completed.load(Ordering::SeqCst));
+    let lines = std::fs::read_to_string(&data_path).unwrap_or_default();
+    assert_eq!(lines.lines().count(), 0, "the abandoned store must not be written");
+}
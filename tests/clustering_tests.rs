@@ -0,0 +1,54 @@
+use rust_embedding::utils::clustering::kmeans;
+
+/// Three tight groups pointing along different axes
+fn grouped_vectors() -> Vec<Vec<f64>> {
+    vec![
+        vec![1.0, 0.1, 0.0],
+        vec![0.0, 1.0, 0.1],
+        vec![0.9, 0.0, 0.1],
+        vec![0.1, 0.0, 1.0],
+        vec![0.1, 0.9, 0.0],
+        vec![0.0, 0.1, 0.9],
+        vec![2.0, 0.1, 0.1],
+    ]
+}
+
+#[test]
+fn test_kmeans_separates_groups() {
+    let result = kmeans(&grouped_vectors(), 3, 100);
+
+    let a = result.assignments;
+    assert_eq!(a[0], a[2]);
+    assert_eq!(a[0], a[6]);
+    assert_eq!(a[1], a[4]);
+    assert_eq!(a[3], a[5]);
+    assert_ne!(a[0], a[1]);
+    assert_ne!(a[0], a[3]);
+    assert_ne!(a[1], a[3]);
+    assert!(result.iterations < 100);
+    // Centroids are unit length
+    for centroid in &result.centroids {
+        let norm: f64 = centroid.iter().map(|x| x * x).sum::<f64>().sqrt();
+        assert!((norm - 1.0).abs() < 1e-9);
+    }
+}
+
+#[test]
+fn test_kmeans_is_deterministic() {
+    let first = kmeans(&grouped_vectors(), 3, 100);
+    let second = kmeans(&grouped_vectors(), 3, 100);
+    assert_eq!(first.assignments, second.assignments);
+    assert_eq!(first.centroids, second.centroids);
+}
+
+#[test]
+fn test_kmeans_caps_k() {
+    let vectors = vec![vec![1.0, 0.0], vec![0.0, 1.0]];
+    let result = kmeans(&vectors, 10, 100);
+    assert_eq!(result.centroids.len(), 2);
+    assert_ne!(result.assignments[0], result.assignments[1]);
+
+    let result = kmeans(&[], 3, 100);
+    assert!(result.assignments.is_empty());
+    assert!(result.centroids.is_empty());
+}
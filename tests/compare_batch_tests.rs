@@ -0,0 +1,127 @@
+use axum::{routing::post, Router};
+use rust_embedding::embeddings::provider::{EmbeddingProvider, InputType, MockProvider, ProviderError};
+use rust_embedding::embeddings::service::EmbeddingService;
+use rust_embedding::utils::metrics::{self, STORE_READS_TOTAL};
+use rust_embedding::{compare_batch, compare_embedding};
+use serde_json::{json, Value};
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use tokio::net::TcpListener;
+
+const DIMENSIONS: usize = 16;
+
+/// The mock provider, counting its single and batch calls
+#[derive(Default)]
+struct CountingProvider {
+    embed_calls: Arc<AtomicUsize>,
+    batch_calls: Arc<AtomicUsize>,
+}
+
+#[async_trait::async_trait]
+impl EmbeddingProvider for CountingProvider {
+    fn name(&self) -> &'static str {
+        "counting"
+    }
+
+    fn default_model(&self) -> &'static str {
+        "counting"
+    }
+
+    async fn embed(
+        &self,
+        _client: &reqwest::Client,
+        text: &str,
+        _model: &str,
+        _dimensions: Option<usize>,
+        _input_type: InputType,
+    ) -> Result<Vec<f64>, ProviderError> {
+        self.embed_calls.fetch_add(1, Ordering::SeqCst);
+        Ok(MockProvider::embed_text(text, DIMENSIONS))
+    }
+
+    async fn embed_batch(
+        &self,
+        _client: &reqwest::Client,
+        texts: &[String],
+        _model: &str,
+        _dimensions: Option<usize>,
+        _input_type: InputType,
+    ) -> Result<Vec<Vec<f64>>, ProviderError> {
+        self.batch_calls.fetch_add(1, Ordering::SeqCst);
+        Ok(texts.iter().map(|text| MockProvider::embed_text(text, DIMENSIONS)).collect())
+    }
+}
+
+/// Reads of the store so far, from the metrics. This file holds a single test, since the
+/// counter is global.
+fn store_reads() -> u64 {
+    metrics::render()
+        .lines()
+        .find_map(|line| line.strip_prefix(STORE_READS_TOTAL)?.trim().parse().ok())
+        .unwrap_or(0)
+}
+
+#[tokio::test]
+async fn test_compare_batch_embeds_and_reads_the_store_once() {
+    metrics::init();
+    let data_path = tempfile::NamedTempFile::new().unwrap().into_temp_path();
+    let provider = CountingProvider::default();
+    let (embed_calls, batch_calls) = (provider.embed_calls.clone(), provider.batch_calls.clone());
+    let service = EmbeddingService::with_provider_and_data_path(Box::new(provider), data_path.to_str().unwrap());
+    for text in ["apple pie recipe", "car engine repair", "garden flowers in spring", "apple orchard tour"] {
+        let embedding = MockProvider::embed_text(text, DIMENSIONS);
+        service.save_embedding(text, &embedding, "counting", "note", None).await.unwrap();
+    }
+
+    let app = Router::new()
+        .route("/compare", post(compare_embedding))
+        .route("/compare/batch", post(compare_batch))
+        .with_state(Arc::new(service));
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let base_url = format!("http://{}", listener.local_addr().unwrap());
+    tokio::spawn(async move {
+        axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>()).await.unwrap();
+    });
+    let client = reqwest::Client::new();
+    let post = |path: &str, body: Value| client.post(format!("{}{}", base_url, path)).json(&body).send();
+
+    let reads_before = store_reads();
+    let response = post("/compare/batch", json!({ "queries": [
+        { "text": "garden flowers in spring", "top_k": 2 },
+        { "text": "car engine repair", "best_only": true },
+        { "text": "apple pie recipe", "top_k": 1, "include_query_embedding": true },
+    ] }))
+    .await
+    .unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::OK);
+    let body: Value = response.json().await.unwrap();
+    assert_eq!(store_reads() - reads_before, 1, "the store should be read once for all queries");
+    assert_eq!(batch_calls.load(Ordering::SeqCst), 1);
+    assert_eq!(embed_calls.load(Ordering::SeqCst), 0);
+
+    let results = body["results"].as_array().unwrap();
+    assert_eq!(results.len(), 3);
+    assert_eq!(results[0]["results"].as_array().unwrap().len(), 2);
+    assert_eq!(results[0]["results"][0]["text"], "garden flowers in spring");
+    assert_eq!(results[1]["best"]["text"], "car engine repair");
+    assert_eq!(results[2]["results"][0]["text"], "apple pie recipe");
+    assert_eq!(results[2]["query_embedding"].as_array().unwrap().len(), DIMENSIONS);
+
+    // Each answer is what /compare gives for the same query
+    let single: Value = post("/compare", json!({ "text": "garden flowers in spring", "top_k": 2 }))
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(results[0], single);
+
+    // An invalid query fails the batch, naming its position
+    let response = post("/compare/batch", json!({ "queries": [{ "text": "fine" }, { "text": "" }] })).await.unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::BAD_REQUEST);
+    let body: Value = response.json().await.unwrap();
+    assert!(body["error"].as_str().unwrap().starts_with("queries[1]: "), "{}", body);
+    let response = post("/compare/batch", json!({ "queries": [] })).await.unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::BAD_REQUEST);
+}
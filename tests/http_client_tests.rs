@@ -0,0 +1,126 @@
+use rust_embedding::http::client::{build_client, make_http_request, HttpError};
+use reqwest::{Client, Method};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+
+/// Spawn a server that accepts connections but never answers them
+async fn spawn_unresponsive_server() -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    tokio::spawn(async move {
+        let mut connections = Vec::new();
+        loop {
+            let (socket, _) = listener.accept().await.unwrap();
+            // Hold the socket open without ever writing a response
+            connections.push(socket);
+        }
+    });
+
+    format!("http://{}", addr)
+}
+
+/// Spawn a keep-alive HTTP server answering every request with `ok`, counting TCP connections
+async fn spawn_counting_server() -> (String, Arc<AtomicUsize>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let connections = Arc::new(AtomicUsize::new(0));
+    let counter = connections.clone();
+
+    tokio::spawn(async move {
+        loop {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            counter.fetch_add(1, Ordering::SeqCst);
+            tokio::spawn(async move {
+                let mut buf = vec![0u8; 4096];
+                let mut pending = Vec::new();
+                loop {
+                    let n = match socket.read(&mut buf).await {
+                        Ok(0) | Err(_) => return,
+                        Ok(n) => n,
+                    };
+                    pending.extend_from_slice(&buf[..n]);
+                    // Requests carry no body, so each header terminator is one request
+                    while let Some(end) = pending.windows(4).position(|w| w == b"\r\n\r\n") {
+                        pending.drain(..end + 4);
+                        let response = b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok";
+                        if socket.write_all(response).await.is_err() {
+                            return;
+                        }
+                    }
+                }
+            });
+        }
+    });
+
+    (format!("http://{}", addr), connections)
+}
+
+#[tokio::test]
+async fn test_request_times_out() {
+    let client = Client::builder().timeout(Duration::from_secs(1)).build().unwrap();
+    let url = spawn_unresponsive_server().await;
+
+    let start = Instant::now();
+    let result = make_http_request(&client, Method::GET, &url, None, None, None).await;
+
+    assert!(matches!(result, Err(HttpError::Timeout)), "expected a timeout, got {:?}", result);
+    assert!(start.elapsed() < Duration::from_secs(5));
+}
+
+#[tokio::test]
+async fn test_shared_client_reuses_connections() {
+    const REQUESTS: usize = 50;
+
+    // A client per request, as before: every call pays for a new connection
+    let (url, connections) = spawn_counting_server().await;
+    let start = Instant::now();
+    for _ in 0..REQUESTS {
+        let body = make_http_request(&build_client(), Method::GET, &url, None, None, None).await.unwrap().body;
+        assert_eq!(body, "ok");
+    }
+    let fresh_elapsed = start.elapsed();
+    assert_eq!(connections.load(Ordering::SeqCst), REQUESTS);
+
+    // One shared client: sequential calls go over a single pooled connection
+    let (url, connections) = spawn_counting_server().await;
+    let client = build_client();
+    let start = Instant::now();
+    for _ in 0..REQUESTS {
+        let body = make_http_request(&client, Method::GET, &url, None, None, None).await.unwrap().body;
+        assert_eq!(body, "ok");
+    }
+    let shared_elapsed = start.elapsed();
+    assert_eq!(connections.load(Ordering::SeqCst), 1);
+
+    println!(
+        "{} requests: {:?} with a client per request, {:?} with a shared client",
+        REQUESTS, fresh_elapsed, shared_elapsed
+    );
+}
+
+#[tokio::test]
+async fn test_response_exposes_status_and_headers() {
+    use axum::{http::StatusCode, routing::get, Router};
+
+    let app = Router::new().route(
+        "/",
+        get(|| async { (StatusCode::TOO_MANY_REQUESTS, [("Retry-After", "7")], "slow down") }),
+    );
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}/", listener.local_addr().unwrap());
+    tokio::spawn(async move {
+        axum::serve(listener, app).await.unwrap();
+    });
+
+    let response = make_http_request(&build_client(), Method::GET, &url, None, None, None).await.unwrap();
+
+    assert_eq!(response.status, 429);
+    assert!(!response.is_success());
+    assert_eq!(response.headers.get("retry-after").map(String::as_str), Some("7"));
+    assert_eq!(response.header("Retry-After"), Some("7"));
+    assert_eq!(response.body, "slow down");
+}
@@ -0,0 +1,31 @@
+use rust_embedding::utils::lexical::{term_frequencies, tokenize, Bm25Corpus};
+
+#[test]
+fn test_tokenize_lowercases_and_splits_on_punctuation() {
+    assert_eq!(tokenize("Hello, World! it's 2024"), ["hello", "world", "it", "s", "2024"]);
+    assert!(tokenize(" -- ").is_empty());
+    let terms = tokenize("the cat and the hat");
+    assert_eq!(term_frequencies(&terms)["the"], 2);
+}
+
+#[test]
+fn test_bm25_favours_rare_terms_and_shorter_texts() {
+    let corpus = Bm25Corpus::new([
+        "the quick brown fox",
+        "the lazy dog",
+        "the quick dog jumps over the lazy brown fox again and again",
+        "nothing in common",
+    ]);
+    assert_eq!(corpus.len(), 4);
+
+    let scores = corpus.scores("fox");
+    // Same single match, so the shorter text scores higher
+    assert!(scores[0] > scores[2], "{:?}", scores);
+    assert_eq!(scores[1], 0.0);
+    assert_eq!(scores[3], 0.0);
+
+    // "the" is in most texts, so it counts for less than the rarer "lazy"
+    let scores = corpus.scores("the lazy");
+    assert!(scores[1] > scores[0], "{:?}", scores);
+    assert!(corpus.scores("THE")[0] > 0.0, "queries are lowercased too");
+}
@@ -0,0 +1,91 @@
+use rust_embedding::embeddings::provider::MockProvider;
+use rust_embedding::embeddings::service::{EmbeddingService, PARALLEL_COMPARE_MIN_ENTRIES};
+use rust_embedding::embeddings::storage::{append_records, build_record, StorageFormat, StoragePrecision, StoredEmbedding};
+use rust_embedding::CompareFilter;
+use std::time::Instant;
+
+/// Deterministic vectors with components in [-1, 1), from a linear congruential generator
+fn synthetic_vectors(count: usize, dimensions: usize, seed: u64) -> Vec<Vec<f64>> {
+    let mut state = seed;
+    (0..count)
+        .map(|_| {
+            (0..dimensions)
+                .map(|_| {
+                    state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+                    (state >> 11) as f64 / (1u64 << 52) as f64 - 1.0
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// A bincode store of `count` synthetic vectors over a few types, with every tenth text
+/// repeated under its type so deduplication is exercised
+fn synthetic_store(count: usize, dimensions: usize) -> tempfile::TempPath {
+    let path = tempfile::NamedTempFile::new().unwrap().into_temp_path();
+    let records: Vec<_> = synthetic_vectors(count, dimensions, 7)
+        .iter()
+        .enumerate()
+        .map(|(i, vector)| {
+            let text = if i % 10 == 9 { format!("text {}", i - 1) } else { format!("text {}", i) };
+            let embedding = StoredEmbedding::encode(vector, StoragePrecision::F64);
+            build_record(&text, &embedding, "test", ["a", "b", "c"][i % 3], false, None)
+        })
+        .collect();
+    append_records(path.to_str().unwrap(), StorageFormat::Bincode, &records).unwrap();
+    path
+}
+
+fn service(path: &tempfile::TempPath, parallel: bool) -> EmbeddingService {
+    EmbeddingService::with_provider_and_data_path(Box::new(MockProvider::default()), path.to_str().unwrap())
+        .with_storage_format(StorageFormat::Bincode)
+        .with_parallel_compare(parallel)
+}
+
+/// Compare and keep what identifies each result and its rank
+async fn ranking(service: &EmbeddingService, query: &[f64], top_k: Option<usize>, filter: CompareFilter) -> Vec<(String, String, f64)> {
+    service
+        .compare_embeddings("query", query, top_k, false, filter, None)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|result| (result.text, result.embedding_type, result.similarity))
+        .collect()
+}
+
+#[tokio::test]
+async fn test_parallel_compare_matches_serial() {
+    let path = synthetic_store(PARALLEL_COMPARE_MIN_ENTRIES * 2, 32);
+    let (serial, parallel) = (service(&path, false), service(&path, true));
+    let query = &synthetic_vectors(1, 32, 99)[0];
+
+    for top_k in [None, Some(10)] {
+        let filters = [
+            CompareFilter::default(),
+            CompareFilter { embedding_types: Some(vec!["a".to_string(), "c".to_string()]), ..CompareFilter::default() },
+        ];
+        for filter in filters {
+            let expected = ranking(&serial, query, top_k, filter.clone()).await;
+            assert_eq!(ranking(&parallel, query, top_k, filter).await, expected);
+        }
+    }
+}
+
+/// Not a strict benchmark, since timings on shared CI machines are noisy, but it prints how
+/// long a full scan of 50k vectors takes each way. Run with `--nocapture` to see them.
+#[tokio::test]
+async fn test_parallel_compare_timing_50k() {
+    let path = synthetic_store(50_000, 64);
+    let (serial, parallel) = (service(&path, false), service(&path, true));
+    let query = &synthetic_vectors(1, 64, 99)[0];
+
+    let start = Instant::now();
+    let expected = ranking(&serial, query, Some(10), CompareFilter::default()).await;
+    let serial_time = start.elapsed();
+    let start = Instant::now();
+    let results = ranking(&parallel, query, Some(10), CompareFilter::default()).await;
+    let parallel_time = start.elapsed();
+
+    assert_eq!(results, expected);
+    eprintln!("compare over 50k vectors: serial {:?}, parallel {:?}", serial_time, parallel_time);
+}
@@ -0,0 +1,57 @@
+use rust_embedding::utils::paths::{sanitize_path_component, MAX_PATH_COMPONENT_LEN};
+
+#[test]
+fn test_plain_names_are_accepted() {
+    for name in ["team-a", "team_b", "v2", "archive.2024", "a"] {
+        assert_eq!(sanitize_path_component(name), Ok(name));
+    }
+    let longest = "a".repeat(MAX_PATH_COMPONENT_LEN);
+    assert!(sanitize_path_component(&longest).is_ok());
+    assert!(sanitize_path_component(&format!("{}a", longest)).is_err());
+}
+
+#[test]
+fn test_traversal_attempts_are_rejected() {
+    for name in [
+        "",
+        ".",
+        "..",
+        "../etc/passwd",
+        "../../etc/passwd",
+        "a/../../b",
+        "a/b",
+        "a\\b",
+        "..\\windows",
+        "/etc/passwd",
+        "C:\\data",
+        "C:data",
+        ".hidden",
+        "...",
+        "name\0.jsonl",
+        "name\n",
+        " name",
+    ] {
+        assert!(sanitize_path_component(name).is_err(), "accepted {:?}", name);
+    }
+}
+
+#[test]
+fn test_unicode_tricks_are_rejected() {
+    for name in [
+        // Slashes and dots that some tools normalize to ASCII ones
+        "\u{ff0f}etc",
+        "a\u{2215}b",
+        "a\u{2044}b",
+        "\u{ff0e}\u{ff0e}",
+        "\u{2024}\u{2024}",
+        // Invisible and direction-changing characters
+        "team\u{200b}a",
+        "team\u{202e}lnosj.",
+        "\u{feff}team",
+        // Look-alike letters
+        "t\u{0435}am",
+        "caf\u{e9}",
+    ] {
+        assert!(sanitize_path_component(name).is_err(), "accepted {:?}", name);
+    }
+}
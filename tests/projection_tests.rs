@@ -0,0 +1,104 @@
+use rust_embedding::embeddings::provider::MockProvider;
+use rust_embedding::embeddings::service::EmbeddingService;
+use rust_embedding::embeddings::storage::{append_records, build_record, StorageFormat, StoragePrecision, StoredEmbedding};
+use rust_embedding::utils::projection::{Projection, DEFAULT_FIT_ITERATIONS};
+use rust_embedding::CompareFilter;
+use std::collections::HashSet;
+
+/// Deterministic values in [-1, 1), from a linear congruential generator
+fn uniform(state: &mut u64) -> f64 {
+    *state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+    (*state >> 11) as f64 / (1u64 << 52) as f64 - 1.0
+}
+
+/// Vectors that mostly lie in a subspace of `rank` directions, plus a little noise, as
+/// embeddings of related texts tend to
+fn low_rank_vectors(count: usize, dimensions: usize, rank: usize, seed: u64) -> Vec<Vec<f64>> {
+    let mut state = seed;
+    let factors: Vec<Vec<f64>> = (0..rank).map(|_| (0..dimensions).map(|_| uniform(&mut state)).collect()).collect();
+    (0..count)
+        .map(|_| {
+            let weights: Vec<f64> = (0..rank).map(|_| uniform(&mut state)).collect();
+            (0..dimensions)
+                .map(|d| factors.iter().zip(&weights).map(|(factor, w)| w * factor[d]).sum::<f64>() + 0.05 * uniform(&mut state))
+                .collect()
+        })
+        .collect()
+}
+
+fn dot(a: &[f64], b: &[f64]) -> f64 {
+    a.iter().zip(b).map(|(x, y)| x * y).sum()
+}
+
+#[test]
+fn test_fit_gives_orthonormal_components() {
+    let vectors = low_rank_vectors(200, 32, 6, 1);
+    let projection = Projection::fit(&vectors, 8, DEFAULT_FIT_ITERATIONS).unwrap();
+    assert_eq!((projection.source_dimensions(), projection.dimensions()), (32, 8));
+    for (i, a) in projection.components.iter().enumerate() {
+        for (j, b) in projection.components.iter().enumerate() {
+            let expected = if i == j { 1.0 } else { 0.0 };
+            assert!((dot(a, b) - expected).abs() < 1e-9, "components {} and {} give {}", i, j, dot(a, b));
+        }
+    }
+    // Six directions carry nearly everything, so eight keep nearly all of it
+    assert!(projection.explained_variance(&vectors) > 0.99);
+    assert_eq!(projection.project(&vectors[0]).unwrap().len(), 8);
+    assert_eq!(projection.project(&[1.0; 31]), None);
+}
+
+#[test]
+fn test_fit_rejects_bad_dimensions() {
+    let vectors = low_rank_vectors(10, 8, 2, 1);
+    for dimensions in [0, 8, 9] {
+        let error = Projection::fit(&vectors, dimensions, DEFAULT_FIT_ITERATIONS).unwrap_err();
+        assert!(error.starts_with("cannot fit projection"), "{}", error);
+    }
+    assert!(Projection::fit(&vectors[..3], 4, DEFAULT_FIT_ITERATIONS).is_err());
+    assert!(Projection::fit(&[], 4, DEFAULT_FIT_ITERATIONS).is_err());
+}
+
+/// Top-10 results with the projection are nearly those of a full scan when the projection
+/// keeps most of the vectors' energy
+#[tokio::test]
+async fn test_projected_compare_recall() {
+    let path = tempfile::NamedTempFile::new().unwrap().into_temp_path();
+    let vectors = low_rank_vectors(500, 64, 8, 7);
+    let records: Vec<_> = vectors
+        .iter()
+        .enumerate()
+        .map(|(i, vector)| {
+            let embedding = StoredEmbedding::encode(vector, StoragePrecision::F64);
+            build_record(&format!("text {}", i), &embedding, "test", "text", false, None)
+        })
+        .collect();
+    append_records(path.to_str().unwrap(), StorageFormat::Bincode, &records).unwrap();
+    let service = |projected| {
+        EmbeddingService::with_provider_and_data_path(Box::new(MockProvider::default()), path.to_str().unwrap())
+            .with_storage_format(StorageFormat::Bincode)
+            .with_projected_compare(projected)
+    };
+    let (exact, projected) = (service(false), service(true));
+
+    let fitted = projected.fit_projection(16).await.unwrap();
+    assert_eq!((fitted.source_dimensions, fitted.dimensions, fitted.vectors), (64, 16, 500));
+    assert!(fitted.explained_variance > 0.95, "explained variance {}", fitted.explained_variance);
+
+    let queries = low_rank_vectors(20, 64, 8, 99);
+    let (mut found, mut total) = (0, 0);
+    for query in &queries {
+        let top = |results: Vec<rust_embedding::ComparisonResult>| results.into_iter().map(|r| r.text).collect::<HashSet<_>>();
+        let expected = top(exact.compare_embeddings("query", query, Some(10), false, CompareFilter::default(), None).await.unwrap());
+        let approximate = top(projected.compare_embeddings("query", query, Some(10), false, CompareFilter::default(), None).await.unwrap());
+        found += expected.intersection(&approximate).count();
+        total += expected.len();
+    }
+    let recall = found as f64 / total as f64;
+    assert!(recall >= 0.9, "recall {}", recall);
+
+    // The projection is saved next to the data and read back by a new service
+    let (saved, fitted) = (service(true).projection().unwrap(), projected.projection().unwrap());
+    assert_eq!((saved.source_dimensions(), saved.dimensions()), (64, 16));
+    let saved_values = saved.components.iter().flatten();
+    assert!(saved_values.zip(fitted.components.iter().flatten()).all(|(a, b)| (a - b).abs() < 1e-12));
+}
@@ -0,0 +1,302 @@
+use axum::{http::StatusCode, routing::post, Json, Router};
+use rust_embedding::embeddings::provider::{
+    CohereProvider, EmbeddingProvider, InputType, MockProvider, OllamaProvider, OpenAiProvider, ProviderError,
+    COHERE_MAX_BATCH_SIZE, DEFAULT_OPENAI_URL,
+};
+use rust_embedding::http::client::build_client;
+use rust_embedding::utils::similarity::cosine_similarity;
+use serde_json::{json, Value};
+use tokio::net::TcpListener;
+
+/// Spawn a server answering OpenAI's `/v1/embeddings` with a fixed status and body
+async fn spawn_openai_mock(status: StatusCode, body: Value) -> String {
+    let app = Router::new().route("/v1/embeddings", post(move || async move { (status, Json(body)) }));
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        axum::serve(listener, app).await.unwrap();
+    });
+    format!("http://{}", addr)
+}
+
+fn openai_at(url: String) -> OpenAiProvider {
+    OpenAiProvider::new().with_base_url(url).with_api_key("sk-test")
+}
+
+#[test]
+fn test_openai_embeddings_url() {
+    for base in [
+        "http://localhost:1234",
+        "http://localhost:1234/",
+        "http://localhost:1234/v1",
+        "http://localhost:1234/v1/",
+        "http://localhost:1234/v1/embeddings",
+    ] {
+        let provider = OpenAiProvider::new().with_base_url(base);
+        assert_eq!(provider.embeddings_url(), "http://localhost:1234/v1/embeddings", "base {}", base);
+    }
+    assert_eq!(
+        OpenAiProvider::new().with_base_url(DEFAULT_OPENAI_URL).embeddings_url(),
+        "https://api.openai.com/v1/embeddings"
+    );
+}
+
+#[tokio::test]
+async fn test_openai_invalid_base_url_is_not_configured() {
+    let provider = openai_at("http://not a host".to_string());
+    let error = provider.embed(&build_client(), "text", "text-embedding-3-large", None, InputType::Query).await.unwrap_err();
+    assert!(matches!(error, ProviderError::NotConfigured(_)), "{:?}", error);
+    assert!(error.to_string().contains("OPENAI_BASE_URL"), "{}", error);
+    assert!(provider.check_configured().is_err());
+}
+
+#[tokio::test]
+async fn test_openai_invalid_header_value_is_not_configured() {
+    for provider in [
+        openai_at("http://127.0.0.1:1".to_string()).with_organization("org-1\nX-Injected: 1"),
+        openai_at("http://127.0.0.1:1".to_string()).with_project("proj\u{7f}"),
+    ] {
+        let error = provider.embed(&build_client(), "text", "text-embedding-3-large", None, InputType::Query).await.unwrap_err();
+        assert!(matches!(error, ProviderError::NotConfigured(_)), "{:?}", error);
+        assert!(provider.check_configured().is_err());
+    }
+}
+
+#[test]
+fn test_openai_dimensions_within_model_max() {
+    let provider = OpenAiProvider::new();
+    assert!(provider.validate_dimensions("text-embedding-3-large", 256).is_ok());
+    assert!(provider.validate_dimensions("text-embedding-3-large", 3072).is_ok());
+    assert!(provider.validate_dimensions("text-embedding-3-small", 1536).is_ok());
+}
+
+#[test]
+fn test_openai_dimensions_too_large() {
+    let provider = OpenAiProvider::new();
+    let error = provider.validate_dimensions("text-embedding-3-small", 3072).unwrap_err();
+    assert!(error.contains("1536"), "{}", error);
+    assert!(provider.validate_dimensions("text-embedding-3-large", 0).is_err());
+}
+
+#[test]
+fn test_dimensions_unsupported() {
+    assert!(OpenAiProvider::new().validate_dimensions("text-embedding-ada-002", 256).is_err());
+    assert!(OllamaProvider::new("http://localhost:11434")
+        .validate_dimensions("nomic-embed-text", 256)
+        .is_err());
+}
+
+#[tokio::test]
+async fn test_openai_error_body_is_surfaced() {
+    let url = spawn_openai_mock(
+        StatusCode::UNAUTHORIZED,
+        json!({ "error": {
+            "message": "Incorrect API key provided",
+            "type": "invalid_request_error",
+            "code": "invalid_api_key"
+        } }),
+    )
+    .await;
+
+    let error = openai_at(url).embed(&build_client(), "text", "text-embedding-3-large", None, InputType::Query).await.unwrap_err();
+    assert_eq!(error.to_string(), "OpenAI: Incorrect API key provided");
+    match error {
+        ProviderError::Api { status, error_type, .. } => {
+            assert_eq!(status, 401);
+            assert_eq!(error_type.as_deref(), Some("invalid_request_error"));
+        }
+        other => panic!("expected an API error, got {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn test_openai_error_status_without_error_body() {
+    let url = spawn_openai_mock(StatusCode::BAD_GATEWAY, json!("upstream unavailable")).await;
+
+    let error = openai_at(url).embed(&build_client(), "text", "text-embedding-3-large", None, InputType::Query).await.unwrap_err();
+    assert_eq!(error.status(), Some(502));
+}
+
+#[tokio::test]
+async fn test_openai_success() {
+    let url = spawn_openai_mock(StatusCode::OK, json!({ "data": [{ "embedding": [0.5, -0.5] }] })).await;
+
+    let embedding = openai_at(url).embed(&build_client(), "text", "text-embedding-3-large", None, InputType::Query).await.unwrap();
+    assert_eq!(embedding, vec![0.5, -0.5]);
+}
+
+#[tokio::test]
+async fn test_openai_batch_is_returned_in_input_order() {
+    let url = spawn_openai_mock(StatusCode::OK, json!({ "data": [
+        { "index": 1, "embedding": [0.0, 1.0] },
+        { "index": 0, "embedding": [1.0, 0.0] },
+    ] })).await;
+    let provider = openai_at(url);
+    let texts = vec!["first".to_string(), "second".to_string()];
+
+    let embeddings = provider.embed_batch(&build_client(), &texts, "text-embedding-3-large", None, InputType::Query).await.unwrap();
+    assert_eq!(embeddings, vec![vec![1.0, 0.0], vec![0.0, 1.0]]);
+    // A vector missing for one of the texts is an error rather than a shorter list
+    let texts = vec!["first".to_string(), "second".to_string(), "third".to_string()];
+    let error = provider.embed_batch(&build_client(), &texts, "text-embedding-3-large", None, InputType::Query).await.unwrap_err();
+    assert!(matches!(error, ProviderError::InvalidResponse(_)), "{:?}", error);
+}
+
+#[tokio::test]
+async fn test_cohere_error_body_is_surfaced() {
+    let app = Router::new().route(
+        "/embed",
+        post(|| async { (StatusCode::UNAUTHORIZED, Json(json!({ "message": "invalid api token" }))) }),
+    );
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    tokio::spawn(async move {
+        axum::serve(listener, app).await.unwrap();
+    });
+
+    let provider = CohereProvider::new().with_base_url(url).with_api_key("bad");
+    let error = provider.embed(&build_client(), "text", "embed-english-v3.0", None, InputType::Document).await.unwrap_err();
+    assert_eq!(error.to_string(), "Cohere: invalid api token");
+    assert_eq!(error.status(), Some(401));
+}
+
+#[tokio::test]
+async fn test_cohere_batch_is_split_at_the_limit() {
+    // Each text's vector is its position in the call, and each call's size is recorded
+    let calls = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+    let recorded = calls.clone();
+    let app = Router::new().route(
+        "/embed",
+        post(move |Json(body): Json<Value>| async move {
+            let texts = body["texts"].as_array().unwrap().len();
+            recorded.lock().unwrap().push(texts);
+            let embeddings: Vec<Vec<f64>> = (0..texts).map(|i| vec![i as f64]).collect();
+            Json(json!({ "embeddings": embeddings }))
+        }),
+    );
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    tokio::spawn(async move {
+        axum::serve(listener, app).await.unwrap();
+    });
+
+    let provider = CohereProvider::new().with_base_url(url).with_api_key("key");
+    let texts: Vec<String> = (0..COHERE_MAX_BATCH_SIZE + 4).map(|i| format!("text {}", i)).collect();
+    let embeddings =
+        provider.embed_batch(&build_client(), &texts, "embed-english-v3.0", None, InputType::Document).await.unwrap();
+    assert_eq!(*calls.lock().unwrap(), vec![COHERE_MAX_BATCH_SIZE, 4]);
+    assert_eq!(embeddings.len(), texts.len());
+    assert_eq!(embeddings[COHERE_MAX_BATCH_SIZE - 1], vec![(COHERE_MAX_BATCH_SIZE - 1) as f64]);
+    assert_eq!(embeddings[COHERE_MAX_BATCH_SIZE], vec![0.0]);
+}
+
+#[tokio::test]
+async fn test_missing_api_key_is_an_error() {
+    // A base URL nothing listens on: the key check must fail before any request is made
+    let provider = CohereProvider {
+        base_url: "http://127.0.0.1:1".to_string(),
+        api_key: None,
+    };
+    if std::env::var("COHERE_API_KEY").is_err() {
+        let error = provider.embed(&build_client(), "text", "embed-english-v3.0", None, InputType::Query).await.unwrap_err();
+        assert!(matches!(error, ProviderError::NotConfigured(_)), "{:?}", error);
+        assert!(provider.check_configured().is_err());
+    }
+}
+
+#[tokio::test]
+async fn test_openai_api_key_from_file() {
+    // The embedding returned tells which key was sent
+    let app = Router::new().route(
+        "/v1/embeddings",
+        post(|headers: axum::http::HeaderMap| async move {
+            let auth = headers["authorization"].to_str().unwrap().to_string();
+            let embedding = if auth == "Bearer sk-from-file" { vec![1.0, 2.0] } else { vec![0.0] };
+            Json(json!({ "data": [{ "embedding": embedding }] }))
+        }),
+    );
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    tokio::spawn(async move {
+        axum::serve(listener, app).await.unwrap();
+    });
+
+    // Trailing newlines, as most secret mounts have, are trimmed
+    let key_file = tempfile::NamedTempFile::new().unwrap();
+    std::fs::write(key_file.path(), "sk-from-file\n").unwrap();
+    std::env::set_var("OPENAI_API_KEY_FILE", key_file.path());
+
+    let provider = OpenAiProvider::new().with_base_url(url);
+    assert!(provider.check_configured().is_ok());
+    let embedding = provider.embed(&build_client(), "text", "text-embedding-3-large", None, InputType::Query).await.unwrap();
+    assert_eq!(embedding, vec![1.0, 2.0]);
+
+    // A file that can't be read is a configuration error, not a fallback to OPENAI_API_KEY
+    std::env::set_var("OPENAI_API_KEY_FILE", key_file.path().with_extension("missing"));
+    let error = provider.embed(&build_client(), "text", "text-embedding-3-large", None, InputType::Query).await.unwrap_err();
+    assert!(matches!(error, ProviderError::NotConfigured(_)), "{:?}", error);
+    assert!(error.to_string().contains("OPENAI_API_KEY_FILE"), "{}", error);
+    std::env::remove_var("OPENAI_API_KEY_FILE");
+}
+
+#[tokio::test]
+async fn test_openai_organization_and_project_headers() {
+    // Record the organization and project headers of each call
+    let seen = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+    let recorded = seen.clone();
+    let app = Router::new().route(
+        "/v1/embeddings",
+        post(move |headers: axum::http::HeaderMap| async move {
+            let header = |name: &str| headers.get(name).map(|v| v.to_str().unwrap().to_string());
+            recorded.lock().unwrap().push((header("openai-organization"), header("openai-project")));
+            Json(json!({ "data": [{ "embedding": [1.0] }] }))
+        }),
+    );
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    tokio::spawn(async move {
+        axum::serve(listener, app).await.unwrap();
+    });
+    let embed = |provider: OpenAiProvider| async move {
+        provider.embed(&build_client(), "text", "text-embedding-3-large", None, InputType::Query).await.unwrap();
+    };
+
+    std::env::set_var("OPENAI_ORG_ID", "org-123");
+    std::env::set_var("OPENAI_PROJECT_ID", "proj_abc");
+    let configured = OpenAiProvider::new();
+    std::env::remove_var("OPENAI_ORG_ID");
+    std::env::remove_var("OPENAI_PROJECT_ID");
+    assert_eq!(configured.organization.as_deref(), Some("org-123"));
+    embed(configured.with_base_url(url.clone()).with_api_key("sk-test")).await;
+    embed(OpenAiProvider::new().with_base_url(url.clone()).with_api_key("sk-test")).await;
+    embed(openai_at(url).with_project("proj_only")).await;
+
+    assert_eq!(
+        *seen.lock().unwrap(),
+        vec![
+            (Some("org-123".to_string()), Some("proj_abc".to_string())),
+            (None, None),
+            (None, Some("proj_only".to_string())),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn test_mock_provider_is_deterministic() {
+    let provider = MockProvider::default();
+    let client = build_client();
+    let embed = |text: &'static str| provider.embed(&client, text, "text-embedding-3-small", None, InputType::Query);
+
+    let first = embed("The quick brown fox").await.unwrap();
+    assert_eq!(first.len(), 64);
+    assert_eq!(first, embed("the quick  brown FOX").await.unwrap());
+
+    // Shared words make texts more similar than unrelated ones
+    let related = cosine_similarity(&first, &embed("a quick brown dog").await.unwrap()).unwrap();
+    let unrelated = cosine_similarity(&first, &embed("stock market report").await.unwrap()).unwrap();
+    assert!(related > unrelated, "{} <= {}", related, unrelated);
+
+    assert!(embed("").await.unwrap().iter().any(|x| *x != 0.0));
+    let short = provider.embed(&client, "text", "text-embedding-3-small", Some(8), InputType::Query).await.unwrap();
+    assert_eq!(short.len(), 8);
+}
@@ -0,0 +1,125 @@
+use rust_embedding::embeddings::provider::MockProvider;
+use rust_embedding::embeddings::storage::{append_records, build_record, StorageFormat, StoragePrecision, StoredEmbedding};
+use rust_embedding::{rank_candidates, CompareFilter, EmbeddingService, RankOptions};
+use serde_json::Value;
+
+fn record(text: &str, vector: &[f64], embedding_type: &str) -> Value {
+    build_record(text, &StoredEmbedding::encode(vector, StoragePrecision::F64), "test", embedding_type, false, None)
+}
+
+/// What identifies each result and its rank
+fn ranking(results: &[rust_embedding::ComparisonResult]) -> Vec<(String, String)> {
+    results.iter().map(|result| (result.text.clone(), result.embedding_type.clone())).collect()
+}
+
+fn fixture() -> Vec<Value> {
+    vec![
+        record("far", &[0.0, 1.0], "a"),
+        record("near", &[1.0, 0.1], "a"),
+        record("nearest", &[1.0, 0.0], "b"),
+        // A repeat of "near" with another vector; the first copy wins
+        record("near", &[0.0, -1.0], "a"),
+        record("middle", &[1.0, 1.0], "b"),
+    ]
+}
+
+#[test]
+fn test_rank_candidates_orders_and_limits() {
+    let records = fixture();
+    let results = rank_candidates(&records, "", &[1.0, 0.0], &RankOptions::default());
+    let texts: Vec<&str> = results.iter().map(|result| result.text.as_str()).collect();
+    assert_eq!(texts, ["nearest", "near", "middle", "far"]);
+    assert!(results.iter().all(|result| result.embedding.is_none()));
+
+    let options = RankOptions { top_k: Some(2), include_embeddings: true, ..RankOptions::default() };
+    let results = rank_candidates(&records, "", &[1.0, 0.0], &options);
+    assert_eq!(ranking(&results), [("nearest".to_string(), "b".to_string()), ("near".to_string(), "a".to_string())]);
+    assert_eq!(results[0].embedding, Some(vec![1.0, 0.0]));
+}
+
+#[test]
+fn test_rank_candidates_applies_filters() {
+    let records = fixture();
+    let options = RankOptions {
+        filter: CompareFilter { embedding_types: Some(vec!["b".to_string()]), ..CompareFilter::default() },
+        ..RankOptions::default()
+    };
+    let texts: Vec<String> = rank_candidates(&records, "", &[1.0, 0.0], &options).into_iter().map(|result| result.text).collect();
+    assert_eq!(texts, ["nearest", "middle"]);
+
+    let options = RankOptions { min_similarity: Some(0.9), ..RankOptions::default() };
+    assert_eq!(rank_candidates(&records, "", &[1.0, 0.0], &options).len(), 2);
+
+    // The query's own text is left out within the requested types
+    let options = RankOptions {
+        filter: CompareFilter { embedding_types: Some(vec!["b".to_string()]), ..CompareFilter::default() },
+        ..RankOptions::default()
+    };
+    let texts: Vec<String> = rank_candidates(&records, "nearest", &[1.0, 0.0], &options).into_iter().map(|result| result.text).collect();
+    assert_eq!(texts, ["middle"]);
+
+    assert!(rank_candidates(Vec::<Value>::new(), "", &[1.0, 0.0], &RankOptions::default()).is_empty());
+}
+
+#[test]
+fn test_rank_candidates_per_type_k() {
+    let records = fixture();
+    let options = RankOptions { top_k: Some(1), per_type_k: Some(1), ..RankOptions::default() };
+    // `top_k` is ignored, and each type is ranked on its own, in order of the type names
+    let results = rank_candidates(&records, "", &[1.0, 0.0], &options);
+    assert_eq!(ranking(&results), [("near".to_string(), "a".to_string()), ("nearest".to_string(), "b".to_string())]);
+}
+
+/// The service's full-scan comparison is `rank_candidates` over the data file
+#[tokio::test]
+async fn test_rank_candidates_matches_compare_embeddings() {
+    let vectors: Vec<Vec<f64>> = (0..300).map(|i| MockProvider::embed_text(&format!("word{} common {}", i % 17, i % 5), 32)).collect();
+    let records: Vec<Value> = vectors
+        .iter()
+        .enumerate()
+        .map(|(i, vector)| record(&format!("text {}", i % 250), vector, ["a", "b"][i % 2]))
+        .collect();
+    let path = tempfile::NamedTempFile::new().unwrap().into_temp_path();
+    append_records(path.to_str().unwrap(), StorageFormat::Jsonl, &records).unwrap();
+    let service = EmbeddingService::with_provider_and_data_path(Box::new(MockProvider::default()), path.to_str().unwrap());
+    let query = MockProvider::embed_text("word3 common", 32);
+
+    for top_k in [None, Some(10)] {
+        let filter = CompareFilter { dedup_threshold: Some(0.99), ..CompareFilter::default() };
+        for filter in [CompareFilter::default(), filter] {
+            let expected = service.compare_embeddings("text 3", &query, top_k, false, filter.clone(), None).await.unwrap();
+            for parallel in [false, true] {
+                let options = RankOptions { top_k, filter: filter.clone(), parallel, ..RankOptions::default() };
+                let results = rank_candidates(&records, "text 3", &query, &options);
+                assert_eq!(ranking(&results), ranking(&expected));
+                let similarities: Vec<f64> = results.iter().map(|result| result.similarity).collect();
+                assert_eq!(similarities, expected.iter().map(|result| result.similarity).collect::<Vec<_>>());
+            }
+        }
+    }
+}
+
+#[test]
+fn test_rank_candidates_hybrid_keyword_match() {
+    let records = vec![
+        record("automobile maintenance guide", &[1.0, 0.1], "a"),
+        record("rust embedding service", &[0.6, 0.8], "a"),
+        record("unrelated words here", &[0.0, 1.0], "a"),
+    ];
+    let hybrid = |alpha| RankOptions {
+        filter: CompareFilter { hybrid_alpha: Some(alpha), ..CompareFilter::default() },
+        ..RankOptions::default()
+    };
+
+    // By similarity alone the closer vector wins
+    let texts: Vec<String> = rank_candidates(&records, "rust embedding", &[1.0, 0.0], &hybrid(1.0)).into_iter().map(|result| result.text).collect();
+    assert_eq!(texts[0], "automobile maintenance guide");
+
+    // Weighted towards keywords, the text sharing the query's words outranks it
+    let results = rank_candidates(&records, "rust embedding", &[1.0, 0.0], &hybrid(0.2));
+    assert_eq!(results[0].text, "rust embedding service");
+    assert_eq!(results[0].lexical_score, Some(1.0));
+    assert!((results[0].similarity - 0.6).abs() < 1e-9, "the raw similarity is kept");
+    assert!((results[0].adjusted_similarity.unwrap() - (0.2 * 0.6 + 0.8)).abs() < 1e-9);
+    assert_eq!(results[1].lexical_score, Some(0.0));
+}
@@ -0,0 +1,108 @@
+use rust_embedding::utils::similarity::{
+    cosine_similarity, descending_similarity, dot_product, euclidean_distance, normalize_vector, round_significant, round_to,
+    top_k_by,
+};
+
+#[test]
+fn test_cosine_similarity_identical_vectors() {
+    let similarity = cosine_similarity(&[1.0, 2.0, 3.0], &[1.0, 2.0, 3.0]).unwrap();
+    assert!((similarity - 1.0).abs() < 1e-12);
+}
+
+#[test]
+fn test_cosine_similarity_orthogonal_vectors() {
+    let similarity = cosine_similarity(&[1.0, 0.0], &[0.0, 1.0]).unwrap();
+    assert!(similarity.abs() < 1e-12);
+}
+
+#[test]
+fn test_cosine_similarity_zero_vector() {
+    assert_eq!(cosine_similarity(&[0.0, 0.0, 0.0], &[1.0, 2.0, 3.0]), None);
+    assert_eq!(cosine_similarity(&[1.0, 2.0, 3.0], &[0.0, 0.0, 0.0]), None);
+}
+
+#[test]
+fn test_cosine_similarity_dimension_mismatch() {
+    assert_eq!(cosine_similarity(&[1.0, 2.0, 3.0], &[1.0, 2.0]), None);
+}
+
+#[test]
+fn test_cosine_similarity_empty_vectors() {
+    assert_eq!(cosine_similarity(&[], &[]), None);
+}
+
+#[test]
+fn test_normalize_vector_unit_length() {
+    let normalized = normalize_vector(&[3.0, 4.0]).unwrap();
+    assert_eq!(normalized, vec![0.6, 0.8]);
+}
+
+#[test]
+fn test_normalize_vector_zero_vector() {
+    assert_eq!(normalize_vector(&[0.0, 0.0]), None);
+}
+
+#[test]
+fn test_dot_product_of_normalized_matches_cosine() {
+    let a = [1.0, 2.0, 3.0];
+    let b = [4.0, -5.0, 6.0];
+    let dot = dot_product(&normalize_vector(&a).unwrap(), &normalize_vector(&b).unwrap()).unwrap();
+    assert!((dot - cosine_similarity(&a, &b).unwrap()).abs() < 1e-12);
+}
+
+#[test]
+fn test_dot_product_dimension_mismatch() {
+    assert_eq!(dot_product(&[1.0, 2.0], &[1.0]), None);
+}
+
+#[test]
+fn test_euclidean_distance() {
+    assert_eq!(euclidean_distance(&[0.0, 0.0], &[3.0, 4.0]), Some(5.0));
+    assert_eq!(euclidean_distance(&[1.0, 2.0], &[1.0, 2.0]), Some(0.0));
+    assert_eq!(euclidean_distance(&[1.0, 2.0], &[1.0]), None);
+}
+
+#[test]
+fn test_round_to() {
+    assert_eq!(round_to(0.8234729384729837, 3), 0.823);
+    assert_eq!(round_to(0.8235, 0), 1.0);
+    assert_eq!(round_to(-0.45678, 2), -0.46);
+    // More places than f64 holds leaves the value alone rather than overflowing
+    assert_eq!(round_to(0.8234729384729837, 400), 0.8234729384729837);
+    assert!(round_to(f64::NAN, 2).is_nan());
+}
+
+#[test]
+fn test_round_significant() {
+    assert_eq!(round_significant(0.8234729384729837, 3), 0.823);
+    assert_eq!(round_significant(-0.000123456789, 4), -0.0001235);
+    assert_eq!(round_significant(98765.4321, 2), 99000.0);
+    assert_eq!(round_significant(0.0, 3), 0.0);
+    assert_eq!(round_significant(0.8234729384729837, 17), 0.8234729384729837);
+    assert!(round_significant(f64::NAN, 3).is_nan());
+}
+
+#[test]
+fn test_top_k_matches_full_sort() {
+    // Scores with plenty of ties and a few NaNs, from a fixed linear congruential sequence
+    let mut seed: u64 = 42;
+    let scores: Vec<f64> = (0..2000)
+        .map(|i| {
+            seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            if i % 97 == 0 {
+                f64::NAN
+            } else {
+                ((seed >> 33) % 200) as f64 / 100.0 - 1.0
+            }
+        })
+        .collect();
+    let items: Vec<(usize, f64)> = scores.into_iter().enumerate().collect();
+
+    let mut sorted = items.clone();
+    sorted.sort_by(|a, b| descending_similarity(a.1, b.1));
+    for k in [0, 1, 5, 100, 1999, 2000, 5000] {
+        let heap = top_k_by(items.iter().copied(), k, |item| item.1);
+        let expected: Vec<usize> = sorted.iter().take(k).map(|item| item.0).collect();
+        assert_eq!(heap.iter().map(|item| item.0).collect::<Vec<_>>(), expected, "k = {}", k);
+    }
+}
@@ -0,0 +1,121 @@
+use axum::{routing::post, Router};
+use rust_embedding::embeddings::provider::{EmbeddingProvider, InputType, MockProvider, ProviderError};
+use rust_embedding::embeddings::service::EmbeddingService;
+use rust_embedding::http::slow_requests::{log_slow_requests, SlowRequestLog};
+use rust_embedding::{compare_embedding, store_embedding};
+use serde_json::json;
+use std::io::Write;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::net::TcpListener;
+
+/// A provider that takes `delay` to answer each request
+struct SlowProvider {
+    delay: Duration,
+}
+
+#[async_trait::async_trait]
+impl EmbeddingProvider for SlowProvider {
+    fn name(&self) -> &'static str {
+        "slow"
+    }
+
+    fn default_model(&self) -> &'static str {
+        "slow"
+    }
+
+    async fn embed(
+        &self,
+        _client: &reqwest::Client,
+        text: &str,
+        _model: &str,
+        _dimensions: Option<usize>,
+        _input_type: InputType,
+    ) -> Result<Vec<f64>, ProviderError> {
+        tokio::time::sleep(self.delay).await;
+        Ok(MockProvider::embed_text(text, 16))
+    }
+}
+
+/// Collects everything logged, for the assertions. This file holds a single test, since the
+/// subscriber it installs is global.
+#[derive(Clone, Default)]
+struct CapturedLogs(Arc<Mutex<Vec<u8>>>);
+
+impl Write for CapturedLogs {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl CapturedLogs {
+    fn slow_request_lines(&self) -> Vec<String> {
+        String::from_utf8_lossy(&self.0.lock().unwrap())
+            .lines()
+            .filter(|line| line.contains("slow request"))
+            .map(str::to_string)
+            .collect()
+    }
+}
+
+#[tokio::test]
+async fn test_slow_requests_are_logged_with_their_slowest_phase() {
+    let logs = CapturedLogs::default();
+    let writer = logs.clone();
+    tracing::subscriber::set_global_default(
+        tracing_subscriber::fmt().with_ansi(false).with_writer(move || writer.clone()).finish(),
+    )
+    .unwrap();
+
+    let data_path = tempfile::NamedTempFile::new().unwrap().into_temp_path();
+    let service = EmbeddingService::with_provider_and_data_path(
+        Box::new(SlowProvider { delay: Duration::from_millis(300) }),
+        data_path.to_str().unwrap(),
+    );
+    let app = Router::new()
+        .route("/store", post(store_embedding))
+        .route("/compare", post(compare_embedding))
+        .layer(axum::middleware::from_fn_with_state(Arc::new(SlowRequestLog::new(200)), log_slow_requests))
+        .with_state(Arc::new(service));
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let base_url = format!("http://{}", listener.local_addr().unwrap());
+    tokio::spawn(async move {
+        axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>()).await.unwrap();
+    });
+    let client = reqwest::Client::new();
+
+    let response = client
+        .post(format!("{}/store", base_url))
+        .json(&json!({ "text": "slow to embed", "embedding_type": "test" }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::OK);
+    let lines = logs.slow_request_lines();
+    assert_eq!(lines.len(), 1, "{:?}", lines);
+    assert!(lines[0].contains("path=\"/store\"") || lines[0].contains("path=/store"), "{}", lines[0]);
+    assert!(lines[0].contains("slowest_phase=\"provider\""), "{}", lines[0]);
+    let provider_ms: u64 = lines[0]
+        .split("provider_ms=")
+        .nth(1)
+        .and_then(|rest| rest.split_whitespace().next())
+        .and_then(|value| value.parse().ok())
+        .unwrap();
+    assert!(provider_ms >= 300, "{}", lines[0]);
+
+    // An exact-match lookup doesn't call the provider, so it is quick and isn't logged
+    let response = client
+        .post(format!("{}/compare", base_url))
+        .json(&json!({ "text": "slow to embed", "exact_only": true }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::OK);
+    assert_eq!(logs.slow_request_lines().len(), 1);
+}
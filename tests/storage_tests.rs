@@ -0,0 +1,253 @@
+use rust_embedding::embeddings::storage::{
+    append_records_to_bincode, append_records_to_gzip, append_records_to_jsonl, load_entries_from_bincode,
+    load_entries_from_gzip, load_entries_from_jsonl, migrate_jsonl_to_bincode,
+    record_embedding, StoragePrecision, StoredEmbedding,
+};
+use rust_embedding::utils::similarity::cosine_similarity;
+use serde_json::json;
+
+const DIMENSIONS: usize = 3072;
+
+/// Reference vectors shaped like real embeddings: small values of mixed sign, one with an
+/// outlier component that stretches the int8 scale
+fn reference_vectors() -> Vec<Vec<f64>> {
+    let smooth: Vec<f64> = (0..DIMENSIONS).map(|i| (i as f64 * 0.37).sin() * 0.05).collect();
+    let mixed: Vec<f64> = (0..DIMENSIONS).map(|i| ((i * 7919) % 1000) as f64 / 1000.0 - 0.5).collect();
+    let mut outlier = smooth.clone();
+    outlier[17] = 0.9;
+    vec![smooth, mixed, outlier]
+}
+
+fn record_size(embedding: &[f64], precision: StoragePrecision) -> usize {
+    let mut record = json!({ "text": "reference", "model": "test", "embedding_type": "test" });
+    StoredEmbedding::encode(embedding, precision).write_to(&mut record);
+    format!("{}\n", record).len()
+}
+
+fn round_trip(embedding: &[f64], precision: StoragePrecision) -> Vec<f64> {
+    let mut record = json!({ "text": "reference" });
+    StoredEmbedding::encode(embedding, precision).write_to(&mut record);
+    // Decode from the serialized line, as the store does
+    let line = record.to_string();
+    record_embedding(&serde_json::from_str(&line).unwrap()).unwrap()
+}
+
+#[test]
+fn test_quantized_storage_shrinks_records() {
+    for vector in reference_vectors() {
+        let f64_size = record_size(&vector, StoragePrecision::F64);
+        let f32_size = record_size(&vector, StoragePrecision::F32);
+        let bf16_size = record_size(&vector, StoragePrecision::Bf16);
+        let int8_size = record_size(&vector, StoragePrecision::Int8);
+        println!(
+            "record sizes: f64 {} bytes, f32 {} bytes, bf16 {} bytes, int8 {} bytes",
+            f64_size, f32_size, bf16_size, int8_size
+        );
+
+        assert!(f32_size * 100 < f64_size * 65, "f32 record is {} of {} bytes", f32_size, f64_size);
+        // As text, bf16 only saves on values with long decimals; its 2 bytes a value show in
+        // the binary format
+        assert!(bf16_size <= f32_size, "bf16 record is {} of {} bytes", bf16_size, f32_size);
+        assert!(int8_size * 10 < f64_size * 3, "int8 record is {} of {} bytes", int8_size, f64_size);
+    }
+}
+
+#[test]
+fn test_quantized_storage_cosine_error() {
+    for vector in reference_vectors() {
+        let f32_error = 1.0 - cosine_similarity(&vector, &round_trip(&vector, StoragePrecision::F32)).unwrap();
+        let bf16_error = 1.0 - cosine_similarity(&vector, &round_trip(&vector, StoragePrecision::Bf16)).unwrap();
+        let int8_error = 1.0 - cosine_similarity(&vector, &round_trip(&vector, StoragePrecision::Int8)).unwrap();
+        println!("cosine error: f32 {:e}, bf16 {:e}, int8 {:e}", f32_error, bf16_error, int8_error);
+
+        assert!(f32_error < 1e-12);
+        // Each value keeps 8 bits of mantissa whatever the other components are
+        assert!(bf16_error < 1e-5);
+        // An outlier component coarsens the int8 steps for the rest of the vector, which
+        // pushes the error from ~1e-5 to ~1e-3
+        assert!(int8_error < 5e-3);
+    }
+}
+
+#[test]
+fn test_f64_round_trip_keeps_full_precision() {
+    let vector = &reference_vectors()[1];
+    let decoded = round_trip(vector, StoragePrecision::F64);
+    // Up to the last bit serde_json may lose when parsing floats
+    for (a, b) in vector.iter().zip(&decoded) {
+        assert!((a - b).abs() <= f64::EPSILON * a.abs());
+    }
+}
+
+#[test]
+fn test_int8_values_and_scale() {
+    let stored = StoredEmbedding::encode(&[0.5, -1.0, 0.25, 0.0], StoragePrecision::Int8);
+    assert_eq!(stored.values, vec![64.0, -127.0, 32.0, 0.0]);
+    assert_eq!(stored.scale, Some(1.0 / 127.0));
+
+    // An all-zero vector still encodes to something decodable
+    let zeros = StoredEmbedding::encode(&[0.0, 0.0], StoragePrecision::Int8);
+    assert_eq!(zeros.to_vec(), vec![0.0, 0.0]);
+}
+
+#[test]
+fn test_bf16_values() {
+    let stored = StoredEmbedding::encode(&[0.1, -1.0, 1e-3, 3.0e38, 0.0], StoragePrecision::Bf16);
+    // The shortest decimals that round to the same bfloat16 numbers
+    assert_eq!(stored.values, vec![0.1, -1.0, 0.001, 3.0e38, 0.0]);
+    // Decoded as the exact bfloat16 values
+    assert_eq!(stored.to_vec()[0], 0.10009765625);
+
+    // The same values come back from a JSONL line
+    let vector = &reference_vectors()[1];
+    let decoded = round_trip(vector, StoragePrecision::Bf16);
+    assert_eq!(decoded, StoredEmbedding::encode(vector, StoragePrecision::Bf16).to_vec());
+}
+
+/// In the binary format a bf16 vector takes 2 bytes a value: half of `f32`, a quarter of `f64`
+#[test]
+fn test_bf16_halves_binary_records() {
+    let vector = &reference_vectors()[0];
+    let file_size = |precision| {
+        let path = tempfile::NamedTempFile::new().unwrap().into_temp_path();
+        append_records_to_bincode(path.to_str().unwrap(), &[sample_record("reference", vector, precision)]).unwrap();
+        std::fs::metadata(&path).unwrap().len() as usize
+    };
+    let (f64_size, f32_size, bf16_size) = (file_size(StoragePrecision::F64), file_size(StoragePrecision::F32), file_size(StoragePrecision::Bf16));
+    println!("binary record sizes: f64 {} bytes, f32 {} bytes, bf16 {} bytes", f64_size, f32_size, bf16_size);
+    // Everything but the values is the same size
+    assert_eq!(f32_size - bf16_size, 2 * DIMENSIONS);
+    assert_eq!(f64_size - bf16_size, 6 * DIMENSIONS);
+}
+
+#[test]
+fn test_records_without_precision_are_f64() {
+    let entry = json!({ "text": "legacy", "embedding": [0.1, 0.2] });
+    let stored = StoredEmbedding::from_record(&entry).unwrap();
+    assert_eq!(stored.precision, StoragePrecision::F64);
+    assert_eq!(stored.to_vec(), vec![0.1, 0.2]);
+
+    // An int8 record is unreadable without its scale
+    assert!(record_embedding(&json!({ "embedding": [1, 2], "precision": "int8" })).is_none());
+}
+
+fn sample_record(text: &str, embedding: &[f64], precision: StoragePrecision) -> serde_json::Value {
+    let mut record = json!({ "text": text, "model": "test", "embedding_type": "test", "normalized": false });
+    StoredEmbedding::encode(embedding, precision).write_to(&mut record);
+    record
+}
+
+#[test]
+fn test_bincode_round_trip() {
+    let path = tempfile::NamedTempFile::new().unwrap().into_temp_path();
+    let path = path.to_str().unwrap();
+    let records = vec![
+        sample_record("f64", &[0.1, -0.2, 0.3], StoragePrecision::F64),
+        sample_record("f32", &[0.1, -0.2, 0.3], StoragePrecision::F32),
+        sample_record("int8", &[0.1, -0.2, 0.3], StoragePrecision::Int8),
+        sample_record("bf16", &[0.1, -0.2, 0.3], StoragePrecision::Bf16),
+    ];
+
+    append_records_to_bincode(path, &records[..1]).unwrap();
+    append_records_to_bincode(path, &records[1..]).unwrap();
+
+    assert_eq!(load_entries_from_bincode(path).unwrap(), records);
+}
+
+#[test]
+fn test_bincode_round_trip_keeps_metadata_and_created_at() {
+    let path = tempfile::NamedTempFile::new().unwrap().into_temp_path();
+    let path = path.to_str().unwrap();
+    let mut tagged = sample_record("tagged", &[0.1, -0.2, 0.3], StoragePrecision::F32);
+    tagged["metadata"] = json!({ "source": "https://example.com", "tags": ["a", "b"] });
+    let mut dated = sample_record("dated", &[0.6, 0.7], StoragePrecision::Int8);
+    dated["created_at"] = json!("2024-05-01T08:00:00.000Z");
+    dated["metadata"] = json!({ "source": "import" });
+    let records = vec![tagged, sample_record("untagged", &[0.4, 0.5], StoragePrecision::F64), dated];
+
+    append_records_to_bincode(path, &records).unwrap();
+
+    assert_eq!(load_entries_from_bincode(path).unwrap(), records);
+}
+
+#[test]
+fn test_bincode_partial_write_is_detected() {
+    let path = tempfile::NamedTempFile::new().unwrap().into_temp_path();
+    let path = path.to_str().unwrap();
+    let first = sample_record("first", &[1.0, 2.0], StoragePrecision::F64);
+    append_records_to_bincode(path, std::slice::from_ref(&first)).unwrap();
+
+    // Simulate a write interrupted halfway through a second record
+    let complete = std::fs::read(path).unwrap();
+    let mut partial = complete.clone();
+    partial.extend_from_slice(&complete[..complete.len() / 2]);
+    std::fs::write(path, &partial).unwrap();
+    assert_eq!(load_entries_from_bincode(path).unwrap(), vec![first.clone()]);
+
+    // The next append replaces the partial record rather than writing after it
+    let second = sample_record("second", &[3.0, 4.0], StoragePrecision::F64);
+    append_records_to_bincode(path, std::slice::from_ref(&second)).unwrap();
+    assert_eq!(load_entries_from_bincode(path).unwrap(), vec![first, second]);
+}
+
+#[test]
+fn test_gzip_round_trip() {
+    let jsonl_path = tempfile::NamedTempFile::new().unwrap().into_temp_path();
+    let gzip_path = tempfile::NamedTempFile::new().unwrap().into_temp_path();
+    let records: Vec<_> = reference_vectors()
+        .iter()
+        .enumerate()
+        .map(|(i, vector)| sample_record(&format!("reference {}", i), vector, StoragePrecision::F64))
+        .collect();
+    append_records_to_jsonl(jsonl_path.to_str().unwrap(), &records).unwrap();
+
+    // Each append adds a gzip member; reads see them as one stream
+    append_records_to_gzip(gzip_path.to_str().unwrap(), &records[..1]).unwrap();
+    append_records_to_gzip(gzip_path.to_str().unwrap(), &records[1..]).unwrap();
+    let loaded = load_entries_from_gzip(gzip_path.to_str().unwrap()).unwrap();
+    assert_eq!(loaded, load_entries_from_jsonl(jsonl_path.to_str().unwrap()).unwrap());
+
+    let uncompressed = std::fs::metadata(&jsonl_path).unwrap().len();
+    let compressed = std::fs::metadata(&gzip_path).unwrap().len();
+    println!("jsonl {} bytes, gzip {} bytes", uncompressed, compressed);
+    assert!(compressed < uncompressed);
+}
+
+#[test]
+fn test_gzip_partial_member_keeps_earlier_records() {
+    let path = tempfile::NamedTempFile::new().unwrap().into_temp_path();
+    let path = path.to_str().unwrap();
+    let first = sample_record("first", &[1.0, 2.0], StoragePrecision::F64);
+    append_records_to_gzip(path, std::slice::from_ref(&first)).unwrap();
+
+    // Simulate a write interrupted halfway through a second member
+    let complete = std::fs::read(path).unwrap();
+    let mut partial = complete.clone();
+    partial.extend_from_slice(&complete[..complete.len() / 2]);
+    std::fs::write(path, &partial).unwrap();
+
+    assert_eq!(load_entries_from_gzip(path).unwrap(), vec![first]);
+}
+
+#[test]
+fn test_migrate_jsonl_to_bincode() {
+    let jsonl_path = tempfile::NamedTempFile::new().unwrap().into_temp_path();
+    let bincode_path = tempfile::NamedTempFile::new().unwrap().into_temp_path();
+    let records: Vec<_> = reference_vectors()
+        .iter()
+        .enumerate()
+        .map(|(i, vector)| sample_record(&format!("reference {}", i), vector, StoragePrecision::F64))
+        .collect();
+    append_records_to_jsonl(jsonl_path.to_str().unwrap(), &records).unwrap();
+
+    let migrated = migrate_jsonl_to_bincode(jsonl_path.to_str().unwrap(), bincode_path.to_str().unwrap()).unwrap();
+
+    assert_eq!(migrated, records.len());
+    let loaded = load_entries_from_bincode(bincode_path.to_str().unwrap()).unwrap();
+    assert_eq!(loaded, load_entries_from_jsonl(jsonl_path.to_str().unwrap()).unwrap());
+    // Binary records skip the text encoding of every float
+    let jsonl_size = std::fs::metadata(&jsonl_path).unwrap().len();
+    let bincode_size = std::fs::metadata(&bincode_path).unwrap().len();
+    println!("jsonl {} bytes, bincode {} bytes", jsonl_size, bincode_size);
+    assert!(bincode_size < jsonl_size);
+}
@@ -0,0 +1,80 @@
+use rust_embedding::embeddings::provider::MockProvider;
+use rust_embedding::embeddings::service::EmbeddingService;
+use rust_embedding::embeddings::storage::{append_records, build_record, StorageFormat, StoragePrecision, StoredEmbedding};
+use rust_embedding::CompareFilter;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Wraps the system allocator to track the bytes currently allocated and the highest count
+/// since the last reset. This file holds a single test so no other test skews the numbers.
+struct TrackingAllocator;
+
+static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for TrackingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc(layout);
+        if !ptr.is_null() {
+            let allocated = ALLOCATED.fetch_add(layout.size(), Ordering::SeqCst) + layout.size();
+            PEAK.fetch_max(allocated, Ordering::SeqCst);
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout);
+        ALLOCATED.fetch_sub(layout.size(), Ordering::SeqCst);
+    }
+}
+
+#[global_allocator]
+static GLOBAL: TrackingAllocator = TrackingAllocator;
+
+/// Write a JSONL store of `count` vectors, a chunk at a time so the test itself never holds
+/// them all, returning its size in bytes
+fn write_store(path: &str, count: usize, dimensions: usize) -> u64 {
+    let mut state = 7u64;
+    for start in (0..count).step_by(1_000) {
+        let records: Vec<_> = (start..(start + 1_000).min(count))
+            .map(|i| {
+                let vector: Vec<f64> = (0..dimensions)
+                    .map(|_| {
+                        state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+                        (state >> 11) as f64 / (1u64 << 52) as f64 - 1.0
+                    })
+                    .collect();
+                let embedding = StoredEmbedding::encode(&vector, StoragePrecision::F64);
+                build_record(&format!("text {}", i), &embedding, "test", "test", false, None)
+            })
+            .collect();
+        append_records(path, StorageFormat::Jsonl, &records).unwrap();
+    }
+    std::fs::metadata(path).unwrap().len()
+}
+
+#[tokio::test]
+async fn test_compare_streams_large_jsonl_store() {
+    let path = tempfile::NamedTempFile::new().unwrap().into_temp_path();
+    let file_size = write_store(path.to_str().unwrap(), 40_000, 32) as usize;
+    let service = EmbeddingService::with_provider_and_data_path(Box::new(MockProvider::default()), path.to_str().unwrap());
+    let query = vec![0.5; 32];
+
+    let baseline = ALLOCATED.load(Ordering::SeqCst);
+    PEAK.store(baseline, Ordering::SeqCst);
+    let results = service
+        .compare_embeddings("query", &query, Some(5), false, CompareFilter::default(), None)
+        .await
+        .unwrap();
+    let peak = PEAK.load(Ordering::SeqCst) - baseline;
+
+    assert_eq!(results.len(), 5);
+    // Parsed records take several times their JSON size, so loading the whole file would
+    // need well over `file_size`; streaming only keeps the dedup keys and the top results
+    assert!(
+        peak < file_size / 4,
+        "compare allocated {} bytes at its peak for a {} byte file",
+        peak,
+        file_size
+    );
+}
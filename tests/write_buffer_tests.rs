@@ -0,0 +1,115 @@
+use rust_embedding::embeddings::config::Config;
+use rust_embedding::embeddings::provider::MockProvider;
+use rust_embedding::{CompareFilter, CreatedRange, EmbeddingService};
+use std::sync::Arc;
+use std::time::Duration;
+
+const DIMENSIONS: usize = 64;
+
+/// A service buffering stores for `flush_interval_ms`, or until `max_records` are waiting
+fn buffered_service(path: &str, flush_interval_ms: u64, max_records: usize) -> EmbeddingService {
+    let config = Config {
+        flush_interval_ms,
+        flush_max_records: max_records,
+        ..Config::new(Box::new(MockProvider::default()), path)
+    };
+    EmbeddingService::new_with_config(config)
+}
+
+async fn store(service: &EmbeddingService, text: &str) {
+    let embedding = MockProvider::embed_text(text, DIMENSIONS);
+    service.save_embedding(text, &embedding, "mock", "test", None).await.unwrap();
+}
+
+/// Number of records in the data file, not counting buffered ones
+fn lines_on_disk(path: &str) -> usize {
+    std::fs::read_to_string(path).map_or(0, |content| content.lines().count())
+}
+
+#[tokio::test]
+async fn test_unflushed_stores_are_searchable() {
+    let path = tempfile::NamedTempFile::new().unwrap().into_temp_path();
+    let path = path.to_str().unwrap();
+    let service = buffered_service(path, 60_000, 1_000);
+
+    for i in 0..200 {
+        store(&service, &format!("note {} about topic{}", i, i)).await;
+    }
+    assert_eq!(lines_on_disk(path), 0, "nothing should be flushed yet");
+
+    let query = "note 199 about topic199";
+    let results = service
+        .compare_embeddings(query, &MockProvider::embed_text(query, DIMENSIONS), Some(1), false, CompareFilter::default(), None)
+        .await
+        .unwrap();
+    assert_eq!(results[0].text, query);
+    assert_eq!(service.count_embeddings(Some("test"), None).unwrap(), 200);
+    let (items, total) = service.list_embeddings(195, 10, None, CreatedRange::default(), false).await.unwrap();
+    assert_eq!(total, 200);
+    assert_eq!(items.last().unwrap().text, query);
+    // Duplicates are still caught while the first copy is buffered
+    let embedding = MockProvider::embed_text(query, DIMENSIONS);
+    assert!(service.save_embedding(query, &embedding, "mock", "test", None).await.is_err());
+
+    assert_eq!(service.flush().await.unwrap(), 200);
+    assert_eq!(lines_on_disk(path), 200);
+    assert_eq!(service.flush().await.unwrap(), 0);
+    let results = service
+        .compare_embeddings(query, &embedding, None, false, CompareFilter::default(), None)
+        .await
+        .unwrap();
+    assert_eq!(results.len(), 200, "flushed records must not be seen twice");
+}
+
+#[tokio::test]
+async fn test_buffer_flushes_when_full_and_on_drop() {
+    let path = tempfile::NamedTempFile::new().unwrap().into_temp_path();
+    let path = path.to_str().unwrap();
+    let service = buffered_service(path, 60_000, 10);
+
+    for i in 0..25 {
+        store(&service, &format!("entry{}", i)).await;
+    }
+    assert_eq!(lines_on_disk(path), 20);
+
+    // Rewriting the file writes out the buffer first, so its records survive
+    assert_eq!(service.clear_type("other").await.unwrap(), 0);
+    assert_eq!(lines_on_disk(path), 25);
+
+    store(&service, "last entry").await;
+    assert_eq!(lines_on_disk(path), 25);
+    drop(service);
+    assert_eq!(lines_on_disk(path), 26);
+}
+
+#[tokio::test]
+async fn test_flush_task_writes_buffer_periodically() {
+    let path = tempfile::NamedTempFile::new().unwrap().into_temp_path();
+    let path = path.to_str().unwrap();
+    let service = Arc::new(buffered_service(path, 50, 1_000));
+    let task = service.spawn_flush_task().expect("stores are buffered");
+
+    for i in 0..3 {
+        store(&service, &format!("periodic{}", i)).await;
+    }
+    let deadline = tokio::time::Instant::now() + Duration::from_secs(5);
+    while lines_on_disk(path) < 3 {
+        assert!(tokio::time::Instant::now() < deadline, "buffer was never flushed");
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+
+    // The task holds no reference that keeps the service alive
+    drop(service);
+    tokio::time::timeout(Duration::from_secs(5), task).await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn test_unbuffered_service_writes_each_store() {
+    let path = tempfile::NamedTempFile::new().unwrap().into_temp_path();
+    let path = path.to_str().unwrap();
+    let service = Arc::new(buffered_service(path, 0, 1_000));
+    assert!(service.spawn_flush_task().is_none());
+
+    store(&service, "written right away").await;
+    assert_eq!(lines_on_disk(path), 1);
+}